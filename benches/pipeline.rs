@@ -0,0 +1,123 @@
+//! Benchmarks of the phases of a translation over the chapters of the test corpus: parsing,
+//! text-node extraction, serialization, archive repacking and dispatch through the pipeline.
+//!
+//! Run with `cargo bench`, or `cargo bench -- serialize` for one phase.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use epub_translator::epub::{content_document_paths, BookArchive, BookFiles, UnzipLimits};
+use epub_translator::options::TranslateOptions;
+use epub_translator::providers::Provider;
+use epub_translator::testing::read_fixture;
+use epub_translator::translate_epub_bytes;
+use epub_translator::xhtml::{
+    decode_xhtml, get_document_node, get_text_nodes, serialize_document_to_string,
+};
+use walkdir::WalkDir;
+
+// Every XHTML file of the test data, by path.
+fn corpus() -> Vec<(PathBuf, String)> {
+    let mut chapters: Vec<(PathBuf, String)> = WalkDir::new("tests/data")
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "xhtml" || ext == "htm")
+        })
+        .map(|entry| {
+            let bytes = fs::read(entry.path()).expect("the corpus is readable");
+            let content = decode_xhtml(&bytes).expect("the corpus is decodable");
+            (entry.path().to_path_buf(), content)
+        })
+        .collect();
+    chapters.sort();
+    chapters
+}
+
+fn bench_documents(c: &mut Criterion) {
+    let chapters = corpus();
+    let bytes: usize = chapters.iter().map(|(_, content)| content.len()).sum();
+    let mut group = c.benchmark_group("documents");
+    group.throughput(Throughput::Bytes(bytes as u64));
+
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            for (_, content) in &chapters {
+                get_document_node(content).unwrap();
+            }
+        })
+    });
+    group.bench_function("text_nodes", |b| {
+        b.iter_batched(
+            || {
+                chapters
+                    .iter()
+                    .map(|(_, content)| get_document_node(content).unwrap())
+                    .collect::<Vec<_>>()
+            },
+            |documents| {
+                for document in &documents {
+                    get_text_nodes(document).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    let documents: Vec<_> = chapters
+        .iter()
+        .map(|(_, content)| get_document_node(content).unwrap())
+        .collect();
+    group.bench_function("serialize", |b| {
+        b.iter(|| {
+            for document in &documents {
+                serialize_document_to_string(document).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_book(c: &mut Criterion) {
+    let epub = read_fixture(Path::new("tests/data/opf_book")).unwrap();
+    let limits = UnzipLimits::default();
+    let mut group = c.benchmark_group("book");
+    group.throughput(Throughput::Bytes(epub.len() as u64));
+
+    group.bench_function("read", |b| {
+        b.iter(|| BookArchive::read(epub.clone(), &limits).unwrap())
+    });
+    // Unchanged entries are copied through, the documents are compressed again
+    group.bench_function("repack", |b| {
+        b.iter_batched(
+            || {
+                let mut book = BookArchive::read(epub.clone(), &limits).unwrap();
+                for path in content_document_paths(&book).unwrap() {
+                    let content = book.read(&path).unwrap();
+                    book.write(&path, content).unwrap();
+                }
+                book
+            },
+            |book| book.to_bytes(None).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let options = TranslateOptions {
+        target_lang: "ES".to_string(),
+        provider: Provider::Identity,
+        ..TranslateOptions::default()
+    };
+    group.bench_function("dispatch", |b| {
+        b.to_async(&runtime)
+            .iter(|| async { translate_epub_bytes(epub.clone(), &options).await.unwrap() })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_documents, bench_book);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+// Writes the C header of the `ffi` module to `OUT_DIR`, only built with the `ffi` feature. The
+// tests of the module check that `include/epub_translator.h` is the same.
+fn main() {
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        cbindgen::generate(&crate_dir)
+            .expect("the C header of the ffi module cannot be generated")
+            .write_to_file(std::path::Path::new(&out_dir).join("epub_translator.h"));
+    }
+}
@@ -0,0 +1,123 @@
+#ifndef EPUB_TRANSLATOR_H
+#define EPUB_TRANSLATOR_H
+
+/* Generated by cbindgen from src/ffi/mod.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Where the translations come from, see `Provider`.
+ */
+typedef enum EpubTranslatorProvider {
+  EPUB_TRANSLATOR_PROVIDER_DEEPL,
+  EPUB_TRANSLATOR_PROVIDER_IDENTITY,
+  EPUB_TRANSLATOR_PROVIDER_PSEUDO,
+} EpubTranslatorProvider;
+
+/**
+ * Where a translation stands, returned by `epub_translator_poll`.
+ */
+typedef enum EpubTranslatorState {
+  EPUB_TRANSLATOR_STATE_RUNNING,
+  /**
+   * The translated EPUB is written, units that failed keep their original text.
+   */
+  EPUB_TRANSLATOR_STATE_FINISHED,
+  /**
+   * Nothing is written, see `epub_translator_error`.
+   */
+  EPUB_TRANSLATOR_STATE_FAILED,
+} EpubTranslatorState;
+
+/**
+ * A translation started by `epub_translator_translate_file`, opaque to C.
+ */
+typedef struct EpubTranslation EpubTranslation;
+
+/**
+ * The counts of a translation so far, see `TranslationReport`.
+ */
+typedef struct EpubTranslatorProgress {
+  /**
+   * Units of the book, 0 until its documents are parsed.
+   */
+  size_t total;
+  size_t translated;
+  size_t failed;
+} EpubTranslatorProgress;
+
+/**
+ * Starts translating `input_file` into `output_file`, in `target_lang`, on a new thread.
+ *
+ * `auth_key` is the DeepL key, `pro` selects the API of the paid plans. It may be null with a
+ * local provider. `provider` is an `EpubTranslatorProvider`, taken as an integer since C may
+ * pass any value. Returns null when an argument is null or not UTF-8, or on an unknown
+ * provider.
+ *
+ * # Safety
+ *
+ * The strings must be null or valid C strings, they are copied before the call returns.
+ */
+struct EpubTranslation *epub_translator_translate_file(const char *input_file,
+                                                       const char *output_file,
+                                                       const char *target_lang,
+                                                       const char *auth_key,
+                                                       bool pro,
+                                                       uint32_t provider);
+
+/**
+ * Copies the counts of `translation` so far into `progress`, which may be null, and returns
+ * whether it is still running.
+ *
+ * # Safety
+ *
+ * `translation` must come from `epub_translator_translate_file` and not be freed yet.
+ * `progress` must be null or point to an `EpubTranslatorProgress`.
+ */
+enum EpubTranslatorState epub_translator_poll(const struct EpubTranslation *translation,
+                                              struct EpubTranslatorProgress *progress);
+
+/**
+ * Stops sending requests. What is already translated is still written, the translation ends
+ * as finished.
+ *
+ * # Safety
+ *
+ * `translation` must come from `epub_translator_translate_file` and not be freed yet.
+ */
+void epub_translator_cancel(const struct EpubTranslation *translation);
+
+/**
+ * The error of a failed translation, null otherwise. The string belongs to the caller, who
+ * frees it with `epub_translator_string_free`.
+ *
+ * # Safety
+ *
+ * `translation` must come from `epub_translator_translate_file` and not be freed yet.
+ */
+char *epub_translator_error(const struct EpubTranslation *translation);
+
+/**
+ * Frees a string returned by the library.
+ *
+ * # Safety
+ *
+ * `string` must be null or come from the library, and not be freed yet.
+ */
+void epub_translator_string_free(char *string);
+
+/**
+ * Frees a translation. One still running is cancelled first, and awaited.
+ *
+ * # Safety
+ *
+ * `translation` must be null or come from `epub_translator_translate_file`, and not be freed
+ * yet.
+ */
+void epub_translator_free(struct EpubTranslation *translation);
+
+#endif  /* EPUB_TRANSLATOR_H */
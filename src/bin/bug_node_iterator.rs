@@ -6,7 +6,7 @@ use kuchiki::NodeRef;
 pub fn print_all_text_nodes(html_content: &str) -> Result<(), Box<dyn std::error::Error>> {
     let document = parse_html().one(html_content);
 
-    let iterator = if let Some(root) = document.select_first("html").ok() {
+    let iterator = if let Ok(root) = document.select_first("html") {
         root.as_node().descendants()
     } else {
         return Err(Box::new(std::io::Error::new(
@@ -34,7 +34,7 @@ pub fn get_text_nodes_iterator(
 ) -> Result<Descendants, Box<dyn std::error::Error>> {
     let document = parse_html().one(html_content);
 
-    let iterator = if let Some(root) = document.select_first("html").ok() {
+    let iterator = if let Ok(root) = document.select_first("html") {
         root.as_node().descendants()
     } else {
         return Err(Box::new(std::io::Error::new(
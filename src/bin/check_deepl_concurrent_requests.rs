@@ -1,4 +1,4 @@
-use deepl::models::DeepLConfiguration;
+use deepl::models::{DeepLConfiguration, TranslationRequest};
 use epub_translator::deepl;
 use reqwest::Client;
 use std::env;
@@ -16,13 +16,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let start = Instant::now();
     // Get API key from env variable
     let api_key = env::var("DEEPL_API_KEY").expect("DEEP_API_KEY environment variable not set");
-    let config = Arc::new(DeepLConfiguration::new_with_determine(api_key).await?);
+    let client = Arc::new(Client::new());
+    let config = Arc::new(DeepLConfiguration::new_with_determine(api_key, &client).await?);
 
     let semaphore = Arc::new(Semaphore::new(CONCURRENT_REQUESTS));
     let barrier = Arc::new(Barrier::new(CONCURRENT_REQUESTS));
 
-    let client = Arc::new(Client::new());
-
     let mut handles = Vec::new();
 
     for i in 0..CONCURRENT_REQUESTS {
@@ -45,9 +44,15 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
             let permits_available = semaphore.available_permits();
             println!("Permits available: {}, thread: {}", permits_available, i);
-            deepl::translate(&config, &TEXT_TO_TRANSLATE, "ES", true, &client, i, 0)
-                .await
-                .ok()
+            deepl::translate(
+                &config,
+                &TranslationRequest::new(TEXT_TO_TRANSLATE, "ES"),
+                &client,
+                i,
+                0,
+            )
+            .await
+            .ok()
         });
         handles.push(task);
     }
@@ -1,4 +1,4 @@
-use deepl::models::DeepLConfiguration;
+use deepl::models::{DeepLConfiguration, TranslationRequest};
 use epub_translator::deepl;
 use reqwest::Client;
 use std::env;
@@ -10,21 +10,26 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Get API key from env variable
     let api_key = env::var("DEEPL_API_KEY").expect("DEEP_API_KEY environment variable not set");
 
-    let config = DeepLConfiguration::new_with_determine(api_key).await?;
+    let client = Client::new();
+    let config = DeepLConfiguration::new_with_determine(api_key, &client).await?;
 
     let text_to_translate = "Hello, world!";
 
-    let client = Client::new();
-
-    let translated_text =
-        deepl::translate(&config, &text_to_translate, "ES", true, &client, 1, 0).await?;
+    let translated_text = deepl::translate(
+        &config,
+        &TranslationRequest::new(text_to_translate, "ES"),
+        &client,
+        1,
+        0,
+    )
+    .await?;
 
     println!(
         "Text: {} got translated to {}",
         text_to_translate, translated_text
     );
 
-    let usage = deepl::get_usage(&config, true).await?;
+    let usage = deepl::get_usage(&config, &client).await?;
 
     println!("Usage: {:?}", usage);
 
@@ -13,7 +13,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let epub_path = Path::new(&args[1]);
     let output_dir = Path::new(&args[2]);
 
-    unzip_epub_from_path(epub_path, &output_dir)?;
+    unzip_epub_from_path(epub_path, output_dir)?;
 
     println!("EPUB file unzipped successfully.");
     Ok(())
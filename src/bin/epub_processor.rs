@@ -36,7 +36,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let start = Instant::now();
 
     // Unzip to temporary folder
-    unzip_epub_from_path(input_epub_path, &temp_dir_path)?;
+    unzip_epub_from_path(input_epub_path, temp_dir_path)?;
 
     let end_unzip = Instant::now();
     let unzip_duration = end_unzip - start;
@@ -54,7 +54,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Serialize documents
     for (document, path) in &documents {
-        serialize_document(&document, &path)?;
+        serialize_document(document, path)?;
     }
 
     let end_serialization = Instant::now();
@@ -66,7 +66,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     // Zip folder into epub
-    zip_folder_to_epub(&temp_dir_path, output_epub_path)?;
+    zip_folder_to_epub(temp_dir_path, output_epub_path)?;
 
     let end_zip = Instant::now();
     let zip_duration = end_zip - end_serialization;
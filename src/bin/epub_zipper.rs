@@ -13,7 +13,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input_dir = Path::new(&args[1]);
     let epub_path = Path::new(&args[2]);
 
-    zip_folder_to_epub(&input_dir, epub_path)?;
+    zip_folder_to_epub(input_dir, epub_path)?;
 
     println!("EPUB file unzipped successfully from folder:");
     println!("{}", &input_dir.to_str().unwrap());
@@ -1,5 +1,5 @@
 use csv::ReaderBuilder;
-use epub_translator::deepl::models::DeepLConfiguration;
+use epub_translator::deepl::models::{DeepLConfiguration, TranslationRequest};
 use epub_translator::deepl::translate;
 use reqwest::Client;
 use std::fs::File;
@@ -32,7 +32,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let char_count = text.chars().count();
 
         let start = Instant::now();
-        let translated = translate(&config, text, target_lang, true, &client, record_id, 0).await?;
+        let translated = translate(
+            &config,
+            &TranslationRequest::new(text, target_lang),
+            &client,
+            record_id,
+            0,
+        )
+        .await?;
         let duration = start.elapsed();
         let dpc = if char_count > 0 {
             duration.as_millis() as f32 / char_count as f32
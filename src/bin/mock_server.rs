@@ -1,25 +1,78 @@
-use epub_translator::deepl;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use epub_translator::deepl::{self, Cassette, MockBehavior, MockServerOptions};
 
 use tokio::signal;
 
 // Runs the mock server, useful for testing changes to epubs.
+// Listens on the address given as first argument, 127.0.0.1:3030 by default, port 0 picks a free
+// one. The second argument sets how it answers, e.g. `latency=100-900,429=0.1`, see
+// `MockBehavior`.
+// With `--record UPSTREAM FILE` the translation requests are forwarded to the API at UPSTREAM,
+// e.g. https://api-free.deepl.com/v2, and recorded in FILE, `--replay FILE` answers them with
+// the recorded responses, see `Cassette`. `--languages FILE` sets the target languages it
+// lists, see `MockServerOptions::languages`.
 // Use Ctrl+C to stop the server.
 #[tokio::main]
 async fn main() {
+    let usage = |e: String| -> ! {
+        eprintln!(
+            "Usage: mock_server [--record UPSTREAM FILE | --replay FILE] [--languages FILE] \
+             [address] [settings], e.g. 127.0.0.1:0 latency=100-900,500=0.1: {}",
+            e
+        );
+        std::process::exit(1);
+    };
+    let mut options = MockServerOptions::default();
+    let mut args = env::args().skip(1).peekable();
+    let missing = |what: &str| -> String { usage(format!("missing {}", what)) };
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        match flag.as_str() {
+            "--record" => {
+                let upstream = args.next().unwrap_or_else(|| missing("upstream"));
+                let path = args.next().unwrap_or_else(|| missing("cassette"));
+                options.cassette = Some(Cassette::Record {
+                    upstream,
+                    path: PathBuf::from(path),
+                });
+            }
+            "--replay" => {
+                let path = args.next().unwrap_or_else(|| missing("cassette"));
+                options.cassette = Some(Cassette::Replay {
+                    path: PathBuf::from(path),
+                });
+            }
+            "--languages" => {
+                let path = args.next().unwrap_or_else(|| missing("languages"));
+                options.languages = Some(PathBuf::from(path));
+            }
+            _ => usage(format!("unknown flag {}", flag)),
+        }
+    }
+    if let Some(address) = args.next() {
+        options.address = address
+            .parse::<SocketAddr>()
+            .unwrap_or_else(|e| usage(e.to_string()));
+    }
+    if let Some(behavior) = args.next() {
+        options.behavior = behavior
+            .parse::<MockBehavior>()
+            .unwrap_or_else(|e| usage(e));
+    }
+
     println!("Starting mock server...");
-    let shutdown_signal = deepl::start_deepl_server()
+    let server = deepl::start_deepl_server(&options)
         .await
         .expect("Failed to create mock server");
+    println!("Listening on http://{}", server.address());
 
     // Wait for a Ctrl+C signal to initiate shutdown
     signal::ctrl_c()
         .await
         .expect("Failed to listen for shutdown signal");
 
-    // Send shutdown signal to the mock server
-    shutdown_signal
-        .send(())
-        .expect("Failed to send shutdown signal");
-
     println!("Shutting down mock server...");
+    server.stop();
 }
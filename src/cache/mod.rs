@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+use crate::error::EpubTranslatorError;
+
+/// Translations received from DeepL in earlier runs, reused instead of being requested and
+/// billed again, see `TranslateOptions::cache`. A unit is found by its text as it is sent,
+/// placeholders included, and by its target language.
+///
+/// The file holds an entry per line, as JSON. New translations are appended as they arrive, an
+/// interrupted run keeps the ones it received.
+pub struct TranslationCache {
+    entries: HashMap<(String, String), String>,
+    file: File,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    target_lang: String,
+    source: String,
+    translation: String,
+}
+
+impl TranslationCache {
+    /// Opens the cache at `path`, created when missing.
+    pub fn open(path: &Path) -> Result<Self, EpubTranslatorError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let mut entries = HashMap::new();
+        for (index, line) in BufReader::new(&file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line).map_err(|e| ConfigError {
+                path: path.to_path_buf(),
+                message: format!("line {}: {}", index + 1, e),
+            })?;
+            entries.insert((entry.target_lang, entry.source), entry.translation);
+        }
+        Ok(Self { entries, file })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The translation of `source`, a unit as it is sent, into `target_lang`.
+    pub fn get(&self, source: &str, target_lang: &str) -> Option<&str> {
+        self.entries
+            .get(&(target_lang.to_uppercase(), source.to_string()))
+            .map(String::as_str)
+    }
+
+    /// Adds the translation of `source` into `target_lang` and appends it to the file.
+    pub fn insert(
+        &mut self,
+        source: &str,
+        target_lang: &str,
+        translation: &str,
+    ) -> Result<(), EpubTranslatorError> {
+        let entry = Entry {
+            target_lang: target_lang.to_uppercase(),
+            source: source.to_string(),
+            translation: translation.to_string(),
+        };
+        let mut line = serde_json::to_string(&entry).map_err(std::io::Error::other)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.entries
+            .insert((entry.target_lang, entry.source), entry.translation);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("cache.jsonl");
+
+        let mut cache = TranslationCache::open(&path)?;
+        assert!(cache.is_empty());
+        cache.insert("<g0>Hello</g0> world", "es", "<g0>Hola</g0> mundo")?;
+        assert_eq!(
+            cache.get("<g0>Hello</g0> world", "ES"),
+            Some("<g0>Hola</g0> mundo")
+        );
+
+        // Kept for the next runs, for their target language only
+        let cache = TranslationCache::open(&path)?;
+        assert_eq!(cache.len(), 1);
+        assert_eq!(
+            cache.get("<g0>Hello</g0> world", "ES"),
+            Some("<g0>Hola</g0> mundo")
+        );
+        assert_eq!(cache.get("<g0>Hello</g0> world", "FR"), None);
+
+        std::fs::write(&path, "not json\n")?;
+        assert!(TranslationCache::open(&path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::deepl::models::{AuthKey, Formality};
+use crate::pricing::Rate;
+
+/// Name of the per-project configuration file, read from the current directory.
+pub const PROJECT_CONFIG_FILE: &str = "epub-translator.toml";
+
+/// Settings read from TOML configuration files. All of them are optional, environment
+/// variables and command line flags take precedence.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// DeepL API keys, the requests are balanced across them.
+    pub api_keys: Option<Vec<AuthKey>>,
+    pub target_lang: Option<String>,
+    pub source_lang: Option<String>,
+    /// Maximum number of requests sent to the API at the same time.
+    pub parallel: Option<usize>,
+    pub formality: Option<Formality>,
+    pub notranslate_classes: Option<Vec<String>>,
+    pub skip_tags: Option<Vec<String>>,
+    pub exclude_selectors: Option<Vec<String>>,
+    pub translate_attributes: Option<Vec<String>>,
+    /// CSV glossary applied to the translations, see `terms::Glossary`. Relative to the current
+    /// directory, like the other paths.
+    pub glossary: Option<PathBuf>,
+    /// File of terms kept untranslated, see `terms::read_keep_terms`.
+    pub keep_terms: Option<PathBuf>,
+    /// Rates of the cost estimates, `pricing::default_rates` otherwise.
+    pub rates: Option<Vec<Rate>>,
+}
+
+/// Error returned for configuration files that cannot be read or parsed.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads the configuration file at `path`. Unknown settings are errors.
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let error = |message: String| ConfigError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let content = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        toml::from_str(&content).map_err(|e| error(e.message().to_string()))
+    }
+
+    /// Reads the configuration files, each one overriding the settings of the previous ones:
+    /// the user file (see `user_config_path`), the `PROJECT_CONFIG_FILE` of the current
+    /// directory and `explicit`. Missing files are skipped, except `explicit`.
+    pub fn load(explicit: Option<&Path>) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let defaults = user_config_path()
+            .into_iter()
+            .chain([PathBuf::from(PROJECT_CONFIG_FILE)])
+            .filter(|path| path.is_file());
+        for path in defaults {
+            config = config.merge(Self::read(&path)?);
+        }
+        if let Some(path) = explicit {
+            config = config.merge(Self::read(path)?);
+        }
+        Ok(config)
+    }
+
+    /// The settings of `self`, overridden by the ones set in `other`.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            api_keys: other.api_keys.or(self.api_keys),
+            target_lang: other.target_lang.or(self.target_lang),
+            source_lang: other.source_lang.or(self.source_lang),
+            parallel: other.parallel.or(self.parallel),
+            formality: other.formality.or(self.formality),
+            notranslate_classes: other.notranslate_classes.or(self.notranslate_classes),
+            skip_tags: other.skip_tags.or(self.skip_tags),
+            exclude_selectors: other.exclude_selectors.or(self.exclude_selectors),
+            translate_attributes: other.translate_attributes.or(self.translate_attributes),
+            glossary: other.glossary.or(self.glossary),
+            keep_terms: other.keep_terms.or(self.keep_terms),
+            rates: other.rates.or(self.rates),
+        }
+    }
+}
+
+/// `epub-translator/config.toml` in `$XDG_CONFIG_HOME`, `~/.config` by default.
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("epub-translator").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let user = temp_dir.path().join("user.toml");
+        fs::write(
+            &user,
+            "api_keys = [\"a\", \"b\"]\ntarget_lang = \"ES\"\nparallel = 8\n\
+             formality = \"prefer_less\"\nglossary = \"glossary.csv\"\n",
+        )?;
+        let project = temp_dir.path().join("project.toml");
+        fs::write(
+            &project,
+            "target_lang = \"FR\"\nskip_tags = []\nkeep_terms = \"terms.txt\"\n\n\
+             [[rates]]\nplan = \"Reseller\"\nper_million = 18.5\ncurrency = \"EUR\"\n",
+        )?;
+
+        let config = Config::read(&user)?.merge(Config::read(&project)?);
+        assert_eq!(
+            config,
+            Config {
+                api_keys: Some(vec!["a".into(), "b".into()]),
+                target_lang: Some("FR".to_string()),
+                parallel: Some(8),
+                formality: Some(Formality::PreferLess),
+                skip_tags: Some(Vec::new()),
+                glossary: Some(PathBuf::from("glossary.csv")),
+                keep_terms: Some(PathBuf::from("terms.txt")),
+                rates: Some(vec![Rate {
+                    plan: "Reseller".to_string(),
+                    per_million: 18.5,
+                    currency: "EUR".to_string(),
+                }]),
+                ..Default::default()
+            }
+        );
+
+        // Typos are not silently ignored
+        fs::write(&project, "target_language = \"FR\"\n")?;
+        let error = Config::read(&project).unwrap_err();
+        assert!(error.message.contains("target_language"));
+        assert!(Config::load(Some(&temp_dir.path().join("missing.toml"))).is_err());
+        Ok(())
+    }
+}
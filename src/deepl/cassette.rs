@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use super::mock::{message_response, MockState};
+use super::models::{TranslationRequest, DEEPL_TRANSLATE_PATH};
+use crate::config::ConfigError;
+use crate::error::EpubTranslatorError;
+
+/// Record-and-replay of the translations of the real API, see `MockServerOptions::cassette`.
+/// A cassette is a file of JSON lines, one per translation request with its response. The
+/// keys are never written to it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cassette {
+    /// Forwards the translation requests to `upstream`, the URL of an API like
+    /// `https://api-free.deepl.com/v2`, and appends them with their responses to `path`.
+    Record { upstream: String, path: PathBuf },
+    /// Answers the translation requests with the responses recorded in `path`. A request
+    /// recorded several times gets its responses in turn, then the last one again, and one
+    /// never recorded gets 404 Not Found.
+    Replay { path: PathBuf },
+}
+
+// A recorded request, the JSON body sent to `/v2/translate`, and its response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct Exchange {
+    request: serde_json::Value,
+    status: u16,
+    response: String,
+}
+
+impl Exchange {
+    fn response(&self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::BAD_GATEWAY);
+        let content_type = [(CONTENT_TYPE, "application/json")];
+        (status, content_type, self.response.clone()).into_response()
+    }
+}
+
+// Requests match whatever the order of their fields, the maps of `serde_json` are sorted.
+fn request_key(request: &serde_json::Value) -> String {
+    request.to_string()
+}
+
+pub(super) enum CassetteState {
+    Record {
+        upstream: String,
+        client: Client,
+        file: Mutex<File>,
+    },
+    // The responses of each request, with the number already served
+    Replay(Mutex<HashMap<String, (Vec<Exchange>, usize)>>),
+}
+
+impl CassetteState {
+    pub(super) fn open(cassette: &Cassette) -> Result<Self, EpubTranslatorError> {
+        match cassette {
+            Cassette::Record { upstream, path } => Ok(Self::Record {
+                upstream: upstream.trim_end_matches('/').to_string(),
+                client: Client::new(),
+                file: Mutex::new(OpenOptions::new().create(true).append(true).open(path)?),
+            }),
+            Cassette::Replay { path } => {
+                let error = |message: String| ConfigError {
+                    path: path.clone(),
+                    message,
+                };
+                let content = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+                let mut exchanges: HashMap<String, (Vec<Exchange>, usize)> = HashMap::new();
+                for (number, line) in content.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let exchange: Exchange = serde_json::from_str(line)
+                        .map_err(|e| error(format!("line {}: {}", number + 1, e)))?;
+                    let key = request_key(&exchange.request);
+                    exchanges.entry(key).or_default().0.push(exchange);
+                }
+                Ok(Self::Replay(Mutex::new(exchanges)))
+            }
+        }
+    }
+}
+
+// Answers `/v2/translate` when the mock has a cassette.
+pub(super) async fn r_translate(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return message_response(StatusCode::BAD_REQUEST, "Bad request");
+    };
+    if let Ok(translation_request) = serde_json::from_value::<TranslationRequest>(json.clone()) {
+        state.received.push(&headers, translation_request);
+    }
+
+    let Some(cassette) = &state.cassette else {
+        return message_response(StatusCode::INTERNAL_SERVER_ERROR, "No cassette");
+    };
+    match cassette {
+        CassetteState::Record {
+            upstream,
+            client,
+            file,
+        } => {
+            let mut forwarded = client
+                .post(format!("{}{}", upstream, DEEPL_TRANSLATE_PATH))
+                .header("Content-Type", "application/json")
+                .body(body.to_vec());
+            if let Some(authorization) = headers.get("Authorization") {
+                forwarded = forwarded.header("Authorization", authorization.as_bytes());
+            }
+            let (status, response) = match forwarded.send().await {
+                Ok(response) => (
+                    response.status().as_u16(),
+                    response.text().await.unwrap_or_default(),
+                ),
+                // Nothing is recorded, the next run may reach the API
+                Err(e) => {
+                    warn!("Recording proxy: {}", e);
+                    return message_response(StatusCode::BAD_GATEWAY, &e.to_string());
+                }
+            };
+            let exchange = Exchange {
+                request: json,
+                status,
+                response,
+            };
+            let line = serde_json::to_string(&exchange).expect("exchanges serialize");
+            let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(e) = writeln!(file, "{}", line) {
+                warn!("Recording proxy: cannot write the cassette: {}", e);
+            }
+            exchange.response()
+        }
+        CassetteState::Replay(exchanges) => {
+            let mut exchanges = exchanges.lock().unwrap_or_else(PoisonError::into_inner);
+            match exchanges.get_mut(&request_key(&json)) {
+                Some((recorded, served)) => {
+                    let exchange = &recorded[(*served).min(recorded.len() - 1)];
+                    *served += 1;
+                    exchange.response()
+                }
+                None => {
+                    debug!("Request not in the cassette: {}", json);
+                    message_response(StatusCode::NOT_FOUND, "Not in the cassette")
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deepl::{start_deepl_server, translate, MockServerOptions};
+
+    #[tokio::test]
+    async fn test_record_and_replay() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("cassette.jsonl");
+        let client = Client::new();
+        let request = |text: &str| TranslationRequest::new(text, "ES");
+
+        // The mock stands for the real API
+        let upstream = start_deepl_server(&MockServerOptions::ephemeral()).await?;
+        let recorder = start_deepl_server(&MockServerOptions {
+            cassette: Some(Cassette::Record {
+                upstream: upstream.config().api_url,
+                path: path.clone(),
+            }),
+            ..MockServerOptions::ephemeral()
+        })
+        .await?;
+        let recorded = translate(
+            &recorder.key_config("secret-key"),
+            &request("Hello"),
+            &client,
+            0,
+            1,
+        )
+        .await?;
+        assert_eq!(recorded, "--|Hello|-- Translated to ES");
+        recorder.stop();
+        upstream.stop();
+        assert!(!fs::read_to_string(&path)?.contains("secret-key"));
+
+        let replayer = start_deepl_server(&MockServerOptions {
+            cassette: Some(Cassette::Replay { path: path.clone() }),
+            ..MockServerOptions::ephemeral()
+        })
+        .await?;
+        let config = replayer.config();
+        for _ in 0..2 {
+            assert_eq!(
+                translate(&config, &request("Hello"), &client, 0, 1).await?,
+                recorded
+            );
+        }
+        assert!(matches!(
+            translate(&config, &request("Other"), &client, 0, 1).await,
+            Err(EpubTranslatorError::Provider { status: 404, .. })
+        ));
+        replayer.stop();
+
+        fs::write(&path, "{\"request\": 1}\n")?;
+        assert!(start_deepl_server(&MockServerOptions {
+            cassette: Some(Cassette::Replay { path }),
+            ..MockServerOptions::ephemeral()
+        })
+        .await
+        .is_err());
+        Ok(())
+    }
+}
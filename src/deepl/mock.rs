@@ -0,0 +1,643 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use axum::extract::{Form, FromRequest, Query, Request, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, info};
+
+use super::cassette::{self, Cassette, CassetteState};
+use super::mock_documents::{self, Documents};
+use super::mock_glossaries::{self, Glossaries};
+use super::models::{
+    AuthKey, DeepLConfiguration, Language, LanguageType, LanguagesResponse, Translation,
+    TranslationRequest, TranslationResponse, UsageResponse,
+};
+use super::QUOTA_EXCEEDED_STATUS;
+use crate::config::ConfigError;
+use crate::error::EpubTranslatorError;
+
+/// How the mock answers the translation requests, to exercise the retries of the client. It
+/// is read from a comma-separated list of settings, e.g.
+/// `latency=100-900,429=0.1,456=0.05,500=0.1,malformed=0.02`:
+///
+/// - `latency`: delay of the answers in milliseconds, fixed or drawn uniformly from a range.
+/// - `429`, `456`, `500`: share of the requests answered with that status, Too Many Requests,
+///   Quota Exceeded and Internal Server Error.
+/// - `malformed`: share of the requests answered with a body that is not the JSON expected.
+/// - `quota`: character quota of each key, 500000 by default. The requests that would go over
+///   it are answered with 456 Quota Exceeded.
+/// - `used`: characters each key has used when the server starts, 1000 by default.
+/// - `translation`: how the texts are translated, `marked`, `reversed` or `piglatin`, see
+///   `MockTranslation`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockBehavior {
+    /// 400 ms by default.
+    pub latency: RangeInclusive<Duration>,
+    pub too_many_requests: f64,
+    pub quota_exceeded: f64,
+    pub server_error: f64,
+    pub malformed: f64,
+    pub character_limit: u64,
+    pub character_count: u64,
+    pub translation: MockTranslation,
+}
+
+impl Default for MockBehavior {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(400)..=Duration::from_millis(400),
+            too_many_requests: 0.0,
+            quota_exceeded: 0.0,
+            server_error: 0.0,
+            malformed: 0.0,
+            character_limit: 500000,
+            character_count: 1000,
+            translation: MockTranslation::default(),
+        }
+    }
+}
+
+impl FromStr for MockBehavior {
+    type Err = String;
+
+    fn from_str(settings: &str) -> Result<Self, Self::Err> {
+        let mut behavior = Self::default();
+        for setting in settings.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("`{}` is not a setting like `429=0.1`", setting))?;
+            let rate = || match value.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+                _ => Err(format!("the rate of `{}` must be between 0 and 1", name)),
+            };
+            match name {
+                "latency" => {
+                    let millis = |millis: &str| {
+                        millis
+                            .parse()
+                            .map(Duration::from_millis)
+                            .map_err(|_| format!("invalid latency `{}`", value))
+                    };
+                    let (min, max) = value.split_once('-').unwrap_or((value, value));
+                    let (min, max) = (millis(min)?, millis(max)?);
+                    if min > max {
+                        return Err(format!("invalid latency `{}`", value));
+                    }
+                    behavior.latency = min..=max;
+                }
+                "429" => behavior.too_many_requests = rate()?,
+                "456" => behavior.quota_exceeded = rate()?,
+                "500" => behavior.server_error = rate()?,
+                "malformed" => behavior.malformed = rate()?,
+                "quota" | "used" => {
+                    let characters = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of characters `{}`", value))?;
+                    match name {
+                        "quota" => behavior.character_limit = characters,
+                        _ => behavior.character_count = characters,
+                    }
+                }
+                "translation" => behavior.translation = value.parse()?,
+                _ => {
+                    return Err(format!(
+                        "unknown setting `{}`, expected `latency`, `429`, `456`, `500`, \
+                         `malformed`, `quota`, `used` or `translation`",
+                        name
+                    ))
+                }
+            }
+        }
+        let failures = behavior.too_many_requests
+            + behavior.quota_exceeded
+            + behavior.server_error
+            + behavior.malformed;
+        if failures > 1.0 {
+            return Err("the rates add up to more than 1".to_string());
+        }
+        Ok(behavior)
+    }
+}
+
+/// How the mock translates a text. Every mode is deterministic, the same text always gets the
+/// same translation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MockTranslation {
+    /// The whole text is marked as translated: `Hello` becomes `--|Hello|-- Translated to ES`.
+    #[default]
+    Marked,
+    /// The letters of each word are reversed: `Hello <b>world</b>` becomes
+    /// `olleH <b>dlrow</b>`.
+    Reversed,
+    /// Each word goes to pig latin: `Hello <b>world</b>` becomes `Ellohay <b>orldway</b>`.
+    PigLatin,
+}
+
+impl FromStr for MockTranslation {
+    type Err = String;
+
+    fn from_str(translation: &str) -> Result<Self, Self::Err> {
+        match translation {
+            "marked" => Ok(Self::Marked),
+            "reversed" => Ok(Self::Reversed),
+            "piglatin" => Ok(Self::PigLatin),
+            _ => Err(format!(
+                "unknown translation `{}`, expected `marked`, `reversed` or `piglatin`",
+                translation
+            )),
+        }
+    }
+}
+
+impl MockTranslation {
+    /// Translates `text`. With `markup`, as for requests with a `tag_handling`, the tags and
+    /// the entities of the text are kept as they are, only the words between them change.
+    pub fn translate(self, text: &str, target_lang: &str, markup: bool) -> String {
+        let word: fn(&str) -> String = match self {
+            Self::Marked => return format!("--|{}|-- Translated to {}", text, target_lang),
+            Self::Reversed => |word| word.chars().rev().collect(),
+            Self::PigLatin => pig_latin,
+        };
+
+        let mut translated = String::new();
+        let mut current = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c.is_alphanumeric() {
+                current.push(c);
+                continue;
+            }
+            translated.push_str(&word(&current));
+            current.clear();
+            translated.push(c);
+            // Tags and entities are copied whole
+            if markup && (c == '<' || c == '&') {
+                let closing = if c == '<' { '>' } else { ';' };
+                for c in chars.by_ref() {
+                    translated.push(c);
+                    if c == closing {
+                        break;
+                    }
+                }
+            }
+        }
+        translated.push_str(&word(&current));
+        translated
+    }
+}
+
+// `word` in pig latin: its first consonants go to its end with `ay`, words starting with a
+// vowel get `way`. The case of the first letter stays in front.
+fn pig_latin(word: &str) -> String {
+    if !word.chars().any(char::is_alphabetic) {
+        return word.to_string();
+    }
+    let vowel = |c: &char| "aeiouAEIOU".contains(*c);
+    let split = word
+        .char_indices()
+        .find(|(_, c)| vowel(c))
+        .map_or(word.len(), |(index, _)| index);
+    if split == 0 {
+        return format!("{}way", word);
+    }
+    if split == word.len() {
+        return format!("{}ay", word);
+    }
+    let (onset, rest) = word.split_at(split);
+    if !onset.starts_with(char::is_uppercase) {
+        return format!("{}{}ay", rest, onset);
+    }
+    let mut letters = rest.chars();
+    let first: String = letters
+        .next()
+        .into_iter()
+        .flat_map(char::to_uppercase)
+        .collect();
+    format!("{}{}{}ay", first, letters.as_str(), onset.to_lowercase())
+}
+
+// Characters used by each key, by the key sent in the `Authorization` header.
+#[derive(Default)]
+pub(super) struct Usage(Mutex<HashMap<String, u64>>);
+
+impl Usage {
+    // Runs `f` on the characters used by the key of `request`.
+    pub(super) fn with<T>(
+        &self,
+        headers: &HeaderMap,
+        behavior: &MockBehavior,
+        f: impl FnOnce(&mut u64) -> T,
+    ) -> T {
+        let key = headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .trim_start_matches("DeepL-Auth-Key ")
+            .to_string();
+        let mut usage = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        f(usage.entry(key).or_insert(behavior.character_count))
+    }
+}
+
+/// A translation request received by the mock, see `MockServer::requests`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReceivedRequest {
+    pub request: TranslationRequest,
+    /// The headers of the request, by lowercase name, e.g. `authorization`.
+    pub headers: BTreeMap<String, String>,
+}
+
+// The translation requests received, in the order they arrived.
+#[derive(Default)]
+pub(super) struct Received(Mutex<Vec<ReceivedRequest>>);
+
+impl Received {
+    pub(super) fn push(&self, headers: &HeaderMap, request: TranslationRequest) {
+        let headers = headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(ReceivedRequest { request, headers });
+    }
+
+    fn list(&self) -> Vec<ReceivedRequest> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn clear(&self) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+// The state of a running mock, shared by its routes.
+pub(super) struct MockState {
+    pub(super) behavior: MockBehavior,
+    languages: LanguagesResponse,
+    pub(super) usage: Usage,
+    pub(super) received: Received,
+    pub(super) glossaries: Glossaries,
+    pub(super) documents: Documents,
+    pub(super) cassette: Option<CassetteState>,
+}
+
+// The body of a request, as JSON or as a form, DeepL takes both.
+pub(super) struct JsonOrForm<T>(pub(super) T);
+
+impl<S: Send + Sync, T: DeserializeOwned> FromRequest<S> for JsonOrForm<T> {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let json = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/json"));
+        if json {
+            let Json(value) = Json::from_request(request, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value))
+        } else {
+            let Form(value) = Form::from_request(request, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value))
+        }
+    }
+}
+
+// An error status, with the JSON body DeepL sends along.
+pub(super) fn message_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({ "message": message }))).into_response()
+}
+
+fn error_response(status: StatusCode) -> Response {
+    message_response(
+        status,
+        status.canonical_reason().unwrap_or("Quota exceeded"),
+    )
+}
+
+// A random id shaped like the UUIDs of DeepL.
+pub(super) fn mock_id() -> String {
+    let digits = format!("{:032x}", rand::thread_rng().gen::<u128>());
+    format!(
+        "{}-{}-{}-{}-{}",
+        &digits[..8],
+        &digits[8..12],
+        &digits[12..16],
+        &digits[16..20],
+        &digits[20..]
+    )
+}
+
+async fn r_translate(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Json(req): Json<TranslationRequest>,
+) -> Response {
+    let behavior = &state.behavior;
+    let text_to_translate = &req.text[0];
+    debug!("Received translate request: |{}|", text_to_translate);
+    state.received.push(&headers, req.clone());
+
+    // The random numbers are drawn before awaiting, the generator is not `Send`
+    let (latency, draw) = {
+        let mut rng = rand::thread_rng();
+        (rng.gen_range(behavior.latency.clone()), rng.gen::<f64>())
+    };
+    sleep(latency).await;
+
+    // Each failure takes its share of [0, 1), in turn
+    let quota_exceeded = StatusCode::from_u16(QUOTA_EXCEEDED_STATUS).unwrap();
+    let failures = [
+        (behavior.too_many_requests, StatusCode::TOO_MANY_REQUESTS),
+        (behavior.quota_exceeded, quota_exceeded),
+        (behavior.server_error, StatusCode::INTERNAL_SERVER_ERROR),
+    ];
+    let mut threshold = 0.0;
+    for (rate, status) in failures {
+        threshold += rate;
+        if draw < threshold {
+            return error_response(status);
+        }
+    }
+
+    let text = match state.glossaries.apply(&req) {
+        Ok(text) => text,
+        Err((status, message)) => return message_response(status, message),
+    };
+
+    // The characters are billed unless they would go over the quota
+    let characters: u64 = req
+        .text
+        .iter()
+        .map(|text| text.chars().count() as u64)
+        .sum();
+    let billed = state.usage.with(&headers, behavior, |used| {
+        let billed = *used + characters <= behavior.character_limit;
+        if billed {
+            *used += characters;
+        }
+        billed
+    });
+    if !billed {
+        return error_response(quota_exceeded);
+    }
+
+    if draw < threshold + behavior.malformed {
+        return (
+            [(CONTENT_TYPE, "application/json")],
+            r#"{"translations": [{"text": "#,
+        )
+            .into_response();
+    }
+
+    let translations = vec![Translation {
+        detected_source_language: "EN".to_string(),
+        text: behavior
+            .translation
+            .translate(&text, &req.target_lang, req.tag_handling.is_some()),
+    }];
+
+    Json(TranslationResponse { translations }).into_response()
+}
+
+async fn r_usage(State(state): State<Arc<MockState>>, headers: HeaderMap) -> Json<UsageResponse> {
+    debug!("Received usage request");
+    Json(UsageResponse {
+        character_count: state.usage.with(&headers, &state.behavior, |used| *used),
+        character_limit: state.behavior.character_limit,
+    })
+}
+
+async fn r_requests(State(state): State<Arc<MockState>>) -> Json<Vec<ReceivedRequest>> {
+    Json(state.received.list())
+}
+
+async fn r_clear_requests(State(state): State<Arc<MockState>>) -> StatusCode {
+    state.received.clear();
+    StatusCode::NO_CONTENT
+}
+
+async fn r_languages(
+    State(state): State<Arc<MockState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Json<LanguagesResponse> {
+    debug!("Received languages request");
+
+    // Like DeepL, the source languages are listed unless the target ones are asked for
+    if query.get("type").map(String::as_str) == Some(LanguageType::Target.as_str()) {
+        Json(state.languages.clone())
+    } else {
+        Json(source_languages(state.languages.clone()))
+    }
+}
+
+// The target languages of DeepL, listed unless `MockServerOptions::languages` is given.
+const LANGUAGES_JSON: &str = include_str!("languages.json");
+
+// The target languages listed by the mock, read from `path` when given.
+fn target_languages(path: Option<&Path>) -> Result<LanguagesResponse, EpubTranslatorError> {
+    let Some(path) = path else {
+        return Ok(serde_json::from_str(LANGUAGES_JSON).expect("the embedded languages parse"));
+    };
+    let error = |message: String| ConfigError {
+        path: path.to_path_buf(),
+        message,
+    };
+    let content = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    Ok(serde_json::from_str(&content).map_err(|e| error(e.to_string()))?)
+}
+
+// The source languages of the mock: the target ones without their regional variants.
+fn source_languages(target: LanguagesResponse) -> LanguagesResponse {
+    let mut source: Vec<Language> = Vec::new();
+    for language in target.0 {
+        let code = language.language.split('-').next().unwrap_or_default();
+        if source.iter().any(|source| source.language == code) {
+            continue;
+        }
+        source.push(Language {
+            language: code.to_string(),
+            name: language
+                .name
+                .split(" (")
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            supports_formality: None,
+        });
+    }
+    LanguagesResponse(source)
+}
+
+/// Settings of the mock of the DeepL API, see `start_deepl_server`.
+#[derive(Debug, Clone)]
+pub struct MockServerOptions {
+    /// Address the server listens on, the one of `DEEPL_MOCK_API_URL` by default. With port 0
+    /// a free port is picked, see `MockServer::address`.
+    pub address: SocketAddr,
+    pub behavior: MockBehavior,
+    /// Translations recorded from the real API, or replayed from a recording, instead of the
+    /// ones of the mock. Usage and languages stay the ones of the mock.
+    pub cassette: Option<Cassette>,
+    /// A file of the target languages listed by the mock, like DeepL lists them for
+    /// `/v2/languages?type=target`. The ones embedded in the library by default.
+    pub languages: Option<PathBuf>,
+}
+
+impl Default for MockServerOptions {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 3030)),
+            behavior: MockBehavior::default(),
+            cassette: None,
+            languages: None,
+        }
+    }
+}
+
+impl MockServerOptions {
+    /// Listens on a free port of the loopback interface, so that several servers can run at
+    /// once, e.g. in parallel tests.
+    pub fn ephemeral() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 0)),
+            ..Default::default()
+        }
+    }
+}
+
+/// A running mock of the DeepL API, stopped by `stop` or when dropped.
+pub struct MockServer {
+    address: SocketAddr,
+    state: Arc<MockState>,
+    shutdown: oneshot::Sender<()>,
+}
+
+impl MockServer {
+    /// The address the server listens on, with the port picked when port 0 was asked for.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// A configuration sending the requests to this server, like `get_test_config` does to the
+    /// default address.
+    pub fn config(&self) -> DeepLConfiguration {
+        self.key_config("mock_auth_key")
+    }
+
+    /// A configuration sending the requests to this server with `auth_key`. Each key has its
+    /// own quota, see `MockBehavior`.
+    pub fn key_config(&self, auth_key: impl Into<AuthKey>) -> DeepLConfiguration {
+        DeepLConfiguration {
+            api_url: format!("http://{}/v2", self.address),
+            auth_key: auth_key.into(),
+        }
+    }
+
+    /// The translation requests received so far, in the order they arrived, the failed ones
+    /// included. Other processes get them as JSON from `GET /mock/requests`.
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.state.received.list()
+    }
+
+    /// Forgets the requests received so far, like `DELETE /mock/requests`.
+    pub fn clear_requests(&self) {
+        self.state.received.clear()
+    }
+
+    /// Stops the server, the requests in flight are answered first.
+    pub fn stop(self) {
+        let _ = self.shutdown.send(());
+    }
+}
+
+/// Starts a mock of the DeepL API on `options.address`. Translations are the text marked as
+/// translated, after the latency of `options.behavior`, 400 ms by default.
+///
+/// Glossaries can be created, listed, read and deleted under `/v2/glossaries`, and their
+/// entries are replaced in the texts translated with them. Documents uploaded to
+/// `/v2/document` go through `queued`, `translating` and `done`, a step per status request,
+/// and their translation can then be downloaded once. Text and HTML files are marked like the
+/// texts, other files come back unchanged, and at least 50000 characters are billed for each.
+pub async fn start_deepl_server(
+    options: &MockServerOptions,
+) -> Result<MockServer, EpubTranslatorError> {
+    let (tx, rx) = oneshot::channel::<()>();
+
+    let state = Arc::new(MockState {
+        behavior: options.behavior.clone(),
+        languages: target_languages(options.languages.as_deref())?,
+        usage: Usage::default(),
+        received: Received::default(),
+        glossaries: Glossaries::default(),
+        documents: Documents::default(),
+        cassette: match &options.cassette {
+            Some(cassette) => Some(CassetteState::open(cassette)?),
+            None => None,
+        },
+    });
+    let translate = match options.cassette {
+        Some(_) => post(cassette::r_translate),
+        None => post(r_translate),
+    };
+    let router = Router::new()
+        .route("/v2/translate", translate)
+        .route("/v2/usage", get(r_usage))
+        .route("/v2/languages", get(r_languages))
+        .route("/mock/requests", get(r_requests).delete(r_clear_requests))
+        .merge(mock_glossaries::routes())
+        .merge(mock_documents::routes())
+        .with_state(state.clone());
+
+    // The port picked by the system when 0 was asked for
+    let listener = TcpListener::bind(options.address).await?;
+    let address = listener.local_addr()?;
+
+    // The server runs on the runtime of the caller, until the shutdown signal or until the
+    // `MockServer` is dropped
+    tokio::spawn(async move {
+        let shutdown = async {
+            rx.await.ok();
+            info!("Shutdown signal received, stopping server...");
+        };
+        axum::serve(listener, router)
+            .with_graceful_shutdown(shutdown)
+            .await
+            .expect("Server failed to run");
+    });
+
+    info!("Server started and listening on http://{}", address);
+
+    Ok(MockServer {
+        address,
+        state,
+        shutdown: tx,
+    })
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use axum::extract::{Multipart, Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Deserialize;
+
+use super::mock::{message_response, mock_id, JsonOrForm, MockState, MockTranslation};
+use super::models::{DocumentHandle, DocumentState, DocumentStatus};
+use super::QUOTA_EXCEEDED_STATUS;
+
+// Characters billed at least for a document, like DeepL does.
+const MINIMUM_BILLED_CHARACTERS: u64 = 50000;
+
+struct Document {
+    key: String,
+    // Status requests answered so far, each one moves the document a step forward
+    polls: u32,
+    translated: Vec<u8>,
+    billed_characters: u64,
+}
+
+impl Document {
+    fn state(&self) -> DocumentState {
+        match self.polls {
+            0 | 1 => DocumentState::Queued,
+            2 => DocumentState::Translating,
+            _ => DocumentState::Done,
+        }
+    }
+}
+
+// The documents uploaded and not downloaded yet, by id.
+#[derive(Default)]
+pub(super) struct Documents(Mutex<HashMap<String, Document>>);
+
+#[derive(Deserialize)]
+struct DocumentKey {
+    document_key: String,
+}
+
+// The translation of a document: text files are translated like the texts of `/translate`,
+// HTML ones with their markup kept, other files come back as they are.
+fn translate_document(
+    content: Vec<u8>,
+    filename: &str,
+    target_lang: &str,
+    translation: MockTranslation,
+) -> (Vec<u8>, u64) {
+    let filename = filename.to_lowercase();
+    let html = filename.ends_with(".html") || filename.ends_with(".htm");
+    match String::from_utf8(content) {
+        Ok(text) if html || filename.ends_with(".txt") => {
+            let characters = text.chars().count() as u64;
+            let translated = translation.translate(&text, target_lang, html);
+            (translated.into_bytes(), characters)
+        }
+        Ok(text) => (text.into_bytes(), 0),
+        Err(e) => (e.into_bytes(), 0),
+    }
+}
+
+async fn r_upload(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    let invalid = || message_response(StatusCode::BAD_REQUEST, "Invalid multipart body");
+    let mut fields: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut filename = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(_) => return invalid(),
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            filename = field.file_name().map(str::to_string);
+        }
+        let Ok(data) = field.bytes().await else {
+            return invalid();
+        };
+        fields.insert(name, data.to_vec());
+    }
+
+    let text_field = |name: &str| {
+        fields
+            .get(name)
+            .map(|value| String::from_utf8_lossy(value).to_string())
+    };
+    let (Some(target_lang), Some(filename)) = (
+        text_field("target_lang"),
+        text_field("filename").or(filename),
+    ) else {
+        return message_response(
+            StatusCode::BAD_REQUEST,
+            "Parameters file, filename and target_lang are required",
+        );
+    };
+    let Some(content) = fields.remove("file") else {
+        return message_response(StatusCode::BAD_REQUEST, "Parameter file is required");
+    };
+
+    let behavior = &state.behavior;
+    let (translated, characters) =
+        translate_document(content, &filename, &target_lang, behavior.translation);
+    let billed_characters = characters.max(MINIMUM_BILLED_CHARACTERS);
+    let billed = state.usage.with(&headers, behavior, |used| {
+        let billed = *used + billed_characters <= behavior.character_limit;
+        if billed {
+            *used += billed_characters;
+        }
+        billed
+    });
+    if !billed {
+        return message_response(
+            StatusCode::from_u16(QUOTA_EXCEEDED_STATUS).unwrap(),
+            "Quota exceeded",
+        );
+    }
+
+    let handle = DocumentHandle {
+        document_id: mock_id(),
+        document_key: mock_id(),
+    };
+    state
+        .documents
+        .0
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(
+            handle.document_id.clone(),
+            Document {
+                key: handle.document_key.clone(),
+                polls: 0,
+                translated,
+                billed_characters,
+            },
+        );
+    Json(handle).into_response()
+}
+
+// Runs `f` on the document `id`, unless it is unknown or `key` is not its key.
+fn with_document(
+    documents: &Documents,
+    id: &str,
+    key: &str,
+    f: impl FnOnce(&mut HashMap<String, Document>) -> Response,
+) -> Response {
+    let mut documents = documents.0.lock().unwrap_or_else(PoisonError::into_inner);
+    match documents.get(id) {
+        None => message_response(StatusCode::NOT_FOUND, "Document not found"),
+        Some(document) if document.key != key => {
+            message_response(StatusCode::FORBIDDEN, "Invalid document key")
+        }
+        Some(_) => f(&mut documents),
+    }
+}
+
+async fn r_status(
+    State(state): State<Arc<MockState>>,
+    Path(id): Path<String>,
+    JsonOrForm(key): JsonOrForm<DocumentKey>,
+) -> Response {
+    with_document(&state.documents, &id, &key.document_key, |documents| {
+        let document = documents.get_mut(id.as_str()).expect("checked above");
+        document.polls += 1;
+        let status = document.state();
+        Json(DocumentStatus {
+            document_id: id.clone(),
+            status,
+            seconds_remaining: (status == DocumentState::Translating).then_some(1),
+            billed_characters: (status == DocumentState::Done)
+                .then_some(document.billed_characters),
+            error_message: None,
+        })
+        .into_response()
+    })
+}
+
+async fn r_result(
+    State(state): State<Arc<MockState>>,
+    Path(id): Path<String>,
+    JsonOrForm(key): JsonOrForm<DocumentKey>,
+) -> Response {
+    with_document(&state.documents, &id, &key.document_key, |documents| {
+        if documents[id.as_str()].state() != DocumentState::Done {
+            return message_response(StatusCode::SERVICE_UNAVAILABLE, "Document not ready");
+        }
+        // Like DeepL, a translation is downloaded once
+        let document = documents.remove(id.as_str()).expect("checked above");
+        (
+            [(CONTENT_TYPE, "application/octet-stream")],
+            document.translated,
+        )
+            .into_response()
+    })
+}
+
+pub(super) fn routes() -> Router<Arc<MockState>> {
+    Router::new()
+        .route("/v2/document", post(r_upload))
+        .route("/v2/document/{document_id}", post(r_status))
+        .route("/v2/document/{document_id}/result", post(r_result))
+}
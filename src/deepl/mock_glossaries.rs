@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use axum::extract::{Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{SecondsFormat, Utc};
+use serde::Deserialize;
+
+use super::mock::{message_response, mock_id, JsonOrForm, MockState};
+use super::models::{GlossariesResponse, GlossaryInfo, TranslationRequest};
+
+// A glossary to create, sent as a form or as JSON. `entries` are TSV or CSV, by
+// `entries_format`.
+#[derive(Deserialize)]
+struct NewGlossary {
+    name: String,
+    source_lang: String,
+    target_lang: String,
+    entries: String,
+    entries_format: String,
+}
+
+#[derive(Clone)]
+pub(super) struct Glossary {
+    info: GlossaryInfo,
+    entries: Vec<(String, String)>,
+}
+
+// The glossaries created, by id.
+#[derive(Default)]
+pub(super) struct Glossaries(Mutex<BTreeMap<String, Glossary>>);
+
+impl Glossaries {
+    fn get(&self, id: &str) -> Option<Glossary> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(id)
+            .cloned()
+    }
+
+    // The text of `request` with the entries of its glossary replaced, as written, or the
+    // error DeepL answers with.
+    pub(super) fn apply(
+        &self,
+        request: &TranslationRequest,
+    ) -> Result<String, (StatusCode, &'static str)> {
+        let text = request.text[0].clone();
+        let Some(id) = &request.glossary_id else {
+            return Ok(text);
+        };
+        let Some(source_lang) = &request.source_lang else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Use of a glossary requires the source_lang parameter to be specified",
+            ));
+        };
+        let glossary = self
+            .get(id)
+            .ok_or((StatusCode::NOT_FOUND, "Glossary not found"))?;
+        let language = |lang: &str| lang.split('-').next().unwrap_or_default().to_lowercase();
+        if language(source_lang) != glossary.info.source_lang
+            || language(&request.target_lang) != glossary.info.target_lang
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Language pair of the glossary does not match",
+            ));
+        }
+        Ok(glossary
+            .entries
+            .iter()
+            .fold(text, |text, (source, target)| text.replace(source, target)))
+    }
+}
+
+// The entries of a new glossary, each source term once.
+fn parse_entries(entries: &str, format: &str) -> Result<Vec<(String, String)>, String> {
+    let mut parsed: Vec<(String, String)> = Vec::new();
+    let records: Vec<Vec<String>> = match format {
+        "tsv" => entries
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split('\t').map(str::to_string).collect())
+            .collect(),
+        "csv" => csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(entries.as_bytes())
+            .records()
+            .map(|record| record.map(|record| record.iter().map(str::to_string).collect()))
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?,
+        _ => return Err(format!("Unsupported entries format `{}`", format)),
+    };
+    for record in records {
+        let [source, target, ..] = record.as_slice() else {
+            return Err(format!("Invalid entry `{}`", record.join(" ")));
+        };
+        let (source, target) = (source.trim(), target.trim());
+        if source.is_empty() || target.is_empty() {
+            return Err(format!("Invalid entry `{}`", record.join(" ")));
+        }
+        if parsed.iter().any(|(parsed, _)| parsed == source) {
+            return Err(format!("Duplicate source term `{}`", source));
+        }
+        parsed.push((source.to_string(), target.to_string()));
+    }
+    if parsed.is_empty() {
+        return Err("No entries".to_string());
+    }
+    Ok(parsed)
+}
+
+async fn r_create(
+    State(state): State<Arc<MockState>>,
+    JsonOrForm(new): JsonOrForm<NewGlossary>,
+) -> Response {
+    let entries = match parse_entries(&new.entries, &new.entries_format) {
+        Ok(entries) => entries,
+        Err(message) => return message_response(StatusCode::BAD_REQUEST, &message),
+    };
+    let info = GlossaryInfo {
+        glossary_id: mock_id(),
+        name: new.name,
+        ready: true,
+        source_lang: new.source_lang.to_lowercase(),
+        target_lang: new.target_lang.to_lowercase(),
+        creation_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        entry_count: entries.len(),
+    };
+    state
+        .glossaries
+        .0
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(
+            info.glossary_id.clone(),
+            Glossary {
+                info: info.clone(),
+                entries,
+            },
+        );
+    (StatusCode::CREATED, Json(info)).into_response()
+}
+
+async fn r_list(State(state): State<Arc<MockState>>) -> Json<GlossariesResponse> {
+    let glossaries = state
+        .glossaries
+        .0
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    Json(GlossariesResponse {
+        glossaries: glossaries
+            .values()
+            .map(|glossary| glossary.info.clone())
+            .collect(),
+    })
+}
+
+async fn r_get(State(state): State<Arc<MockState>>, Path(id): Path<String>) -> Response {
+    match state.glossaries.get(&id) {
+        Some(glossary) => Json(glossary.info).into_response(),
+        None => message_response(StatusCode::NOT_FOUND, "Glossary not found"),
+    }
+}
+
+async fn r_entries(State(state): State<Arc<MockState>>, Path(id): Path<String>) -> Response {
+    match state.glossaries.get(&id) {
+        Some(glossary) => (
+            [(CONTENT_TYPE, "text/tab-separated-values")],
+            glossary
+                .entries
+                .iter()
+                .map(|(source, target)| format!("{}\t{}\n", source, target))
+                .collect::<String>(),
+        )
+            .into_response(),
+        None => message_response(StatusCode::NOT_FOUND, "Glossary not found"),
+    }
+}
+
+async fn r_delete(State(state): State<Arc<MockState>>, Path(id): Path<String>) -> Response {
+    let removed = state
+        .glossaries
+        .0
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(id.as_str());
+    match removed {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => message_response(StatusCode::NOT_FOUND, "Glossary not found"),
+    }
+}
+
+pub(super) fn routes() -> Router<Arc<MockState>> {
+    Router::new()
+        .route("/v2/glossaries", post(r_create).get(r_list))
+        .route("/v2/glossaries/{glossary_id}", get(r_get).delete(r_delete))
+        .route("/v2/glossaries/{glossary_id}/entries", get(r_entries))
+}
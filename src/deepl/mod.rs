@@ -1,104 +1,145 @@
+#[cfg(feature = "mock-server")]
+mod cassette;
+#[cfg(feature = "mock-server")]
+mod mock;
+#[cfg(feature = "mock-server")]
+mod mock_documents;
+#[cfg(feature = "mock-server")]
+mod mock_glossaries;
 pub mod models;
 
-use reqwest::Client;
-use std::error::Error;
-use std::fs;
-use std::thread;
+#[cfg(feature = "mock-server")]
+pub use cassette::Cassette;
+#[cfg(feature = "mock-server")]
+pub use mock::{
+    start_deepl_server, MockBehavior, MockServer, MockServerOptions, MockTranslation,
+    ReceivedRequest,
+};
 
-use std::collections::HashMap;
+use reqwest::{Client, Response};
+use std::thread;
+use std::time::Duration;
 
 use models::{
-    DeepLConfiguration, LanguagesResponse, Translation, TranslationRequest, TranslationResponse,
-    UsageResponse, DEEPL_LANGUAGES_PATH, DEEPL_MOCK_API_URL, DEEPL_TRANSLATE_PATH,
-    DEEPL_USAGE_PATH,
+    AuthKey, DeepLConfiguration, LanguageType, LanguagesResponse, TranslationRequest,
+    TranslationResponse, UsageResponse, DEEPL_LANGUAGES_PATH, DEEPL_MOCK_API_URL,
+    DEEPL_TRANSLATE_PATH, DEEPL_USAGE_PATH,
 };
 
-use tokio::sync::oneshot;
-use tokio::time::sleep;
-use tokio::time::Duration;
-use tokio::time::Instant;
+use web_time::Instant;
 
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use tracing::{debug, trace};
 
-macro_rules! conditional_named_log {
-    ($enabled:expr, $name:expr, $($arg:tt)*) => {
-        if $enabled {
-            eprintln!("[{}] {}:{} - {}", stringify!($name), file!(), line!(), format!($($arg)*));
-        }
-    };
-}
+use crate::error::EpubTranslatorError;
+use crate::telemetry;
 
-macro_rules! api_log {
-    ($enabled:expr, $($arg:tt)*) => {
-        conditional_named_log!($enabled, "API CALL", $($arg)*);
+// Raw CSV lines consumed by `treat-trace.sh`, logged at the trace level.
+macro_rules! trace_log {
+    ($($arg:tt)*) => {
+        trace!("[TRACE]{}", format!($($arg)*))
     };
 }
 
-macro_rules! mock_log {
-    ($($arg:tt)*) => {
-        conditional_named_log!(true, "MOCK SERVER", $($arg)*);
-    };
+/// Status of the responses of DeepL when the character quota of the key is exhausted.
+pub const QUOTA_EXCEEDED_STATUS: u16 = 456;
+
+// Turns an error status into a `Provider` error, or `QuotaExceeded`, other responses are
+// returned as they are.
+async fn check_status(response: Response) -> Result<Response, EpubTranslatorError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response.text().await.unwrap_or_default().trim().to_string();
+    Err(match status.as_u16() {
+        QUOTA_EXCEEDED_STATUS => EpubTranslatorError::QuotaExceeded { message },
+        status => EpubTranslatorError::Provider { status, message },
+    })
 }
 
 // translate.sh
+// Translates the first text of `body`.
 pub async fn translate(
     config: &DeepLConfiguration,
-    text: &str,
-    target_lang: &str,
-    verbose: bool,
+    body: &TranslationRequest,
     client: &Client,
     id: usize,
     available_permits: usize,
-) -> Result<String, Box<dyn Error>> {
-    api_log!(
-        verbose,
+) -> Result<String, EpubTranslatorError> {
+    let text = &body.text[0];
+    let target_lang = &body.target_lang;
+    debug!(
         "Request id: {} - Translation of text: |{}| to {}",
-        id,
-        text,
-        target_lang
+        id, text, target_lang
     );
     let thread = thread::current().id();
     let len = text.len();
 
-    let body = TranslationRequest {
-        text: vec![text.to_string()],
-        target_lang: target_lang.to_string(),
-    };
-
     let request = client
         .post(format!("{}{}", config.api_url, DEEPL_TRANSLATE_PATH))
-        .json(&body)
-        .header(
-            "Authorization",
-            format!("DeepL-Auth-Key {}", config.auth_key),
-        );
+        .json(body)
+        .header("Authorization", config.auth_key.authorization()?);
 
     let start = Instant::now();
     let response_ = match request.send().await {
         Ok(resp) => {
-            let request_duration = start.elapsed().as_nanos();
+            let elapsed = start.elapsed();
+            let request_duration = elapsed.as_nanos();
+            let resp = match check_status(resp).await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    let kind = match e {
+                        EpubTranslatorError::QuotaExceeded { .. } => "quota_exceeded",
+                        _ => "status",
+                    };
+                    telemetry::record_request(&config.auth_key, elapsed, Some(kind));
+                    let error_code = 3; // Error status
+                    trace_log!(
+                        "{},{},{},{:?},{},{},{:?}",
+                        id,
+                        len,
+                        error_code,
+                        start,
+                        request_duration,
+                        available_permits,
+                        thread
+                    );
+                    return Err(e);
+                }
+            };
             match resp.json::<TranslationResponse>().await {
                 Ok(data) => {
+                    telemetry::record_request(&config.auth_key, elapsed, None);
                     let error_code = 0;
                     let translated_text = data.translations[0].text.clone();
-                    eprintln!(
-                        "[TRACE]{},{},{},{:?},{},{},{:?}",
-                        id, len, error_code, start, request_duration, available_permits, thread
+                    trace_log!(
+                        "{},{},{},{:?},{},{},{:?}",
+                        id,
+                        len,
+                        error_code,
+                        start,
+                        request_duration,
+                        available_permits,
+                        thread
                     );
-                    api_log!(
-                        verbose,
+                    debug!(
                         "Translated to {}: |{}| -> |{}|",
-                        target_lang,
-                        text,
-                        &translated_text
+                        target_lang, text, &translated_text
                     );
                     Ok(translated_text)
                 }
                 Err(e) => {
+                    telemetry::record_request(&config.auth_key, elapsed, Some("invalid_response"));
                     let error_code = 2; // Parsing failed
-                    eprintln!(
-                        "[TRACE]{},{},{},{:?},{},{},{:?}",
-                        id, len, error_code, start, request_duration, available_permits, thread
+                    trace_log!(
+                        "{},{},{},{:?},{},{},{:?}",
+                        id,
+                        len,
+                        error_code,
+                        start,
+                        request_duration,
+                        available_permits,
+                        thread
                     );
 
                     Err(e)
@@ -106,11 +147,19 @@ pub async fn translate(
             }
         }
         Err(e) => {
-            let request_duration = start.elapsed().as_nanos();
+            let elapsed = start.elapsed();
+            telemetry::record_request(&config.auth_key, elapsed, Some("network"));
+            let request_duration = elapsed.as_nanos();
             let error_code = 1; // Call failed
-            eprintln!(
-                "[TRACE]{},{},{},{:?},{},{},{:?}",
-                id, len, error_code, start, request_duration, available_permits, thread
+            trace_log!(
+                "{},{},{},{:?},{},{},{:?}",
+                id,
+                len,
+                error_code,
+                start,
+                request_duration,
+                available_permits,
+                thread
             );
             Err(e)
         }
@@ -119,22 +168,54 @@ pub async fn translate(
     Ok(response_?)
 }
 
+/// Settings of the HTTP client of the requests to the API. One client is built per run and
+/// shared by all its requests, so that connections are reused.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientOptions {
+    /// Time a request may take, from connection to response, none by default.
+    pub timeout: Option<Duration>,
+    /// URL of the proxy the requests go through, instead of the one of the `HTTPS_PROXY` or
+    /// `ALL_PROXY` environment variables.
+    pub proxy: Option<String>,
+    /// Idle connections kept open to the API, unlimited by default.
+    pub max_idle_connections: Option<usize>,
+}
+
+impl ClientOptions {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn build(&self) -> Result<Client, EpubTranslatorError> {
+        let mut builder = Client::builder();
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        if let Some(max_idle_connections) = self.max_idle_connections {
+            builder = builder.pool_max_idle_per_host(max_idle_connections);
+        }
+        Ok(builder.build()?)
+    }
+
+    // In the browser, `fetch` handles the connections and the proxies
+    #[cfg(target_arch = "wasm32")]
+    pub fn build(&self) -> Result<Client, EpubTranslatorError> {
+        Ok(Client::new())
+    }
+}
+
 // usage.sh
 pub async fn get_usage(
     config: &DeepLConfiguration,
-    verbose: bool,
-) -> Result<UsageResponse, Box<dyn Error>> {
-    api_log!(verbose, "Getting usage from {}", config.api_url);
-    let client = Client::new();
+    client: &Client,
+) -> Result<UsageResponse, EpubTranslatorError> {
+    debug!("Getting usage from {}", config.api_url);
 
     let request = client
         .get(format!("{}{}", config.api_url, DEEPL_USAGE_PATH))
-        .header(
-            "Authorization",
-            format!("DeepL-Auth-Key {}", config.auth_key),
-        );
+        .header("Authorization", config.auth_key.authorization()?);
 
-    let response: UsageResponse = request.send().await?.json().await?;
+    let response: UsageResponse = check_status(request.send().await?).await?.json().await?;
 
     Ok(response)
 }
@@ -142,19 +223,21 @@ pub async fn get_usage(
 // languages.sh
 pub async fn get_languages(
     config: &DeepLConfiguration,
-    verbose: bool,
-) -> Result<LanguagesResponse, Box<dyn Error>> {
-    api_log!(verbose, "Getting languages from {}", config.api_url);
-    let client = Client::new();
+    kind: LanguageType,
+    client: &Client,
+) -> Result<LanguagesResponse, EpubTranslatorError> {
+    debug!(
+        "Getting {} languages from {}",
+        kind.as_str(),
+        config.api_url
+    );
 
     let request = client
         .get(format!("{}{}", config.api_url, DEEPL_LANGUAGES_PATH))
-        .header(
-            "Authorization",
-            format!("DeepL-Auth-Key {}", config.auth_key),
-        );
+        .query(&[("type", kind.as_str())])
+        .header("Authorization", config.auth_key.authorization()?);
 
-    let response: LanguagesResponse = request.send().await?.json().await?;
+    let response: LanguagesResponse = check_status(request.send().await?).await?.json().await?;
 
     Ok(response)
 }
@@ -162,104 +245,41 @@ pub async fn get_languages(
 pub fn get_test_config() -> DeepLConfiguration {
     DeepLConfiguration {
         api_url: format!("{}/v2", DEEPL_MOCK_API_URL),
-        auth_key: "mock_auth_key".to_string(),
-    }
-}
-
-#[post("/v2/translate")]
-async fn r_translate(req: web::Json<TranslationRequest>) -> impl Responder {
-    let text_to_translate = &req.text[0];
-    mock_log!("Received translate request: |{}|", text_to_translate);
-
-    sleep(Duration::from_millis(400)).await;
-
-    let translations = vec![Translation {
-        detected_source_language: "EN".to_string(),
-        text: format!("--|{}|-- Translated to {}", req.text[0], req.target_lang),
-    }];
-
-    HttpResponse::Ok().json(TranslationResponse { translations })
-}
-
-#[get("/v2/usage")]
-async fn r_usage() -> impl Responder {
-    mock_log!("[MOCK SERVER] Received usage request");
-    let usage_response = UsageResponse {
-        character_count: 1000,
-        character_limit: 500000,
-    };
-
-    HttpResponse::Ok().json(usage_response)
-}
-
-#[get("/v2/languages")]
-async fn r_languages(query: web::Query<HashMap<String, String>>) -> impl Responder {
-    mock_log!("Received languages request");
-    let languages_json =
-        fs::read_to_string("src/deepl/languages.json").expect("Failed to read languages.json");
-    let languages_response: LanguagesResponse =
-        serde_json::from_str(&languages_json).expect("Failed to parse languages.json");
-
-    let type_param = query.get("type");
-
-    if type_param.is_none() || type_param.unwrap() != "target" {
-        HttpResponse::Ok().json(&languages_response)
-    } else {
-        HttpResponse::Ok().json(&LanguagesResponse(vec![]))
+        auth_key: AuthKey::new("mock_auth_key"),
     }
 }
 
-pub async fn start_deepl_server() -> Result<oneshot::Sender<()>, Box<dyn Error>> {
-    let (tx, rx) = oneshot::channel::<()>();
-
-    let server = HttpServer::new(|| {
-        App::new()
-            .service(r_translate)
-            .service(r_usage)
-            .service(r_languages)
-    })
-    .bind("127.0.0.1:3030")?;
-
-    let server = server.run();
-    let server_handle = server.handle();
-
-    // Spawn a task to run the server
-    tokio::spawn(async move {
-        server.await.expect("Server failed to run");
-    });
-
-    tokio::spawn(async move {
-        rx.await.ok();
-        mock_log!("Shutdown signal received, stopping server...");
-        server_handle.stop(true).await;
-    });
-
-    mock_log!("Server started and listening on http://127.0.0.1:3030");
-
-    Ok(tx)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::error::Error;
 
+    #[cfg(feature = "mock-server")]
     #[tokio::test]
     async fn test_translate_usage_and_languages() -> Result<(), Box<dyn Error>> {
-        // Wait for the mock server to be ready, run by the tests in lib.rs
-        tokio::time::sleep(Duration::from_millis(100)).await;
+        use std::fs;
 
-        let config = get_test_config();
+        let server = start_deepl_server(&MockServerOptions::ephemeral()).await?;
+        let config = server.config();
 
         let client = Client::new();
 
-        let translate_result = translate(&config, "Hello", "ES", true, &client, 0, 0).await?;
-        let usage_result = get_usage(&config, true).await?;
-        let languages_result = get_languages(&config, true).await?;
+        let translate_result = translate(
+            &config,
+            &TranslationRequest::new("Hello", "ES"),
+            &client,
+            0,
+            0,
+        )
+        .await?;
+        let usage_result = get_usage(&config, &client).await?;
+        let languages_result = get_languages(&config, LanguageType::Target, &client).await?;
+        let source_languages = get_languages(&config, LanguageType::Source, &client).await?;
 
         // Translate check
         assert_eq!(translate_result, "--|Hello|-- Translated to ES");
-        // // Usage check
-        assert_eq!(usage_result.character_count, 1000);
+        // // Usage check, the characters translated above are counted
+        assert_eq!(usage_result.character_count, 1000 + "Hello".len() as u64);
         assert_eq!(usage_result.character_limit, 500000);
 
         // Languages check
@@ -268,7 +288,278 @@ mod tests {
         let expected_languages_response: LanguagesResponse =
             serde_json::from_str(&expected_languages).expect("Failed to parse languages.json");
         assert_eq!(languages_result, expected_languages_response);
+        assert!(source_languages.0.iter().any(|l| l.language == "EN"));
+        assert!(!source_languages.0.iter().any(|l| l.language == "EN-GB"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn test_received_requests() -> Result<(), Box<dyn Error>> {
+        let server = start_deepl_server(&MockServerOptions {
+            behavior: "latency=0".parse()?,
+            ..MockServerOptions::ephemeral()
+        })
+        .await?;
+        let client = Client::new();
+        let request = TranslationRequest::new_xml("<g1>Hello</g1>", "ES")
+            .with_context(Some("Hi"))
+            .with_formality(Some(models::Formality::PreferLess));
+        translate(&server.config(), &request, &client, 0, 0).await?;
+
+        let received = server.requests();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].request, request);
+        assert_eq!(
+            received[0].headers.get("authorization").map(String::as_str),
+            Some("DeepL-Auth-Key mock_auth_key")
+        );
+        assert_eq!(
+            received[0].headers.get("content-type").map(String::as_str),
+            Some("application/json")
+        );
+
+        // The same list is served to other processes
+        let url = format!("http://{}/mock/requests", server.address());
+        let listed: Vec<ReceivedRequest> = client.get(&url).send().await?.json().await?;
+        assert_eq!(listed, received);
+        client.delete(&url).send().await?.error_for_status()?;
+        assert!(server.requests().is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn test_mock_glossaries_and_documents() -> Result<(), Box<dyn Error>> {
+        use models::{
+            DocumentHandle, DocumentState, DocumentStatus, GlossariesResponse, GlossaryInfo,
+            DEEPL_DOCUMENT_PATH, DEEPL_GLOSSARIES_PATH,
+        };
+        use reqwest::multipart::{Form, Part};
+        use reqwest::StatusCode;
+
+        let server = start_deepl_server(&MockServerOptions {
+            behavior: "latency=0,used=0".parse()?,
+            ..MockServerOptions::ephemeral()
+        })
+        .await?;
+        let config = server.config();
+        let client = Client::new();
+        let url = |path: &str| format!("{}{}", config.api_url, path);
+        let authorization = config.auth_key.authorization()?;
+
+        // Glossaries
+        let glossary: GlossaryInfo = client
+            .post(url(DEEPL_GLOSSARIES_PATH))
+            .header("Authorization", authorization.clone())
+            .json(&serde_json::json!({
+                "name": "Shire",
+                "source_lang": "EN",
+                "target_lang": "ES",
+                "entries": "hobbit\tmediano\nShire\tComarca",
+                "entries_format": "tsv",
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        assert_eq!(glossary.entry_count, 2);
+        assert_eq!(glossary.target_lang, "es");
+        let listed: GlossariesResponse = client
+            .get(url(DEEPL_GLOSSARIES_PATH))
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(listed.glossaries, vec![glossary.clone()]);
+
+        let mut request = TranslationRequest::new("A hobbit of the Shire", "ES");
+        request.glossary_id = Some(glossary.glossary_id.clone());
+        assert!(translate(&config, &request, &client, 0, 0).await.is_err());
+        request.source_lang = Some("EN".to_string());
+        assert_eq!(
+            translate(&config, &request, &client, 0, 0).await?,
+            "--|A mediano of the Comarca|-- Translated to ES"
+        );
+
+        let glossary_url = url(&format!(
+            "{}/{}",
+            DEEPL_GLOSSARIES_PATH, glossary.glossary_id
+        ));
+        let entries = client
+            .get(format!("{}/entries", glossary_url))
+            .send()
+            .await?
+            .text()
+            .await?;
+        assert_eq!(entries, "hobbit\tmediano\nShire\tComarca\n");
+        let deleted = client.delete(&glossary_url).send().await?;
+        assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+        let missing = client.get(&glossary_url).send().await?;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        // Documents
+        let form = Form::new()
+            .text("target_lang", "ES")
+            .part("file", Part::text("Hello").file_name("hello.txt"));
+        let document: DocumentHandle = client
+            .post(url(DEEPL_DOCUMENT_PATH))
+            .header("Authorization", authorization)
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let document_url = url(&format!("{}/{}", DEEPL_DOCUMENT_PATH, document.document_id));
+        let key = serde_json::json!({ "document_key": document.document_key });
+        let result_url = format!("{}/result", document_url);
+
+        let early = client.post(&result_url).json(&key).send().await?;
+        assert_eq!(early.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let wrong_key = serde_json::json!({ "document_key": "wrong" });
+        let forbidden = client.post(&document_url).json(&wrong_key).send().await?;
+        assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+        let mut states = Vec::new();
+        for _ in 0..3 {
+            let status: DocumentStatus = client
+                .post(&document_url)
+                .json(&key)
+                .send()
+                .await?
+                .json()
+                .await?;
+            states.push(status.status);
+            if status.status == DocumentState::Done {
+                assert_eq!(status.billed_characters, Some(50000));
+            }
+        }
+        assert_eq!(
+            states,
+            [
+                DocumentState::Queued,
+                DocumentState::Translating,
+                DocumentState::Done
+            ]
+        );
+        let translated = client.post(&result_url).json(&key).send().await?;
+        assert_eq!(translated.text().await?, "--|Hello|-- Translated to ES");
+        let downloaded = client.post(&result_url).json(&key).send().await?;
+        assert_eq!(downloaded.status(), StatusCode::NOT_FOUND);
+
+        let usage = get_usage(&config, &client).await?;
+        assert_eq!(
+            usage.character_count,
+            50000 + "A hobbit of the Shire".len() as u64
+        );
+        server.stop();
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn test_mock_languages() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("languages.json");
+        std::fs::write(
+            &path,
+            r#"[{"language": "EN-GB", "name": "English (British)", "supports_formality": false}]"#,
+        )?;
+        let server = start_deepl_server(&MockServerOptions {
+            languages: Some(path.clone()),
+            ..MockServerOptions::ephemeral()
+        })
+        .await?;
+        let target = get_languages(&server.config(), LanguageType::Target, &Client::new()).await?;
+        let source = get_languages(&server.config(), LanguageType::Source, &Client::new()).await?;
+        assert_eq!(target.0.len(), 1);
+        assert_eq!(source.0[0].language, "EN");
+        server.stop();
+
+        std::fs::write(&path, "{}")?;
+        let error = start_deepl_server(&MockServerOptions {
+            languages: Some(path),
+            ..MockServerOptions::ephemeral()
+        })
+        .await
+        .err()
+        .unwrap();
+        assert!(matches!(error, EpubTranslatorError::Config(_)));
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[test]
+    fn test_mock_behavior() {
+        use std::time::Duration;
+
+        let behavior: MockBehavior = "latency=100-900, 429=0.1,malformed=0.02".parse().unwrap();
+        assert_eq!(
+            behavior.latency,
+            Duration::from_millis(100)..=Duration::from_millis(900)
+        );
+        assert_eq!(behavior.too_many_requests, 0.1);
+        assert_eq!(behavior.malformed, 0.02);
+        assert_eq!(behavior.server_error, 0.0);
+        assert_eq!("".parse(), Ok(MockBehavior::default()));
+        let behavior: MockBehavior = "quota=100,used=90".parse().unwrap();
+        assert_eq!(
+            (behavior.character_limit, behavior.character_count),
+            (100, 90)
+        );
+
+        assert!("429=2".parse::<MockBehavior>().is_err());
+        assert!("500=0.6,456=0.6".parse::<MockBehavior>().is_err());
+        assert!("latency=900-100".parse::<MockBehavior>().is_err());
+        assert!("404=0.1"
+            .parse::<MockBehavior>()
+            .unwrap_err()
+            .contains("unknown"));
+        assert!("translation=upside-down".parse::<MockBehavior>().is_err());
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[test]
+    fn test_mock_translation() {
+        let behavior: MockBehavior = "translation=piglatin".parse().unwrap();
+        assert_eq!(behavior.translation, MockTranslation::PigLatin);
+
+        let text = "<g1>Hello</g1> &amp; strong apples, 42";
+        assert_eq!(
+            MockTranslation::Reversed.translate(text, "ES", true),
+            "<g1>olleH</g1> &amp; gnorts selppa, 24"
+        );
+        assert_eq!(
+            MockTranslation::PigLatin.translate(text, "ES", true),
+            "<g1>Ellohay</g1> &amp; ongstray applesway, 42"
+        );
+        assert_eq!(
+            MockTranslation::Marked.translate(text, "ES", true),
+            format!("--|{}|-- Translated to ES", text)
+        );
+        // Without markup, tags are words like any other
+        assert_eq!(
+            MockTranslation::Reversed.translate("<em>Hi</em>", "ES", false),
+            "<me>iH</me>"
+        );
+        assert_eq!(
+            MockTranslation::PigLatin.translate("Rhythm", "ES", false),
+            "Rhythmay"
+        );
+    }
+
+    #[test]
+    fn test_auth_key_redacted() -> Result<(), Box<dyn Error>> {
+        let config = DeepLConfiguration::new("0123-secret:fx", false);
+        assert!(!format!("{:?}", config).contains("secret"));
+        assert_eq!(config.auth_key.to_string(), "<redacted>");
 
+        let header = config.auth_key.authorization()?;
+        assert!(header.is_sensitive());
+        assert!(!format!("{:?}", header).contains("secret"));
+        assert_eq!(header.to_str()?, "DeepL-Auth-Key 0123-secret:fx");
         Ok(())
     }
 }
@@ -1,6 +1,12 @@
+use reqwest::header::{HeaderValue, InvalidHeaderValue};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+use tracing::info;
+
+use crate::error::EpubTranslatorError;
 
 pub const DEEPL_FREE_API_URL: &str = "https://api-free.deepl.com/v2";
 pub const DEEPL_PRO_API_URL: &str = "https://api.deepl.com/v2";
@@ -8,12 +14,103 @@ pub const DEEPL_MOCK_API_URL: &str = "http://127.0.0.1:3030";
 
 pub const DEEPL_TRANSLATE_PATH: &str = "/translate";
 pub const DEEPL_USAGE_PATH: &str = "/usage";
+pub const DEEPL_GLOSSARIES_PATH: &str = "/glossaries";
+pub const DEEPL_DOCUMENT_PATH: &str = "/document";
 pub const DEEPL_LANGUAGES_PATH: &str = "/languages";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TranslationRequest {
     pub text: Vec<String>,
     pub target_lang: String,
+    /// Detected by DeepL when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_lang: Option<String>,
+    /// Glossary applied to the translation, `source_lang` is required along with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glossary_id: Option<String>,
+    /// `xml` when the text contains markup that must be kept in place.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_handling: Option<String>,
+    /// Text that helps the translation but is not translated itself, e.g. the chapter heading.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    /// Keep the formatting of the source, DeepL corrects punctuation and case otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_formatting: Option<bool>,
+    /// Formal or informal register of the translation, see `Language::supports_formality`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formality: Option<Formality>,
+}
+
+/// Register of a translation, for the target languages that have one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Formality {
+    More,
+    Less,
+    /// `More` where the target language supports it, the default register elsewhere. DeepL
+    /// refuses `More` and `Less` for the other languages.
+    PreferMore,
+    PreferLess,
+}
+
+impl FromStr for Formality {
+    type Err = String;
+
+    fn from_str(formality: &str) -> Result<Self, Self::Err> {
+        match formality {
+            "more" => Ok(Self::More),
+            "less" => Ok(Self::Less),
+            "prefer_more" => Ok(Self::PreferMore),
+            "prefer_less" => Ok(Self::PreferLess),
+            _ => Err(format!(
+                "unknown formality `{}`, expected `more`, `less`, `prefer_more` or `prefer_less`",
+                formality
+            )),
+        }
+    }
+}
+
+impl TranslationRequest {
+    /// Request for a single plain text.
+    pub fn new(text: &str, target_lang: &str) -> Self {
+        Self {
+            text: vec![text.to_string()],
+            target_lang: target_lang.to_string(),
+            source_lang: None,
+            glossary_id: None,
+            tag_handling: None,
+            context: None,
+            preserve_formatting: None,
+            formality: None,
+        }
+    }
+
+    /// Request for a single XML fragment, tags are preserved by the API.
+    pub fn new_xml(text: &str, target_lang: &str) -> Self {
+        Self {
+            tag_handling: Some("xml".to_string()),
+            ..Self::new(text, target_lang)
+        }
+    }
+
+    pub fn with_context(self, context: Option<&str>) -> Self {
+        Self {
+            context: context.map(str::to_string),
+            ..self
+        }
+    }
+
+    pub fn with_preserved_formatting(self, preserve_formatting: bool) -> Self {
+        Self {
+            preserve_formatting: preserve_formatting.then_some(true),
+            ..self
+        }
+    }
+
+    pub fn with_formality(self, formality: Option<Formality>) -> Self {
+        Self { formality, ..self }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -33,37 +130,161 @@ pub struct UsageResponse {
     pub character_limit: u64,
 }
 
+/// A glossary stored by DeepL, as listed by `/glossaries`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlossaryInfo {
+    pub glossary_id: String,
+    pub name: String,
+    pub ready: bool,
+    pub source_lang: String,
+    pub target_lang: String,
+    /// ISO 8601, e.g. `2021-08-03T14:16:18.329Z`.
+    pub creation_time: String,
+    pub entry_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlossariesResponse {
+    pub glossaries: Vec<GlossaryInfo>,
+}
+
+/// A document uploaded to `/document`, both fields are needed to poll it and download it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentHandle {
+    pub document_id: String,
+    pub document_key: String,
+}
+
+/// Where the translation of a document stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentState {
+    Queued,
+    Translating,
+    /// The translation can be downloaded, once.
+    Done,
+    Error,
+}
+
+/// The status of a document, polled from `/document/{document_id}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentStatus {
+    pub document_id: String,
+    pub status: DocumentState,
+    /// An estimate while translating.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seconds_remaining: Option<u64>,
+    /// Once done.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub billed_characters: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+}
+
+/// A DeepL API key. It is never shown by `Debug` or `Display`, so that it cannot end up in logs
+/// or error messages, only sent in the `Authorization` header.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthKey(String);
+
+impl AuthKey {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self(key.into())
+    }
+
+    /// The key itself, to be kept out of anything printed.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// The last characters of the key, which tell keys apart without giving them away.
+    pub fn hint(&self) -> String {
+        let key = self.0.strip_suffix(":fx").unwrap_or(&self.0);
+        let start = key
+            .char_indices()
+            .rev()
+            .nth(3)
+            .map_or(0, |(index, _)| index);
+        key[start..].to_string()
+    }
+
+    /// The `Authorization` header of the requests, marked as sensitive so that the HTTP client
+    /// redacts it from its own debug output.
+    pub fn authorization(&self) -> Result<HeaderValue, InvalidHeaderValue> {
+        let mut value = HeaderValue::from_str(&format!("DeepL-Auth-Key {}", self.0))?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+impl fmt::Debug for AuthKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AuthKey(<redacted>)")
+    }
+}
+
+impl fmt::Display for AuthKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl From<String> for AuthKey {
+    fn from(key: String) -> Self {
+        Self(key)
+    }
+}
+
+impl From<&str> for AuthKey {
+    fn from(key: &str) -> Self {
+        Self::new(key)
+    }
+}
+
+impl FromStr for AuthKey {
+    type Err = Infallible;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        Ok(Self::new(key))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DeepLConfiguration {
-    pub auth_key: String,
+    pub auth_key: AuthKey,
     pub api_url: String,
 }
 
 impl DeepLConfiguration {
-    pub fn new(auth_key: String, is_pro: bool) -> Self {
+    pub fn new(auth_key: impl Into<AuthKey>, is_pro: bool) -> Self {
         let api_url = if is_pro {
             DEEPL_PRO_API_URL
         } else {
             DEEPL_FREE_API_URL
         };
         Self {
-            auth_key,
+            auth_key: auth_key.into(),
             api_url: api_url.to_string(),
         }
     }
 
-    pub async fn new_with_determine(auth_key: String) -> Result<Self, Box<dyn Error>> {
-        let is_pro = Self::determine_api_type(&auth_key).await?;
-        Ok(Self::new(auth_key.to_string(), is_pro))
+    pub async fn new_with_determine(
+        auth_key: impl Into<AuthKey>,
+        client: &Client,
+    ) -> Result<Self, EpubTranslatorError> {
+        let auth_key = auth_key.into();
+        let is_pro = Self::determine_api_type(&auth_key, client).await?;
+        Ok(Self::new(auth_key, is_pro))
     }
 
-    pub async fn determine_api_type(auth_key: &str) -> Result<bool, Box<dyn Error>> {
-        println!("Determining API type...");
-        let client = Client::new();
+    pub async fn determine_api_type(
+        auth_key: &AuthKey,
+        client: &Client,
+    ) -> Result<bool, EpubTranslatorError> {
+        info!("Determining API type...");
 
         let response = client
             .get(format!("{}{}", DEEPL_PRO_API_URL, DEEPL_USAGE_PATH))
-            .header("Authorization", format!("DeepL-Auth-Key {}", auth_key))
+            .header("Authorization", auth_key.authorization()?)
             .send()
             .await?;
 
@@ -71,6 +292,24 @@ impl DeepLConfiguration {
     }
 }
 
+/// The languages listed by `/languages`. DeepL lists the source ones unless asked for the target
+/// ones, which have regional variants like `EN-GB` instead of `EN`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LanguageType {
+    Source,
+    Target,
+}
+
+impl LanguageType {
+    /// The value of the `type` query parameter.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Source => "source",
+            Self::Target => "target",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Language {
     pub language: String,
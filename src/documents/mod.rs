@@ -0,0 +1,337 @@
+//! The documents of a run, parsed once and kept until they are serialized.
+//!
+//! The nodes of a document cannot leave the thread that parsed it. With the `native` feature,
+//! each document is parsed on one of a few threads kept for the run, where its units are
+//! extracted, its translations applied and it is serialized: only texts cross threads, the
+//! units as `UnattachedSegment` and the translations as markup. Without it, they are all
+//! handled on the calling thread.
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use markup5ever_rcdom::Node;
+
+use crate::error::EpubTranslatorError;
+use crate::options::TranslateOptions;
+use crate::segment::{get_segments, Segment};
+use crate::xhtml::{
+    decode_xhtml, get_document_node, rewrite_lang, serialize_document_to_string_with, EntityPolicy,
+    EntityReferences, ExtractOptions,
+};
+use crate::{copy_originals, source_references};
+
+// A parsed document, on the thread that parsed it.
+struct Document {
+    node: Rc<Node>,
+    references: EntityReferences,
+    // Its units as last extracted, the translations are applied to them
+    segments: Vec<Segment>,
+}
+
+/// How a file was parsed, see `Documents::parse`.
+pub(crate) enum Parsed {
+    /// Kept until it is serialized.
+    Document,
+    /// Without text, like a cover: left byte for byte as it is.
+    Untouched,
+    Failed(EpubTranslatorError),
+}
+
+#[cfg(feature = "native")]
+type Job = Box<dyn FnOnce(&mut Vec<Document>) + Send>;
+
+/// The parsed documents of a run, in the order of the files they were parsed from.
+pub(crate) struct Documents {
+    #[cfg(feature = "native")]
+    workers: Vec<Worker>,
+    #[cfg(not(feature = "native"))]
+    documents: std::cell::RefCell<Vec<Document>>,
+    // Documents held by each worker, the first ones go to the first worker
+    counts: Vec<usize>,
+}
+
+#[cfg(feature = "native")]
+struct Worker {
+    jobs: Option<std::sync::mpsc::Sender<Job>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Documents {
+    /// Decodes and parses the files read, in order. Files without text are not parsed and files
+    /// that cannot be read, decoded or parsed are left out, each one is told in the result.
+    pub(crate) fn parse(
+        contents: Vec<std::io::Result<Vec<u8>>>,
+        extract: &ExtractOptions,
+        entities: EntityPolicy,
+    ) -> (Self, Vec<Parsed>) {
+        let mut documents = Self::spawn(contents.len());
+        let extract = extract.clone();
+        let parsed = documents.run(
+            chunks(contents, documents.counts.len()),
+            move |documents, contents| {
+                contents
+                    .into_iter()
+                    .map(|bytes| match parse(bytes, &extract, entities) {
+                        Ok(Some(document)) => {
+                            documents.push(document);
+                            Parsed::Document
+                        }
+                        Ok(None) => Parsed::Untouched,
+                        Err(error) => Parsed::Failed(error),
+                    })
+                    .collect::<Vec<_>>()
+            },
+        );
+        documents.counts = parsed
+            .iter()
+            .map(|parsed| {
+                parsed
+                    .iter()
+                    .filter(|parsed| matches!(parsed, Parsed::Document))
+                    .count()
+            })
+            .collect();
+        (documents, parsed.into_iter().flatten().collect())
+    }
+
+    /// The units of each document, see `get_segments`. They replace the ones extracted before,
+    /// the translations are applied to these.
+    pub(crate) fn segments(&self, extract: &ExtractOptions) -> Vec<Vec<Segment>> {
+        let extract = extract.clone();
+        self.run(vec![(); self.counts.len()], move |documents, ()| {
+            documents
+                .iter_mut()
+                .map(|document| {
+                    document.segments = get_segments(&document.node, &extract);
+                    document
+                        .segments
+                        .iter()
+                        .map(Segment::unattached)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        })
+        .into_iter()
+        .flatten()
+        .map(|segments| segments.into_iter().map(Segment::from).collect())
+        .collect()
+    }
+
+    /// Applies `markups`, the translations of the units of each document in the order of
+    /// `segments`, then serializes the documents in `lang`.
+    pub(crate) fn write(
+        &self,
+        markups: Vec<Vec<Option<String>>>,
+        lang: &str,
+        options: &TranslateOptions,
+    ) -> Vec<Result<String, EpubTranslatorError>> {
+        let lang = lang.to_string();
+        let options = Arc::new(options.clone());
+        let mut markups = markups.into_iter();
+        let chunks = self
+            .counts
+            .iter()
+            .map(|&count| markups.by_ref().take(count).collect::<Vec<_>>())
+            .collect();
+        self.run(chunks, move |documents, markups| {
+            documents
+                .iter()
+                .zip(markups)
+                .map(|(document, markups)| write(document, &markups, &lang, &options))
+                .collect::<Vec<_>>()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    // Runs `f` on each worker with its input, the results are in the order of the workers.
+    #[cfg(feature = "native")]
+    fn run<T, R, F>(&self, inputs: Vec<T>, f: F) -> Vec<R>
+    where
+        T: Send + 'static,
+        R: Send + 'static,
+        F: Fn(&mut Vec<Document>, T) -> R + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let results: Vec<_> = self
+            .workers
+            .iter()
+            .zip(inputs)
+            .map(|(worker, input)| {
+                let (sender, receiver) = std::sync::mpsc::channel();
+                let f = f.clone();
+                let job: Job = Box::new(move |documents| {
+                    let _ = sender.send(f(documents, input));
+                });
+                if let Some(jobs) = &worker.jobs {
+                    let _ = jobs.send(job);
+                }
+                receiver
+            })
+            .collect();
+        results
+            .into_iter()
+            .map(|receiver| receiver.recv().expect("a document thread panicked"))
+            .collect()
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn run<T, R, F>(&self, inputs: Vec<T>, f: F) -> Vec<R>
+    where
+        F: Fn(&mut Vec<Document>, T) -> R,
+    {
+        let mut documents = self.documents.borrow_mut();
+        inputs
+            .into_iter()
+            .map(|input| f(&mut documents, input))
+            .collect()
+    }
+
+    // As many workers as rayon has threads, at most one per file.
+    #[cfg(feature = "native")]
+    fn spawn(files: usize) -> Self {
+        let workers = (0..rayon::current_num_threads().min(files).max(1))
+            .map(|_| {
+                let (jobs, receiver) = std::sync::mpsc::channel::<Job>();
+                let thread = std::thread::spawn(move || {
+                    let mut documents = Vec::new();
+                    for job in receiver {
+                        job(&mut documents);
+                    }
+                });
+                Worker {
+                    jobs: Some(jobs),
+                    thread: Some(thread),
+                }
+            })
+            .collect::<Vec<_>>();
+        Self {
+            counts: vec![0; workers.len()],
+            workers,
+        }
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn spawn(_files: usize) -> Self {
+        Self {
+            documents: Default::default(),
+            counts: vec![0],
+        }
+    }
+}
+
+// The documents are dropped on their threads once these stop.
+#[cfg(feature = "native")]
+impl Drop for Documents {
+    fn drop(&mut self) {
+        for worker in &mut self.workers {
+            worker.jobs.take();
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+// Splits `items` in `count` runs of about the same length, in order.
+fn chunks<T>(items: Vec<T>, count: usize) -> Vec<Vec<T>> {
+    let size = items.len().div_ceil(count).max(1);
+    let mut items = items.into_iter();
+    (0..count)
+        .map(|_| items.by_ref().take(size).collect())
+        .collect()
+}
+
+fn parse(
+    bytes: std::io::Result<Vec<u8>>,
+    extract: &ExtractOptions,
+    entities: EntityPolicy,
+) -> Result<Option<Document>, EpubTranslatorError> {
+    let content = decode_xhtml(&bytes?)?;
+    if !extract.may_have_text(&content) {
+        return Ok(None);
+    }
+    Ok(Some(Document {
+        references: source_references(&content, entities)?,
+        node: get_document_node(&content)?,
+        segments: Vec::new(),
+    }))
+}
+
+fn write(
+    document: &Document,
+    markups: &[Option<String>],
+    lang: &str,
+    options: &TranslateOptions,
+) -> Result<String, EpubTranslatorError> {
+    if markups.len() != document.segments.len() {
+        return Err(EpubTranslatorError::InvalidTranslation(format!(
+            "{} translations for the {} units of the document",
+            markups.len(),
+            document.segments.len()
+        )));
+    }
+    let originals = copy_originals(&document.node, &document.segments, options);
+    for (segment, markup) in document.segments.iter().zip(markups) {
+        if let Some(markup) = markup {
+            segment.apply(markup)?;
+        }
+    }
+    rewrite_lang(&document.node, lang, options.serialize.rewrite_inner_lang);
+    if let Some(originals) = originals {
+        originals.insert();
+    }
+    serialize_document_to_string_with(&document.node, &document.references)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_documents() -> Result<(), Box<dyn std::error::Error>> {
+        let contents = vec![
+            Ok(b"<html><body><p>Hello <b>world</b></p></body></html>".to_vec()),
+            Ok(b"<html><body></body></html>".to_vec()),
+            Ok(b"<html><body><p>Bye</p><p>now</p></body></html>".to_vec()),
+        ];
+        let extract = ExtractOptions::default();
+        let (documents, parsed) = Documents::parse(contents, &extract, EntityPolicy::Preserve);
+        assert!(matches!(
+            parsed[..],
+            [Parsed::Document, Parsed::Untouched, Parsed::Document]
+        ));
+
+        let segments = documents.segments(&extract);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0][0].text, "Hello <g0>world</g0>");
+        assert_eq!(segments[1].len(), 2);
+        // Translations are checked before they are sent back to the documents
+        assert!(segments[0][0].apply("Hola mundo").is_err());
+        segments[0][0].apply("Hola <g0>mundo</g0>")?;
+        assert_eq!(segments[0][0].translation().as_deref(), Some("Hola mundo"));
+        assert_eq!(segments[0][0].location().as_deref(), Some("/html/body/p"));
+
+        let options = TranslateOptions::default();
+        let markups = vec![
+            vec![segments[0][0].translation_markup()],
+            vec![Some("Adiós".to_string()), None],
+        ];
+        let outputs = documents.write(markups, "es", &options);
+        let first = outputs[0].as_ref().unwrap();
+        assert!(first.contains("<p>Hola <b>mundo</b></p>"), "{}", first);
+        let second = outputs[1].as_ref().unwrap();
+        assert!(second.contains("<p>Adiós</p><p>now</p>"), "{}", second);
+
+        // Each document gets the translations of its units, no more and no less
+        let outputs = documents.write(vec![vec![None, None], vec![None, None]], "es", &options);
+        assert!(matches!(
+            outputs[0],
+            Err(EpubTranslatorError::InvalidTranslation(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use percent_encoding::percent_decode_str;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use super::package::attributes;
+use super::BookFiles;
+use crate::error::EpubTranslatorError;
+
+/// Location of the encryption file, relative to the root of an extracted EPUB.
+pub const ENCRYPTION_PATH: &str = "META-INF/encryption.xml";
+
+/// Font obfuscation algorithms: the IDPF one and the older Adobe one. They only mangle the
+/// first bytes of embedded fonts, the text of the book stays readable.
+pub const FONT_OBFUSCATION_ALGORITHMS: [&str; 2] = [
+    "http://www.idpf.org/2008/embedding",
+    "http://ns.adobe.com/pdf/enc#RC",
+];
+
+/// A file listed in the encryption file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedResource {
+    /// Path relative to the root of the book.
+    pub path: PathBuf,
+    /// URI of the encryption algorithm, empty when not given.
+    pub algorithm: String,
+}
+
+impl EncryptedResource {
+    pub fn is_obfuscated_font(&self) -> bool {
+        FONT_OBFUSCATION_ALGORITHMS.contains(&self.algorithm.as_str())
+    }
+}
+
+/// Error returned for books whose content is encrypted, usually by DRM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedEpubError {
+    /// The encrypted files, obfuscated fonts left out.
+    pub resources: Vec<EncryptedResource>,
+}
+
+impl fmt::Display for EncryptedEpubError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The EPUB is encrypted (DRM protected) and cannot be translated: {} encrypted files",
+            self.resources.len()
+        )?;
+        if let Some(first) = self.resources.first() {
+            write!(f, ", e.g. {}", first.path.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for EncryptedEpubError {}
+
+/// The files listed in the encryption file of a book, empty when it has none.
+pub fn encrypted_resources(
+    files: &dyn BookFiles,
+) -> Result<Vec<EncryptedResource>, EpubTranslatorError> {
+    if !files.is_file(Path::new(ENCRYPTION_PATH)) {
+        return Ok(Vec::new());
+    }
+    let content = files.read_to_string(Path::new(ENCRYPTION_PATH))?;
+    let mut reader = Reader::from_str(&content);
+    let mut resources = Vec::new();
+    let mut algorithm = String::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) | Event::Empty(element) => match element.local_name().as_ref() {
+                "EncryptedData" => algorithm.clear(),
+                "EncryptionMethod" => {
+                    algorithm = attributes(&element)?
+                        .remove("Algorithm")
+                        .unwrap_or_default();
+                }
+                "CipherReference" => {
+                    if let Some(uri) = attributes(&element)?.remove("URI") {
+                        resources.push(EncryptedResource {
+                            path: PathBuf::from(
+                                percent_decode_str(&uri).decode_utf8()?.into_owned(),
+                            ),
+                            algorithm: algorithm.clone(),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(resources)
+}
+
+/// Fails with an `EncryptedEpubError` when anything but fonts is encrypted. Returns the
+/// obfuscated fonts, which are copied as they are.
+pub fn check_encryption(
+    files: &dyn BookFiles,
+) -> Result<Vec<EncryptedResource>, EpubTranslatorError> {
+    let (fonts, encrypted): (Vec<_>, Vec<_>) = encrypted_resources(files)?
+        .into_iter()
+        .partition(EncryptedResource::is_obfuscated_font);
+    if !encrypted.is_empty() {
+        return Err(EncryptedEpubError {
+            resources: encrypted,
+        }
+        .into());
+    }
+    Ok(fonts)
+}
@@ -0,0 +1,125 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+use tempfile::tempdir;
+
+use crate::error::EpubTranslatorError;
+
+/// Environment variable pointing at the epubcheck JAR.
+pub const EPUBCHECK_JAR_VAR: &str = "EPUBCHECK_JAR";
+
+/// A local installation of epubcheck.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Epubcheck {
+    /// The `epubcheck` launcher, as installed by package managers.
+    Binary(PathBuf),
+    /// The JAR of a release, run with `java -jar`.
+    Jar(PathBuf),
+}
+
+impl Epubcheck {
+    /// Finds epubcheck: the JAR named by `EPUBCHECK_JAR` first, then `epubcheck` on the `PATH`.
+    pub fn find() -> Option<Self> {
+        if let Some(jar) = env::var_os(EPUBCHECK_JAR_VAR).map(PathBuf::from) {
+            if jar.is_file() {
+                return Some(Self::Jar(jar));
+            }
+        }
+        env::split_paths(&env::var_os("PATH")?)
+            .map(|dir| dir.join("epubcheck"))
+            .find(|path| path.is_file())
+            .map(Self::Binary)
+    }
+
+    /// Checks the EPUB at `epub_path`. The report is returned whatever epubcheck finds, only
+    /// failing to run it is an error.
+    pub fn check(&self, epub_path: &Path) -> Result<EpubcheckReport, EpubTranslatorError> {
+        let temp_dir = tempdir()?;
+        let json_path = temp_dir.path().join("epubcheck.json");
+        let mut command = match self {
+            Self::Binary(binary) => Command::new(binary),
+            Self::Jar(jar) => {
+                let mut command = Command::new("java");
+                command.arg("-jar").arg(jar);
+                command
+            }
+        };
+        let output = command
+            .arg(epub_path)
+            .arg("--json")
+            .arg(&json_path)
+            .output()?;
+
+        // epubcheck exits with an error status when it finds errors, the JSON is still written
+        let Ok(json) = fs::read_to_string(&json_path) else {
+            return Err(EpubTranslatorError::Parse(format!(
+                "epubcheck did not write its report: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        };
+        EpubcheckReport::from_json(&json)
+    }
+}
+
+/// The messages of an epubcheck run.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct EpubcheckReport {
+    pub messages: Vec<EpubcheckMessage>,
+}
+
+impl EpubcheckReport {
+    /// Reads the report written by `epubcheck --json`.
+    pub fn from_json(json: &str) -> Result<Self, EpubTranslatorError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Messages of severity `FATAL` or `ERROR`, the book is invalid if there is any.
+    pub fn errors(&self) -> impl Iterator<Item = &EpubcheckMessage> {
+        self.messages
+            .iter()
+            .filter(|message| matches!(message.severity.as_str(), "FATAL" | "ERROR"))
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &EpubcheckMessage> {
+        self.messages
+            .iter()
+            .filter(|message| message.severity == "WARNING")
+    }
+}
+
+/// A message of epubcheck, e.g. `RSC-005`, with the places it was found at.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EpubcheckMessage {
+    #[serde(rename = "ID")]
+    pub id: String,
+    /// `FATAL`, `ERROR`, `WARNING`, `USAGE` or `INFO`.
+    pub severity: String,
+    pub message: String,
+    #[serde(default)]
+    pub locations: Vec<EpubcheckLocation>,
+}
+
+impl fmt::Display for EpubcheckMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.severity, self.id)?;
+        if let Some(location) = self.locations.first() {
+            write!(f, " {}", location.path)?;
+            if location.line > 0 {
+                write!(f, ":{}", location.line)?;
+            }
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Where an epubcheck message was found. `line` and `column` are -1 when unknown.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EpubcheckLocation {
+    pub path: String,
+    pub line: i64,
+    pub column: i64,
+}
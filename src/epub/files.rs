@@ -0,0 +1,241 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use walkdir::WalkDir;
+use zip::read::ZipFile;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::UnzipLimits;
+use crate::error::EpubTranslatorError;
+
+/// The files of an EPUB, by path relative to the root of the book: an extracted folder or an
+/// archive held in memory.
+pub trait BookFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    fn write(&mut self, path: &Path, content: Vec<u8>) -> io::Result<()>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Every file of the book.
+    fn paths(&self) -> Vec<PathBuf>;
+
+    /// Where `path` is, for messages and progress events.
+    fn location(&self, path: &Path) -> PathBuf {
+        path.to_path_buf()
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// An EPUB extracted into a folder.
+pub struct BookFolder {
+    root: PathBuf,
+}
+
+impl BookFolder {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+impl BookFiles for BookFolder {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(self.root.join(path))
+    }
+
+    fn write(&mut self, path: &Path, content: Vec<u8>) -> io::Result<()> {
+        fs::write(self.root.join(path), content)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.root.join(path).is_file()
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        WalkDir::new(&self.root)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| Some(entry.path().strip_prefix(&self.root).ok()?.to_path_buf()))
+            .collect()
+    }
+
+    fn location(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+#[derive(Clone)]
+struct ArchiveEntry {
+    name: String,
+    content: Vec<u8>,
+    /// Index of the entry in the source archive, `None` for added files.
+    source_index: Option<usize>,
+    modified: bool,
+}
+
+/// An EPUB held in memory, read from and written to an archive without touching the disk.
+#[derive(Clone)]
+pub struct BookArchive {
+    source: Vec<u8>,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl BookArchive {
+    /// Reads an EPUB archive, with the same checks as `unzip_epub_with_limits`.
+    pub fn read(source: Vec<u8>, limits: &UnzipLimits) -> Result<Self, EpubTranslatorError> {
+        let mut archive = ZipArchive::new(Cursor::new(&source))?;
+        super::check_declared_sizes(&mut archive, limits)?;
+
+        let mut entries = Vec::new();
+        let mut total = 0;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let name = entry_name(&file)?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut content = Vec::new();
+            super::copy_entry(&mut file, &mut content, limits, &mut total)?;
+            entries.push(ArchiveEntry {
+                name,
+                content,
+                source_index: Some(i),
+                modified: false,
+            });
+        }
+        drop(archive);
+
+        Ok(Self { source, entries })
+    }
+
+    /// Writes the book as an EPUB archive. As in `repack_epub`, unchanged entries are copied
+    /// from the source archive as they are, the entry order is kept and written entries are
+    /// dated `time`, now when not given.
+    pub fn write_to<W: Write + Seek>(
+        &self,
+        output: W,
+        time: Option<DateTime<Utc>>,
+    ) -> Result<(), EpubTranslatorError> {
+        let mut archive = ZipArchive::new(Cursor::new(&self.source))?;
+        let mut zip = ZipWriter::new(output);
+        let deflated = super::entry_options(CompressionMethod::Deflated, time);
+
+        for entry in &self.entries {
+            let Some(index) = entry.source_index else {
+                zip.start_file(entry.name.as_str(), deflated)?;
+                zip.write_all(&entry.content)?;
+                continue;
+            };
+            let file = archive.by_index(index)?;
+            if !entry.modified {
+                zip.raw_copy_file(file)?;
+                continue;
+            }
+            let mut options = super::entry_options(file.compression(), time);
+            if let Some(mode) = file.unix_mode() {
+                options = options.unix_permissions(mode);
+            }
+            drop(file);
+            zip.start_file(entry.name.as_str(), options)?;
+            zip.write_all(&entry.content)?;
+        }
+
+        zip.finish()?;
+        Ok(())
+    }
+
+    /// The book as EPUB archive bytes, see `write_to`.
+    pub fn to_bytes(&self, time: Option<DateTime<Utc>>) -> Result<Vec<u8>, EpubTranslatorError> {
+        let mut output = Cursor::new(Vec::new());
+        self.write_to(&mut output, time)?;
+        Ok(output.into_inner())
+    }
+
+    /// Reads the EPUB at `path` into memory.
+    pub fn open(path: &Path, limits: &UnzipLimits) -> Result<Self, EpubTranslatorError> {
+        let mut source = Vec::new();
+        File::open(path)?.read_to_end(&mut source)?;
+        Self::read(source, limits)
+    }
+
+    fn entry(&self, path: &Path) -> Option<&ArchiveEntry> {
+        let name = normalize(path)?;
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+}
+
+impl BookFiles for BookArchive {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.entry(path)
+            .map(|entry| entry.content.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.display().to_string()))
+    }
+
+    fn write(&mut self, path: &Path, content: Vec<u8>) -> io::Result<()> {
+        let name = normalize(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, path.display().to_string())
+        })?;
+        match self.entries.iter_mut().find(|entry| entry.name == name) {
+            Some(entry) => {
+                entry.modified |= entry.content != content;
+                entry.content = content;
+            }
+            None => self.entries.push(ArchiveEntry {
+                name,
+                content,
+                source_index: None,
+                modified: true,
+            }),
+        }
+        Ok(())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.entry(path).is_some()
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .map(|entry| PathBuf::from(&entry.name))
+            .collect()
+    }
+}
+
+// Name of an archive entry, rejected if it would point outside of the book.
+fn entry_name(file: &ZipFile) -> Result<String, EpubTranslatorError> {
+    file.enclosed_name()
+        .and_then(|path| normalize(&path))
+        .ok_or_else(|| {
+            EpubTranslatorError::InvalidEpub(format!(
+                "EPUB entry `{}` points outside of the book",
+                file.name()
+            ))
+        })
+}
+
+// `path` as an archive entry name: `/` separated, without `.` and `..` components. `None` if it
+// leaves the root of the book.
+fn normalize(path: &Path) -> Option<String> {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
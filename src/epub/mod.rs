@@ -1,20 +1,134 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::{self, Read, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use walkdir::WalkDir;
+use zip::read::ZipFile;
 use zip::write::SimpleFileOptions;
-use zip::{write::FileOptions, ZipArchive, ZipWriter};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::error::EpubTranslatorError;
+
+mod encryption;
+mod epubcheck;
+mod files;
+mod ncx;
+mod package;
+mod validate;
+pub use encryption::{
+    check_encryption, encrypted_resources, EncryptedEpubError, EncryptedResource, ENCRYPTION_PATH,
+    FONT_OBFUSCATION_ALGORITHMS,
+};
+pub use epubcheck::{
+    Epubcheck, EpubcheckLocation, EpubcheckMessage, EpubcheckReport, EPUBCHECK_JAR_VAR,
+};
+pub use files::{BookArchive, BookFiles, BookFolder};
+pub use ncx::{navigation_labels, set_navigation_labels, NavigationLabel};
+pub use package::{
+    FixedLayoutError, ManifestItem, MetadataEntry, Package, SpineItem, CONTAINER_PATH,
+    NCX_MEDIA_TYPE, PRE_PAGINATED, XHTML_MEDIA_TYPE,
+};
+pub use validate::{
+    validate_book, validate_epub, validate_epub_bytes, ValidationIssue, EPUB_MIMETYPE,
+};
+
+/// Paths of the XHTML content documents of a book, relative to its root, as listed in its
+/// package manifest. Books without a container file fall back to every `.xhtml`/`.html` file.
+pub fn content_document_paths(files: &dyn BookFiles) -> Result<Vec<PathBuf>, EpubTranslatorError> {
+    if !files.is_file(Path::new(CONTAINER_PATH)) {
+        return Ok(files
+            .paths()
+            .into_iter()
+            .filter(|path| {
+                path.extension()
+                    .map(|ext| ext == "xhtml" || ext == "html")
+                    .unwrap_or(false)
+            })
+            .collect());
+    }
+    let package = Package::open(files)?;
+    // Manifest entries pointing at missing files are left for epubcheck to report
+    Ok(package
+        .content_documents()
+        .into_iter()
+        .filter(|path| files.is_file(path))
+        .collect())
+}
+
+/// Paths of the XHTML content documents of an extracted EPUB, see `content_document_paths`.
+pub fn get_content_document_paths(book_dir: &Path) -> Result<Vec<PathBuf>, EpubTranslatorError> {
+    let files = BookFolder::new(book_dir);
+    Ok(content_document_paths(&files)?
+        .iter()
+        .map(|path| files.location(path))
+        .collect())
+}
+
+/// Labels a book as machine translated from `source_lang`, at `time`, see
+/// `Package::add_provenance`. Books without a container file are left as they are.
+pub fn add_provenance(
+    files: &mut dyn BookFiles,
+    source_lang: Option<&str>,
+    time: DateTime<Utc>,
+) -> Result<(), EpubTranslatorError> {
+    if !files.is_file(Path::new(CONTAINER_PATH)) {
+        return Ok(());
+    }
+    let timestamp = time.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    Package::open(files)?.add_provenance(files, source_lang, &timestamp)
+}
+
+/// Declares `lang` as the language of a book, in its package metadata and NCX.
+/// Books without a container file are left as they are.
+pub fn set_book_language(files: &mut dyn BookFiles, lang: &str) -> Result<(), EpubTranslatorError> {
+    if !files.is_file(Path::new(CONTAINER_PATH)) {
+        return Ok(());
+    }
+    Package::open(files)?.set_language(files, lang)
+}
+
+/// The language of a book, the first `dc:language` of its package metadata. `None` for books
+/// without a container file.
+pub fn book_language(files: &dyn BookFiles) -> Result<Option<String>, EpubTranslatorError> {
+    if !files.is_file(Path::new(CONTAINER_PATH)) {
+        return Ok(None);
+    }
+    Ok(Package::open(files)?.language)
+}
+
+/// The text of the first metadata element of a book named `name`, e.g. `title` or `creator`.
+/// `None` for books without a container file.
+pub fn book_metadata(
+    files: &dyn BookFiles,
+    name: &str,
+) -> Result<Option<String>, EpubTranslatorError> {
+    if !files.is_file(Path::new(CONTAINER_PATH)) {
+        return Ok(None);
+    }
+    Package::open(files)?.first_metadata(files, name)
+}
+
+/// Declares `lang` as a further language of a book, after the one of `set_book_language`.
+/// Books without a container file are left as they are.
+pub fn add_book_language(files: &mut dyn BookFiles, lang: &str) -> Result<(), EpubTranslatorError> {
+    if !files.is_file(Path::new(CONTAINER_PATH)) {
+        return Ok(());
+    }
+    Package::open(files)?.add_language(files, lang)
+}
 
 // Get an operator over all the xhtml files in the epub folder
 pub fn get_xhtml_paths(
     epub_folder_path: &Path,
-) -> Result<impl Iterator<Item = String>, Box<dyn std::error::Error>> {
+) -> Result<impl Iterator<Item = String>, EpubTranslatorError> {
     if !epub_folder_path.exists() || !epub_folder_path.is_dir() {
-        return Err(Box::new(std::io::Error::new(
+        return Err(std::io::Error::new(
             std::io::ErrorKind::NotFound,
             "The path is not a directory or does not exist",
-        )));
+        )
+        .into());
     }
 
     let walker = WalkDir::new(epub_folder_path).into_iter();
@@ -32,10 +146,39 @@ pub fn get_xhtml_paths(
     Ok(xhtml_files)
 }
 
+/// Size limits enforced while extracting an EPUB, against zip bombs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnzipLimits {
+    /// Maximum uncompressed size of a single entry, in bytes.
+    pub max_entry_size: u64,
+    /// Maximum uncompressed size of all the entries together, in bytes.
+    pub max_total_size: u64,
+}
+
+impl Default for UnzipLimits {
+    fn default() -> Self {
+        Self {
+            max_entry_size: 512 * 1024 * 1024,
+            max_total_size: 2 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
 pub fn unzip_epub_from_path(
     epub_path: &Path,
     output_dir: &Path,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), EpubTranslatorError> {
+    unzip_epub_with_limits(epub_path, output_dir, &UnzipLimits::default())
+}
+
+/// Extracts an EPUB into `output_dir`. Entries whose path would land outside of `output_dir`
+/// (`../`, absolute paths) are rejected, as are archives exceeding `limits`. Sizes are checked
+/// on the extracted data, not only on the sizes the archive declares.
+pub fn unzip_epub_with_limits(
+    epub_path: &Path,
+    output_dir: &Path,
+    limits: &UnzipLimits,
+) -> Result<(), EpubTranslatorError> {
     // Open Epub file
     let file = File::open(epub_path)?;
 
@@ -45,35 +188,121 @@ pub fn unzip_epub_from_path(
     // Open the ZIP archive
     let mut archive = ZipArchive::new(file)?;
 
-    // Create a PathBuf for the output directory
-    let output_path_buf = PathBuf::from(output_dir);
+    // Check the declared sizes before writing anything
+    check_declared_sizes(&mut archive, limits)?;
 
     // Extract all files
+    let mut total: u64 = 0;
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
-        let outpath = output_path_buf.join(file.name());
+        let relative_path = file.enclosed_name().ok_or_else(|| {
+            EpubTranslatorError::InvalidEpub(format!(
+                "EPUB entry `{}` points outside of the book",
+                file.name()
+            ))
+        })?;
+        let outpath = output_dir.join(relative_path);
 
-        if file.name().ends_with('/') {
+        if file.is_dir() {
             fs::create_dir_all(&outpath)?;
             continue;
-        } else {
-            if let Some(parent) = outpath.parent() {
-                if !parent.exists() {
-                    fs::create_dir_all(parent)?;
-                }
-            }
-            let mut outfile = File::create(&outpath)?;
-            io::copy(&mut file, &mut outfile)?;
         }
+        if let Some(parent) = outpath.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut outfile = BufWriter::new(File::create(&outpath)?);
+        copy_entry(&mut file, &mut outfile, limits, &mut total)?;
+        outfile.flush()?;
     }
     Ok(())
 }
 
-pub fn zip_folder_to_epub(
-    folder_path: &Path,
-    epub_path: &Path,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let epub_file = File::create(epub_path)?;
+/// The size of an EPUB once extracted, as declared by its entries.
+pub fn extracted_size(epub_path: &Path) -> Result<u64, EpubTranslatorError> {
+    let mut archive = ZipArchive::new(File::open(epub_path)?)?;
+    let mut total: u64 = 0;
+    for i in 0..archive.len() {
+        total = total.saturating_add(archive.by_index(i)?.size());
+    }
+    Ok(total)
+}
+
+fn check_declared_sizes<R: Read + io::Seek>(
+    archive: &mut ZipArchive<R>,
+    limits: &UnzipLimits,
+) -> Result<(), EpubTranslatorError> {
+    let mut declared_total: u64 = 0;
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        check_entry_size(file.name(), file.size(), limits)?;
+        declared_total = declared_total.saturating_add(file.size());
+    }
+    if declared_total > limits.max_total_size {
+        return Err(EpubTranslatorError::InvalidEpub(format!(
+            "EPUB too large once extracted: {} bytes, the limit is {}",
+            declared_total, limits.max_total_size
+        )));
+    }
+    Ok(())
+}
+
+// Extracts an entry into `output`, `total` being the size extracted so far.
+fn copy_entry(
+    file: &mut ZipFile,
+    output: &mut impl Write,
+    limits: &UnzipLimits,
+    total: &mut u64,
+) -> Result<(), EpubTranslatorError> {
+    // The declared size can't be trusted, at most one byte past the limit is read
+    let limit = limits
+        .max_entry_size
+        .min(limits.max_total_size.saturating_sub(*total));
+    let written = io::copy(&mut file.take(limit + 1), output)?;
+    check_entry_size(file.name(), written, limits)?;
+    *total += written;
+    if *total > limits.max_total_size {
+        return Err(EpubTranslatorError::InvalidEpub(format!(
+            "EPUB too large once extracted, the limit is {} bytes",
+            limits.max_total_size
+        )));
+    }
+    Ok(())
+}
+
+fn check_entry_size(
+    name: &str,
+    size: u64,
+    limits: &UnzipLimits,
+) -> Result<(), EpubTranslatorError> {
+    if size > limits.max_entry_size {
+        return Err(EpubTranslatorError::InvalidEpub(format!(
+            "EPUB entry `{}` too large once extracted: {} bytes, the limit is {}",
+            name, size, limits.max_entry_size
+        )));
+    }
+    Ok(())
+}
+
+// Options of an entry written with `method`, dated `time`, or now when not given. Zip dates
+// start in 1980, earlier times are written as 1980-01-01.
+fn entry_options(method: CompressionMethod, time: Option<DateTime<Utc>>) -> SimpleFileOptions {
+    let options = FileOptions::default().compression_method(method);
+    let Some(time) = time else {
+        return options;
+    };
+    let zip_time = zip::DateTime::from_date_and_time(
+        u16::try_from(time.year()).unwrap_or(0),
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    );
+    options.last_modified_time(zip_time.unwrap_or_default())
+}
+
+pub fn zip_folder_to_epub(folder_path: &Path, epub_path: &Path) -> Result<(), EpubTranslatorError> {
+    let epub_file = BufWriter::new(File::create(epub_path)?);
     let mut zip = ZipWriter::new(epub_file);
 
     // Add mimetype file first, without compression
@@ -84,10 +313,7 @@ pub fn zip_folder_to_epub(
     let mimetype_path = folder_path.join("mimetype");
     if mimetype_path.exists() {
         zip.start_file("mimetype", stored_options)?;
-        let mut mimetype_file = File::open(mimetype_path)?;
-        let mut buffer = Vec::new();
-        mimetype_file.read_to_end(&mut buffer)?;
-        zip.write_all(&buffer)?;
+        io::copy(&mut File::open(mimetype_path)?, &mut zip)?;
     }
 
     // Add the rest of the files with compression
@@ -95,11 +321,15 @@ pub fn zip_folder_to_epub(
         .compression_method(zip::CompressionMethod::Deflated)
         .unix_permissions(0o755);
 
-    let walker = WalkDir::new(folder_path).into_iter();
+    let walker = WalkDir::new(folder_path).sort_by_file_name().into_iter();
 
     for entry in walker.filter_map(|e| e.ok()) {
         let path = entry.path();
-        let name = path.strip_prefix(folder_path)?.to_str().unwrap();
+        let name = path
+            .strip_prefix(folder_path)
+            .map_err(io::Error::other)?
+            .to_str()
+            .unwrap();
 
         if name == "mimetype" {
             continue;
@@ -107,34 +337,116 @@ pub fn zip_folder_to_epub(
 
         if path.is_file() {
             zip.start_file(name, deflated_options)?;
-            let mut file = File::open(path)?;
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)?;
-            zip.write_all(&buffer)?;
+            io::copy(&mut BufReader::new(File::open(path)?), &mut zip)?;
         } else if !name.is_empty() {
             zip.add_directory(name, deflated_options)?;
         }
     }
 
-    zip.finish()?;
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+/// Writes the extracted `folder_path` of `source_epub` back into an EPUB.
+///
+/// Unlike `zip_folder_to_epub`, entries whose file is unchanged (same size and CRC-32) are
+/// copied from the source archive as they are, without being decompressed and compressed again.
+/// Changed files are compressed with the method of their source entry. The entry order of the
+/// source is kept, files that are not in the source are added at the end, by name. Written
+/// entries are dated `time`, now when not given.
+pub fn repack_epub(
+    source_epub: &Path,
+    folder_path: &Path,
+    epub_path: &Path,
+    time: Option<DateTime<Utc>>,
+) -> Result<(), EpubTranslatorError> {
+    let mut archive = ZipArchive::new(File::open(source_epub)?)?;
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(epub_path)?));
+    let mut written: HashSet<PathBuf> = HashSet::new();
+
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        let Some(relative_path) = entry.enclosed_name() else {
+            continue;
+        };
+        let path = folder_path.join(&relative_path);
+        if !path.exists() || !written.insert(relative_path) {
+            continue;
+        }
+        if entry.is_dir() {
+            zip.raw_copy_file(entry)?;
+            continue;
+        }
+
+        if fs::metadata(&path)?.len() == entry.size() && file_crc32(&path)? == entry.crc32() {
+            zip.raw_copy_file(entry)?;
+            continue;
+        }
+        let mut options = entry_options(entry.compression(), time);
+        if let Some(mode) = entry.unix_mode() {
+            options = options.unix_permissions(mode);
+        }
+        let name = entry.name().to_string();
+        drop(entry);
+        zip.start_file(name, options)?;
+        io::copy(&mut BufReader::new(File::open(&path)?), &mut zip)?;
+    }
+
+    let deflated_options = entry_options(CompressionMethod::Deflated, time);
+    let walker = WalkDir::new(folder_path).sort_by_file_name().into_iter();
+    for entry in walker.filter_map(|e| e.ok()) {
+        let relative_path = entry
+            .path()
+            .strip_prefix(folder_path)
+            .map_err(io::Error::other)?;
+        if !entry.path().is_file() || written.contains(relative_path) {
+            continue;
+        }
+        let name = relative_path.to_str().ok_or_else(|| {
+            EpubTranslatorError::InvalidEpub("Non UTF-8 file name in the book".to_string())
+        })?;
+        zip.start_file(name.replace('\\', "/"), deflated_options)?;
+        io::copy(&mut BufReader::new(File::open(entry.path())?), &mut zip)?;
+    }
+
+    zip.finish()?.flush()?;
     Ok(())
 }
 
-pub fn epubcheck(epub_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+// The CRC-32 of the file at `path`, read a buffer at a time.
+fn file_crc32(path: &Path) -> io::Result<u32> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut hasher = crc32fast::Hasher::new();
+    loop {
+        let buffer = file.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(buffer);
+        let length = buffer.len();
+        file.consume(length);
+    }
+}
+
+pub fn epubcheck(epub_path: &Path) -> Result<(), EpubTranslatorError> {
     // Get folder from path
     let folder = epub_path.parent().unwrap();
 
     // Get name from path
-    let epub_name = epub_path.strip_prefix(folder)?.to_str().unwrap();
+    let epub_name = epub_path
+        .strip_prefix(folder)
+        .map_err(io::Error::other)?
+        .to_str()
+        .unwrap();
 
     let output = Command::new("docker")
-        .args(&[
+        .args([
             "run",
             "--rm",
             "-v",
             &format!("{}:/data", folder.to_str().unwrap()),
             "carlosfy/epubcheck",
-            &format!("{}", epub_name),
+            epub_name,
         ])
         .output()?;
 
@@ -151,6 +463,7 @@ pub fn epubcheck(epub_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use tempfile::tempdir;
 
     // https://github.com/w3c/epubcheck
@@ -165,7 +478,7 @@ mod tests {
         let output_epub_path = temp_dir_path.join("output.epub");
 
         // Unzip the EPUB file
-        unzip_epub_from_path(&input_epub_path, &extracted_dir)?;
+        unzip_epub_from_path(input_epub_path, &extracted_dir)?;
 
         // Zip the extracted folder back to EPUB
         zip_folder_to_epub(&extracted_dir, &output_epub_path)?;
@@ -175,6 +488,124 @@ mod tests {
         Ok(())
     }
 
+    // Writes an archive with the given entries, as a malicious EPUB would be built.
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut zip = ZipWriter::new(File::create(path)?);
+        for (name, content) in entries {
+            zip.start_file(*name, SimpleFileOptions::default())?;
+            zip.write_all(content)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unzip_rejects_unsafe_archives() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let book_dir = temp_dir.path().join("book");
+
+        let slip_path = temp_dir.path().join("slip.epub");
+        write_zip(
+            &slip_path,
+            &[("mimetype", b"application/epub+zip"), ("../evil.txt", b"x")],
+        )?;
+        assert!(unzip_epub_from_path(&slip_path, &book_dir).is_err());
+        assert!(!temp_dir.path().join("evil.txt").exists());
+
+        let bomb_path = temp_dir.path().join("bomb.epub");
+        write_zip(&bomb_path, &[("OEBPS/big.xhtml", &[b'a'; 1000])])?;
+        let limits = UnzipLimits {
+            max_entry_size: 100,
+            ..Default::default()
+        };
+        assert!(unzip_epub_with_limits(&bomb_path, &book_dir, &limits).is_err());
+        let limits = UnzipLimits {
+            max_entry_size: 1000,
+            max_total_size: 999,
+        };
+        assert!(unzip_epub_with_limits(&bomb_path, &book_dir, &limits).is_err());
+        assert!(unzip_epub_with_limits(&bomb_path, &book_dir, &UnzipLimits::default()).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_repack_copies_unchanged_entries() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let source_path = temp_dir.path().join("source.epub");
+        let book_dir = temp_dir.path().join("book");
+        let output_path = temp_dir.path().join("output.epub");
+        write_zip(
+            &source_path,
+            &[
+                ("mimetype", b"application/epub+zip"),
+                ("OEBPS/ch1.xhtml", b"<p>Hello</p>"),
+                ("OEBPS/cover.jpg", &[0xff; 64]),
+            ],
+        )?;
+        unzip_epub_from_path(&source_path, &book_dir)?;
+        fs::write(book_dir.join("OEBPS/ch1.xhtml"), "<p>Hola</p>")?;
+
+        repack_epub(&source_path, &book_dir, &output_path, None)?;
+
+        let mut source = ZipArchive::new(File::open(&source_path)?)?;
+        let mut output = ZipArchive::new(File::open(&output_path)?)?;
+        let names: Vec<&str> = output.file_names().collect();
+        assert_eq!(
+            names,
+            vec!["mimetype", "OEBPS/ch1.xhtml", "OEBPS/cover.jpg"]
+        );
+        let mut content = String::new();
+        output
+            .by_name("OEBPS/ch1.xhtml")?
+            .read_to_string(&mut content)?;
+        assert_eq!(content, "<p>Hola</p>");
+        // Unchanged entries keep their source metadata, timestamps included
+        let cover = output.by_name("OEBPS/cover.jpg")?.last_modified();
+        assert_eq!(cover, source.by_name("OEBPS/cover.jpg")?.last_modified());
+        Ok(())
+    }
+
+    #[test]
+    fn test_book_archive() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let source_path = temp_dir.path().join("source.epub");
+        write_zip(
+            &source_path,
+            &[
+                ("mimetype", b"application/epub+zip"),
+                ("OEBPS/ch1.xhtml", b"<p>Hello</p>"),
+                ("OEBPS/cover.jpg", &[0xff; 64]),
+            ],
+        )?;
+
+        let mut book = BookArchive::open(&source_path, &UnzipLimits::default())?;
+        assert!(book.is_file(Path::new("OEBPS/text/../ch1.xhtml")));
+        book.write(Path::new("OEBPS/ch1.xhtml"), b"<p>Hola</p>".to_vec())?;
+        book.write(Path::new("OEBPS/added.css"), b"p {}".to_vec())?;
+
+        let mut output = ZipArchive::new(io::Cursor::new(book.to_bytes(None)?))?;
+        let names: Vec<&str> = output.file_names().collect();
+        assert_eq!(
+            names,
+            vec![
+                "mimetype",
+                "OEBPS/ch1.xhtml",
+                "OEBPS/cover.jpg",
+                "OEBPS/added.css"
+            ]
+        );
+        let mut content = String::new();
+        output
+            .by_name("OEBPS/ch1.xhtml")?
+            .read_to_string(&mut content)?;
+        assert_eq!(content, "<p>Hola</p>");
+
+        let slip_path = temp_dir.path().join("slip.epub");
+        write_zip(&slip_path, &[("../evil.txt", b"x")])?;
+        assert!(BookArchive::open(&slip_path, &UnzipLimits::default()).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_get_xhtml_paths() -> Result<(), Box<dyn std::error::Error>> {
         let test_data_dir = Path::new("tests/data/epub_folder");
@@ -182,4 +613,265 @@ mod tests {
         assert_eq!(xhtml_files.count(), 10);
         Ok(())
     }
+
+    #[test]
+    fn test_get_content_document_paths() -> Result<(), Box<dyn std::error::Error>> {
+        let book_dir = Path::new("tests/data/opf_book");
+        let paths = get_content_document_paths(book_dir)?;
+        let expected: Vec<PathBuf> = ["text/chapter 1.htm", "text/notes", "nav.xhtml"]
+            .iter()
+            .map(|href| book_dir.join("OEBPS").join(href))
+            .collect();
+        // Spine order first. `.htm` and extensionless documents are found, the unlisted source
+        // dump is not
+        assert_eq!(paths, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_book_language() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let book_dir = temp_dir.path();
+        for path in [
+            "META-INF/container.xml",
+            "OEBPS/content.opf",
+            "OEBPS/toc.ncx",
+        ] {
+            fs::create_dir_all(book_dir.join(path).parent().unwrap())?;
+            fs::copy(
+                Path::new("tests/data/opf_book").join(path),
+                book_dir.join(path),
+            )?;
+        }
+        let mut files = BookFolder::new(book_dir);
+        assert_eq!(Package::open(&files)?.language.as_deref(), Some("en"));
+
+        set_book_language(&mut files, "pt-BR")?;
+
+        let opf = fs::read_to_string(book_dir.join("OEBPS/content.opf"))?;
+        assert!(opf.contains("<dc:language>pt-BR</dc:language>"));
+        assert!(opf.contains("<dc:title>A Small Book</dc:title>"));
+        assert_eq!(Package::open(&files)?.language.as_deref(), Some("pt-BR"));
+        let ncx = fs::read_to_string(book_dir.join("OEBPS/toc.ncx"))?;
+        assert!(ncx.contains(r#"version="2005-1" xml:lang="pt-BR">"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_navigation_labels() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let ncx_path = temp_dir.path().join("toc.ncx");
+        fs::copy("tests/data/opf_book/OEBPS/toc.ncx", &ncx_path)?;
+
+        let mut files = BookFolder::new(temp_dir.path());
+        let labels = navigation_labels(&files, Path::new("toc.ncx"))?;
+        let texts: Vec<&str> = labels.iter().map(|label| label.text.as_str()).collect();
+        assert_eq!(texts, vec!["Chapter One", "Notes"]);
+
+        let translations = HashMap::from([(0, "Capítulo uno".to_string())]);
+        set_navigation_labels(&mut files, Path::new("toc.ncx"), &translations)?;
+
+        let ncx = fs::read_to_string(&ncx_path)?;
+        assert!(ncx.contains("<navLabel><text>Capítulo uno</text></navLabel>"));
+        assert!(ncx.contains("<navLabel><text>Notes</text></navLabel>"));
+        // The book title is not a navigation label
+        assert!(ncx.contains("<docTitle><text>A Small Book</text></docTitle>"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_provenance() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let opf_path = temp_dir.path().join("content.opf");
+        fs::copy("tests/data/opf_book/OEBPS/content.opf", &opf_path)?;
+
+        let mut files = BookFolder::new(temp_dir.path());
+        let package = Package::read(&files, Path::new("content.opf"))?;
+        package.add_provenance(&mut files, None, "2024-05-01T10:00:00Z")?;
+
+        let opf = fs::read_to_string(&opf_path)?;
+        assert!(opf.contains(
+            r#"<dc:contributor id="machine-translation">machine translation via DeepL</dc:contributor>"#
+        ));
+        assert!(opf.contains(
+            r##"<meta refines="#machine-translation" property="role" scheme="marc:relators">trl</meta>"##
+        ));
+        assert!(opf.contains(r#"<meta name="epub-translator:source-language" content="en"/>"#));
+        assert!(opf.contains(
+            r#"<meta name="epub-translator:translated" content="2024-05-01T10:00:00Z"/>"#
+        ));
+        Package::read(&files, Path::new("content.opf"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_metadata_translations() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let opf_path = temp_dir.path().join("content.opf");
+        fs::copy("tests/data/opf_book/OEBPS/content.opf", &opf_path)?;
+        let mut files = BookFolder::new(temp_dir.path());
+        let package = Package::read(&files, Path::new("content.opf"))?;
+
+        let entries = package.translatable_metadata(&files)?;
+        let texts: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.text.as_str()))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                ("title", "A Small Book"),
+                ("description", "Stories & notes from the coast."),
+                ("subject", "Lighthouses"),
+            ]
+        );
+        assert_eq!(
+            package.first_metadata(&files, "description")?.as_deref(),
+            Some("Stories & notes from the coast.")
+        );
+        assert_eq!(package.first_metadata(&files, "creator")?, None);
+
+        let translations = HashMap::from([
+            (0, "Un libro pequeño".to_string()),
+            (1, "Historias & notas de la costa.".to_string()),
+        ]);
+        package.set_metadata_translations(&mut files, &translations)?;
+
+        let opf = fs::read_to_string(&opf_path)?;
+        assert!(opf.contains(r#"<dc:title id="translated-title">Un libro pequeño</dc:title>"#));
+        assert!(opf.contains(
+            r##"  <meta refines="#translated-title" property="alternate-script" xml:lang="en">A Small Book</meta>"##
+        ));
+        assert!(opf.contains("<dc:description>Historias &amp; notas de la costa.</dc:description>"));
+        assert!(opf.contains("<dc:subject>Lighthouses</dc:subject>"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_encryption() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        fs::create_dir_all(temp_dir.path().join("META-INF"))?;
+        let mut files = BookFolder::new(temp_dir.path());
+        assert!(check_encryption(&files)?.is_empty());
+
+        let font = r#"<enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://www.idpf.org/2008/embedding"/>
+    <enc:CipherData><enc:CipherReference URI="OEBPS/fonts/Serif%20Bold.otf"/></enc:CipherData>
+  </enc:EncryptedData>"#;
+        let chapter = r#"<enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://www.w3.org/2001/04/xmlenc#aes128-cbc"/>
+    <enc:CipherData><enc:CipherReference URI="OEBPS/chapter1.xhtml"/></enc:CipherData>
+  </enc:EncryptedData>"#;
+        let encryption = |data: &[&str]| {
+            format!(
+                r#"<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container" xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+  {}
+</encryption>"#,
+                data.join("\n  ")
+            )
+            .into_bytes()
+        };
+
+        files.write(Path::new(ENCRYPTION_PATH), encryption(&[font]))?;
+        let fonts = check_encryption(&files)?;
+        assert_eq!(fonts.len(), 1);
+        assert_eq!(fonts[0].path, Path::new("OEBPS/fonts/Serif Bold.otf"));
+
+        files.write(Path::new(ENCRYPTION_PATH), encryption(&[font, chapter]))?;
+        let Err(EpubTranslatorError::Encrypted(error)) = check_encryption(&files) else {
+            panic!("chapter should be refused as encrypted");
+        };
+        assert_eq!(
+            error.resources,
+            vec![EncryptedResource {
+                path: PathBuf::from("OEBPS/chapter1.xhtml"),
+                algorithm: "http://www.w3.org/2001/04/xmlenc#aes128-cbc".to_string(),
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_epub() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        assert_eq!(validate_epub(&epub_path)?, Vec::new());
+
+        let mut book = BookArchive::open(&epub_path, &UnzipLimits::default())?;
+        let chapter = Path::new("OEBPS/text/chapter 1.htm");
+        let content = book.read_to_string(chapter)?.replace("</p>", "</div>");
+        book.write(chapter, content.into_bytes())?;
+        let opf = Path::new("OEBPS/content.opf");
+        let content = book
+            .read_to_string(opf)?
+            .replace("</spine>", r#"<itemref idref="missing"/></spine>"#);
+        book.write(opf, content.into_bytes())?;
+
+        let issues = validate_epub_bytes(book.to_bytes(None)?)?;
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].path.as_deref(), Some(chapter));
+        assert!(issues[0].message.starts_with("line 5: "));
+        assert_eq!(
+            issues[1].to_string(),
+            "OEBPS/content.opf: spine item `missing` is not in the manifest"
+        );
+
+        let compressed_path = temp_dir.path().join("compressed.epub");
+        write_zip(&compressed_path, &[("mimetype", b"application/epub+zip")])?;
+        assert_eq!(
+            validate_epub(&compressed_path)?,
+            vec![
+                ValidationIssue {
+                    path: Some(PathBuf::from("mimetype")),
+                    message: "the entry is compressed".to_string(),
+                },
+                ValidationIssue {
+                    path: Some(PathBuf::from(CONTAINER_PATH)),
+                    message: "missing".to_string(),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_epubcheck_report() -> Result<(), Box<dyn std::error::Error>> {
+        let json = r#"{
+  "checker": { "path": "book.epub", "nFatal": 0, "nError": 1, "nWarning": 1 },
+  "messages": [
+    {
+      "ID": "RSC-005",
+      "severity": "ERROR",
+      "message": "Error while parsing file: element \"div\" not allowed here",
+      "additionalLocations": 0,
+      "locations": [
+        { "path": "OEBPS/text/chapter 1.htm", "line": 5, "column": 12, "context": null }
+      ],
+      "suggestion": null
+    },
+    {
+      "ID": "OPF-053",
+      "severity": "WARNING",
+      "message": "Date value \"\" does not follow recommended syntax",
+      "locations": [{ "path": "OEBPS/content.opf", "line": -1, "column": -1 }]
+    }
+  ]
+}"#;
+        let report = EpubcheckReport::from_json(json)?;
+        let errors: Vec<String> = report.errors().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            vec![
+                r#"ERROR RSC-005 OEBPS/text/chapter 1.htm:5: Error while parsing file: element "div" not allowed here"#
+            ]
+        );
+        assert_eq!(
+            report.warnings().next().map(ToString::to_string).as_deref(),
+            Some(
+                r#"WARNING OPF-053 OEBPS/content.opf: Date value "" does not follow recommended syntax"#
+            )
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use quick_xml::escape::unescape;
+use quick_xml::events::{BytesText, Event};
+use quick_xml::Reader;
+
+use super::package::{push_escaped_text, rewrite_xml};
+use super::BookFiles;
+use crate::error::EpubTranslatorError;
+
+/// A navigation label of an NCX: the `text` of a `navLabel` in the `navMap` or a `navList`.
+/// Page list labels are page numbers and are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavigationLabel {
+    /// Position among the labels of the NCX, blank ones included.
+    pub index: usize,
+    pub text: String,
+}
+
+// Whether the innermost element of `ancestors` holds a navigation label.
+fn is_label(ancestors: &[String]) -> bool {
+    matches!(ancestors, [.., label, text] if label == "navLabel" && text == "text")
+        && !ancestors.iter().any(|name| name == "pageList")
+}
+
+/// The non-blank navigation labels of the NCX at `path`, in document order.
+pub fn navigation_labels(
+    files: &dyn BookFiles,
+    path: &Path,
+) -> Result<Vec<NavigationLabel>, EpubTranslatorError> {
+    let content = files.read_to_string(path)?;
+    let mut reader = Reader::from_str(&content);
+    let mut ancestors: Vec<String> = Vec::new();
+    let mut labels = Vec::new();
+    let mut index = 0;
+    // Escaped content of the label being read
+    let mut current: Option<String> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) => {
+                ancestors.push(element.local_name().as_ref().to_string());
+                if is_label(&ancestors) {
+                    current = Some(String::new());
+                }
+            }
+            Event::End(_) => {
+                if let Some(content) = current.take() {
+                    let text = unescape(&content)?.trim().to_string();
+                    if !text.is_empty() {
+                        labels.push(NavigationLabel { index, text });
+                    }
+                    index += 1;
+                }
+                ancestors.pop();
+            }
+            Event::Eof => break,
+            event => {
+                if let Some(content) = &mut current {
+                    push_escaped_text(&event, content);
+                }
+            }
+        }
+    }
+    Ok(labels)
+}
+
+/// Replaces navigation labels of the NCX at `path` with their translation, by
+/// `NavigationLabel::index`.
+pub fn set_navigation_labels(
+    files: &mut dyn BookFiles,
+    path: &Path,
+    translations: &HashMap<usize, String>,
+) -> Result<(), EpubTranslatorError> {
+    let mut ancestors: Vec<String> = Vec::new();
+    let mut index = 0;
+    let mut replacing = false;
+
+    rewrite_xml(files, path, |event| match event {
+        Event::Start(element) => {
+            ancestors.push(element.local_name().as_ref().to_string());
+            if !is_label(&ancestors) {
+                return vec![Event::Start(element)];
+            }
+            index += 1;
+            match translations.get(&(index - 1)) {
+                Some(translation) => {
+                    replacing = true;
+                    let text = BytesText::new(translation).into_owned();
+                    vec![Event::Start(element), Event::Text(text)]
+                }
+                None => vec![Event::Start(element)],
+            }
+        }
+        Event::End(element) => {
+            replacing = false;
+            ancestors.pop();
+            vec![Event::End(element)]
+        }
+        _ if replacing => Vec::new(),
+        event => vec![event],
+    })
+}
@@ -0,0 +1,708 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use percent_encoding::percent_decode_str;
+use quick_xml::escape::unescape;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer, XmlVersion};
+
+use super::BookFiles;
+use crate::error::EpubTranslatorError;
+
+/// Location of the container file, relative to the root of an extracted EPUB.
+pub const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+const PACKAGE_MEDIA_TYPE: &str = "application/oebps-package+xml";
+
+/// Media type of XHTML content documents.
+pub const XHTML_MEDIA_TYPE: &str = "application/xhtml+xml";
+
+/// Media type of the EPUB 2 navigation control file (NCX).
+pub const NCX_MEDIA_TYPE: &str = "application/x-dtbncx+xml";
+
+/// Metadata elements translated with `ExtractOptions::translate_metadata`.
+const TRANSLATABLE_METADATA: &[&str] = &["title", "description", "subject"];
+
+/// Id given to the main title when its original is kept and it has none.
+const TITLE_ID: &str = "translated-title";
+
+/// Id of the contributor added by `Package::add_provenance`.
+const PROVENANCE_ID: &str = "machine-translation";
+
+const MACHINE_TRANSLATION: &str = "machine translation via DeepL";
+
+/// `rendition:layout` of fixed-layout publications.
+pub const PRE_PAGINATED: &str = "pre-paginated";
+
+/// An item of the package manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestItem {
+    pub id: String,
+    /// Path relative to the package document, percent-decoded and without fragment.
+    pub href: String,
+    pub media_type: String,
+    pub properties: Vec<String>,
+}
+
+/// An item of the package spine, in reading order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpineItem {
+    pub idref: String,
+    pub properties: Vec<String>,
+}
+
+/// A translatable entry of the package metadata: a `dc:title`, `dc:description` or `dc:subject`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataEntry {
+    /// Position among the translatable elements of the metadata, blank ones included.
+    pub index: usize,
+    /// Local name of the element, e.g. `title`.
+    pub name: String,
+    pub text: String,
+}
+
+/// The package document (OPF) of an EPUB.
+#[derive(Debug, Clone)]
+pub struct Package {
+    /// Path of the package document, relative to the root of the book.
+    pub path: PathBuf,
+    /// The `version` of the package, `2.0` or `3.0`.
+    pub version: String,
+    /// The first `dc:language` of the metadata.
+    pub language: Option<String>,
+    /// The `rendition:layout` of the publication, `pre-paginated` for fixed layouts.
+    pub layout: Option<String>,
+    pub manifest: Vec<ManifestItem>,
+    pub spine: Vec<SpineItem>,
+}
+
+/// Error returned for fixed-layout books unless `TranslateOptions::fixed_layout` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedLayoutError {
+    /// The fixed-layout pages, relative to the root of the book.
+    pub pages: Vec<PathBuf>,
+}
+
+impl fmt::Display for FixedLayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The EPUB has a fixed layout ({} pages), translated text may not fit its pages",
+            self.pages.len()
+        )
+    }
+}
+
+impl std::error::Error for FixedLayoutError {}
+
+impl Package {
+    /// Reads the package document declared in the `META-INF/container.xml` of the book.
+    pub fn open(files: &dyn BookFiles) -> Result<Self, EpubTranslatorError> {
+        let container = files.read_to_string(Path::new(CONTAINER_PATH))?;
+        let rootfile = find_rootfile(&container)?.ok_or_else(|| {
+            EpubTranslatorError::InvalidEpub(format!(
+                "no package document declared in {}",
+                CONTAINER_PATH
+            ))
+        })?;
+        Self::read(files, Path::new(&rootfile))
+    }
+
+    /// Reads the package document at `path`.
+    pub fn read(files: &dyn BookFiles, path: &Path) -> Result<Self, EpubTranslatorError> {
+        let content = files.read_to_string(path)?;
+        let mut reader = Reader::from_str(&content);
+        let mut manifest = Vec::new();
+        let mut spine = Vec::new();
+        let mut version = String::new();
+        let mut language = None;
+        let mut in_language = false;
+        let mut layout = None;
+        let mut in_layout = false;
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(element) if element.local_name().as_ref() == "package" => {
+                    version = attributes(&element)?.remove("version").unwrap_or_default();
+                }
+                Event::Start(element) if element.local_name().as_ref() == "language" => {
+                    in_language = language.is_none();
+                }
+                Event::Text(text) if in_language => {
+                    language = Some(text.xml_content(XmlVersion::default()).trim().to_string());
+                }
+                Event::Text(text) if in_layout => {
+                    let value = text.xml_content(XmlVersion::default());
+                    if !value.trim().is_empty() {
+                        layout = Some(value.trim().to_string());
+                    }
+                }
+                Event::End(_) => {
+                    in_language = false;
+                    in_layout = false;
+                }
+                Event::Start(element) | Event::Empty(element)
+                    if element.local_name().as_ref() == "meta" =>
+                {
+                    let attributes = attributes(&element)?;
+                    let get = |name: &str| attributes.get(name).map(String::as_str);
+                    if get("property") == Some("rendition:layout") {
+                        in_layout = true;
+                    } else if get("name") == Some("fixed-layout") && get("content") == Some("true")
+                    {
+                        // Older convention of Apple and Amazon readers
+                        layout.get_or_insert_with(|| PRE_PAGINATED.to_string());
+                    }
+                }
+                Event::Start(element) | Event::Empty(element)
+                    if element.local_name().as_ref() == "itemref" =>
+                {
+                    let attributes = attributes(&element)?;
+                    spine.push(SpineItem {
+                        idref: attributes.get("idref").cloned().unwrap_or_default(),
+                        properties: split_properties(attributes.get("properties")),
+                    });
+                }
+                Event::Start(element) | Event::Empty(element)
+                    if element.local_name().as_ref() == "item" =>
+                {
+                    let attributes = attributes(&element)?;
+                    let Some(href) = attributes.get("href") else {
+                        continue;
+                    };
+                    let href = href.split('#').next().unwrap_or_default();
+                    manifest.push(ManifestItem {
+                        id: attributes.get("id").cloned().unwrap_or_default(),
+                        href: percent_decode_str(href).decode_utf8()?.into_owned(),
+                        media_type: attributes.get("media-type").cloned().unwrap_or_default(),
+                        properties: split_properties(attributes.get("properties")),
+                    });
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            version,
+            language,
+            layout,
+            manifest,
+            spine,
+        })
+    }
+
+    /// Path of a manifest item, relative to the root of the book.
+    pub fn item_path(&self, item: &ManifestItem) -> PathBuf {
+        self.path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&item.href)
+    }
+
+    /// Path of the NCX listed in the manifest, if any.
+    pub fn ncx_path(&self) -> Option<PathBuf> {
+        self.manifest
+            .iter()
+            .find(|item| item.media_type == NCX_MEDIA_TYPE)
+            .map(|item| self.item_path(item))
+    }
+
+    /// Paths of the XHTML content documents listed in the manifest: the ones of the spine in
+    /// reading order, then the others in manifest order.
+    pub fn content_documents(&self) -> Vec<PathBuf> {
+        let mut documents: Vec<PathBuf> = self
+            .spine_items()
+            .filter(|item| item.media_type == XHTML_MEDIA_TYPE)
+            .map(|item| self.item_path(item))
+            .collect();
+        for item in &self.manifest {
+            let path = self.item_path(item);
+            if item.media_type == XHTML_MEDIA_TYPE && !documents.contains(&path) {
+                documents.push(path);
+            }
+        }
+        documents
+    }
+
+    /// Position of the document at `path` in the spine, from 1.
+    pub fn chapter(&self, path: &Path) -> Option<usize> {
+        self.spine_items()
+            .position(|item| self.item_path(item) == path)
+            .map(|index| index + 1)
+    }
+
+    // Manifest items of the spine, in reading order.
+    fn spine_items(&self) -> impl Iterator<Item = &ManifestItem> {
+        self.spine
+            .iter()
+            .filter_map(|itemref| self.manifest.iter().find(|item| item.id == itemref.idref))
+    }
+
+    /// Paths of the spine documents laid out as fixed pages, by the layout of the publication
+    /// or their own `rendition:layout-*` property.
+    pub fn fixed_layout_documents(&self) -> Vec<PathBuf> {
+        let fixed = self.layout.as_deref() == Some(PRE_PAGINATED);
+        let has = |item: &SpineItem, property: &str| item.properties.iter().any(|p| p == property);
+        self.spine
+            .iter()
+            .filter(|item| {
+                has(item, "rendition:layout-pre-paginated")
+                    || (fixed && !has(item, "rendition:layout-reflowable"))
+            })
+            .filter_map(|item| self.manifest.iter().find(|m| m.id == item.idref))
+            .map(|item| self.item_path(item))
+            .collect()
+    }
+
+    /// Declares `lang` as the language of the publication: the first `dc:language` of the
+    /// package document and the `xml:lang` of the NCX, where present, are rewritten.
+    pub fn set_language(
+        &mut self,
+        files: &mut dyn BookFiles,
+        lang: &str,
+    ) -> Result<(), EpubTranslatorError> {
+        let mut in_language = false;
+        let mut done = false;
+        rewrite_xml(files, &self.path, |event| match event {
+            Event::Start(element) if element.local_name().as_ref() == "language" && !done => {
+                in_language = true;
+                vec![Event::Start(element)]
+            }
+            Event::Text(_) if in_language => vec![Event::Text(BytesText::new(lang).into_owned())],
+            Event::End(element) if in_language => {
+                in_language = false;
+                done = true;
+                vec![Event::End(element)]
+            }
+            event => vec![event],
+        })?;
+
+        for item in self.manifest.iter() {
+            let path = self.item_path(item);
+            if item.media_type != NCX_MEDIA_TYPE || !files.is_file(&path) {
+                continue;
+            }
+            rewrite_xml(files, &path, |event| match event {
+                Event::Start(element) if element.local_name().as_ref() == "ncx" => {
+                    vec![Event::Start(with_attributes(
+                        &element,
+                        &[("xml:lang", lang)],
+                    ))]
+                }
+                event => vec![event],
+            })?;
+        }
+
+        self.language = Some(lang.to_string());
+        Ok(())
+    }
+
+    /// Adds a `dc:language` with `lang` at the end of the metadata, the first one stays the
+    /// language of the publication.
+    pub fn add_language(
+        &self,
+        files: &mut dyn BookFiles,
+        lang: &str,
+    ) -> Result<(), EpubTranslatorError> {
+        let mut entry = Some(text_element("dc:language", &[], lang));
+        rewrite_xml(files, &self.path, |event| match event {
+            Event::End(element) if element.local_name().as_ref() == "metadata" => {
+                append_to_metadata(entry.take().into_iter().collect(), element)
+            }
+            event => vec![event],
+        })
+    }
+
+    /// The non-blank `dc:title`, `dc:description` and `dc:subject` entries, in document order.
+    pub fn translatable_metadata(
+        &self,
+        files: &dyn BookFiles,
+    ) -> Result<Vec<MetadataEntry>, EpubTranslatorError> {
+        let content = files.read_to_string(&self.path)?;
+        let mut reader = Reader::from_str(&content);
+        let mut entries = Vec::new();
+        let mut in_metadata = false;
+        let mut index = 0;
+        // Local name and escaped content of the entry being read
+        let mut current: Option<(String, String)> = None;
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(element) if element.local_name().as_ref() == "metadata" => {
+                    in_metadata = true;
+                }
+                Event::End(element) if element.local_name().as_ref() == "metadata" => {
+                    in_metadata = false;
+                }
+                Event::Start(element)
+                    if in_metadata
+                        && TRANSLATABLE_METADATA.contains(&element.local_name().as_ref()) =>
+                {
+                    current = Some((element.local_name().as_ref().to_string(), String::new()));
+                }
+                Event::End(_) => {
+                    if let Some((name, content)) = current.take() {
+                        let text = unescape(&content)?.trim().to_string();
+                        if !text.is_empty() {
+                            entries.push(MetadataEntry { index, name, text });
+                        }
+                        index += 1;
+                    }
+                }
+                Event::Eof => break,
+                event => {
+                    if let Some((_, content)) = &mut current {
+                        push_escaped_text(&event, content);
+                    }
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The text of the first non-blank metadata element named `name`, e.g. `creator` for the
+    /// first `dc:creator`.
+    pub fn first_metadata(
+        &self,
+        files: &dyn BookFiles,
+        name: &str,
+    ) -> Result<Option<String>, EpubTranslatorError> {
+        let content = files.read_to_string(&self.path)?;
+        let mut reader = Reader::from_str(&content);
+        let mut in_metadata = false;
+        // Escaped content of the element being read
+        let mut current: Option<String> = None;
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(element) if element.local_name().as_ref() == "metadata" => {
+                    in_metadata = true;
+                }
+                Event::End(element) if element.local_name().as_ref() == "metadata" => {
+                    in_metadata = false;
+                }
+                Event::Start(element) if in_metadata && element.local_name().as_ref() == name => {
+                    current = Some(String::new());
+                }
+                Event::End(_) => {
+                    if let Some(content) = current.take() {
+                        let text = unescape(&content)?.trim().to_string();
+                        if !text.is_empty() {
+                            return Ok(Some(text));
+                        }
+                    }
+                }
+                Event::Eof => return Ok(None),
+                event => {
+                    if let Some(content) = &mut current {
+                        push_escaped_text(&event, content);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces translatable metadata entries with their translation, by `MetadataEntry::index`.
+    ///
+    /// The original of the first title is kept, with the source language: as an
+    /// `alternate-script` meta refining the title in EPUB 3, as `opf:alt-rep` and
+    /// `opf:alt-rep-lang` attributes in EPUB 2.
+    pub fn set_metadata_translations(
+        &self,
+        files: &mut dyn BookFiles,
+        translations: &HashMap<usize, String>,
+    ) -> Result<(), EpubTranslatorError> {
+        let entries = self.translatable_metadata(files)?;
+        let epub3 = self.version.starts_with('3');
+        let source_lang = self.language.clone().unwrap_or_default();
+        let mut in_metadata = false;
+        let mut index = 0;
+        let mut replacing = false;
+        // Id of the translated title and its original, until written in EPUB 3
+        let mut original_title: Option<(String, String)> = None;
+        let mut title_kept = false;
+
+        rewrite_xml(files, &self.path, |event| match event {
+            Event::Start(element) if element.local_name().as_ref() == "metadata" => {
+                in_metadata = true;
+                vec![metadata_start(element, epub3)]
+            }
+            Event::Start(element)
+                if in_metadata
+                    && TRANSLATABLE_METADATA.contains(&element.local_name().as_ref()) =>
+            {
+                let position = index;
+                index += 1;
+                let Some(translation) = translations.get(&position) else {
+                    return vec![Event::Start(element)];
+                };
+                replacing = true;
+                let text = Event::Text(BytesText::new(translation).into_owned());
+
+                let original = entries.iter().find(|entry| entry.index == position);
+                let element = match original {
+                    Some(original) if original.name == "title" && !title_kept => {
+                        title_kept = true;
+                        if epub3 {
+                            let id = attributes(&element)
+                                .ok()
+                                .and_then(|mut attributes| attributes.remove("id"));
+                            let element = match id {
+                                Some(_) => element.into_owned(),
+                                None => with_attributes(&element, &[("id", TITLE_ID)]),
+                            };
+                            let id = id.unwrap_or_else(|| TITLE_ID.to_string());
+                            original_title = Some((id, original.text.clone()));
+                            element
+                        } else {
+                            with_attributes(
+                                &element,
+                                &[
+                                    ("opf:alt-rep", &original.text),
+                                    ("opf:alt-rep-lang", &source_lang),
+                                ],
+                            )
+                        }
+                    }
+                    _ => element.into_owned(),
+                };
+                vec![Event::Start(element), text]
+            }
+            Event::End(element) if replacing => {
+                replacing = false;
+                vec![Event::End(element)]
+            }
+            _ if replacing => Vec::new(),
+            Event::End(element) if element.local_name().as_ref() == "metadata" => {
+                in_metadata = false;
+                let Some((id, original)) = original_title.take() else {
+                    return vec![Event::End(element)];
+                };
+                let refines = format!("#{}", id);
+                let mut meta = vec![
+                    ("refines", refines.as_str()),
+                    ("property", "alternate-script"),
+                ];
+                if !source_lang.is_empty() {
+                    meta.push(("xml:lang", &source_lang));
+                }
+                let entry = text_element("meta", &meta, &original);
+                append_to_metadata(vec![entry], element)
+            }
+            event => vec![event],
+        })
+    }
+
+    /// Labels the publication as machine translated: a `dc:contributor` with the `trl`
+    /// (translator) role, the source language and the time of the translation are added to
+    /// the metadata. `source_lang` defaults to the `dc:language` of the package.
+    pub fn add_provenance(
+        &self,
+        files: &mut dyn BookFiles,
+        source_lang: Option<&str>,
+        timestamp: &str,
+    ) -> Result<(), EpubTranslatorError> {
+        let epub3 = self.version.starts_with('3');
+        let source_lang = source_lang
+            .or(self.language.as_deref())
+            .unwrap_or_default()
+            .to_string();
+
+        let mut entries: Vec<Vec<Event>> = Vec::new();
+        if epub3 {
+            entries.push(text_element(
+                "dc:contributor",
+                &[("id", PROVENANCE_ID)],
+                MACHINE_TRANSLATION,
+            ));
+            let refines = format!("#{}", PROVENANCE_ID);
+            entries.push(text_element(
+                "meta",
+                &[
+                    ("refines", &refines),
+                    ("property", "role"),
+                    ("scheme", "marc:relators"),
+                ],
+                "trl",
+            ));
+        } else {
+            entries.push(text_element(
+                "dc:contributor",
+                &[("opf:role", "trl")],
+                MACHINE_TRANSLATION,
+            ));
+        }
+        for (name, content) in [
+            ("epub-translator:source-language", source_lang.as_str()),
+            ("epub-translator:translated", timestamp),
+        ] {
+            let meta =
+                BytesStart::new("meta").with_attributes([("name", name), ("content", content)]);
+            entries.push(vec![Event::Empty(meta.into_owned())]);
+        }
+
+        let mut in_metadata = false;
+        rewrite_xml(files, &self.path, |event| match event {
+            Event::Start(element) if element.local_name().as_ref() == "metadata" => {
+                in_metadata = true;
+                vec![metadata_start(element, epub3)]
+            }
+            Event::End(element) if in_metadata && element.local_name().as_ref() == "metadata" => {
+                in_metadata = false;
+                append_to_metadata(std::mem::take(&mut entries), element)
+            }
+            event => vec![event],
+        })
+    }
+}
+
+// Start of the `metadata` element. EPUB 2 entries use `opf:` attributes, the prefix is declared
+// there if needed.
+fn metadata_start(element: BytesStart, epub3: bool) -> Event {
+    if epub3
+        || element
+            .try_get_attribute("xmlns:opf")
+            .ok()
+            .flatten()
+            .is_some()
+    {
+        return Event::Start(element);
+    }
+    let opf = ("xmlns:opf", "http://www.idpf.org/2007/opf");
+    Event::Start(with_attributes(&element, &[opf]))
+}
+
+// Events closing the `metadata` element with `entries` appended, one per line.
+fn append_to_metadata<'a>(entries: Vec<Vec<Event<'a>>>, end: BytesEnd<'a>) -> Vec<Event<'a>> {
+    let mut events = Vec::new();
+    for entry in entries {
+        events.push(Event::Text(BytesText::new("  ")));
+        events.extend(entry);
+        events.push(Event::Text(BytesText::new("\n  ")));
+    }
+    events.push(Event::End(end));
+    events
+}
+
+// Events of an element holding only text.
+fn text_element(name: &str, attributes: &[(&str, &str)], text: &str) -> Vec<Event<'static>> {
+    let start = BytesStart::new(name).with_attributes(attributes.iter().copied());
+    vec![
+        Event::Start(start.into_owned()),
+        Event::Text(BytesText::new(text).into_owned()),
+        Event::End(BytesEnd::new(name.to_string())),
+    ]
+}
+
+// Copy of `element` with `values` set, replacing attributes of the same name.
+fn with_attributes(element: &BytesStart, values: &[(&str, &str)]) -> BytesStart<'static> {
+    let mut rewritten = BytesStart::new(element.name().as_ref().to_string());
+    for attribute in element.attributes().flatten() {
+        if let Some(value) = values
+            .iter()
+            .find(|(name, _)| *name == attribute.key.as_ref())
+        {
+            rewritten.push_attribute(*value);
+        } else {
+            rewritten.push_attribute(Attribute {
+                key: attribute.key,
+                value: attribute.value,
+            });
+        }
+    }
+    for value in values {
+        if element.try_get_attribute(value.0).ok().flatten().is_none() {
+            rewritten.push_attribute(*value);
+        }
+    }
+    rewritten.into_owned()
+}
+
+// Rewrites the XML file at `path` event by event, each event is replaced by the events returned
+// by `edit`. Events passed through are written back as read, the rest of the file is kept byte
+// for byte.
+pub(super) fn rewrite_xml(
+    files: &mut dyn BookFiles,
+    path: &Path,
+    mut edit: impl FnMut(Event) -> Vec<Event>,
+) -> Result<(), EpubTranslatorError> {
+    let content = files.read_to_string(path)?;
+    let mut reader = Reader::from_str(&content);
+    let mut writer = Writer::new(Vec::new());
+    loop {
+        match reader.read_event()? {
+            Event::Eof => break,
+            event => {
+                for event in edit(event) {
+                    writer.write_event(event)?;
+                }
+            }
+        }
+    }
+    files.write(path, writer.into_inner())?;
+    Ok(())
+}
+
+// Appends the text of a text or reference event to `content`, escaped as in the source.
+pub(super) fn push_escaped_text(event: &Event, content: &mut String) {
+    match event {
+        Event::Text(text) => content.push_str(text),
+        Event::GeneralRef(reference) => {
+            content.push('&');
+            content.push_str(reference);
+            content.push(';');
+        }
+        _ => {}
+    }
+}
+
+// `full-path` of the first package document rootfile of a container file.
+fn find_rootfile(container: &str) -> Result<Option<String>, EpubTranslatorError> {
+    let mut reader = Reader::from_str(container);
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) | Event::Empty(element)
+                if element.local_name().as_ref() == "rootfile" =>
+            {
+                let mut attributes = attributes(&element)?;
+                let media_type = attributes.get("media-type").map(String::as_str);
+                if media_type.is_none() || media_type == Some(PACKAGE_MEDIA_TYPE) {
+                    if let Some(path) = attributes.remove("full-path") {
+                        return Ok(Some(path));
+                    }
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+// Whitespace separated values of a `properties` attribute.
+fn split_properties(properties: Option<&String>) -> Vec<String> {
+    properties
+        .map(|properties| properties.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+// Attribute values of `element` by local name.
+pub(super) fn attributes(
+    element: &BytesStart,
+) -> Result<HashMap<String, String>, EpubTranslatorError> {
+    let mut attributes = HashMap::new();
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        let name = attribute.key.local_name().as_ref().to_string();
+        attributes.insert(
+            name,
+            attribute
+                .normalized_value(XmlVersion::default())?
+                .into_owned(),
+        );
+    }
+    Ok(attributes)
+}
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use zip::{CompressionMethod, ZipArchive};
+
+use super::{BookArchive, BookFiles, Package, UnzipLimits, CONTAINER_PATH, NCX_MEDIA_TYPE};
+use crate::error::EpubTranslatorError;
+
+/// Content of the `mimetype` entry of every EPUB.
+pub const EPUB_MIMETYPE: &str = "application/epub+zip";
+
+/// Manifest media types checked for XML well-formedness.
+const XML_MEDIA_TYPES: &[&str] = &[super::XHTML_MEDIA_TYPE, NCX_MEDIA_TYPE, "image/svg+xml"];
+
+/// A structural problem of an EPUB, found by `validate_epub`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    /// The file at fault, relative to the root of the book. `None` for the archive itself.
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn new(path: Option<&Path>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.map(Path::to_path_buf),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path.display(), self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Checks the structure of the EPUB at `path`, see `validate_epub_bytes`.
+pub fn validate_epub(path: &Path) -> Result<Vec<ValidationIssue>, EpubTranslatorError> {
+    validate_epub_bytes(fs::read(path)?)
+}
+
+/// Checks the structure of an EPUB archive: the `mimetype` entry, then everything
+/// `validate_book` checks. An empty list means no problem was found. Archives that cannot be
+/// read at all are errors.
+///
+/// This is no replacement for epubcheck, only the mistakes that make reading systems reject a
+/// book are looked for.
+pub fn validate_epub_bytes(bytes: Vec<u8>) -> Result<Vec<ValidationIssue>, EpubTranslatorError> {
+    let mut issues = check_mimetype(&bytes)?;
+    let book = BookArchive::read(bytes, &UnzipLimits::default())?;
+    issues.extend(validate_book(&book));
+    Ok(issues)
+}
+
+// The `mimetype` entry must come first, stored uncompressed, so the archive can be
+// recognized from its first bytes.
+fn check_mimetype(bytes: &[u8]) -> Result<Vec<ValidationIssue>, EpubTranslatorError> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let path = Some(Path::new("mimetype"));
+    if archive.is_empty() {
+        return Ok(vec![ValidationIssue::new(None, "the archive is empty")]);
+    }
+    let mut first = archive.by_index(0)?;
+    if first.name() != "mimetype" {
+        let message = format!(
+            "the first entry is `{}` instead of `mimetype`",
+            first.name()
+        );
+        return Ok(vec![ValidationIssue::new(None, message)]);
+    }
+
+    let mut issues = Vec::new();
+    if first.compression() != CompressionMethod::Stored {
+        issues.push(ValidationIssue::new(path, "the entry is compressed"));
+    }
+    let mut content = Vec::new();
+    first.read_to_end(&mut content)?;
+    if content != EPUB_MIMETYPE.as_bytes() {
+        let message = format!("the content is not `{}`", EPUB_MIMETYPE);
+        issues.push(ValidationIssue::new(path, message));
+    }
+    Ok(issues)
+}
+
+/// Checks the container file, the manifest and spine of the package document, and the XML
+/// well-formedness of the package, content documents and NCX.
+pub fn validate_book(files: &dyn BookFiles) -> Vec<ValidationIssue> {
+    let container = Path::new(CONTAINER_PATH);
+    if !files.is_file(container) {
+        return vec![ValidationIssue::new(Some(container), "missing")];
+    }
+    let mut issues = Vec::new();
+    check_xml(files, container, &mut issues);
+    let package = match Package::open(files) {
+        Ok(package) => package,
+        Err(error) => {
+            issues.push(ValidationIssue::new(Some(container), error.to_string()));
+            return issues;
+        }
+    };
+    check_xml(files, &package.path, &mut issues);
+    let opf = Some(package.path.as_path());
+
+    let mut ids = HashSet::new();
+    for item in &package.manifest {
+        if !ids.insert(item.id.as_str()) {
+            let message = format!("duplicate manifest id `{}`", item.id);
+            issues.push(ValidationIssue::new(opf, message));
+        }
+        // Remote resources are allowed for audio, video and fonts
+        if item.href.contains("://") {
+            continue;
+        }
+        let path = package.item_path(item);
+        if !files.is_file(&path) {
+            let message = format!("manifest item `{}` points to a missing file", item.id);
+            issues.push(ValidationIssue::new(Some(&path), message));
+        } else if XML_MEDIA_TYPES.contains(&item.media_type.as_str()) {
+            check_xml(files, &path, &mut issues);
+        }
+    }
+
+    if package.spine.is_empty() {
+        issues.push(ValidationIssue::new(opf, "the spine is empty"));
+    }
+    for item in &package.spine {
+        if !ids.contains(item.idref.as_str()) {
+            let message = format!("spine item `{}` is not in the manifest", item.idref);
+            issues.push(ValidationIssue::new(opf, message));
+        }
+    }
+    issues
+}
+
+fn check_xml(files: &dyn BookFiles, path: &Path, issues: &mut Vec<ValidationIssue>) {
+    let result = match files.read_to_string(path) {
+        Ok(content) => well_formed(&content),
+        Err(error) => Err(error.to_string()),
+    };
+    if let Err(message) = result {
+        issues.push(ValidationIssue::new(Some(path), message));
+    }
+}
+
+// Checks that `content` is a well-formed XML document. Entity references are not resolved,
+// XHTML documents may declare theirs in a DTD.
+fn well_formed(content: &str) -> Result<(), String> {
+    let mut reader = Reader::from_str(content);
+    let mut open: Vec<String> = Vec::new();
+    let mut has_root = false;
+    let at = |position: u64| {
+        let end = (position as usize).min(content.len());
+        let line = content.as_bytes()[..end]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count()
+            + 1;
+        format!("line {}", line)
+    };
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|error| format!("{}: {}", at(reader.error_position()), error))?;
+        match &event {
+            Event::Start(element) | Event::Empty(element) => {
+                if open.is_empty() && has_root {
+                    return Err(format!(
+                        "{}: second root element",
+                        at(reader.buffer_position())
+                    ));
+                }
+                has_root = true;
+                for attribute in element.attributes() {
+                    attribute
+                        .map_err(|error| format!("{}: {}", at(reader.buffer_position()), error))?;
+                }
+                if let Event::Start(element) = &event {
+                    open.push(element.name().as_ref().to_string());
+                }
+            }
+            Event::End(_) => {
+                open.pop();
+            }
+            Event::Text(text) if open.is_empty() && !text.trim().is_empty() => {
+                return Err(format!(
+                    "{}: text outside of the root element",
+                    at(reader.buffer_position())
+                ));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    match open.last() {
+        Some(name) => Err(format!("element `{}` is not closed", name)),
+        None if !has_root => Err("no root element".to_string()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+
+use reqwest::header::InvalidHeaderValue;
+use thiserror::Error;
+
+use crate::config::ConfigError;
+use crate::deepl::QUOTA_EXCEEDED_STATUS;
+use crate::epub::{EncryptedEpubError, FixedLayoutError};
+use crate::languages::SameLanguageError;
+
+/// Errors of the library, by cause: the book, the provider or the options of the run.
+///
+/// A cancelled run is not an error: what is translated by then is still written, see
+/// `TranslationReport::was_cancelled`. Neither are units whose translation failed, they are
+/// listed in `TranslationReport::failures`.
+#[derive(Debug, Error)]
+pub enum EpubTranslatorError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The EPUB is not a readable ZIP archive.
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    /// A document of the book, or a response of the API, that cannot be parsed.
+    #[error("{0}")]
+    Parse(String),
+    /// A book that is not a valid or safe EPUB, e.g. with entries outside of it.
+    #[error("{0}")]
+    InvalidEpub(String),
+    #[error(transparent)]
+    Encrypted(#[from] EncryptedEpubError),
+    #[error(transparent)]
+    FixedLayout(#[from] FixedLayoutError),
+    #[error(transparent)]
+    SameLanguage(#[from] SameLanguageError),
+    /// A translation that cannot be written back into its document, e.g. with a placeholder
+    /// missing.
+    #[error("{0}")]
+    InvalidTranslation(String),
+    /// The API could not be reached.
+    #[error(transparent)]
+    Network(reqwest::Error),
+    /// An error status returned by the API, e.g. 403 when the key is rejected. `message` is the
+    /// body of the response, DeepL explains the error there.
+    #[error("DeepL API error {status}: {message}")]
+    Provider { status: u16, message: String },
+    /// The character quota of the API key is exhausted, retrying is pointless.
+    #[error("DeepL API error {}: {message}", QUOTA_EXCEEDED_STATUS)]
+    QuotaExceeded { message: String },
+    /// A terms, glossary or configuration file that cannot be read.
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// Options that cannot be used, e.g. a workdir that is not empty.
+    #[error("{0}")]
+    InvalidOptions(String),
+    /// The workdir or the output cannot fit on their filesystem, found before the book is
+    /// extracted.
+    #[error(
+        "not enough space in {}: {needed} bytes needed, {available} available",
+        path.display()
+    )]
+    NoSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+    /// A run on a workdir failed, the workdir is kept to find out why.
+    #[error("{source} (workdir kept at {})", workdir.display())]
+    WorkdirKept {
+        workdir: PathBuf,
+        source: Box<EpubTranslatorError>,
+    },
+}
+
+impl EpubTranslatorError {
+    /// The cause of the error when it is kept in a workdir, the error itself otherwise.
+    pub fn cause(&self) -> &Self {
+        match self {
+            Self::WorkdirKept { source, .. } => source.cause(),
+            error => error,
+        }
+    }
+}
+
+impl From<reqwest::Error> for EpubTranslatorError {
+    fn from(error: reqwest::Error) -> Self {
+        // A response that is not the JSON expected
+        if error.is_decode() {
+            return Self::Parse(error.to_string());
+        }
+        Self::Network(error)
+    }
+}
+
+impl From<InvalidHeaderValue> for EpubTranslatorError {
+    fn from(_: InvalidHeaderValue) -> Self {
+        Self::InvalidOptions("the API key has characters not allowed in a header".to_string())
+    }
+}
+
+// Errors of the parsers, only their message is kept.
+macro_rules! parse_error_from {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for EpubTranslatorError {
+                fn from(error: $error) -> Self {
+                    Self::Parse(error.to_string())
+                }
+            }
+        )*
+    };
+}
+
+parse_error_from!(
+    quick_xml::Error,
+    quick_xml::events::attributes::AttrError,
+    quick_xml::encoding::EncodingError,
+    quick_xml::escape::EscapeError,
+    std::str::Utf8Error,
+    std::string::FromUtf8Error,
+    serde_json::Error
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workdir_kept() {
+        let error = EpubTranslatorError::WorkdirKept {
+            workdir: PathBuf::from("work"),
+            source: Box::new(EpubTranslatorError::QuotaExceeded {
+                message: "Quota exceeded".to_string(),
+            }),
+        };
+        assert_eq!(
+            error.to_string(),
+            "DeepL API error 456: Quota exceeded (workdir kept at work)"
+        );
+        assert!(matches!(
+            error.cause(),
+            EpubTranslatorError::QuotaExceeded { .. }
+        ));
+        assert_eq!(
+            EpubTranslatorError::from(quick_xml::escape::EscapeError::UnterminatedEntity(0..1))
+                .to_string(),
+            quick_xml::escape::EscapeError::UnterminatedEntity(0..1).to_string()
+        );
+    }
+}
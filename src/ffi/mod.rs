@@ -0,0 +1,350 @@
+//! C interface of the library, for the apps that cannot link Rust, see
+//! `include/epub_translator.h`.
+//!
+//! A translation runs on its own thread. The app polls its progress, may cancel it, and frees
+//! it once done:
+//!
+//! ```c
+//! EpubTranslation *translation = epub_translator_translate_file(
+//!     "book.epub", "book.es.epub", "ES", "my-key:fx", false, EPUB_TRANSLATOR_PROVIDER_DEEPL);
+//! EpubTranslatorProgress progress;
+//! while (epub_translator_poll(translation, &progress) == EPUB_TRANSLATOR_STATE_RUNNING) {
+//!     render(progress.translated, progress.total);
+//! }
+//! epub_translator_free(translation);
+//! ```
+
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::deepl::models::DeepLConfiguration;
+use crate::options::TranslateOptions;
+use crate::progress::{ProgressEvent, ProgressSink};
+use crate::providers::Provider;
+
+/// Where the translations come from, see `Provider`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EpubTranslatorProvider {
+    Deepl,
+    Identity,
+    Pseudo,
+}
+
+/// Where a translation stands, returned by `epub_translator_poll`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EpubTranslatorState {
+    Running,
+    /// The translated EPUB is written, units that failed keep their original text.
+    Finished,
+    /// Nothing is written, see `epub_translator_error`.
+    Failed,
+}
+
+/// The counts of a translation so far, see `TranslationReport`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EpubTranslatorProgress {
+    /// Units of the book, 0 until its documents are parsed.
+    pub total: usize,
+    pub translated: usize,
+    pub failed: usize,
+}
+
+/// A translation started by `epub_translator_translate_file`, opaque to C.
+pub struct EpubTranslation {
+    cancel: CancellationToken,
+    shared: Arc<Mutex<Shared>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    progress: EpubTranslatorProgress,
+    // `None` while the translation runs
+    outcome: Option<Result<(), String>>,
+}
+
+struct SharedProgress(Arc<Mutex<Shared>>);
+
+impl ProgressSink for SharedProgress {
+    fn on_event(&self, event: ProgressEvent) {
+        let mut shared = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match event {
+            ProgressEvent::Started { total } => shared.progress.total = total,
+            ProgressEvent::NodeTranslated { .. } => shared.progress.translated += 1,
+            ProgressEvent::NodeFailed { .. } => shared.progress.failed += 1,
+            _ => {}
+        }
+    }
+}
+
+// A borrowed C string, `None` when null or not UTF-8.
+unsafe fn string_arg(value: *const c_char) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    CStr::from_ptr(value).to_str().ok().map(str::to_string)
+}
+
+// The provider of an `EpubTranslatorProvider` value, `None` for any other value.
+fn provider_arg(value: u32) -> Option<Provider> {
+    [
+        (EpubTranslatorProvider::Deepl, Provider::DeepL),
+        (EpubTranslatorProvider::Identity, Provider::Identity),
+        (EpubTranslatorProvider::Pseudo, Provider::Pseudo),
+    ]
+    .into_iter()
+    .find(|(provider, _)| *provider as u32 == value)
+    .map(|(_, provider)| provider)
+}
+
+/// Starts translating `input_file` into `output_file`, in `target_lang`, on a new thread.
+///
+/// `auth_key` is the DeepL key, `pro` selects the API of the paid plans. It may be null with a
+/// local provider. `provider` is an `EpubTranslatorProvider`, taken as an integer since C may
+/// pass any value. Returns null when an argument is null or not UTF-8, or on an unknown
+/// provider.
+///
+/// # Safety
+///
+/// The strings must be null or valid C strings, they are copied before the call returns.
+#[no_mangle]
+pub unsafe extern "C" fn epub_translator_translate_file(
+    input_file: *const c_char,
+    output_file: *const c_char,
+    target_lang: *const c_char,
+    auth_key: *const c_char,
+    pro: bool,
+    provider: u32,
+) -> *mut EpubTranslation {
+    let (Some(input_file), Some(output_file), Some(target_lang), Some(provider)) = (
+        string_arg(input_file),
+        string_arg(output_file),
+        string_arg(target_lang),
+        provider_arg(provider),
+    ) else {
+        return std::ptr::null_mut();
+    };
+    let auth_key = string_arg(auth_key);
+
+    let cancel = CancellationToken::new();
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    let options = TranslateOptions {
+        target_lang,
+        configurations: auth_key
+            .map(|key| Arc::new(DeepLConfiguration::new(key, pro)))
+            .into_iter()
+            .collect(),
+        provider,
+        progress: Arc::new(SharedProgress(shared.clone())),
+        cancel: cancel.clone(),
+        ..Default::default()
+    };
+
+    let thread_shared = shared.clone();
+    let thread = std::thread::spawn(move || {
+        let (input_file, output_file) = (PathBuf::from(input_file), PathBuf::from(output_file));
+        // A panic must end the translation too, the app would poll it forever
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            let runtime = tokio::runtime::Runtime::new()?;
+            runtime.block_on(crate::translate_epub_with(
+                &input_file,
+                &output_file,
+                &options,
+            ))
+        }));
+        let outcome = match outcome {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(error)) => Err(error.to_string()),
+            Err(_) => Err("the translation panicked".to_string()),
+        };
+        let mut shared = thread_shared.lock().unwrap_or_else(PoisonError::into_inner);
+        shared.outcome = Some(outcome);
+    });
+
+    Box::into_raw(Box::new(EpubTranslation {
+        cancel,
+        shared,
+        thread: Some(thread),
+    }))
+}
+
+/// Copies the counts of `translation` so far into `progress`, which may be null, and returns
+/// whether it is still running.
+///
+/// # Safety
+///
+/// `translation` must come from `epub_translator_translate_file` and not be freed yet.
+/// `progress` must be null or point to an `EpubTranslatorProgress`.
+#[no_mangle]
+pub unsafe extern "C" fn epub_translator_poll(
+    translation: *const EpubTranslation,
+    progress: *mut EpubTranslatorProgress,
+) -> EpubTranslatorState {
+    let shared = (*translation)
+        .shared
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if !progress.is_null() {
+        *progress = shared.progress;
+    }
+    match shared.outcome {
+        None => EpubTranslatorState::Running,
+        Some(Ok(())) => EpubTranslatorState::Finished,
+        Some(Err(_)) => EpubTranslatorState::Failed,
+    }
+}
+
+/// Stops sending requests. What is already translated is still written, the translation ends
+/// as finished.
+///
+/// # Safety
+///
+/// `translation` must come from `epub_translator_translate_file` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn epub_translator_cancel(translation: *const EpubTranslation) {
+    (*translation).cancel.cancel();
+}
+
+/// The error of a failed translation, null otherwise. The string belongs to the caller, who
+/// frees it with `epub_translator_string_free`.
+///
+/// # Safety
+///
+/// `translation` must come from `epub_translator_translate_file` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn epub_translator_error(translation: *const EpubTranslation) -> *mut c_char {
+    match &(*translation)
+        .shared
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .outcome
+    {
+        // An error message never has a null byte, but it must not panic across the boundary
+        Some(Err(error)) => CString::new(error.replace('\0', ""))
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Frees a string returned by the library.
+///
+/// # Safety
+///
+/// `string` must be null or come from the library, and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn epub_translator_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Frees a translation. One still running is cancelled first, and awaited.
+///
+/// # Safety
+///
+/// `translation` must be null or come from `epub_translator_translate_file`, and not be freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn epub_translator_free(translation: *mut EpubTranslation) {
+    if translation.is_null() {
+        return;
+    }
+    let mut translation = Box::from_raw(translation);
+    translation.cancel.cancel();
+    if let Some(thread) = translation.thread.take() {
+        let _ = thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_file() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        crate::epub::zip_folder_to_epub(std::path::Path::new("tests/data/opf_book"), &epub_path)?;
+        let input = CString::new(epub_path.to_str().unwrap())?;
+        let output = CString::new(temp_dir.path().join("out.epub").to_str().unwrap())?;
+        let missing = CString::new(temp_dir.path().join("missing.epub").to_str().unwrap())?;
+        let lang = CString::new("ES")?;
+
+        unsafe {
+            let translation = epub_translator_translate_file(
+                input.as_ptr(),
+                output.as_ptr(),
+                lang.as_ptr(),
+                std::ptr::null(),
+                false,
+                EpubTranslatorProvider::Pseudo as u32,
+            );
+            let mut progress = EpubTranslatorProgress::default();
+            while epub_translator_poll(translation, &mut progress) == EpubTranslatorState::Running {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            assert!(progress.total > 0);
+            assert_eq!(progress.translated, progress.total);
+            assert!(epub_translator_error(translation).is_null());
+            epub_translator_free(translation);
+
+            let translation = epub_translator_translate_file(
+                missing.as_ptr(),
+                output.as_ptr(),
+                lang.as_ptr(),
+                std::ptr::null(),
+                false,
+                EpubTranslatorProvider::Pseudo as u32,
+            );
+            while epub_translator_poll(translation, std::ptr::null_mut())
+                == EpubTranslatorState::Running
+            {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            let error = epub_translator_error(translation);
+            assert!(!error.is_null());
+            epub_translator_string_free(error);
+            epub_translator_free(translation);
+
+            assert!(epub_translator_translate_file(
+                std::ptr::null(),
+                output.as_ptr(),
+                lang.as_ptr(),
+                std::ptr::null(),
+                false,
+                EpubTranslatorProvider::Pseudo as u32,
+            )
+            .is_null());
+            assert!(epub_translator_translate_file(
+                input.as_ptr(),
+                output.as_ptr(),
+                lang.as_ptr(),
+                std::ptr::null(),
+                false,
+                3,
+            )
+            .is_null());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_header() {
+        let generated = concat!(env!("OUT_DIR"), "/epub_translator.h");
+        assert!(
+            include_str!(concat!(env!("OUT_DIR"), "/epub_translator.h"))
+                == include_str!("../../include/epub_translator.h"),
+            "include/epub_translator.h is stale, copy {} over it",
+            generated
+        );
+    }
+}
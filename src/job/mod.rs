@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, ConfigError};
+use crate::deepl::models::AuthKey;
+use crate::providers::Provider;
+
+/// A whole translation job, read from a JSON file with `--job`: the books, where their
+/// translations go, and the settings of the run. Settings left out keep the ones of the
+/// configuration files and the defaults, flags of the command line take precedence.
+///
+/// ```json
+/// {
+///   "inputs": ["books/moby-dick.epub", "books/walden.epub"],
+///   "output": "out/{stem}.{lang}.epub",
+///   "target_langs": ["ES", "FR"],
+///   "provider": { "name": "deepl", "api_keys": ["my-key:fx"], "parallel": 100 },
+///   "skip": { "skip_tags": ["pre", "code"], "exclude_selectors": [".poem"] },
+///   "budget": { "max_chars": 500000 }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// The EPUBs translated, each one into every target language.
+    pub inputs: Vec<PathBuf>,
+    /// Output template of the translations, see `OutputTemplate`. `{dir}/{stem}.{lang}.epub`
+    /// when not given.
+    pub output: Option<String>,
+    pub target_langs: Vec<String>,
+    pub source_lang: Option<String>,
+    #[serde(default)]
+    pub provider: JobProvider,
+    #[serde(default)]
+    pub skip: JobSkipRules,
+    #[serde(default)]
+    pub budget: JobBudget,
+}
+
+/// Where the translations of a job come from.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobProvider {
+    pub name: Option<Provider>,
+    /// DeepL API keys, the requests are balanced across them.
+    pub api_keys: Option<Vec<AuthKey>>,
+    /// Maximum number of requests sent to the API at the same time.
+    pub parallel: Option<usize>,
+}
+
+/// What a job leaves untranslated, with the syntax of the matching flags.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobSkipRules {
+    pub notranslate_classes: Option<Vec<String>>,
+    pub skip_tags: Option<Vec<String>>,
+    pub exclude_selectors: Option<Vec<String>>,
+    pub translate_attributes: Option<Vec<String>>,
+    /// Chapters translated, by position in the spine, e.g. `["1-5", "12"]`.
+    pub chapters: Option<Vec<String>>,
+    /// Glob patterns of the documents translated, relative to the root of the book.
+    pub include: Option<Vec<String>>,
+    /// Glob patterns of the documents left untouched.
+    pub exclude: Option<Vec<String>>,
+}
+
+/// How much of each book a job translates.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobBudget {
+    /// Billable characters sent per book and language, retries included.
+    pub max_chars: Option<usize>,
+    /// Share of the segments translated, between 0 and 1.
+    pub sample: Option<f64>,
+    pub sample_seed: Option<u64>,
+}
+
+impl Job {
+    /// Reads the job file at `path`. Unknown settings are errors, like in configuration files.
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let error = |message: String| ConfigError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let content = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        let job: Self = serde_json::from_str(&content).map_err(|e| error(e.to_string()))?;
+        if job.inputs.is_empty() {
+            return Err(error("no inputs".to_string()));
+        }
+        Ok(job)
+    }
+
+    /// The settings of the job that configuration files have too, to be merged over them.
+    pub fn config(&self) -> Config {
+        Config {
+            api_keys: self.provider.api_keys.clone(),
+            target_lang: (!self.target_langs.is_empty()).then(|| self.target_langs.join(",")),
+            source_lang: self.source_lang.clone(),
+            parallel: self.provider.parallel,
+            formality: None,
+            notranslate_classes: self.skip.notranslate_classes.clone(),
+            skip_tags: self.skip.skip_tags.clone(),
+            exclude_selectors: self.skip.exclude_selectors.clone(),
+            translate_attributes: self.skip.translate_attributes.clone(),
+            glossary: None,
+            keep_terms: None,
+            rates: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_job() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("job.json");
+        fs::write(
+            &path,
+            r#"{
+                "inputs": ["a.epub", "b.epub"],
+                "output": "out/{stem}.{lang}.epub",
+                "target_langs": ["ES", "FR"],
+                "provider": { "name": "pseudo", "parallel": 8 },
+                "skip": { "skip_tags": ["aside"], "chapters": ["1-3"] },
+                "budget": { "max_chars": 1000 }
+            }"#,
+        )?;
+        let job = Job::read(&path)?;
+        assert_eq!(job.inputs, vec![PathBuf::from("a.epub"), "b.epub".into()]);
+        assert_eq!(job.provider.name, Some(Provider::Pseudo));
+        assert_eq!(job.skip.skip_tags, Some(vec!["aside".to_string()]));
+        assert_eq!(job.budget.max_chars, Some(1000));
+        assert_eq!(job.source_lang, None);
+        let config = Config::default().merge(job.config());
+        assert_eq!(config.target_lang.as_deref(), Some("ES,FR"));
+        assert_eq!(config.parallel, Some(8));
+
+        // Generated jobs read back the same
+        fs::write(&path, serde_json::to_string(&job)?)?;
+        assert_eq!(Job::read(&path)?, job);
+
+        fs::write(&path, r#"{ "inputs": ["a.epub"], "target_lang": "ES" }"#)?;
+        assert!(Job::read(&path)
+            .unwrap_err()
+            .message
+            .contains("target_lang"));
+        fs::write(&path, r#"{ "inputs": [], "target_langs": ["ES"] }"#)?;
+        assert_eq!(Job::read(&path).unwrap_err().message, "no inputs");
+        Ok(())
+    }
+}
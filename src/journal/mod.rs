@@ -0,0 +1,85 @@
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use web_time::Instant;
+
+use crate::error::EpubTranslatorError;
+
+/// Name of the journal file inside the workdir.
+pub const JOURNAL_FILE_NAME: &str = "journal.log";
+
+/// Append-only record of the pipeline transitions of a run.
+///
+/// Each line is `<elapsed ms>\t<event>\t<details>` and is written straight to the file,
+/// so the journal is complete up to the last transition even if the process dies.
+pub struct Journal {
+    file: Option<Mutex<File>>,
+    path: Option<PathBuf>,
+    start: Instant,
+}
+
+impl Journal {
+    /// Creates (or appends to) the journal in the workdir `dir`.
+    pub fn create(dir: &Path) -> Result<Self, EpubTranslatorError> {
+        let path = dir.join(JOURNAL_FILE_NAME);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            file: Some(Mutex::new(file)),
+            path: Some(path),
+            start: Instant::now(),
+        })
+    }
+
+    /// A journal that records nothing.
+    pub fn disabled() -> Self {
+        Self {
+            file: None,
+            path: None,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Appends one transition. Journaling is best effort and never fails the run.
+    pub fn record(&self, event: &str, details: impl Display) {
+        if let Some(file) = &self.file {
+            let line = format!(
+                "{}\t{}\t{}\n",
+                self.start.elapsed().as_millis(),
+                event,
+                details
+            );
+            if let Ok(mut file) = file.lock() {
+                let _ = file.write_all(line.as_bytes());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_journal_appends_lines() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let journal = Journal::create(temp_dir.path())?;
+        journal.record("file_parsed", "chapter001.xhtml units=12");
+        journal.record("segment_dispatched", "id=3 attempt=0");
+
+        let content = fs::read_to_string(temp_dir.path().join(JOURNAL_FILE_NAME))?;
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("\tfile_parsed\tchapter001.xhtml units=12"));
+        assert!(lines[1].ends_with("\tsegment_dispatched\tid=3 attempt=0"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+#[cfg(feature = "native")]
+use std::fs;
+#[cfg(feature = "native")]
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[cfg(feature = "native")]
+use crate::config::ConfigError;
+use crate::deepl::models::AuthKey;
+
+/// Service under which the keys are stored in the OS keyring, each one with its own name.
+pub const KEYRING_SERVICE: &str = "epub-translator";
+
+/// A DeepL API key, with the share of the requests it gets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKey {
+    pub key: AuthKey,
+    /// Name shown in messages instead of the key.
+    pub label: Option<String>,
+    /// Multiplies the share of the requests the key gets from its remaining quota, 1 by
+    /// default.
+    pub weight: f64,
+}
+
+impl ApiKey {
+    pub fn new(key: impl Into<AuthKey>) -> Self {
+        Self {
+            key: key.into(),
+            label: None,
+            weight: 1.0,
+        }
+    }
+
+    /// The label of the key, or its last characters.
+    pub fn name(&self) -> String {
+        if let Some(label) = &self.label {
+            return label.clone();
+        }
+        format!("key ending in {}", self.key.hint())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    key: Option<String>,
+    /// Name of a key stored in the OS keyring.
+    keyring: Option<String>,
+    label: Option<String>,
+    weight: Option<f64>,
+}
+
+/// Parses a TOML keys file, with a `[[keys]]` table per key: either the `key` itself or the
+/// name under which it is stored in the OS keyring, looked up with `keyring`, and an optional
+/// `label` and `weight`.
+pub fn parse_keys_file(
+    content: &str,
+    keyring: impl Fn(&str) -> Result<String, String>,
+) -> Result<Vec<ApiKey>, String> {
+    let file: KeysFile = toml::from_str(content).map_err(|e| e.message().to_string())?;
+    let mut keys = Vec::new();
+    for (index, entry) in file.keys.into_iter().enumerate() {
+        let error = |message: String| format!("key {}: {}", index + 1, message);
+        let key = match (entry.key, entry.keyring) {
+            (Some(key), None) => key,
+            (None, Some(name)) => keyring(&name).map_err(error)?,
+            _ => return Err(error("expected either `key` or `keyring`".to_string())),
+        };
+        let weight = entry.weight.unwrap_or(1.0);
+        if !(weight.is_finite() && weight > 0.0) {
+            return Err(error(format!("weight {} is not a positive number", weight)));
+        }
+        keys.push(ApiKey {
+            key: key.into(),
+            label: entry.label,
+            weight,
+        });
+    }
+    Ok(keys)
+}
+
+/// Reads the keys file at `path`, see `parse_keys_file`. Keys stored in the OS keyring are
+/// looked up with `keyring_key`.
+#[cfg(feature = "native")]
+pub fn read_keys_file(path: &Path) -> Result<Vec<ApiKey>, ConfigError> {
+    let error = |message: String| ConfigError {
+        path: path.to_path_buf(),
+        message,
+    };
+    let content = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    parse_keys_file(&content, keyring_key).map_err(error)
+}
+
+#[cfg(feature = "native")]
+fn keyring_entry(name: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, name).map_err(|e| format!("keyring entry {}: {}", name, e))
+}
+
+/// The key stored in the OS keyring under `name`.
+#[cfg(feature = "native")]
+pub fn keyring_key(name: &str) -> Result<String, String> {
+    keyring_entry(name)?
+        .get_password()
+        .map_err(|e| format!("keyring entry {}: {}", name, e))
+}
+
+/// Stores `key` in the OS keyring under `name`, replacing the key stored there if any.
+#[cfg(feature = "native")]
+pub fn store_keyring_key(name: &str, key: &str) -> Result<(), String> {
+    keyring_entry(name)?
+        .set_password(key)
+        .map_err(|e| format!("keyring entry {}: {}", name, e))
+}
+
+/// Removes the key stored in the OS keyring under `name`.
+#[cfg(feature = "native")]
+pub fn delete_keyring_key(name: &str) -> Result<(), String> {
+    keyring_entry(name)?
+        .delete_credential()
+        .map_err(|e| format!("keyring entry {}: {}", name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys_file() -> Result<(), String> {
+        let keyring = |name: &str| match name {
+            "work" => Ok("stored:fx".to_string()),
+            _ => Err(format!("keyring entry {}: not found", name)),
+        };
+        let keys = parse_keys_file(
+            "[[keys]]\nkey = \"0123456789:fx\"\n\n\
+             [[keys]]\nkeyring = \"work\"\nlabel = \"Work\"\nweight = 2\n",
+            keyring,
+        )?;
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].weight, 1.0);
+        assert_eq!(keys[0].name(), "key ending in 6789");
+        assert_eq!(
+            keys[1],
+            ApiKey {
+                key: "stored:fx".into(),
+                label: Some("Work".to_string()),
+                weight: 2.0,
+            }
+        );
+
+        let error = parse_keys_file("[[keys]]\nkeyring = \"home\"\n", keyring).unwrap_err();
+        assert_eq!(error, "key 1: keyring entry home: not found");
+        assert!(parse_keys_file("[[keys]]\nlabel = \"a\"\n", keyring).is_err());
+        assert!(parse_keys_file("[[keys]]\nkey = \"a\"\nweight = 0\n", keyring).is_err());
+        Ok(())
+    }
+}
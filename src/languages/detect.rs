@@ -0,0 +1,264 @@
+// Below this, a sample says too little about its language
+const MIN_LETTERS: usize = 50;
+const MIN_STOP_WORDS: usize = 10;
+// How many more stop words of the best language than of the runner-up
+const MIN_MARGIN: f64 = 1.2;
+
+// The most frequent words of the languages written in the Latin script, the ones shared by
+// several languages are told apart by the others.
+const STOP_WORDS: &[(&str, &[&str])] = &[
+    (
+        "EN",
+        &[
+            "the", "and", "of", "to", "is", "was", "that", "with", "for", "his", "her", "he",
+            "she", "it", "not", "but", "you", "have", "had", "this", "which", "would", "from",
+            "they", "were", "been", "what", "there",
+        ],
+    ),
+    (
+        "ES",
+        &[
+            "el", "la", "los", "las", "de", "que", "y", "en", "un", "una", "es", "por", "con",
+            "para", "como", "pero", "más", "su", "se", "del", "al", "lo", "le", "fue", "este",
+            "esta", "había", "muy", "también",
+        ],
+    ),
+    (
+        "FR",
+        &[
+            "le", "la", "les", "de", "des", "du", "et", "est", "un", "une", "que", "qui", "dans",
+            "pour", "pas", "sur", "au", "avec", "il", "elle", "ce", "je", "vous", "nous", "mais",
+            "était", "sont", "aux",
+        ],
+    ),
+    (
+        "DE",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "zu", "den", "mit", "sich",
+            "des", "auf", "dem", "auch", "es", "war", "wie", "ich", "sie", "er", "aber", "noch",
+            "nach", "wird", "wurde", "einen",
+        ],
+    ),
+    (
+        "IT",
+        &[
+            "il", "di", "che", "e", "la", "per", "un", "una", "non", "sono", "del", "della", "in",
+            "con", "gli", "le", "si", "da", "ma", "come", "anche", "più", "nel", "alla", "era",
+            "questo", "lo", "ha",
+        ],
+    ),
+    (
+        "PT",
+        &[
+            "o", "a", "os", "as", "de", "que", "e", "do", "da", "em", "um", "uma", "não", "para",
+            "com", "no", "na", "por", "mais", "se", "dos", "das", "ao", "ele", "ela", "foi", "mas",
+            "é", "são", "também",
+        ],
+    ),
+    (
+        "NL",
+        &[
+            "de", "het", "een", "en", "van", "is", "dat", "niet", "op", "te", "zijn", "met",
+            "voor", "hij", "ze", "maar", "er", "die", "was", "ook", "aan", "bij", "naar", "wat",
+            "nog", "heeft", "worden",
+        ],
+    ),
+    (
+        "PL",
+        &[
+            "i", "w", "nie", "na", "się", "z", "że", "do", "to", "jest", "jak", "ale", "o", "co",
+            "po", "tak", "od", "za", "jego", "przez", "już", "był", "była", "tylko", "może", "ich",
+        ],
+    ),
+    (
+        "SV",
+        &[
+            "och", "att", "det", "som", "en", "på", "är", "av", "för", "med", "till", "den", "har",
+            "de", "inte", "om", "ett", "han", "men", "var", "jag", "sig", "så", "från", "hade",
+            "också", "när",
+        ],
+    ),
+    (
+        "DA",
+        &[
+            "og", "at", "det", "som", "en", "på", "er", "af", "for", "med", "til", "den", "har",
+            "de", "ikke", "om", "et", "han", "men", "var", "jeg", "sig", "så", "fra", "havde",
+            "også", "når", "hvad", "blev", "mig",
+        ],
+    ),
+    (
+        "NB",
+        &[
+            "og", "at", "det", "som", "en", "på", "er", "av", "for", "med", "til", "den", "har",
+            "de", "ikke", "om", "et", "han", "men", "var", "jeg", "seg", "så", "fra", "hadde",
+            "også", "når", "hva", "ble", "meg",
+        ],
+    ),
+    (
+        "FI",
+        &[
+            "ja", "on", "ei", "se", "että", "hän", "oli", "ovat", "mutta", "kun", "niin", "joka",
+            "tämä", "sen", "myös", "kuin", "jos", "mitä", "minä", "hänen", "ole", "vain", "sitä",
+            "siitä", "olen",
+        ],
+    ),
+    (
+        "CS",
+        &[
+            "a", "se", "na", "je", "že", "v", "to", "s", "z", "do", "o", "jako", "ale", "by",
+            "jsem", "jsou", "byl", "byla", "není", "jeho", "také", "už", "jen", "který", "která",
+            "pro",
+        ],
+    ),
+    (
+        "TR",
+        &[
+            "bir", "ve", "bu", "da", "de", "için", "ile", "o", "ne", "çok", "daha", "gibi", "ama",
+            "var", "olan", "her", "kadar", "sonra", "değil", "ben", "onun", "şey", "diye",
+            "olarak",
+        ],
+    ),
+];
+
+/// Detects the language of `text`, e.g. a sample of the units of a book, as a DeepL code
+/// without region: by its script, then by its most frequent words for the languages of the
+/// Latin script. `None` when the text is too short or no language stands out.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let mut letters = 0;
+    let (mut kana, mut han, mut hangul, mut cyrillic) = (0, 0, 0, 0);
+    let (mut ukrainian, mut russian) = (0, 0);
+    let (mut greek, mut arabic, mut hebrew) = (0, 0, 0);
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        match c {
+            '\u{3040}'..='\u{30FF}' => kana += 1,
+            '\u{4E00}'..='\u{9FFF}' => han += 1,
+            '\u{1100}'..='\u{11FF}' | '\u{AC00}'..='\u{D7AF}' => hangul += 1,
+            '\u{0400}'..='\u{04FF}' => {
+                cyrillic += 1;
+                match c {
+                    'і' | 'ї' | 'є' | 'ґ' | 'І' | 'Ї' | 'Є' | 'Ґ' => ukrainian += 1,
+                    'ы' | 'э' | 'Ы' | 'Э' => russian += 1,
+                    _ => {}
+                }
+            }
+            '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => greek += 1,
+            '\u{0600}'..='\u{06FF}' => arabic += 1,
+            '\u{0590}'..='\u{05FF}' => hebrew += 1,
+            _ => {}
+        }
+    }
+    if letters < MIN_LETTERS {
+        return None;
+    }
+    let most = |count: usize| count * 2 > letters;
+    // Japanese mixes its kana with Chinese characters
+    if kana * 20 > letters {
+        return Some("JA");
+    }
+    if most(hangul) {
+        return Some("KO");
+    }
+    if most(han) {
+        return Some("ZH");
+    }
+    if most(cyrillic) {
+        // Bulgarian has neither the letters of Ukrainian nor `ы` and `э`
+        return Some(if ukrainian * 200 > cyrillic {
+            "UK"
+        } else if russian > 0 {
+            "RU"
+        } else {
+            "BG"
+        });
+    }
+    if most(greek) {
+        return Some("EL");
+    }
+    if most(arabic) {
+        return Some("AR");
+    }
+    if most(hebrew) {
+        return Some("HE");
+    }
+
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let mut scores: Vec<(&str, usize)> = STOP_WORDS
+        .iter()
+        .map(|(language, stop_words)| {
+            let count = words
+                .iter()
+                .filter(|word| stop_words.contains(&word.as_str()))
+                .count();
+            (*language, count)
+        })
+        .collect();
+    scores.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let (language, best) = scores[0];
+    let runner_up = scores[1].1;
+    (best >= MIN_STOP_WORDS && best as f64 >= runner_up as f64 * MIN_MARGIN).then_some(language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        let samples = [
+            (
+                "EN",
+                "Call me Ishmael. Some years ago, never mind how long precisely, having little or \
+                 no money in my purse, and nothing particular to interest me on shore, I thought \
+                 I would sail about a little and see the watery part of the world. It is a way I \
+                 have of driving off the spleen and regulating the circulation.",
+            ),
+            (
+                "ES",
+                "En un lugar de la Mancha, de cuyo nombre no quiero acordarme, no ha mucho tiempo \
+                 que vivía un hidalgo de los de lanza en astillero, adarga antigua, rocín flaco y \
+                 galgo corredor. Una olla de algo más vaca que carnero, salpicón las más noches.",
+            ),
+            (
+                "FR",
+                "Longtemps, je me suis couché de bonne heure. Parfois, à peine ma bougie éteinte, \
+                 mes yeux se fermaient si vite que je n'avais pas le temps de me dire: je \
+                 m'endors. Et, une demi-heure après, la pensée qu'il était temps de chercher le \
+                 sommeil m'éveillait; je voulais poser le volume que je croyais avoir dans les \
+                 mains et souffler ma lumière.",
+            ),
+            (
+                "DE",
+                "Als Gregor Samsa eines Morgens aus unruhigen Träumen erwachte, fand er sich in \
+                 seinem Bett zu einem ungeheueren Ungeziefer verwandelt. Er lag auf seinem \
+                 panzerartig harten Rücken und sah, wenn er den Kopf ein wenig hob, seinen \
+                 gewölbten, braunen, von bogenförmigen Versteifungen geteilten Bauch, auf dessen \
+                 Höhe sich die Bettdecke, zum gänzlichen Niedergleiten bereit, kaum noch erhalten \
+                 konnte.",
+            ),
+            (
+                "RU",
+                "Все счастливые семьи похожи друг на друга, каждая несчастливая семья несчастлива \
+                 по-своему. Всё смешалось в доме Облонских. Жена узнала, что муж был в связи с \
+                 бывшею в их доме француженкою-гувернанткой.",
+            ),
+            (
+                "JA",
+                "吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。何でも薄暗いじめじめした所で\
+                 ニャーニャー泣いていた事だけは記憶している。",
+            ),
+        ];
+        for (language, text) in samples {
+            assert_eq!(detect_language(text), Some(language), "{}", text);
+        }
+        assert_eq!(detect_language("Chapter One"), None);
+        assert_eq!(
+            detect_language(&"Lorem ipsum dolor sit amet. ".repeat(10)),
+            None
+        );
+    }
+}
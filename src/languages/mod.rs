@@ -0,0 +1,257 @@
+use std::fmt;
+
+use crate::deepl::models::Language;
+
+mod detect;
+pub use detect::detect_language;
+
+/// Other names given to languages, and country codes often taken for language codes, with the
+/// DeepL code they stand for.
+const ALIASES: &[(&str, &str)] = &[
+    ("castilian", "ES"),
+    ("castellano", "ES"),
+    ("español", "ES"),
+    ("espanol", "ES"),
+    ("français", "FR"),
+    ("francais", "FR"),
+    ("deutsch", "DE"),
+    ("italiano", "IT"),
+    ("português", "PT"),
+    ("portugues", "PT"),
+    ("brazilian", "PT-BR"),
+    ("american", "EN-US"),
+    ("british", "EN-GB"),
+    ("mandarin", "ZH"),
+    ("simplified chinese", "ZH-HANS"),
+    ("traditional chinese", "ZH-HANT"),
+    ("br", "PT-BR"),
+    ("cn", "ZH"),
+    ("cz", "CS"),
+    ("dk", "DA"),
+    ("gr", "EL"),
+    ("iw", "HE"),
+    ("jp", "JA"),
+    ("kr", "KO"),
+    ("no", "NB"),
+    ("se", "SV"),
+    ("ua", "UK"),
+];
+
+/// What a language given by the user stands for among the supported ones, see
+/// `match_language`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LanguageMatch {
+    /// The code of a supported language.
+    Found(String),
+    /// A language only supported in several regional variants, e.g. `PT` for `PT-BR` and
+    /// `PT-PT`: one of them must be chosen.
+    Variants(Vec<String>),
+    /// No supported language, with the closest one if any is close enough.
+    Unknown { suggestion: Option<String> },
+}
+
+/// Finds the supported language meant by `input`: a code whatever its case (`es`, `pt_br`), a
+/// name (`Spanish`) or a common alias (`castellano`, `jp`). A regional variant that is not
+/// supported falls back to its language, `ES-419` to `ES`.
+pub fn match_language(input: &str, supported: &[Language]) -> LanguageMatch {
+    let code = input.trim().replace('_', "-").to_uppercase();
+    let name = input.trim().to_lowercase();
+    if let Some(found) = match_code(&code, supported) {
+        return found;
+    }
+    if let Some((_, alias)) = ALIASES.iter().find(|(alias, _)| *alias == name) {
+        if let Some(found) = match_code(alias, supported) {
+            return found;
+        }
+    }
+    if let Some(language) = supported
+        .iter()
+        .find(|language| language.name.to_lowercase() == name)
+    {
+        return LanguageMatch::Found(language.language.clone());
+    }
+    // `English` for `English (British)` and `English (American)`
+    let variants: Vec<String> = supported
+        .iter()
+        .filter(|language| base_name(&language.name).to_lowercase() == name)
+        .map(|language| language.language.clone())
+        .collect();
+    if let Some(found) = one_or_variants(variants) {
+        return found;
+    }
+    if let Some((base, _)) = code.split_once('-') {
+        if let Some(found) = match_code(base, supported) {
+            return found;
+        }
+    }
+    LanguageMatch::Unknown {
+        suggestion: suggestion(&name, supported),
+    }
+}
+
+/// Whether `language`, a language tag like `en-US` or a DeepL code, is the language `target`:
+/// the same language, in the same variant when both have one. `no` is taken for `NB`.
+pub fn is_same_language(language: &str, target: &str) -> bool {
+    let split = |tag: &str| {
+        let tag = tag.trim().replace('_', "-").to_uppercase();
+        let (base, variant) = match tag.split_once('-') {
+            Some((base, variant)) => (base.to_string(), Some(variant.to_string())),
+            None => (tag, None),
+        };
+        let base = if base == "NO" { "NB".to_string() } else { base };
+        (base, variant)
+    };
+    let (base, variant) = split(language);
+    let (target_base, target_variant) = split(target);
+    base == target_base
+        && match (variant, target_variant) {
+            (Some(variant), Some(target_variant)) => variant == target_variant,
+            _ => true,
+        }
+}
+
+/// Error returned for books already in the target language, unless
+/// `TranslateOptions::same_language` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SameLanguageError {
+    /// The language of the book, the detected one or else the declared one.
+    pub language: String,
+    /// Whether the language was detected in the text of the book, rather than read from its
+    /// metadata.
+    pub detected: bool,
+}
+
+impl fmt::Display for SameLanguageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The book is already in the target language, {} ({})",
+            self.language,
+            match self.detected {
+                true => "detected in its text",
+                false => "declared in its metadata",
+            }
+        )
+    }
+}
+
+impl std::error::Error for SameLanguageError {}
+
+// A supported code, or the regional variants of `code`.
+fn match_code(code: &str, supported: &[Language]) -> Option<LanguageMatch> {
+    if let Some(language) = supported
+        .iter()
+        .find(|language| language.language.eq_ignore_ascii_case(code))
+    {
+        return Some(LanguageMatch::Found(language.language.clone()));
+    }
+    let prefix = format!("{}-", code);
+    let variants = supported
+        .iter()
+        .filter(|language| language.language.to_uppercase().starts_with(&prefix))
+        .map(|language| language.language.clone())
+        .collect();
+    one_or_variants(variants)
+}
+
+fn one_or_variants(mut variants: Vec<String>) -> Option<LanguageMatch> {
+    match variants.len() {
+        0 => None,
+        1 => variants.pop().map(LanguageMatch::Found),
+        _ => Some(LanguageMatch::Variants(variants)),
+    }
+}
+
+// The name without its variant, `English (British)` -> `English`.
+fn base_name(name: &str) -> &str {
+    name.split(" (").next().unwrap_or(name).trim()
+}
+
+// The supported code whose code or name is the closest to `name`, when a typo or two apart.
+fn suggestion(name: &str, supported: &[Language]) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    supported
+        .iter()
+        .flat_map(|language| {
+            [&language.language, &language.name]
+                .map(|candidate| (distance(name, &candidate.to_lowercase()), language))
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, language)| language.language.clone())
+}
+
+// Levenshtein distance, in characters.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_language() {
+        let supported: Vec<Language> = [
+            ("ES", "Spanish"),
+            ("EN-GB", "English (British)"),
+            ("EN-US", "English (American)"),
+            ("PT-BR", "Portuguese (Brazilian)"),
+            ("PT-PT", "Portuguese (European)"),
+            ("JA", "Japanese"),
+        ]
+        .into_iter()
+        .map(|(language, name)| Language {
+            language: language.to_string(),
+            name: name.to_string(),
+            supports_formality: None,
+        })
+        .collect();
+        let found = |code: &str| LanguageMatch::Found(code.to_string());
+
+        assert_eq!(match_language("es", &supported), found("ES"));
+        assert_eq!(match_language("Spanish", &supported), found("ES"));
+        assert_eq!(match_language("ES-419", &supported), found("ES"));
+        assert_eq!(match_language("en_gb", &supported), found("EN-GB"));
+        assert_eq!(match_language("jp", &supported), found("JA"));
+        assert_eq!(match_language("brazilian", &supported), found("PT-BR"));
+        assert_eq!(
+            match_language("PT", &supported),
+            LanguageMatch::Variants(vec!["PT-BR".to_string(), "PT-PT".to_string()])
+        );
+        assert_eq!(
+            match_language("english", &supported),
+            LanguageMatch::Variants(vec!["EN-GB".to_string(), "EN-US".to_string()])
+        );
+        assert_eq!(
+            match_language("spansih", &supported),
+            LanguageMatch::Unknown {
+                suggestion: Some("ES".to_string())
+            }
+        );
+        assert_eq!(
+            match_language("klingon", &supported),
+            LanguageMatch::Unknown { suggestion: None }
+        );
+    }
+
+    #[test]
+    fn test_is_same_language() {
+        assert!(is_same_language("en", "EN-GB"));
+        assert!(is_same_language("en-GB", "EN-GB"));
+        assert!(is_same_language("no", "NB"));
+        assert!(!is_same_language("en-US", "EN-GB"));
+        assert!(!is_same_language("pt-BR", "PT-PT"));
+        assert!(!is_same_language("es", "EN"));
+    }
+}
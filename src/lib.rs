@@ -1,57 +1,100 @@
+pub mod cache;
+pub mod config;
 pub mod deepl;
+mod documents;
 pub mod epub;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod job;
+pub mod journal;
+pub mod keys;
+pub mod languages;
+pub mod locale;
+pub mod memory;
+pub mod options;
+pub mod pricing;
+pub mod progress;
+pub mod providers;
+#[cfg(feature = "python")]
+mod python;
+pub mod qa;
+pub mod report;
+pub mod review;
+pub mod segment;
+pub mod telemetry;
+pub mod terms;
+pub mod testing;
+pub mod translator;
 pub mod xhtml;
+pub mod xliff;
 
-use crate::deepl::models::DeepLConfiguration;
+use crate::deepl::models::{self, DeepLConfiguration, Formality};
 use crate::deepl::translate;
+use crate::error::EpubTranslatorError;
 
-use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
-use std::time::Instant;
 
-use epub::{get_xhtml_paths, unzip_epub_from_path, zip_folder_to_epub};
+use cache::TranslationCache;
+use documents::{Documents, Parsed};
+use epub::{
+    add_book_language, add_provenance, book_language, check_encryption, content_document_paths,
+    navigation_labels, repack_epub, set_book_language, set_navigation_labels, unzip_epub_from_path,
+    unzip_epub_with_limits, BookArchive, BookFiles, BookFolder, FixedLayoutError, Package,
+    CONTAINER_PATH,
+};
+use journal::Journal;
+use languages::SameLanguageError;
+use locale::Localizer;
+use options::{Sample, Selection, TranslateOptions};
+use progress::{ProgressEvent, ProgressSink, SilentProgress, TranslationEvent};
+use providers::LocalProvider;
+use report::{
+    AlignedUnit, BackTranslatedUnit, BackTranslation, BudgetStop, ChapterCount, FailedUnit,
+    FileReport, FlaggedUnit, TranslationReport,
+};
 use reqwest::Client;
+use segment::{escape_xml, get_segments, Segment, SharedText};
 use xhtml::{
-    get_document_node_from_path, get_text_nodes, get_text_nodes_from_path, serialize_document,
+    decode_xhtml, get_document_node, get_text_nodes_with, language_tag, read_xhtml_file,
+    rewrite_lang, serialize_document_to_string_with, EntityPolicy, EntityReferences,
+    ExtractOptions, Originals,
 };
+use xliff::Xliff;
 
-use html5ever::tendril::StrTendril;
-use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use chrono::Utc;
+use futures::{future, stream, FutureExt, Stream, StreamExt};
 use markup5ever_rcdom::{Node, NodeData};
 use tempfile::tempdir;
 use tokio::sync::{
     mpsc::{self, Receiver, Sender},
     Semaphore,
 };
-
-#[macro_export]
-macro_rules! profiling_log {
-    ($enabled:expr, $($arg:tt)*) => {
-        if $enabled {
-            eprintln!("[PROFILING] {}:{} - {}", file!(), line!(), format!($($arg)*));
-        }
-
-    };
-}
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, trace, warn};
+use web_time::Instant;
 
 macro_rules! timed {
-    ($print:expr, $func:ident, $($arg:expr),*) => {{
+    ($func:ident, $($arg:expr),*) => {{
         let start = Instant::now();
         let result = $func($($arg),*);
-        let duration = start.elapsed();
-        profiling_log!($print,
-            "{}: function took {:?}",
-            stringify!($func),
-            duration);
+        info!("{}: function took {:?}", stringify!($func), start.elapsed());
         result
-    }
-
-    };
+    }};
 }
 
-/// Translates an EPUB file and put the translation into another EPUB file.
+/// Translates an EPUB file and put the translation into another EPUB file, with the default
+/// options for the others, see `translate_epub_with`.
+///
+/// `verbose` is ignored, the timings are logged by `tracing` at the info level.
+#[deprecated(
+    note = "use `translate_epub_with` and `TranslateOptions`, or `translator::EpubTranslator`"
+)]
 pub async fn translate_epub(
     input_file: &Path,
     output_file: &Path,
@@ -59,34 +102,322 @@ pub async fn translate_epub(
     source_lang: Option<String>,
     concurrent_requests: usize,
     config: Vec<Arc<DeepLConfiguration>>,
-    verbose: bool,
+    _verbose: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Create a temporary directory
-    let temp_dir = tempdir()?;
-    let temp_dir_path = temp_dir.path();
-
-    // Unzips the epub to the output_dir
-    timed!(verbose, unzip_epub_from_path, input_file, temp_dir_path)?;
-
-    // Translates the folder in place. Only files that need to be translated will be modified
-    translate_folder(
-        temp_dir_path,
+    let options = TranslateOptions {
         target_lang,
         source_lang,
         concurrent_requests,
-        config,
-        verbose,
+        configurations: config,
+        ..Default::default()
+    };
+    translate_epub_with(input_file, output_file, &options).await?;
+    Ok(())
+}
+
+/// Translates an EPUB file and put the translation into another EPUB file.
+///
+/// Progress is reported through `options.progress`, silent by default.
+/// When `options.cancel` is triggered the partially translated EPUB is still written, see
+/// `translate_folder`.
+///
+/// The EPUB is extracted into a temporary workdir which also holds the run journal, created in
+/// `options.temp_dir` when given, or into `options.workdir` which is kept. The run fails with
+/// `NoSpace` before extracting anything when there is no room for the book and its translation.
+/// If the run fails the workdir is kept, and its path added to the error, for inspection. With
+/// `options.in_memory` the EPUB is translated in memory instead, see `translate_epub_bytes`.
+pub async fn translate_epub_with(
+    input_file: &Path,
+    output_file: &Path,
+    options: &TranslateOptions,
+) -> Result<TranslationReport, EpubTranslatorError> {
+    if options.in_memory {
+        let input = std::fs::read(input_file)?;
+        let (output, report) = translate_epub_bytes(input, options).await?;
+        std::fs::write(output_file, output)?;
+        return Ok(report);
+    }
+
+    // The book is extracted next to the journal, in a temporary workdir unless one is given
+    let (temp_dir, workdir) = match &options.workdir {
+        Some(workdir) => {
+            prepare_workdir(workdir)?;
+            (None, workdir.clone())
+        }
+        None => {
+            let temp_dir = match &options.temp_dir {
+                Some(dir) => tempfile::tempdir_in(dir)?,
+                None => tempdir()?,
+            };
+            let workdir = temp_dir.path().to_path_buf();
+            (Some(temp_dir), workdir)
+        }
+    };
+    #[cfg(feature = "native")]
+    check_free_space(input_file, &workdir, output_file)?;
+    let book_dir = workdir.join("book");
+    let journal = Journal::create(&workdir)?;
+    journal.record(
+        "run_started",
+        format!("{} -> {}", input_file.display(), output_file.display()),
+    );
+
+    let result = async {
+        // Unzips the epub to the output_dir
+        timed!(
+            unzip_epub_with_limits,
+            input_file,
+            &book_dir,
+            &options.unzip_limits
+        )?;
+        journal.record("epub_extracted", book_dir.display());
+
+        // Translates the folder in place. Only files that need to be translated will be modified
+        let mut files = BookFolder::new(&book_dir);
+        let report = translate_book(&mut files, options, &journal).await?;
+        label_book(&mut files, options, &journal)?;
+
+        // Zip the temporary directory into the output file
+        timed!(
+            repack_epub,
+            input_file,
+            &book_dir,
+            output_file,
+            options.fixed_time
+        )?;
+        journal.record("epub_written", output_file.display());
+
+        Ok::<_, EpubTranslatorError>(report)
+    }
+    .await;
+
+    match result {
+        Ok(report) => Ok(report),
+        // Nothing in the workdir would help with a book refused up front
+        Err(
+            error @ (EpubTranslatorError::Encrypted(_)
+            | EpubTranslatorError::FixedLayout(_)
+            | EpubTranslatorError::SameLanguage(_)),
+        ) => Err(error),
+        Err(error) => {
+            journal.record("run_failed", &error);
+            if let Some(temp_dir) = temp_dir {
+                let _ = temp_dir.into_path();
+            }
+            Err(EpubTranslatorError::WorkdirKept {
+                workdir,
+                source: Box::new(error),
+            })
+        }
+    }
+}
+
+/// Translates an EPUB file into another one like `translate_epub_with`, as a stream of events: the
+/// progress of the run as it happens, including the text of every translated unit, then its
+/// outcome. Nothing runs until the stream is polled, and dropping it stops the run.
+///
+/// The events replace `options.progress`. The stream is not `Send`, it must be polled where it
+/// was created, e.g. pinned on the task of a frontend.
+///
+/// ```no_run
+/// # use std::pin::pin;
+/// # use futures::StreamExt;
+/// # use epub_translator::options::TranslateOptions;
+/// # use epub_translator::progress::{ProgressEvent, TranslationEvent};
+/// # async fn run(options: TranslateOptions) {
+/// let mut events = pin!(epub_translator::translate_epub_stream(
+///     "book.epub".into(),
+///     "book.es.epub".into(),
+///     options,
+/// ));
+/// while let Some(event) = events.next().await {
+///     match event {
+///         TranslationEvent::Progress(ProgressEvent::NodeTranslated { translation, .. }) => {
+///             println!("{}", translation)
+///         }
+///         TranslationEvent::Finished(result) => println!("{:?}", result.map(|r| r.translated)),
+///         _ => {}
+///     }
+/// }
+/// # }
+/// ```
+pub fn translate_epub_stream(
+    input_file: PathBuf,
+    output_file: PathBuf,
+    mut options: TranslateOptions,
+) -> impl Stream<Item = TranslationEvent> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    options.progress = Arc::new(EventSender(tx.clone()));
+    let run = async move {
+        let result = translate_epub_with(&input_file, &output_file, &options).await;
+        let _ = tx.send(TranslationEvent::Finished(result));
+    };
+
+    // The channel closes once the run is over and its options dropped, after `Finished`
+    let events = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (event, rx))
+    });
+    stream::select(
+        run.into_stream().filter_map(|()| future::ready(None)),
+        events,
     )
-    .await?;
+}
+
+struct EventSender(mpsc::UnboundedSender<TranslationEvent>);
+
+impl ProgressSink for EventSender {
+    fn on_event(&self, event: ProgressEvent) {
+        let _ = self.0.send(TranslationEvent::Progress(event));
+    }
+}
+
+// Creates the workdir given in the options. Files already there would be mixed with the book.
+fn prepare_workdir(workdir: &Path) -> Result<(), EpubTranslatorError> {
+    std::fs::create_dir_all(workdir)?;
+    if std::fs::read_dir(workdir)?.next().is_some() {
+        return Err(EpubTranslatorError::InvalidOptions(format!(
+            "workdir {} is not empty",
+            workdir.display()
+        )));
+    }
+    Ok(())
+}
+
+// Fails before the book is extracted when the filesystem of the workdir cannot hold it, or
+// the one of the output the translated EPUB, about as large as the original. Filesystems whose
+// free space is unknown are not checked.
+#[cfg(feature = "native")]
+fn check_free_space(
+    input_file: &Path,
+    workdir: &Path,
+    output_file: &Path,
+) -> Result<(), EpubTranslatorError> {
+    let extracted = epub::extracted_size(input_file)?;
+    let output = std::fs::metadata(input_file)?.len();
+    let output_dir = output_file
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let needs = if same_filesystem(workdir, output_dir) {
+        vec![(workdir, extracted + output)]
+    } else {
+        vec![(workdir, extracted), (output_dir, output)]
+    };
+    for (path, needed) in needs {
+        let Ok(available) = fs4::available_space(path) else {
+            continue;
+        };
+        if available < needed {
+            return Err(EpubTranslatorError::NoSpace {
+                path: path.to_path_buf(),
+                needed,
+                available,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "native", unix))]
+fn same_filesystem(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(all(feature = "native", not(unix)))]
+fn same_filesystem(_: &Path, _: &Path) -> bool {
+    false
+}
+
+/// Translates an EPUB held in memory and returns the translated EPUB. Nothing is written to
+/// disk, no journal is kept.
+pub async fn translate_epub_bytes(
+    input: Vec<u8>,
+    options: &TranslateOptions,
+) -> Result<(Vec<u8>, TranslationReport), EpubTranslatorError> {
+    let journal = Journal::disabled();
+    let mut book = BookArchive::read(input, &options.unzip_limits)?;
+    let report = translate_book(&mut book, options, &journal).await?;
+    label_book(&mut book, options, &journal)?;
+    Ok((book.to_bytes(options.fixed_time)?, report))
+}
+
+/// Translates an EPUB held in memory into each of `target_langs` in turn, see
+/// `translate_epub_bytes`. The archive is read and checked once, each language translates a
+/// copy of it.
+///
+/// `options.target_lang` is replaced by each language, and so is `options.extract.skip_lang`
+/// when it is the target language. A cancelled run stops there, the languages left are not
+/// translated. A book already in one of the languages is refused before any is translated,
+/// unless `options.same_language` is set.
+pub async fn translate_epub_bytes_to_languages(
+    input: Vec<u8>,
+    target_langs: &[String],
+    options: &TranslateOptions,
+) -> Result<Vec<(Vec<u8>, TranslationReport)>, EpubTranslatorError> {
+    let journal = Journal::disabled();
+    let source = BookArchive::read(input, &options.unzip_limits)?;
+    if !options.same_language {
+        let (detected, declared) = sample_book_language(&source, options)?;
+        for target_lang in target_langs {
+            if let Some(error) = same_language_error(detected, declared.as_deref(), target_lang) {
+                return Err(error.into());
+            }
+        }
+    }
+    let mut outputs = Vec::new();
+    for target_lang in target_langs {
+        let mut options = options.clone();
+        if options.extract.skip_lang.as_ref() == Some(&options.target_lang) {
+            options.extract.skip_lang = Some(target_lang.clone());
+        }
+        options.target_lang = target_lang.clone();
+
+        let mut book = source.clone();
+        let report = translate_book(&mut book, &options, &journal).await?;
+        label_book(&mut book, &options, &journal)?;
+        let cancelled = report.was_cancelled();
+        outputs.push((book.to_bytes(options.fixed_time)?, report));
+        if cancelled {
+            break;
+        }
+    }
+    Ok(outputs)
+}
 
-    // Zip the temporary directory into the output file
-    timed!(verbose, zip_folder_to_epub, temp_dir_path, output_file)?;
+// Updates the package metadata of a translated book.
+fn label_book(
+    files: &mut dyn BookFiles,
+    options: &TranslateOptions,
+    journal: &Journal,
+) -> Result<(), EpubTranslatorError> {
+    // Machine translated books are labeled as such, before the source language is replaced
+    let source_lang = options.source_lang.as_deref().map(language_tag);
+    let time = options.fixed_time.unwrap_or_else(Utc::now);
+    add_provenance(files, source_lang.as_deref(), time)?;
+
+    // Bilingual books are in both languages, the source one is declared second
+    let original_lang = match options.bilingual {
+        Some(_) => source_lang.or(book_language(files)?),
+        None => None,
+    };
 
+    // Readers pick hyphenation and dictionaries from the package language
+    let lang = language_tag(&options.target_lang);
+    set_book_language(files, &lang)?;
+    journal.record("language_set", &lang);
+    if let Some(original_lang) = original_lang.filter(|original| *original != lang) {
+        add_book_language(files, &original_lang)?;
+        journal.record("language_added", &original_lang);
+    }
     Ok(())
 }
 
 /// Counts the number of characters to translate in an EPUB file.
-pub fn count_epub_char(epub_path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+pub fn count_epub_char(epub_path: &Path) -> Result<usize, EpubTranslatorError> {
     // Create a temporary directory
     let temp_dir = tempdir()?;
     let temp_dir_path = temp_dir.path();
@@ -94,34 +425,187 @@ pub fn count_epub_char(epub_path: &Path) -> Result<usize, Box<dyn std::error::Er
     // Unzip it into a temporary directory
     unzip_epub_from_path(epub_path, temp_dir_path)?;
 
-    // Create iterator over all xhtml files
-    let xhtml_files = get_xhtml_paths(temp_dir_path)?;
+    count_book_char(&BookFolder::new(temp_dir_path))
+}
+
+/// Counts the characters to translate in a book, see `count_epub_char`. Fails with
+/// `EpubTranslatorError::Encrypted` for encrypted books.
+pub fn count_book_char(files: &dyn BookFiles) -> Result<usize, EpubTranslatorError> {
+    Ok(
+        count_book_chapters(files, &ExtractOptions::default(), &Selection::default())?
+            .iter()
+            .map(|chapter| chapter.characters)
+            .sum(),
+    )
+}
+
+/// Per file breakdown of what there is to translate in an EPUB file, see
+/// `count_book_chapters`.
+pub fn count_epub_chapters(
+    epub_path: &Path,
+    options: &ExtractOptions,
+    selection: &Selection,
+) -> Result<Vec<ChapterCount>, EpubTranslatorError> {
+    let temp_dir = tempdir()?;
+    unzip_epub_from_path(epub_path, temp_dir.path())?;
+    count_book_chapters(&BookFolder::new(temp_dir.path()), options, selection)
+}
+
+/// Per file breakdown of what there is to translate in a book: the selected content documents
+/// in reading order, then the package metadata when translated and the NCX.
+pub fn count_book_chapters(
+    files: &dyn BookFiles,
+    options: &ExtractOptions,
+    selection: &Selection,
+) -> Result<Vec<ChapterCount>, EpubTranslatorError> {
+    Ok(book_file_units(files, options, selection)?
+        .into_iter()
+        .map(|file| ChapterCount {
+            units: file.segments.len(),
+            billable_characters: file.segments.iter().map(Segment::billable_chars).sum(),
+            path: file.path,
+            chapter: file.chapter,
+            characters: file.characters,
+        })
+        .collect())
+}
+
+/// The translation units of a book, in the order they are sent, without translation. The
+/// files come in the order of `count_book_chapters`.
+pub fn book_units(
+    files: &dyn BookFiles,
+    options: &ExtractOptions,
+    selection: &Selection,
+) -> Result<Vec<AlignedUnit>, EpubTranslatorError> {
+    let mut units = Vec::new();
+    for file in book_file_units(files, options, selection)? {
+        for (index, segment) in file.segments.iter().enumerate() {
+            units.push(AlignedUnit {
+                path: file.path.clone(),
+                position: index + 1,
+                source: segment.source_text(),
+                translation: None,
+                source_markup: segment.text.to_string(),
+                translation_markup: None,
+            });
+        }
+    }
+    Ok(units)
+}
 
-    let mut counter = 0;
+// The units of a file of a book, see `book_file_units`.
+struct FileUnits {
+    path: PathBuf,
+    chapter: Option<usize>,
+    /// Characters of the translatable text nodes, or of the detached values.
+    characters: usize,
+    segments: Vec<Segment>,
+}
 
-    for xhtml_file in xhtml_files {
-        let nodes = get_text_nodes_from_path(&PathBuf::from(xhtml_file))?;
+// Extracts the units of the files to translate: the selected content documents in reading
+// order, then the package metadata when translated and the NCX. Fails with
+// `EpubTranslatorError::Encrypted` for encrypted books.
+fn book_file_units(
+    files: &dyn BookFiles,
+    options: &ExtractOptions,
+    selection: &Selection,
+) -> Result<Vec<FileUnits>, EpubTranslatorError> {
+    check_encryption(files)?;
+    let package = if files.is_file(Path::new(CONTAINER_PATH)) {
+        Some(Package::open(files)?)
+    } else {
+        None
+    };
+    let mut file_units = Vec::new();
 
-        for handle in nodes {
+    for path in content_document_paths(files)? {
+        let chapter = package.as_ref().and_then(|package| package.chapter(&path));
+        if !selection.selects(&path, chapter) {
+            continue;
+        }
+        let content = decode_xhtml(&files.read(&path)?)?;
+        if !options.may_have_text(&content) {
+            file_units.push(FileUnits {
+                path,
+                chapter,
+                characters: 0,
+                segments: Vec::new(),
+            });
+            continue;
+        }
+        let document = get_document_node(&content)?;
+        let mut characters = 0;
+        for handle in get_text_nodes_with(&document, options)? {
             if let NodeData::Text { contents } = &handle.data {
-                let text = contents.borrow();
-                counter += text.len();
+                characters += contents.borrow().chars().count();
             }
         }
+        file_units.push(FileUnits {
+            path,
+            chapter,
+            characters,
+            segments: get_segments(&document, options),
+        });
+    }
+
+    // Metadata and navigation labels are sent as they are
+    let detached = |path: PathBuf, texts: Vec<String>| FileUnits {
+        path,
+        chapter: None,
+        characters: texts.iter().map(|text| text.chars().count()).sum(),
+        segments: texts
+            .iter()
+            .map(|text| Segment::detached_with(text, None, options))
+            .collect(),
+    };
+    if let Some(package) = &package {
+        if options.translate_metadata {
+            let texts = package.translatable_metadata(files)?;
+            let texts = texts.into_iter().map(|entry| entry.text).collect();
+            file_units.push(detached(package.path.clone(), texts));
+        }
+        if let Some(ncx_path) = package.ncx_path().filter(|path| files.is_file(path)) {
+            let labels = navigation_labels(files, &ncx_path)?;
+            let texts = labels.into_iter().map(|label| label.text).collect();
+            file_units.push(detached(ncx_path, texts));
+        }
     }
 
-    Ok(counter)
+    Ok(file_units)
 }
 
 /// Messages
 struct TranslationRequest {
     id: usize,
-    text: Arc<String>,
+    text: SharedText,
+    context: Option<SharedText>,
+    preserve_whitespace: bool,
 }
 
 struct TranslationResult {
     id: usize,
-    translated_text: Arc<Option<String>>,
+    outcome: TranslationOutcome,
+}
+
+#[derive(Debug)]
+enum TranslationOutcome {
+    Translated(String),
+    Failed(String),
+    /// The API refused the request, the character quota of the key is exhausted.
+    QuotaExceeded(String),
+    /// The run was cancelled before the request reached the API.
+    Cancelled,
+}
+
+/// Resources shared by all the translation tasks spawned by the Translator.
+struct TaskContext {
+    target_lang: String,
+    formality: Option<Formality>,
+    /// Translates instead of the API when set, see `TranslateOptions::provider`.
+    local: Option<&'static dyn LocalProvider>,
+    semaphore: Semaphore,
+    client: Client,
+    cancel: CancellationToken,
 }
 
 /// Handles a single translation task asynchronously.
@@ -133,56 +617,79 @@ struct TranslationResult {
 /// 3. Sends the translation result back to the writer through a channel.
 ///
 /// # Error Handling
-/// - If translation fails, it logs the error and sends a result with `None` for the translated text.
+/// - If translation fails, it logs the error and sends a result with the error message.
+/// - If the run is cancelled before the API is called, it sends a `Cancelled` result.
+///   Requests already sent to the API are awaited so their results are not lost.
 /// - If sending the result back to the writer fails, it logs the error.
 async fn translation_task(
-    id: usize,
-    text: Arc<String>,
-    target_lang: Arc<String>,
-    semaphore: Arc<Semaphore>,
+    request: TranslationRequest,
+    configuration: Option<Arc<DeepLConfiguration>>,
     tx_writer: Sender<TranslationResult>,
-    configuration: Arc<DeepLConfiguration>,
-    client: Client,
+    context: Arc<TaskContext>,
 ) {
-    eprintln!("[{}] [Task] Start of translation id", id);
-    let out_permit = semaphore.acquire().await.unwrap();
-    let available_permits = semaphore.available_permits();
-    eprintln!(
-        "[{}] [Task] Took permit, remaining permits: {}",
-        id, available_permits
-    );
-    let translation_result = match translate(
-        &configuration,
-        &text,
-        &target_lang,
-        true,
-        &client,
+    let TranslationRequest {
         id,
-        available_permits,
-    )
-    .await
-    {
-        Ok(translated_text) => {
-            // Drop permit
-            TranslationResult {
-                id: id,
-                translated_text: Arc::new(Some(translated_text)),
-            }
-        }
-        Err(error) => {
-            eprintln!("[{}] [Task] Error translating node: {}", id, error);
-            TranslationResult {
-                id: id,
-                translated_text: Arc::new(None),
-            }
+        text,
+        context: text_context,
+        preserve_whitespace,
+    } = request;
+    trace!("[{}] [Task] Start of translation id", id);
+
+    let out_permit = tokio::select! {
+        permit = context.semaphore.acquire() => permit.ok(),
+        _ = context.cancel.cancelled() => None,
+    };
+
+    let outcome = match out_permit {
+        Some(_) if context.cancel.is_cancelled() => TranslationOutcome::Cancelled,
+        Some(out_permit) => {
+            let available_permits = context.semaphore.available_permits();
+            trace!(
+                "[{}] [Task] Took permit, remaining permits: {}",
+                id,
+                available_permits
+            );
+            let translation = match (context.local, configuration) {
+                (Some(local), _) => Ok(local.translate(&text, &context.target_lang)),
+                (None, Some(configuration)) => {
+                    translate(
+                        &configuration,
+                        &models::TranslationRequest::new_xml(&text, &context.target_lang)
+                            .with_context(text_context.as_deref())
+                            .with_preserved_formatting(preserve_whitespace)
+                            .with_formality(context.formality),
+                        &context.client,
+                        id,
+                        available_permits,
+                    )
+                    .await
+                }
+                (None, None) => Err(EpubTranslatorError::InvalidOptions(
+                    "no DeepL configuration to send the requests to".to_string(),
+                )),
+            };
+            let outcome = match translation {
+                Ok(translated_text) => TranslationOutcome::Translated(translated_text),
+                Err(error) => {
+                    debug!("[{}] [Task] Error translating node: {}", id, error);
+                    match error {
+                        EpubTranslatorError::QuotaExceeded { .. } => {
+                            TranslationOutcome::QuotaExceeded(error.to_string())
+                        }
+                        _ => TranslationOutcome::Failed(error.to_string()),
+                    }
+                }
+            };
+            drop(out_permit);
+            outcome
         }
+        None => TranslationOutcome::Cancelled,
     };
-    drop(out_permit);
 
-    if let Err(e) = tx_writer.send(translation_result).await {
-        eprintln!("Failed to send translation result to writer: {}", e);
+    if let Err(e) = tx_writer.send(TranslationResult { id, outcome }).await {
+        warn!("Failed to send translation result to writer: {}", e);
     }
-    eprintln!("[{}] [Task] End of translation", id);
+    trace!("[{}] [Task] End of translation", id);
 }
 
 /// Spawns a Translator actor to manage translation tasks.
@@ -198,250 +705,1332 @@ async fn translation_task(
 /// 1. Client
 /// 2. Semaphore
 /// 3. Configurations
+/// 4. Cancellation token
 ///
 /// The actor continues running until the request channel is closed.
 async fn run_translator(
     configurations: Vec<Arc<DeepLConfiguration>>,
-    concurrent_requests: usize,
-    target_lang: String,
+    context: Arc<TaskContext>,
     mut receiver: Receiver<TranslationRequest>,
     sender: Sender<TranslationResult>,
 ) {
-    eprintln!("Created the translator");
-    let semaphore = Arc::new(Semaphore::new(concurrent_requests));
-    let target_lang = Arc::new(target_lang);
-    let client = Client::new();
+    debug!("Created the translator");
     let configuration_length = configurations.len();
 
     while let Some(request) = receiver.recv().await {
-        eprintln!("[{}] - [Translator] Received request ", request.id);
-
-        let config_index = request.id % configuration_length;
+        trace!("[{}] - [Translator] Received request ", request.id);
 
-        let configuration = configurations[config_index].clone();
-        let client = client.clone();
+        // Local providers need no configuration
+        let configuration = match configuration_length {
+            0 => None,
+            length => Some(configurations[request.id % length].clone()),
+        };
         let tx_writer = sender.clone();
-        let target_lang = target_lang.clone();
-        let semaphore = semaphore.clone();
-
-        let _task = tokio::spawn(translation_task(
-            request.id,
-            request.text,
-            target_lang,
-            semaphore,
-            tx_writer,
+
+        spawn(translation_task(
+            request,
             configuration,
-            client,
+            tx_writer,
+            context.clone(),
         ));
     }
-    eprintln!("[Translator] End, closing channel")
+    debug!("[Translator] End, closing channel");
+}
+
+// Runs `future` in the background, on the tokio runtime, or on the event loop of the browser
+// in WebAssembly.
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    tokio::spawn(future);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn spawn(future: impl Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(future);
 }
 
 /// Core function: Translates text in all XHTML files within a folder
 ///
 /// This function:
 /// 1. Creates document iterators, one per file, each as an HTML root.
-/// 2. Iterates through text nodes in each document.
-/// 3. Sets up two channels:
-///     - Translator_Channel (TranslationRequest)
-///     - Writer_Channel (TranslationResult)
-/// 4. Spawns Translator:
-///     - Listens on Translator_Channel, spawning TranslationTasks as needed
-/// 5. For each text node:
-///     - Sends a TranslationRequest to Translator
-/// 6. Spawns Writer:
-///     - Listens on Writer_Channel for TranslationResults
-///         - Modifies nodes if successful; retries if not (up to max attempts)
-///     - Closes channels when done (note: deadlock risk if incomplete)
-/// 7. Serializes documents back to files.
+/// 2. Splits each document into segments, runs of inline content translated as a whole.
+///    Content excluded by `options.extract` is left out.
+/// 3. Translates the segments, see `translate_segments`.
+/// 4. Serializes documents back to files.
 ///
-/// Note: Ideally, TranslationRequests would be sent post-Writer spawn, but this requires moving
-/// Writer (owner of nodes, Vec<Rc<Node>>) across threads.
+/// Every step is reported to `options.progress` as a `ProgressEvent` and recorded in `journal`.
+///
+/// Cancelling `options.cancel` stops new requests from reaching the API. Requests already in
+/// flight are awaited and written back, the partially translated documents are still serialized
+/// and the returned report tells how many units were left untranslated.
 pub async fn translate_folder(
     dir_path: &Path,
-    target_lang: String,
-    _source_lang: Option<String>,
-    concurrent_requests: usize,
-    configurations: Vec<Arc<DeepLConfiguration>>,
-    verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let max_retries = 4;
-    let start = Instant::now();
-
-    let xhtml_files = get_xhtml_paths(dir_path)?;
-
-    // 1. Create document iterator
-    let documents = xhtml_files
-        .map(|file| {
-            let file_path = PathBuf::from(file);
-            let document = get_document_node_from_path(&file_path).unwrap(); // Care about this
-            (document, file_path)
-        })
-        .collect::<Vec<(Rc<Node>, PathBuf)>>();
-
-    // 2. Create text node iterator
-    // This approach enables parallelization across all documents,
-    let nodes = documents
-        .iter()
-        .flat_map(|(document, _)| get_text_nodes(&document).expect("Failed to get text nodes."))
-        .collect::<Vec<Rc<Node>>>();
-
-    let total_nodes = nodes.len();
+    options: &TranslateOptions,
+    journal: &Journal,
+) -> Result<TranslationReport, EpubTranslatorError> {
+    translate_book(&mut BookFolder::new(dir_path), options, journal).await
+}
 
-    let end_preprocessing = Instant::now();
-    let preprocessing_duration = end_preprocessing - start;
-    profiling_log!(
-        verbose,
-        "Preprocessing duration: {:?}",
-        preprocessing_duration
-    );
+/// Translates the files of a book in place, extracted to a folder or held in memory, see
+/// `translate_folder`.
+pub async fn translate_book(
+    files: &mut dyn BookFiles,
+    options: &TranslateOptions,
+    journal: &Journal,
+) -> Result<TranslationReport, EpubTranslatorError> {
+    let (report, _) = translate_files(files, options, journal, false).await?;
+    Ok(report)
+}
 
-    eprintln!(
-        "[TRACE]{},{},{},{},{},{},{}",
-        "id", "len", "error_code", "start", "request_duration", "available_permits", "thread"
-    );
+/// Translates the documents of a directory in place, an extracted EPUB or loose XHTML and HTML
+/// files, and reports on each file, in reading order.
+///
+/// Unlike `translate_folder`, a document that cannot be read, parsed or written does not stop
+/// the run: it is left as it is and its error is kept in its `FileReport`. Nothing is printed,
+/// progress goes to `options.progress`.
+pub async fn translate_directory(
+    dir_path: &Path,
+    options: &TranslateOptions,
+) -> Result<Vec<FileReport>, EpubTranslatorError> {
+    let mut files = BookFolder::new(dir_path);
+    let (_, file_reports) =
+        translate_files(&mut files, options, &Journal::disabled(), true).await?;
+    Ok(file_reports)
+}
 
-    // Create a progress bar
-    let progress_bar =
-        ProgressBar::with_draw_target(Some((total_nodes + 1) as u64), ProgressDrawTarget::stdout());
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({percent}%)")
-            .unwrap()
-            .progress_chars("##-"),
-    );
+// Translates the files of a book in place, see `translate_folder`, with the report split by
+// file. With `per_file`, documents that cannot be read or written are left out of the run and
+// their error kept in their report, instead of failing it.
+async fn translate_files(
+    files: &mut dyn BookFiles,
+    options: &TranslateOptions,
+    journal: &Journal,
+    per_file: bool,
+) -> Result<(TranslationReport, Vec<FileReport>), EpubTranslatorError> {
+    let start = Instant::now();
 
-    progress_bar.inc(1);
+    // Encrypted documents would be translated as garbage, obfuscated fonts are only copied
+    let fonts = check_encryption(files)?;
+    if !fonts.is_empty() {
+        journal.record("fonts_obfuscated", fonts.len());
+        options.progress.on_event(ProgressEvent::Warning {
+            message: format!(
+                "{} embedded fonts are obfuscated, they are copied as they are",
+                fonts.len()
+            ),
+        });
+    }
 
-    // 3. Create Channels
-    let writer_queue_size = 15_000;
+    let package = if files.is_file(Path::new(CONTAINER_PATH)) {
+        Some(Package::open(files)?)
+    } else {
+        None
+    };
 
-    let (tx_translator, rx_translator) = mpsc::channel::<TranslationRequest>(writer_queue_size);
-    let (tx_writer, mut rx_writer) = mpsc::channel::<TranslationResult>(writer_queue_size);
+    // Text of another length may not fit fixed pages
+    let fixed_layout = package
+        .as_ref()
+        .map(Package::fixed_layout_documents)
+        .unwrap_or_default();
+    if !fixed_layout.is_empty() {
+        if !options.fixed_layout {
+            return Err(FixedLayoutError {
+                pages: fixed_layout,
+            }
+            .into());
+        }
+        options.progress.on_event(ProgressEvent::Warning {
+            message: format!(
+                "The EPUB has a fixed layout, check its {} pages for overflowing text",
+                fixed_layout.len()
+            ),
+        });
+    }
 
-    // 4. Spawn a Translator
-    let _translator_handle = tokio::spawn(run_translator(
-        configurations,
-        concurrent_requests,
-        target_lang,
-        rx_translator,
-        tx_writer,
-    ));
+    // Documents left out of the selection are not even parsed
+    let mut xhtml_files = content_document_paths(files)?;
+    xhtml_files.retain(|path| {
+        let chapter = package.as_ref().and_then(|package| package.chapter(path));
+        let selected = options.selection.selects(path, chapter);
+        if !selected {
+            journal.record("file_skipped", files.location(path).display());
+        }
+        selected
+    });
 
-    let texts_enumerated: Vec<Arc<String>> = nodes
+    let mut file_reports: Vec<FileReport> = xhtml_files
         .iter()
-        .map(|node| {
-            if let NodeData::Text { contents } = &node.data {
-                let text = contents.borrow().to_string();
-                Arc::new(text)
-            } else {
-                Arc::new(String::new())
-            }
+        .map(|path| FileReport {
+            path: path.clone(),
+            ..Default::default()
         })
         .collect();
 
-    // 5. Send initial translation requests to the Translator
-    // Note: Ensure the Translator is created and listening before sending requests
-    // to avoid potential failures in message transmission
-    let mut completed = 0;
-    for (id, text) in texts_enumerated.iter().enumerate() {
-        eprintln!(
-            "[{}] NodeContent: |{}| Sending request to Translator",
-            id, &text
-        );
-        if let Err(error) = tx_translator
-            .send(TranslationRequest {
-                id: id,
-                text: text.clone(),
-            })
-            .await
-        {
-            eprintln!("[{}] Error sending message to translator: {}", id, error);
-            completed += 1;
-            progress_bar.inc(1);
-        };
+    // 1. Create document iterator, the files are read in turn, then decoded and parsed in
+    // parallel, see `Documents`. Files without text, like covers, are not parsed and are left
+    // byte for byte as they are
+    let contents: Vec<io::Result<Vec<u8>>> =
+        xhtml_files.iter().map(|path| files.read(path)).collect();
+    let (documents, parsed) =
+        Documents::parse(contents, &options.extract, options.serialize.entities);
+    let mut document_paths: Vec<PathBuf> = Vec::new();
+    for ((file_path, file_report), parsed) in
+        xhtml_files.into_iter().zip(&mut file_reports).zip(parsed)
+    {
+        match parsed {
+            Parsed::Document => document_paths.push(file_path),
+            Parsed::Untouched => {
+                journal.record("file_untouched", files.location(&file_path).display())
+            }
+            Parsed::Failed(error) if per_file => {
+                journal.record(
+                    "file_unreadable",
+                    format!("{} error={}", files.location(&file_path).display(), error),
+                );
+                file_report.error = Some(error.to_string());
+            }
+            Parsed::Failed(error) => return Err(error),
+        }
     }
 
-    eprintln!("Total nodes: {}", total_nodes);
-
-    let mut retries: Vec<usize> = vec![0; total_nodes];
-
-    eprintln!("Actual retries length: {}", retries.len());
+    // Names are found in the whole book before its units are extracted, to be kept in them
+    let names = match options.protect_names {
+        true => book_names(
+            &documents
+                .segments(&options.extract)
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+            options,
+            journal,
+        ),
+        false => Vec::new(),
+    };
+    let extract = keeping_names(&options.extract, &names);
 
-    // 6. Spawn Writer
-    //
-    // Ressources:
-    // - Writer receiver `rx_writer`
-    // - Node reference `vector nodes` (Vec<Rc<Node>>)
-    // - Translator sender `tx_translator`
-    // - Progress counter `completed`
-    // - retries
-    while let Some(TranslationResult {
-        id,
-        translated_text,
-    }) = rx_writer.recv().await
+    // 2. Create segment iterator
+    // This approach enables parallelization across all documents,
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut fixed_layout_pages = Vec::new();
+    // File of each segment and its position there, from 1
+    let mut units: Vec<(&Path, usize)> = Vec::new();
+    // Segments of each document
+    let mut document_units: Vec<usize> = Vec::new();
+    for (document_segments, path) in documents
+        .segments(&extract)
+        .into_iter()
+        .zip(&document_paths)
     {
-        eprintln!(
-            "[{}] [Writer] Received: {}, Received result: {:?}",
-            id, completed, translated_text
+        units.extend((1..=document_segments.len()).map(|position| (path.as_path(), position)));
+        document_units.push(document_segments.len());
+        if !document_segments.is_empty() && fixed_layout.contains(path) {
+            fixed_layout_pages.push(path.clone());
+        }
+        journal.record(
+            "file_parsed",
+            format!(
+                "{} units={}",
+                files.location(path).display(),
+                document_segments.len()
+            ),
         );
-        if let Some(translated_text) = translated_text.borrow() {
-            if let NodeData::Text { contents } = &nodes[id].data {
-                let mut text = contents.borrow_mut();
-                *text = StrTendril::from_slice(translated_text);
-                completed += 1;
-                progress_bar.inc(1);
-            }
-        } else {
-            eprintln!("Actual retries length before if: {}", retries.len());
-            if retries[id] < max_retries {
-                retries[id] += 1;
-                if let Err(error) = tx_translator
-                    .send(TranslationRequest {
-                        id: id,
-                        text: texts_enumerated[id].clone(),
-                    })
-                    .await
-                {
-                    eprintln!("[{}] Error sending message to translator: {}", id, error);
-                    completed += 1;
-                    progress_bar.inc(1);
-                };
-            } else {
-                completed += 1;
-                progress_bar.inc(1);
-            }
+        segments.extend(document_segments);
+    }
+
+    // The package metadata and the NCX are translated along with the documents
+    let mut metadata_segments: Vec<(usize, usize)> = Vec::new();
+    let mut ncx_segments: Vec<(usize, usize)> = Vec::new();
+    let ncx_path = package
+        .as_ref()
+        .and_then(Package::ncx_path)
+        .filter(|path| files.is_file(path));
+    if let Some(package) = package
+        .as_ref()
+        .filter(|_| options.extract.translate_metadata)
+    {
+        for entry in package.translatable_metadata(files)? {
+            metadata_segments.push((entry.index, segments.len()));
+            segments.push(Segment::detached_with(&entry.text, None, &extract));
+            units.push((&package.path, metadata_segments.len()));
         }
-        // Exit condition: All nodes have been processed
-        // Note: This breaks the loop to avoid a deadlock scenario
-        // TODO: Implement a more robust termination mechanism
-        if completed == total_nodes {
-            eprintln!("END OF WRITER");
-            break;
+        journal.record(
+            "file_parsed",
+            format!(
+                "{} units={}",
+                files.location(&package.path).display(),
+                metadata_segments.len()
+            ),
+        );
+    }
+    if let Some(ncx_path) = &ncx_path {
+        for label in navigation_labels(files, ncx_path)? {
+            ncx_segments.push((label.index, segments.len()));
+            segments.push(Segment::detached_with(&label.text, None, &extract));
+            units.push((ncx_path, ncx_segments.len()));
         }
+        journal.record(
+            "file_parsed",
+            format!(
+                "{} units={}",
+                files.location(ncx_path).display(),
+                ncx_segments.len()
+            ),
+        );
     }
 
-    progress_bar.finish_with_message("Translation completed");
+    let end_preprocessing = Instant::now();
+    let preprocessing_duration = end_preprocessing - start;
+    info!("Preprocessing duration: {:?}", preprocessing_duration);
 
-    let end_translation = Instant::now();
-    let translation_duration = end_translation - end_preprocessing;
-    profiling_log!(verbose, "Translation duration: {:?}", translation_duration);
+    // Checked before anything is sent
+    let book_language = check_book_language(
+        &segments[..document_units.iter().sum()],
+        package
+            .as_ref()
+            .and_then(|package| package.language.as_deref()),
+        options,
+        journal,
+    )?;
+    let back_translation = match options.back_translation {
+        Some(sample) => {
+            let source_lang = options.source_lang.clone().or_else(|| {
+                let package = package.as_ref()?;
+                Some(package.language.as_ref()?.to_uppercase())
+            });
+            let Some(source_lang) = source_lang else {
+                return Err(EpubTranslatorError::InvalidOptions(
+                    "the back translation needs the source language, the book declares none"
+                        .to_string(),
+                ));
+            };
+            Some((sample, source_lang))
+        }
+        None => None,
+    };
 
-    // 7. Serialize all documents
-    for (document, path) in &documents {
-        serialize_document(&document, &path)?;
+    // 3. Translate
+    plan_units(&segments, units.iter().map(|&(path, _)| path), options);
+    let (mut report, statuses) = match &options.xliff {
+        Some(xliff) => apply_xliff(&segments, &units, xliff, options, journal),
+        None => translate_segments(&segments, options, journal).await?,
+    };
+    report.fixed_layout_pages = fixed_layout_pages;
+    report.names = names;
+    if !options.localize.is_empty() {
+        let language = book_language.as_deref();
+        localize_units(&segments, &statuses, language, options, journal);
+    }
+    report.flagged = flag_units(&segments, &statuses, journal);
+    if let Some((sample, source_lang)) = back_translation {
+        let back_translation = back_translate(
+            &segments,
+            &statuses,
+            &units,
+            sample,
+            &source_lang,
+            report.billed_characters,
+            options,
+            journal,
+        )
+        .await?;
+        report.billed_characters += back_translation.1;
+        report.back_translation = Some(back_translation.0);
+    }
+    if let Some(stop) = &mut report.budget_stop {
+        let (path, position) = units[stop.position - 1];
+        stop.path = path.to_path_buf();
+        stop.position = position;
+    }
+    for failure in &mut report.failures {
+        let (path, position) = units[failure.position - 1];
+        failure.path = path.to_path_buf();
+        failure.position = position;
+    }
+    for flagged in &mut report.flagged {
+        let (path, position) = units[flagged.position - 1];
+        flagged.path = path.to_path_buf();
+        flagged.position = position;
+    }
+    if options.alignment {
+        report.alignment = segments
+            .iter()
+            .zip(&units)
+            .map(|(segment, &(path, position))| AlignedUnit {
+                path: path.to_path_buf(),
+                position,
+                source: segment.source_text(),
+                translation: segment.translation(),
+                source_markup: segment.text.to_string(),
+                translation_markup: segment.translation_markup(),
+            })
+            .collect();
+        report.source_lang = options
+            .source_lang
+            .as_deref()
+            .map(language_tag)
+            .or_else(|| {
+                package
+                    .as_ref()
+                    .and_then(|package| package.language.clone())
+            });
     }
 
-    let end_serialization = Instant::now();
-    let serialization_duration = end_serialization - end_translation;
-    profiling_log!(
-        verbose,
-        "Serialization duration: {:?}",
-        serialization_duration
-    );
-
-    Ok(())
-}
+    let end_translation = Instant::now();
+    let translation_duration = end_translation - end_preprocessing;
+    info!("Translation duration: {:?}", translation_duration);
+
+    // 4. Serialize all documents in the target language. The translations of their segments are
+    // applied to them on the threads that parsed them
+    let lang = language_tag(&options.target_lang);
+    let mut first = 0;
+    let markups: Vec<Vec<Option<String>>> = document_units
+        .iter()
+        .map(|&count| {
+            let markups = segments[first..first + count]
+                .iter()
+                .map(Segment::translation_markup)
+                .collect();
+            first += count;
+            markups
+        })
+        .collect();
+    let translations = |units: &[(usize, usize)]| -> HashMap<usize, String> {
+        units
+            .iter()
+            .filter_map(|&(index, id)| Some((index, segments[id].translation()?)))
+            .collect()
+    };
+    let metadata_translations = translations(&metadata_segments);
+    let ncx_translations = translations(&ncx_segments);
+    let outputs = documents.write(markups, &lang, options);
+    for (path, output) in document_paths.iter().zip(outputs) {
+        let written = output.and_then(|output| Ok(files.write(path, output.into_bytes())?));
+        match written {
+            Ok(()) => {}
+            Err(error) if per_file => {
+                if let Some(file_report) = file_reports.iter_mut().find(|file| file.path == *path) {
+                    file_report.error = Some(error.to_string());
+                }
+                continue;
+            }
+            Err(error) => return Err(error),
+        }
+        let location = files.location(path);
+        journal.record("file_serialized", location.display());
+        options
+            .progress
+            .on_event(ProgressEvent::FileSerialized { path: location });
+    }
+    if let Some(package) = package.as_ref().filter(|_| !metadata_segments.is_empty()) {
+        package.set_metadata_translations(files, &metadata_translations)?;
+        journal.record("file_serialized", files.location(&package.path).display());
+    }
+    if let Some(ncx_path) = &ncx_path {
+        set_navigation_labels(files, ncx_path, &ncx_translations)?;
+        journal.record("file_serialized", files.location(ncx_path).display());
+    }
+
+    let end_serialization = Instant::now();
+    let serialization_duration = end_serialization - end_translation;
+    info!("Serialization duration: {:?}", serialization_duration);
+
+    finish(&report, options, journal);
+
+    // The metadata and the NCX are reported after the documents, when they were translated
+    for (path, _) in &units {
+        if !file_reports.iter().any(|file| file.path == *path) {
+            file_reports.push(FileReport {
+                path: path.to_path_buf(),
+                ..Default::default()
+            });
+        }
+    }
+    split_report(&report, &statuses, &units, &mut file_reports);
+
+    Ok((report, file_reports))
+}
+
+// Fills the report of each file with the units of the run that are in it.
+fn split_report(
+    report: &TranslationReport,
+    statuses: &[UnitStatus],
+    units: &[(&Path, usize)],
+    file_reports: &mut [FileReport],
+) {
+    let indexes: HashMap<&Path, usize> = file_reports
+        .iter()
+        .enumerate()
+        .map(|(index, file)| (file.path.as_path(), index))
+        .collect();
+    let mut counts = vec![TranslationReport::default(); file_reports.len()];
+    for (status, (path, _)) in statuses.iter().zip(units) {
+        let count = &mut counts[indexes[path]];
+        count.total += 1;
+        match status {
+            UnitStatus::Translated => count.translated += 1,
+            UnitStatus::FromMemory => {
+                count.translated += 1;
+                count.from_memory += 1;
+            }
+            UnitStatus::FromCache => {
+                count.translated += 1;
+                count.from_cache += 1;
+            }
+            UnitStatus::Failed { quota_exceeded } => {
+                count.failed += 1;
+                count.quota_exceeded += usize::from(*quota_exceeded);
+            }
+            UnitStatus::Cancelled => count.cancelled += 1,
+            UnitStatus::OverBudget => count.over_budget += 1,
+            UnitStatus::NotSampled => count.not_sampled += 1,
+            UnitStatus::Pending => {}
+        }
+    }
+    for (file, count) in file_reports.iter_mut().zip(counts) {
+        let in_file = |path: &Path| path == file.path;
+        file.report = TranslationReport {
+            budget_stop: report
+                .budget_stop
+                .clone()
+                .filter(|stop| in_file(&stop.path)),
+            failures: report
+                .failures
+                .iter()
+                .filter(|failure| in_file(&failure.path))
+                .cloned()
+                .collect(),
+            flagged: report
+                .flagged
+                .iter()
+                .filter(|flagged| in_file(&flagged.path))
+                .cloned()
+                .collect(),
+            fixed_layout_pages: report
+                .fixed_layout_pages
+                .iter()
+                .filter(|page| in_file(page))
+                .cloned()
+                .collect(),
+            alignment: report
+                .alignment
+                .iter()
+                .filter(|unit| in_file(&unit.path))
+                .cloned()
+                .collect(),
+            source_lang: report.source_lang.clone(),
+            ..count
+        };
+    }
+}
+
+/// Translates a standalone XHTML (or HTML) document and returns it serialized as XHTML.
+///
+/// The same segmentation, translation and serialization as for EPUBs are used, see
+/// `translate_folder`.
+pub async fn translate_xhtml_string(
+    content: &str,
+    options: &TranslateOptions,
+) -> Result<String, EpubTranslatorError> {
+    let (output, _) = translate_document(content, options).await?;
+    Ok(output)
+}
+
+/// Translates an XHTML (or HTML) file in place, like `translate_folder` does for each file.
+pub async fn translate_xhtml_file(
+    file_path: &Path,
+    options: &TranslateOptions,
+) -> Result<TranslationReport, EpubTranslatorError> {
+    let content = read_xhtml_file(file_path)?;
+    let (output, report) = translate_document(&content, options).await?;
+    std::fs::write(file_path, output)?;
+    options.progress.on_event(ProgressEvent::FileSerialized {
+        path: file_path.to_path_buf(),
+    });
+    Ok(report)
+}
+
+async fn translate_document(
+    content: &str,
+    options: &TranslateOptions,
+) -> Result<(String, TranslationReport), EpubTranslatorError> {
+    let journal = Journal::disabled();
+    let (document, references) = parse_xhtml(content, options.serialize.entities)?;
+    let names = match options.protect_names {
+        true => book_names(
+            &get_segments(&document, &options.extract),
+            options,
+            &journal,
+        ),
+        false => Vec::new(),
+    };
+    let extract = keeping_names(&options.extract, &names);
+    let segments = get_segments(&document, &extract);
+    let originals = copy_originals(&document, &segments, options);
+
+    plan_units(&segments, segments.iter().map(|_| Path::new("")), options);
+    let (mut report, statuses) = translate_segments(&segments, options, &journal).await?;
+    report.names = names;
+    if !options.localize.is_empty() {
+        let language = sample_language(&segments);
+        localize_units(&segments, &statuses, language, options, &journal);
+    }
+    report.flagged = flag_units(&segments, &statuses, &journal);
+
+    rewrite_lang(
+        &document,
+        &language_tag(&options.target_lang),
+        options.serialize.rewrite_inner_lang,
+    );
+    if let Some(originals) = originals {
+        originals.insert();
+    }
+    let output = serialize_document_to_string_with(&document, &references)?;
+
+    finish(&report, options, &journal);
+
+    Ok((output, report))
+}
+
+// Parses a document, the references of the source are kept to write them back, see `EntityPolicy`.
+fn parse_xhtml(
+    content: &str,
+    entities: EntityPolicy,
+) -> Result<(Rc<Node>, EntityReferences), EpubTranslatorError> {
+    Ok((
+        get_document_node(content)?,
+        source_references(content, entities)?,
+    ))
+}
+
+fn source_references(
+    content: &str,
+    entities: EntityPolicy,
+) -> Result<EntityReferences, EpubTranslatorError> {
+    match entities {
+        EntityPolicy::Preserve => EntityReferences::scan(content),
+        EntityPolicy::Characters => Ok(EntityReferences::default()),
+    }
+}
+
+// Copies the blocks of a document before they are translated, when the output is bilingual.
+fn copy_originals(
+    document: &Rc<Node>,
+    segments: &[Segment],
+    options: &TranslateOptions,
+) -> Option<Originals> {
+    let mode = options.bilingual?;
+    let blocks: Vec<Rc<Node>> = segments
+        .iter()
+        .filter_map(Segment::block)
+        .cloned()
+        .collect();
+    let lang = options.source_lang.as_deref().map(language_tag);
+    Some(Originals::copy(document, &blocks, mode, lang))
+}
+
+// Announces the units of a run and the file of each, before they are translated.
+fn plan_units<'a>(
+    segments: &[Segment],
+    paths: impl Iterator<Item = &'a Path>,
+    options: &TranslateOptions,
+) {
+    let picks = sample_picks(segments.len(), options);
+    for (id, (segment, path)) in segments.iter().zip(paths).enumerate() {
+        if !picks[id] {
+            continue;
+        }
+        options.progress.on_event(ProgressEvent::NodePlanned {
+            id,
+            path: path.to_path_buf(),
+            characters: segment.billable_chars(),
+        });
+    }
+}
+
+// Whether each unit of the run is sent, all of them unless `options.sample` is set.
+fn sample_picks(total: usize, options: &TranslateOptions) -> Vec<bool> {
+    match options.sample {
+        Some(sample) => sample.picks(total),
+        None => vec![true; total],
+    }
+}
+
+fn finish(report: &TranslationReport, options: &TranslateOptions, journal: &Journal) {
+    journal.record(
+        "translation_finished",
+        format!(
+            "total={} translated={} failed={} cancelled={} over_budget={} not_sampled={}",
+            report.total,
+            report.translated,
+            report.failed,
+            report.cancelled,
+            report.over_budget,
+            report.not_sampled
+        ),
+    );
+    options.progress.on_event(ProgressEvent::Finished);
+}
+
+/// Translates the segments and writes the translations back into their documents.
+///
+/// This function:
+/// 1. Sets up two channels:
+///     - Translator_Channel (TranslationRequest)
+///     - Writer_Channel (TranslationResult)
+/// 2. Spawns Translator:
+///     - Listens on Translator_Channel, spawning TranslationTasks as needed
+/// 3. For each segment:
+///     - Sends a TranslationRequest to Translator
+/// 4. Spawns Writer:
+///     - Listens on Writer_Channel for TranslationResults
+///         - Modifies segments if successful; retries if not (up to max attempts)
+///     - Closes channels when done (note: deadlock risk if incomplete)
+///
+/// Note: Ideally, TranslationRequests would be sent post-Writer spawn, but this requires moving
+/// Writer (owner of segments, Vec<Segment>) across threads.
+async fn translate_segments(
+    segments: &[Segment],
+    options: &TranslateOptions,
+    journal: &Journal,
+) -> Result<(TranslationReport, Vec<UnitStatus>), EpubTranslatorError> {
+    if options.configurations.is_empty() && options.provider.local().is_none() {
+        return Err(EpubTranslatorError::InvalidOptions(
+            "no DeepL configuration to send the requests to".to_string(),
+        ));
+    }
+    let max_retries = options.max_retries;
+    let progress = &options.progress;
+    let cancel = &options.cancel;
+
+    let total_nodes = segments.len();
+    trace!("[TRACE]id,len,error_code,start,request_duration,available_permits,thread");
+
+    progress.on_event(ProgressEvent::Started { total: total_nodes });
+
+    // 1. Create Channels
+    let writer_queue_size = 15_000;
+
+    let (tx_translator, rx_translator) = mpsc::channel::<TranslationRequest>(writer_queue_size);
+    let (tx_writer, mut rx_writer) = mpsc::channel::<TranslationResult>(writer_queue_size);
+
+    // 2. Spawn a Translator
+    let context = Arc::new(TaskContext {
+        target_lang: options.target_lang.clone(),
+        formality: options.formality,
+        local: options.provider.local(),
+        semaphore: Semaphore::new(options.concurrent_requests),
+        client: options.client.clone(),
+        cancel: cancel.clone(),
+    });
+    spawn(run_translator(
+        options.configurations.clone(),
+        context,
+        rx_translator,
+        tx_writer,
+    ));
+
+    let mut report = TranslationReport {
+        total: total_nodes,
+        ..Default::default()
+    };
+    let mut statuses = vec![UnitStatus::Pending; total_nodes];
+
+    // Billable characters sent so far, no request is sent past `options.max_chars`, nor for
+    // the units past `options.max_units`
+    let mut billed = 0;
+    // Local providers bill nothing, see `telemetry::BILLED_CHARACTERS`
+    let billing = options.provider.local().is_none();
+    let mut cache = match &options.cache {
+        Some(path) if billing => Some(TranslationCache::open(path)?),
+        _ => None,
+    };
+    let within_budget = |billed: usize, id: usize| {
+        options
+            .max_chars
+            .is_none_or(|max| billed + segments[id].billable_chars() <= max)
+            && options.max_units.is_none_or(|max| id < max)
+    };
+
+    // 3. Send initial translation requests to the Translator
+    // Note: Ensure the Translator is created and listening before sending requests
+    // to avoid potential failures in message transmission
+    let picks = sample_picks(segments.len(), options);
+    let mut completed = 0;
+    for (id, segment) in segments.iter().enumerate() {
+        let cached = cache
+            .as_ref()
+            .and_then(|cache| cache.get(&segment.text, &options.target_lang));
+        if let Some(cached) = cached {
+            if segment.apply(&options.glossary.apply(cached)).is_ok() {
+                completed += 1;
+                report.translated += 1;
+                report.from_cache += 1;
+                statuses[id] = UnitStatus::FromCache;
+                journal.record("cache_applied", format!("id={}", id));
+                progress.on_event(ProgressEvent::NodeTranslated {
+                    id,
+                    source: segment.source_text(),
+                    translation: segment.translation().unwrap_or_default(),
+                });
+                continue;
+            }
+        }
+        let remembered = options
+            .memory
+            .as_ref()
+            .and_then(|memory| memory.lookup(&segment.source_text(), &options.target_lang));
+        // A plain translation doesn't fit units with inline markup or kept terms, they are sent
+        if let Some(remembered) = remembered {
+            let translation = options.glossary.apply(&escape_xml(&remembered.translation));
+            if segment.apply(&translation).is_ok() {
+                completed += 1;
+                report.translated += 1;
+                report.from_memory += 1;
+                statuses[id] = UnitStatus::FromMemory;
+                journal.record(
+                    "memory_applied",
+                    format!("id={} similarity={:.2}", id, remembered.similarity),
+                );
+                progress.on_event(ProgressEvent::NodeTranslated {
+                    id,
+                    source: segment.source_text(),
+                    translation: segment.translation().unwrap_or_default(),
+                });
+                continue;
+            }
+        }
+        if !picks[id] {
+            completed += 1;
+            report.not_sampled += 1;
+            statuses[id] = UnitStatus::NotSampled;
+            continue;
+        }
+        // Units are sent in reading order, everything after the first one over budget is left
+        if report.budget_stop.is_some() || !within_budget(billed, id) {
+            if report.budget_stop.is_none() {
+                journal.record("budget_reached", format!("id={} billed={}", id, billed));
+                // The position in the run, `translate_book` turns it into a file and position
+                report.budget_stop = Some(BudgetStop {
+                    path: PathBuf::new(),
+                    position: id + 1,
+                    source: segments[id].source_text(),
+                    billable_characters: billed,
+                });
+            }
+            completed += 1;
+            report.over_budget += 1;
+            statuses[id] = UnitStatus::OverBudget;
+            continue;
+        }
+        billed += segments[id].billable_chars();
+        if billing {
+            telemetry::record_billed(segments[id].billable_chars());
+        }
+        trace!(
+            "[{}] NodeContent: |{}| Sending request to Translator",
+            id,
+            segment.text
+        );
+        if let Err(error) = tx_translator
+            .send(TranslationRequest {
+                id,
+                text: segment.text.clone(),
+                context: segment.context.clone(),
+                preserve_whitespace: segment.preserve_whitespace,
+            })
+            .await
+        {
+            warn!("[{}] Error sending message to translator: {}", id, error);
+            completed += 1;
+            report.failed += 1;
+            statuses[id] = UnitStatus::Failed {
+                quota_exceeded: false,
+            };
+            report
+                .failures
+                .push(failed_unit(&segments[id], id, error.to_string()));
+            progress.on_event(ProgressEvent::NodeFailed {
+                id,
+                error: error.to_string(),
+            });
+        } else {
+            journal.record("segment_dispatched", format!("id={} attempt=0", id));
+            progress.on_event(ProgressEvent::NodeQueued { id });
+        };
+    }
+
+    info!("Segments to translate: {}", total_nodes);
+
+    let mut retries: Vec<usize> = vec![0; total_nodes];
+
+    // 4. Spawn Writer
+    //
+    // Ressources:
+    // - Writer receiver `rx_writer`
+    // - Segment reference `vector segments` (Vec<Segment>)
+    // - Translator sender `tx_translator`
+    // - Progress counter `completed`
+    // - retries
+    //
+    // Every dispatched request produces exactly one result, cancelled or not, so the
+    // writer stops once all nodes are accounted for.
+    while completed < total_nodes {
+        let Some(TranslationResult { id, outcome }) = rx_writer.recv().await else {
+            break;
+        };
+        trace!(
+            "[{}] [Writer] Received: {}, Received result: {:?}",
+            id,
+            completed,
+            outcome
+        );
+        // A translation whose placeholders can't be mapped back counts as a failed attempt
+        let outcome = match outcome {
+            TranslationOutcome::Translated(translated_text) => {
+                let glossed = options.glossary.apply(&translated_text);
+                match segments[id].apply(&glossed) {
+                    Ok(()) => {
+                        // Kept as received, the glossary of the next runs may differ
+                        let text = &segments[id].text;
+                        if let Some(Err(error)) = cache
+                            .as_mut()
+                            .map(|cache| cache.insert(text, &options.target_lang, &translated_text))
+                        {
+                            warn!("[{}] Error writing to the translation cache: {}", id, error);
+                        }
+                        TranslationOutcome::Translated(glossed)
+                    }
+                    Err(error) => TranslationOutcome::Failed(error.to_string()),
+                }
+            }
+            outcome => outcome,
+        };
+        // Retried like other failures, another key may still have characters left
+        let quota_exceeded = matches!(outcome, TranslationOutcome::QuotaExceeded(_));
+        match outcome {
+            TranslationOutcome::Translated(_) => {
+                completed += 1;
+                report.translated += 1;
+                statuses[id] = UnitStatus::Translated;
+                journal.record("result_applied", format!("id={}", id));
+                progress.on_event(ProgressEvent::NodeTranslated {
+                    id,
+                    source: segments[id].source_text(),
+                    translation: segments[id].translation().unwrap_or_default(),
+                });
+            }
+            TranslationOutcome::Cancelled => {
+                completed += 1;
+                report.cancelled += 1;
+                statuses[id] = UnitStatus::Cancelled;
+                journal.record("segment_cancelled", format!("id={}", id));
+            }
+            TranslationOutcome::Failed(last_error)
+            | TranslationOutcome::QuotaExceeded(last_error) => {
+                // Failed requests are not retried once the run is cancelled
+                let retried = retries[id] < max_retries
+                    && !cancel.is_cancelled()
+                    && within_budget(billed, id);
+                if retried {
+                    retries[id] += 1;
+                    billed += segments[id].billable_chars();
+                    telemetry::record_retry();
+                    if billing {
+                        telemetry::record_billed(segments[id].billable_chars());
+                    }
+                    progress.on_event(ProgressEvent::NodeRetried {
+                        id,
+                        attempt: retries[id],
+                        error: last_error.clone(),
+                    });
+                    journal.record(
+                        "segment_dispatched",
+                        format!(
+                            "id={} attempt={} last_error={}",
+                            id, retries[id], last_error
+                        ),
+                    );
+                    if let Err(error) = tx_translator
+                        .send(TranslationRequest {
+                            id,
+                            text: segments[id].text.clone(),
+                            context: segments[id].context.clone(),
+                            preserve_whitespace: segments[id].preserve_whitespace,
+                        })
+                        .await
+                    {
+                        warn!("[{}] Error sending message to translator: {}", id, error);
+                        completed += 1;
+                        report.failed += 1;
+                        statuses[id] = UnitStatus::Failed {
+                            quota_exceeded: false,
+                        };
+                        report
+                            .failures
+                            .push(failed_unit(&segments[id], id, error.to_string()));
+                        progress.on_event(ProgressEvent::NodeFailed {
+                            id,
+                            error: error.to_string(),
+                        });
+                    };
+                } else {
+                    completed += 1;
+                    report.failed += 1;
+                    if quota_exceeded {
+                        report.quota_exceeded += 1;
+                    }
+                    statuses[id] = UnitStatus::Failed { quota_exceeded };
+                    journal.record("segment_failed", format!("id={} error={}", id, last_error));
+                    report
+                        .failures
+                        .push(failed_unit(&segments[id], id, last_error.clone()));
+                    progress.on_event(ProgressEvent::NodeFailed {
+                        id,
+                        error: last_error,
+                    });
+                }
+            }
+        }
+    }
+    debug!("END OF WRITER");
+
+    if report.was_cancelled() {
+        progress.on_event(ProgressEvent::Cancelled {
+            remaining: report.cancelled,
+        });
+    }
+    // Retries finish in any order
+    report.failures.sort_by_key(|failure| failure.position);
+    if billing {
+        report.billed_characters = billed;
+    }
+
+    Ok((report, statuses))
+}
+
+// Applies the targets of `xliff` to the units of a book, by file and position, instead of
+// translating them, see `TranslateOptions::xliff`.
+fn apply_xliff(
+    segments: &[Segment],
+    units: &[(&Path, usize)],
+    xliff: &Xliff,
+    options: &TranslateOptions,
+    journal: &Journal,
+) -> (TranslationReport, Vec<UnitStatus>) {
+    let progress = &options.progress;
+    progress.on_event(ProgressEvent::Started {
+        total: segments.len(),
+    });
+    let mut report = TranslationReport {
+        total: segments.len(),
+        ..Default::default()
+    };
+    let mut statuses = Vec::with_capacity(segments.len());
+    for (id, (segment, &(path, position))) in segments.iter().zip(units).enumerate() {
+        let applied = match xliff.target(path, position) {
+            Some(target) => segment.apply(target).map_err(|e| e.to_string()),
+            None => Err("no target in the XLIFF file".to_string()),
+        };
+        match applied {
+            Ok(()) => {
+                report.translated += 1;
+                statuses.push(UnitStatus::Translated);
+                journal.record("result_applied", format!("id={}", id));
+                progress.on_event(ProgressEvent::NodeTranslated {
+                    id,
+                    source: segment.source_text(),
+                    translation: segment.translation().unwrap_or_default(),
+                });
+            }
+            Err(error) => {
+                report.failed += 1;
+                statuses.push(UnitStatus::Failed {
+                    quota_exceeded: false,
+                });
+                journal.record("segment_failed", format!("id={} error={}", id, error));
+                report
+                    .failures
+                    .push(failed_unit(segment, id, error.clone()));
+                progress.on_event(ProgressEvent::NodeFailed { id, error });
+            }
+        }
+    }
+    (report, statuses)
+}
+
+// What became of a unit in a run, to split its report by file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnitStatus {
+    // Never accounted for, e.g. when the writer channel closed early
+    Pending,
+    Translated,
+    FromMemory,
+    FromCache,
+    Failed { quota_exceeded: bool },
+    Cancelled,
+    OverBudget,
+    NotSampled,
+}
+
+// The failure of the unit `id`, at its position in the run, `translate_book` turns it into a
+// file and position.
+fn failed_unit(segment: &Segment, id: usize, error: String) -> FailedUnit {
+    FailedUnit {
+        path: PathBuf::new(),
+        position: id + 1,
+        location: segment.location(),
+        source: segment.source_text(),
+        error,
+    }
+}
+
+// Checks the translated units of a run, see `qa::check`. The units flagged are at their
+// position in the run, like failed units.
+fn flag_units(
+    segments: &[Segment],
+    statuses: &[UnitStatus],
+    journal: &Journal,
+) -> Vec<FlaggedUnit> {
+    let mut flagged = Vec::new();
+    for (id, (segment, status)) in segments.iter().zip(statuses).enumerate() {
+        if !matches!(
+            status,
+            UnitStatus::Translated | UnitStatus::FromMemory | UnitStatus::FromCache
+        ) {
+            continue;
+        }
+        let anomalies = qa::check_segment(segment);
+        if anomalies.is_empty() {
+            continue;
+        }
+        let messages: Vec<String> = anomalies.iter().map(ToString::to_string).collect();
+        journal.record(
+            "segment_flagged",
+            format!("id={} {}", id, messages.join("; ")),
+        );
+        flagged.push(FlaggedUnit {
+            path: PathBuf::new(),
+            position: id + 1,
+            location: segment.location(),
+            source: segment.source_text(),
+            translation: segment.translation().unwrap_or_default(),
+            anomalies,
+        });
+    }
+    flagged
+}
+
+// Units the language of a book is detected on, at most
+const LANGUAGE_SAMPLE_UNITS: usize = 200;
+
+// Refuses a book already in the target language, or warns about it with
+// `TranslateOptions::same_language`. The language detected in a sample of the units spread
+// across the book wins over the declared one, often left to the default of the tool that made
+// the book. Returns the language of the book, if any.
+fn check_book_language(
+    segments: &[Segment],
+    declared: Option<&str>,
+    options: &TranslateOptions,
+    journal: &Journal,
+) -> Result<Option<String>, EpubTranslatorError> {
+    let detected = sample_language(segments);
+    journal.record("language_detected", detected.unwrap_or("none"));
+    if let Some(error) = same_language_error(detected, declared, &options.target_lang) {
+        if !options.same_language {
+            return Err(error.into());
+        }
+        options.progress.on_event(ProgressEvent::Warning {
+            message: format!("{}, it is translated anyway", error),
+        });
+    }
+    Ok(detected.or(declared).map(str::to_string))
+}
+
+// The error of a book in `target_lang`, by its `detected` language or else the `declared` one.
+fn same_language_error(
+    detected: Option<&str>,
+    declared: Option<&str>,
+    target_lang: &str,
+) -> Option<SameLanguageError> {
+    let language = detected.or(declared)?;
+    languages::is_same_language(language, target_lang).then(|| SameLanguageError {
+        language: language.to_string(),
+        detected: detected.is_some(),
+    })
+}
+
+// The language detected in the selected documents of a book and the one declared in its
+// package, as `translate_book` checks them, to check several target languages at once.
+fn sample_book_language(
+    files: &dyn BookFiles,
+    options: &TranslateOptions,
+) -> Result<(Option<&'static str>, Option<String>), EpubTranslatorError> {
+    let package = match files.is_file(Path::new(CONTAINER_PATH)) {
+        true => Some(Package::open(files)?),
+        false => None,
+    };
+    let mut segments = Vec::new();
+    for path in content_document_paths(files)? {
+        let chapter = package.as_ref().and_then(|package| package.chapter(&path));
+        if !options.selection.selects(&path, chapter) {
+            continue;
+        }
+        let content = decode_xhtml(&files.read(&path)?)?;
+        if options.extract.may_have_text(&content) {
+            let (document, _) = parse_xhtml(&content, options.serialize.entities)?;
+            segments.extend(get_segments(&document, &options.extract));
+        }
+    }
+    let declared = package.and_then(|package| package.language);
+    Ok((sample_language(&segments), declared))
+}
+
+// Rewrites the numbers and dates the translated units copied from their source for the target
+// language, see `TranslateOptions::localize`. `language` is the one of the book, taken when no
+// source language is given.
+fn localize_units(
+    segments: &[Segment],
+    statuses: &[UnitStatus],
+    language: Option<&str>,
+    options: &TranslateOptions,
+    journal: &Journal,
+) {
+    let source = options.source_lang.as_deref().or(language);
+    let Some(localizer) =
+        source.and_then(|source| Localizer::new(source, &options.target_lang, options.localize))
+    else {
+        options.progress.on_event(ProgressEvent::Warning {
+            message: format!(
+                "Formats are not localized, the conventions of {} to {} are not known",
+                source.unwrap_or("the source language"),
+                options.target_lang
+            ),
+        });
+        return;
+    };
+    let mut localized = 0;
+    for (segment, status) in segments.iter().zip(statuses) {
+        let Some(markup) = segment.translation_markup() else {
+            continue;
+        };
+        if *status != UnitStatus::Translated {
+            continue;
+        }
+        let rewritten = localizer.apply(&segment.text, &markup);
+        if rewritten != markup && segment.apply(&rewritten).is_ok() {
+            localized += 1;
+        }
+    }
+    journal.record("units_localized", localized);
+}
+
+// The language detected in the units spread across the book.
+fn sample_language(segments: &[Segment]) -> Option<&'static str> {
+    let step = segments.len() / LANGUAGE_SAMPLE_UNITS + 1;
+    let sample: Vec<String> = segments
+        .iter()
+        .step_by(step)
+        .map(Segment::source_text)
+        .collect();
+    languages::detect_language(&sample.join("\n"))
+}
+
+// The names of `segments`, the units of a book extracted with `options.extract`, see
+// `TranslateOptions::protect_names`. None for German books, whose every noun is capitalized.
+fn book_names(segments: &[Segment], options: &TranslateOptions, journal: &Journal) -> Vec<String> {
+    let language = match &options.source_lang {
+        Some(language) => Some(language.as_str()),
+        None => sample_language(segments),
+    };
+    if language.is_some_and(|language| languages::is_same_language(language, "DE")) {
+        options.progress.on_event(ProgressEvent::Warning {
+            message: "Names are not detected in German, its nouns are all capitalized".to_string(),
+        });
+        return Vec::new();
+    }
+    let texts: Vec<String> = segments.iter().map(Segment::source_text).collect();
+    let names = terms::detect_names(texts.iter().map(String::as_str));
+    journal.record("names_found", names.len());
+    names
+}
+
+// The rules of `extract`, with `names` kept untranslated too.
+fn keeping_names(extract: &ExtractOptions, names: &[String]) -> ExtractOptions {
+    let mut extract = extract.clone();
+    extract
+        .keep_terms
+        .extend(names.iter().map(|name| terms::keep_term(name)));
+    extract
+}
+
+// Translates a sample of the translated units back to `source_lang` with the provider of the
+// run, see `TranslateOptions::back_translation`. Returns the characters billed, the budget of
+// the run is shared with the units already sent.
+#[allow(clippy::too_many_arguments)]
+async fn back_translate(
+    segments: &[Segment],
+    statuses: &[UnitStatus],
+    units: &[(&Path, usize)],
+    sample: Sample,
+    source_lang: &str,
+    billed: usize,
+    options: &TranslateOptions,
+    journal: &Journal,
+) -> Result<(BackTranslation, usize), EpubTranslatorError> {
+    // Translations from the memory are not the provider's
+    let translated: Vec<usize> = (0..segments.len())
+        .filter(|&id| statuses[id] == UnitStatus::Translated)
+        .collect();
+    let picked: Vec<usize> = translated
+        .iter()
+        .zip(sample.picks(translated.len()))
+        .filter_map(|(&id, picked)| picked.then_some(id))
+        .collect();
+    let back_segments: Vec<Segment> = picked
+        .iter()
+        .map(|&id| Segment::detached(&segments[id].translation().unwrap_or_default(), None))
+        .collect();
+    if back_segments.is_empty() {
+        return Ok((BackTranslation::default(), 0));
+    }
+    journal.record("back_translation_started", back_segments.len());
+    let back_options = TranslateOptions {
+        target_lang: source_lang.to_string(),
+        source_lang: Some(options.target_lang.clone()),
+        progress: Arc::new(SilentProgress),
+        max_chars: options.max_chars.map(|max| max.saturating_sub(billed)),
+        max_units: None,
+        sample: None,
+        back_translation: None,
+        glossary: terms::Glossary::default(),
+        memory: None,
+        xliff: None,
+        ..options.clone()
+    };
+    let (back_report, _) = translate_segments(&back_segments, &back_options, journal).await?;
+
+    let mut back_translation = BackTranslation::default();
+    for (&id, back_segment) in picked.iter().zip(&back_segments) {
+        let Some(back) = back_segment.translation() else {
+            back_translation.failed += 1;
+            continue;
+        };
+        let (path, position) = units[id];
+        let source = segments[id].source_text();
+        back_translation.units.push(BackTranslatedUnit {
+            path: path.to_path_buf(),
+            position,
+            similarity: qa::similarity(&source, &back),
+            source,
+            translation: segments[id].translation().unwrap_or_default(),
+            back_translation: back,
+        });
+    }
+    let units = &back_translation.units;
+    back_translation.score = (!units.is_empty())
+        .then(|| units.iter().map(|unit| unit.similarity).sum::<f64>() / units.len() as f64);
+    Ok((back_translation, back_report.billed_characters))
+}
 
 // Integration test for the whole process.
 // It creates a mock server that will be used by all the other tests that need it.
@@ -449,36 +2038,41 @@ pub async fn translate_folder(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use deepl::{get_test_config, start_deepl_server};
-    use epub::epubcheck;
-    use tokio::time::Duration;
+    use deepl::get_test_config;
+    use epub::zip_folder_to_epub;
 
+    fn cancelled_options() -> TranslateOptions {
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            concurrent_requests: 10,
+            configurations: vec![Arc::new(get_test_config())],
+            ..Default::default()
+        };
+        options.cancel.cancel();
+        options
+    }
+
+    #[cfg(feature = "mock-server")]
     #[tokio::test]
     async fn test_translate_epub() -> Result<(), Box<dyn std::error::Error>> {
+        use deepl::{start_deepl_server, MockServerOptions};
+        use epub::epubcheck;
+
         let input_file = PathBuf::from("tests/data/epub_to_test/input.epub");
 
         let temp_dir = tempfile::tempdir()?;
 
         let output_file = temp_dir.path().join("output.epub");
-        let target_lang = "ES".to_string();
-        let source_lang: Option<String> = None;
-        let parallel = 1000;
-        let config = get_test_config();
-        let configurations = vec![Arc::new(config)];
-
-        let shutdown_signal = start_deepl_server().await?;
+        let server = start_deepl_server(&MockServerOptions::ephemeral()).await?;
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            concurrent_requests: 1000,
+            configurations: vec![Arc::new(server.config())],
+            ..Default::default()
+        };
 
         let start = Instant::now();
-        translate_epub(
-            &input_file,
-            &output_file,
-            target_lang,
-            source_lang,
-            parallel,
-            configurations,
-            true,
-        )
-        .await?;
+        translate_epub_with(&input_file, &output_file, &options).await?;
 
         let duration = start.elapsed();
         eprintln!("=========> Time taken {:?}", duration);
@@ -486,11 +2080,863 @@ mod tests {
         // Check the format of the translated epub
         epubcheck(&output_file)?;
 
-        // Wait one extra second to be sure that other tests have finished.
-        tokio::time::sleep(Duration::from_millis(1000)).await;
+        server.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translate_folder_cancelled() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let xhtml_path = temp_dir.path().join("lorem.xhtml");
+        std::fs::copy("tests/data/lorem.xhtml", &xhtml_path)?;
+
+        // Cancelled before starting, no request can reach the API.
+        let options = cancelled_options();
+        let report = translate_folder(temp_dir.path(), &options, &Journal::disabled()).await?;
+
+        assert!(report.total > 0);
+        assert_eq!(report.translated, 0);
+        assert_eq!(report.cancelled, report.total);
+        assert!(report.was_cancelled());
+
+        Ok(())
+    }
 
-        // Shut down mock server
-        shutdown_signal.send(()).unwrap();
+    #[tokio::test]
+    async fn test_translate_epub_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+
+        let input = std::fs::read(&epub_path)?;
+        let (output, report) = translate_epub_bytes(input, &cancelled_options()).await?;
+        assert!(report.was_cancelled());
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(output))?;
+        let read = |archive: &mut zip::ZipArchive<_>, name: &str| -> std::io::Result<String> {
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut archive.by_name(name)?, &mut content)?;
+            Ok(content)
+        };
+        assert!(read(&mut archive, "OEBPS/content.opf")?.contains("<dc:language>es</dc:language>"));
+        assert!(read(&mut archive, "OEBPS/text/notes")?.contains(r#"lang="es" xml:lang="es""#));
+        assert_eq!(read(&mut archive, "OEBPS/style.css")?, "p { margin: 0; }\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translate_fixed_layout() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let mut book = BookArchive::open(&epub_path, &Default::default())?;
+        let opf = book
+            .read_to_string(Path::new("OEBPS/content.opf"))?
+            .replace(
+                "</metadata>",
+                r#"<meta property="rendition:layout">pre-paginated</meta></metadata>"#,
+            )
+            .replace(
+                r#"<itemref idref="notes"/>"#,
+                r#"<itemref idref="notes" properties="rendition:layout-reflowable"/>"#,
+            );
+        book.write(Path::new("OEBPS/content.opf"), opf.into_bytes())?;
+        let pages = vec![PathBuf::from("OEBPS/text/chapter 1.htm")];
+
+        let error = translate_book(&mut book, &cancelled_options(), &Journal::disabled())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            EpubTranslatorError::FixedLayout(FixedLayoutError { pages: ref error_pages })
+                if *error_pages == pages
+        ));
+
+        let options = TranslateOptions {
+            fixed_layout: true,
+            ..cancelled_options()
+        };
+        let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+        assert_eq!(report.fixed_layout_pages, pages);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_book_chapters() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+
+        let archive = BookArchive::open(&epub_path, &Default::default())?;
+        let options = ExtractOptions {
+            translate_metadata: true,
+            ..Default::default()
+        };
+        let chapters = count_book_chapters(&archive, &options, &Selection::default())?;
+        let paths: Vec<&Path> = chapters.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("OEBPS/text/chapter 1.htm"),
+                Path::new("OEBPS/text/notes"),
+                Path::new("OEBPS/nav.xhtml"),
+                Path::new("OEBPS/content.opf"),
+                Path::new("OEBPS/toc.ncx"),
+            ]
+        );
+        // Title, heading and paragraph
+        assert_eq!(
+            chapters[0],
+            ChapterCount {
+                path: PathBuf::from("OEBPS/text/chapter 1.htm"),
+                chapter: Some(1),
+                characters: 62,
+                units: 3,
+                billable_characters: 62,
+            }
+        );
+        assert_eq!((chapters[3].units, chapters[3].characters), (3, 54));
+
+        let count = count_book_char(&archive)?;
+        assert_eq!(count, count_epub_char(&epub_path)?);
+        assert_eq!(count, 126);
+
+        // The navigation document is outside of the spine
+        let selection = Selection {
+            chapters: vec![2..=2],
+            ..Default::default()
+        };
+        let chapters = count_book_chapters(&archive, &options, &selection)?;
+        let paths: Vec<&Path> = chapters.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("OEBPS/text/notes"),
+                Path::new("OEBPS/content.opf"),
+                Path::new("OEBPS/toc.ncx"),
+            ]
+        );
+
+        let units = book_units(&archive, &options, &selection)?;
+        assert_eq!(units.len(), chapters.iter().map(|c| c.units).sum::<usize>());
+        assert_eq!(units[0].path, Path::new("OEBPS/text/notes"));
+        assert_eq!(units[0].position, 1);
+        assert!(units.iter().all(|unit| unit.translation.is_none()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translate_selection() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let mut book = BookArchive::open(&epub_path, &Default::default())?;
+        let chapter = Path::new("OEBPS/text/chapter 1.htm");
+        let original = book.read(chapter)?;
+
+        let options = TranslateOptions {
+            selection: Selection {
+                exclude: vec![glob::Pattern::new("**/chapter*")?],
+                ..Default::default()
+            },
+            alignment: true,
+            ..cancelled_options()
+        };
+        let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+        assert_eq!(
+            report.alignment[0],
+            AlignedUnit {
+                path: PathBuf::from("OEBPS/text/notes"),
+                position: 1,
+                source: "Notes".to_string(),
+                translation: None,
+                source_markup: "Notes".to_string(),
+                translation_markup: None,
+            }
+        );
+        // The language of the book, before it is replaced
+        assert_eq!(report.source_lang.as_deref(), Some("en"));
+
+        assert_eq!(book.read(chapter)?, original);
+        let notes = book.read_to_string(Path::new("OEBPS/text/notes"))?;
+        assert!(notes.contains(r#"lang="es" xml:lang="es""#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translate_to_languages() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let input = std::fs::read(&epub_path)?;
+
+        // The run into ES is cancelled, FR is not even started
+        let languages = ["ES".to_string(), "FR".to_string()];
+        let outputs =
+            translate_epub_bytes_to_languages(input, &languages, &cancelled_options()).await?;
+        assert_eq!(outputs.len(), 1);
+        let book = BookArchive::read(outputs[0].0.clone(), &Default::default())?;
+        assert_eq!(book_language(&book)?.as_deref(), Some("es"));
+
+        // The book is in English, refused before ES is translated
+        let languages = ["ES".to_string(), "EN-GB".to_string()];
+        let options = TranslateOptions {
+            provider: providers::Provider::Pseudo,
+            ..Default::default()
+        };
+        let input = std::fs::read(&epub_path)?;
+        let error = translate_epub_bytes_to_languages(input, &languages, &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EpubTranslatorError::SameLanguage(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_chars() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let mut book = BookArchive::open(&epub_path, &Default::default())?;
+
+        // The title and the heading fit, the paragraph does not
+        let options = TranslateOptions {
+            max_chars: Some(30),
+            ..cancelled_options()
+        };
+        let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+        assert_eq!(
+            report.budget_stop,
+            Some(BudgetStop {
+                path: PathBuf::from("OEBPS/text/chapter 1.htm"),
+                position: 3,
+                source: "The lighthouse keeper counted the ships.".to_string(),
+                billable_characters: 22,
+            })
+        );
+        assert_eq!(report.cancelled, 2);
+        assert_eq!(report.over_budget, report.total - 2);
+
+        let options = TranslateOptions {
+            max_units: Some(2),
+            ..cancelled_options()
+        };
+        let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+        assert_eq!(report.budget_stop.map(|stop| stop.position), Some(3));
+        assert_eq!(report.over_budget, report.total - 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sample() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let mut book = BookArchive::open(&epub_path, &Default::default())?;
+
+        let options = TranslateOptions {
+            sample: Some(options::Sample {
+                fraction: 0.5,
+                seed: 1,
+            }),
+            ..cancelled_options()
+        };
+        let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+        assert_eq!(report.not_sampled, report.total / 2);
+        assert_eq!(report.cancelled + report.not_sampled, report.total);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_workdir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let workdir = temp_dir.path().join("work");
+        let options = TranslateOptions {
+            workdir: Some(workdir.clone()),
+            ..cancelled_options()
+        };
+
+        translate_epub_with(&epub_path, &temp_dir.path().join("out.epub"), &options).await?;
+        assert!(workdir.join("book/OEBPS/text/chapter 1.htm").is_file());
+        assert!(workdir.join(journal::JOURNAL_FILE_NAME).is_file());
+
+        // The files of the first run are not mixed with the book
+        let error = translate_epub_with(&epub_path, &temp_dir.path().join("out.epub"), &options)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("is not empty"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_temp_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let folder_size: u64 = walkdir::WalkDir::new("tests/data/opf_book")
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.metadata().ok().filter(|metadata| metadata.is_file()))
+            .map(|metadata| metadata.len())
+            .sum();
+        assert_eq!(epub::extracted_size(&epub_path)?, folder_size);
+
+        // The output cannot be written, the failed run keeps its workdir
+        let options = TranslateOptions {
+            temp_dir: Some(temp_dir.path().to_path_buf()),
+            ..cancelled_options()
+        };
+        let output = temp_dir.path().join("missing/out.epub");
+        let error = translate_epub_with(&epub_path, &output, &options)
+            .await
+            .unwrap_err();
+        let EpubTranslatorError::WorkdirKept { workdir, .. } = error else {
+            panic!("workdir not kept: {}", error);
+        };
+        assert_eq!(workdir.parent(), Some(temp_dir.path()));
+        assert!(workdir.join("book/OEBPS/content.opf").is_file());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translate_directory() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        std::fs::write(
+            temp_dir.path().join("one.xhtml"),
+            "<html><body><p>First</p><p>Second</p></body></html>",
+        )?;
+        // Declared UTF-8, but not
+        let broken = b"<?xml version=\"1.0\" encoding=\"utf-8\"?><html><p>\xff</p></html>";
+        std::fs::write(temp_dir.path().join("two.xhtml"), broken)?;
+
+        let mut reports = translate_directory(temp_dir.path(), &cancelled_options()).await?;
+        reports.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].path, Path::new("one.xhtml"));
+        assert_eq!(
+            (reports[0].report.total, reports[0].report.cancelled),
+            (2, 2)
+        );
+        assert_eq!(reports[0].error, None);
+        assert_eq!(reports[1].report.total, 0);
+        assert_eq!(reports[1].error.as_deref(), Some("Invalid UTF-8 content"));
+        assert_eq!(std::fs::read(temp_dir.path().join("two.xhtml"))?, broken);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_files_without_text_are_untouched() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let cover = "<?xml version='1.0'?>\n<html><head><style>img { width: 100% }</style></head>\
+            <body><div><img src='cover.jpg' alt=''/></div></body></html>";
+        std::fs::write(temp_dir.path().join("cover.xhtml"), cover)?;
+        std::fs::write(
+            temp_dir.path().join("text.xhtml"),
+            "<html><body><p>First</p></body></html>",
+        )?;
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            provider: providers::Provider::Pseudo,
+            ..Default::default()
+        };
+
+        let mut reports = translate_directory(temp_dir.path(), &options).await?;
+        reports.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!((reports[0].report.total, reports[1].report.total), (0, 1));
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join("cover.xhtml"))?,
+            cover
+        );
+        assert!(!std::fs::read_to_string(temp_dir.path().join("text.xhtml"))?.contains(">First<"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translation_memory() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("text.xhtml");
+        std::fs::write(
+            &path,
+            "<html><body><p> First </p><p>Second <em>one</em></p></body></html>",
+        )?;
+        let memory = memory::TranslationMemory::parse(
+            r#"<tmx version="1.4"><header srclang="en"/><body>
+            <tu><tuv xml:lang="en"><seg>First</seg></tuv><tuv xml:lang="es"><seg>Primero</seg></tuv></tu>
+            <tu><tuv xml:lang="en"><seg>Second one</seg></tuv><tuv xml:lang="es"><seg>Segundo</seg></tuv></tu>
+            </body></tmx>"#,
+        )?;
+        // Nothing is sent, the memory is applied whatever the budget
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            provider: providers::Provider::Pseudo,
+            memory: Some(Arc::new(memory)),
+            max_units: Some(0),
+            ..Default::default()
+        };
+
+        let reports = translate_directory(temp_dir.path(), &options).await?;
+        let report = &reports[0].report;
+        assert_eq!(
+            (report.translated, report.from_memory, report.over_budget),
+            (1, 1, 1)
+        );
+        // The unit with inline markup can't take the plain translation
+        let output = std::fs::read_to_string(&path)?;
+        assert!(output.contains("<p> Primero </p><p>Second <em>one</em></p>"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translation_memory_glossary() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("text.xhtml");
+        std::fs::write(&path, "<html><body><p>The Shire</p></body></html>")?;
+        let memory = memory::TranslationMemory::parse(
+            r#"<tmx version="1.4"><header srclang="en"/><body>
+            <tu><tuv xml:lang="en"><seg>The Shire</seg></tuv><tuv xml:lang="es"><seg>La Comarca &amp; más</seg></tuv></tu>
+            </body></tmx>"#,
+        )?;
+        // The translations of the memory follow the glossary like the ones of the provider
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            provider: providers::Provider::Pseudo,
+            memory: Some(Arc::new(memory)),
+            glossary: terms::Glossary::parse_csv("comarca,Cuaderna\n")?,
+            max_units: Some(0),
+            ..Default::default()
+        };
+
+        let reports = translate_directory(temp_dir.path(), &options).await?;
+        assert_eq!(reports[0].report.from_memory, 1);
+        let output = std::fs::read_to_string(&path)?;
+        assert!(output.contains("<p>La Cuaderna &amp; más</p>"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translation_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("text.xhtml");
+        std::fs::write(&path, "<html><body><p>First</p></body></html>")?;
+        let cache_path = temp_dir.path().join("cache.jsonl");
+        cache::TranslationCache::open(&cache_path)?.insert("First", "ES", "Primero")?;
+        // Found in the cache of an earlier run, nothing is sent to the configuration
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            configurations: vec![Arc::new(get_test_config())],
+            cache: Some(cache_path),
+            ..Default::default()
+        };
+
+        let reports = translate_directory(temp_dir.path(), &options).await?;
+        let report = &reports[0].report;
+        assert_eq!((report.translated, report.from_cache), (1, 1));
+        assert_eq!(report.billed_characters, 0);
+        assert!(std::fs::read_to_string(&path)?.contains("<p>Primero</p>"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn test_positional_translate_epub() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let output = temp_dir.path().join("book.es.epub");
+
+        // The signature of the first releases, the defaults for everything else
+        let error = translate_epub(
+            &epub_path,
+            &output,
+            "ES".to_string(),
+            None,
+            10,
+            vec![],
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            error.to_string().contains("no DeepL configuration"),
+            "{}",
+            error
+        );
+        assert!(!output.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_back_translation() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let mut book = BookArchive::open(&epub_path, &Default::default())?;
+        // Translated into itself, every unit comes back as its source
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            provider: providers::Provider::Identity,
+            back_translation: Some(options::Sample {
+                fraction: 1.0,
+                seed: 0,
+            }),
+            ..Default::default()
+        };
+
+        let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+        let back_translation = report.back_translation.expect("a back translation");
+        assert_eq!(back_translation.units.len(), report.translated);
+        assert_eq!(back_translation.failed, 0);
+        assert_eq!(back_translation.score, Some(1.0));
+
+        // Without a package, the source language must be given
+        std::fs::write(temp_dir.path().join("text.xhtml"), "<p>Hello</p>")?;
+        let error = translate_directory(temp_dir.path(), &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EpubTranslatorError::InvalidOptions(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_same_language() -> Result<(), Box<dyn std::error::Error>> {
+        let epub = testing::read_fixture(Path::new("tests/data/opf_book"))?;
+        let options = TranslateOptions {
+            target_lang: "EN-GB".to_string(),
+            provider: providers::Provider::Pseudo,
+            ..Default::default()
+        };
+        let error = translate_epub_bytes(epub.clone(), &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EpubTranslatorError::SameLanguage(_)));
+
+        let options = TranslateOptions {
+            same_language: true,
+            ..options
+        };
+        let (_, report) = translate_epub_bytes(epub, &options).await?;
+        assert!(report.translated > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_protect_names() -> Result<(), Box<dyn std::error::Error>> {
+        let content = "<html><body><p>They called him Ishmael.</p>\
+            <p>It was Ishmael who sailed.</p><p>Then Ishmael slept.</p></body></html>";
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            provider: providers::Provider::Pseudo,
+            protect_names: true,
+            ..Default::default()
+        };
+        let (output, report) = translate_document(content, &options).await?;
+        assert_eq!(report.names, vec!["Ishmael"]);
+        assert_eq!(output.matches("Ishmael").count(), 3);
+        assert!(!output.contains("They called"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_localize() -> Result<(), Box<dyn std::error::Error>> {
+        let content = "<html><body><p>It cost 1,234.5 dollars <em>on 12/31/2020</em>.</p>\
+            </body></html>";
+        let options = TranslateOptions {
+            source_lang: Some("EN-US".to_string()),
+            target_lang: "DE".to_string(),
+            provider: providers::Provider::Identity,
+            localize: locale::LocalizeOptions {
+                numbers: true,
+                dates: true,
+                units: false,
+            },
+            ..Default::default()
+        };
+        let (output, _) = translate_document(content, &options).await?;
+        assert!(output.contains("It cost 1.234,5 dollars <em>on 31.12.2020</em>."));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_xliff_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let epub = testing::read_fixture(Path::new("tests/data/opf_book"))?;
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            provider: providers::Provider::Pseudo,
+            alignment: true,
+            ..Default::default()
+        };
+        let (_, report) = translate_epub_bytes(epub.clone(), &options).await?;
+        let mut exported = Vec::new();
+        xliff::write_xliff(&report.alignment, "en", "es", &mut exported)?;
+
+        // A post-edited target repeating itself, and a unit left without one
+        let exported = String::from_utf8(exported)?
+            .replace("[Çĥáptér Öñé ~~~~]", "Es el uno. Es el uno.")
+            .replacen("<target>", "<!-- <target>", 1)
+            .replacen("</target>", "</target> -->", 1);
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            xliff: Some(Arc::new(xliff::Xliff::parse(&exported)?)),
+            ..Default::default()
+        };
+        let (output, imported) = translate_epub_bytes(epub, &options).await?;
+        assert_eq!(imported.translated, report.translated - 1);
+        assert_eq!(imported.failures.len(), 1);
+        assert_eq!(imported.failures[0].error, "no target in the XLIFF file");
+        let flagged: Vec<_> = imported
+            .flagged
+            .iter()
+            .map(|unit| (unit.path.to_str().unwrap(), unit.anomalies.clone()))
+            .collect();
+        let repeated = qa::Anomaly::RepeatedSentence("Es el uno.".to_string());
+        assert!(flagged.contains(&("OEBPS/text/chapter 1.htm", vec![repeated])));
+        let book = BookArchive::read(output, &Default::default())?;
+        let chapter = book.read_to_string(Path::new("OEBPS/text/chapter 1.htm"))?;
+        assert!(chapter.contains("<h1>Es el uno. Es el uno.</h1>"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translate_epub_stream() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            provider: providers::Provider::Pseudo,
+            ..Default::default()
+        };
+
+        let events: Vec<_> =
+            translate_epub_stream(epub_path, temp_dir.path().join("out.epub"), options)
+                .collect()
+                .await;
+        let translations: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                TranslationEvent::Progress(ProgressEvent::NodeTranslated {
+                    source,
+                    translation,
+                    ..
+                }) => Some((source.as_str(), translation.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert!(translations.contains(&("Chapter One", "[Çĥáptér Öñé ~~~~]")));
+        let Some(TranslationEvent::Finished(Ok(report))) = events.last() else {
+            panic!("the stream must end with the report");
+        };
+        assert_eq!(report.translated, translations.len());
+        assert!(temp_dir.path().join("out.epub").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixed_time() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            provider: providers::Provider::Pseudo,
+            fixed_time: chrono::DateTime::from_timestamp(1_700_000_000, 0),
+            ..Default::default()
+        };
+
+        let (first, _) = translate_epub_bytes(std::fs::read(&epub_path)?, &options).await?;
+        // The second run would be stamped a second later otherwise
+        tokio::time::sleep(tokio::time::Duration::from_millis(1100)).await;
+        let (second, _) = translate_epub_bytes(std::fs::read(&epub_path)?, &options).await?;
+        assert_eq!(first, second);
+        let book = BookArchive::read(first, &Default::default())?;
+        let opf = String::from_utf8(book.read(Path::new("OEBPS/content.opf"))?)?;
+        assert!(opf.contains("2023-11-14T22:13:20Z"));
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn test_mock_failures() -> Result<(), Box<dyn std::error::Error>> {
+        use deepl::{start_deepl_server, MockServerOptions};
+
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let translate = |behavior: &'static str, max_retries: usize| {
+            let epub_path = epub_path.clone();
+            async move {
+                let server = start_deepl_server(&MockServerOptions {
+                    behavior: behavior.parse()?,
+                    ..MockServerOptions::ephemeral()
+                })
+                .await?;
+                let options = TranslateOptions {
+                    target_lang: "ES".to_string(),
+                    configurations: vec![Arc::new(server.config())],
+                    max_retries,
+                    ..Default::default()
+                };
+                let mut book = BookArchive::open(&epub_path, &Default::default())?;
+                let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+                server.stop();
+                Ok::<_, Box<dyn std::error::Error>>(report)
+            }
+        };
+
+        // Quota errors are retried too, the last attempt tells why the unit failed
+        let report = translate("latency=0,456=1", 1).await?;
+        assert_eq!(report.quota_exceeded, report.total);
+
+        // Half of the answers cannot be read, the retries get the other half
+        let report = translate("latency=0-5,malformed=0.5", 30).await?;
+        assert!(report.is_complete());
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn test_mock_markup() -> Result<(), Box<dyn std::error::Error>> {
+        use deepl::{start_deepl_server, MockServerOptions};
+
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("chapter.xhtml");
+        std::fs::write(
+            &path,
+            "<html><body><p>The <b>keeper</b> &amp; the <a href=\"#ships\">ships</a></p>\
+             </body></html>",
+        )?;
+        let server = start_deepl_server(&MockServerOptions {
+            behavior: "latency=0,translation=reversed".parse()?,
+            ..MockServerOptions::ephemeral()
+        })
+        .await?;
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            configurations: vec![Arc::new(server.config())],
+            ..Default::default()
+        };
+
+        // Each word is translated in its element, the markup comes back as it was
+        let reports = translate_directory(temp_dir.path(), &options).await?;
+        assert!(reports[0].report.is_complete());
+        let translated = std::fs::read_to_string(&path)?;
+        assert!(
+            translated.contains("<p>ehT <b>repeek</b> &amp; eht <a href=\"#ships\">spihs</a></p>"),
+            "{}",
+            translated
+        );
+        server.stop();
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-server")]
+    #[tokio::test]
+    async fn test_mock_quota() -> Result<(), Box<dyn std::error::Error>> {
+        use deepl::{get_usage, start_deepl_server, MockServerOptions};
+
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let server = start_deepl_server(&MockServerOptions {
+            behavior: "latency=0,quota=60,used=0".parse()?,
+            ..MockServerOptions::ephemeral()
+        })
+        .await?;
+
+        // Each key runs out of characters halfway through the book
+        let keys = ["first", "second"];
+        let options = TranslateOptions {
+            target_lang: "ES".to_string(),
+            configurations: keys
+                .iter()
+                .map(|key| Arc::new(server.key_config(*key)))
+                .collect(),
+            concurrent_requests: 1,
+            max_retries: 0,
+            ..Default::default()
+        };
+        let mut book = BookArchive::open(&epub_path, &Default::default())?;
+        let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+        assert!(report.translated > 0);
+        assert!(report.quota_exceeded > 0);
+        assert_eq!(report.translated + report.quota_exceeded, report.total);
+        for key in keys {
+            let usage = get_usage(&server.key_config(key), &options.client).await?;
+            assert!(usage.character_count > 0);
+            assert!(usage.character_count <= usage.character_limit);
+        }
+        server.stop();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failures() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub_path = temp_dir.path().join("book.epub");
+        zip_folder_to_epub(Path::new("tests/data/opf_book"), &epub_path)?;
+        let mut book = BookArchive::open(&epub_path, &Default::default())?;
+
+        // Nothing listens there, every request fails
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let options = TranslateOptions {
+            progress: Arc::new(tx),
+            target_lang: "ES".to_string(),
+            concurrent_requests: 10,
+            configurations: vec![Arc::new(DeepLConfiguration {
+                auth_key: "key".into(),
+                api_url: "http://127.0.0.1:9/v2".to_string(),
+            })],
+            selection: Selection {
+                chapters: vec![1..=1],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = translate_book(&mut book, &options, &Journal::disabled()).await?;
+        assert_eq!(report.failed, report.total);
+        assert_eq!(report.failures.len(), report.total);
+        let failure = &report.failures[2];
+        assert_eq!(failure.path, PathBuf::from("OEBPS/text/chapter 1.htm"));
+        assert_eq!(failure.position, 3);
+        assert_eq!(failure.location.as_deref(), Some("/html/body/p"));
+        assert_eq!(failure.source, "The lighthouse keeper counted the ships.");
+        assert!(!failure.error.is_empty());
+
+        let mut planned = Vec::new();
+        let mut retried = 0;
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                ProgressEvent::NodePlanned {
+                    path, characters, ..
+                } => planned.push((path, characters)),
+                ProgressEvent::NodeRetried { .. } => retried += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(planned.len(), report.total);
+        assert_eq!(planned[2], (PathBuf::from("OEBPS/text/chapter 1.htm"), 40));
+        assert_eq!(retried, 4 * report.total);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_translate_xhtml_string() -> Result<(), Box<dyn std::error::Error>> {
+        let content = r#"<?xml version="1.0" encoding="utf-8"?>
+<html lang="en"><head><title>Page</title></head><body><p>Hello&hellip;</p></body></html>"#;
+
+        let output = translate_xhtml_string(content, &cancelled_options()).await?;
+
+        assert_eq!(
+            output,
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<html lang="es" xml:lang="es"><head><title>Page</title></head><body><p>Hello&hellip;</p></body></html>"#
+        );
 
         Ok(())
     }
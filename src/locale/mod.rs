@@ -0,0 +1,327 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+const NBSP: char = '\u{00A0}';
+const NNBSP: char = '\u{202F}';
+
+/// Which formats `Localizer` rewrites, see `TranslateOptions::localize`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LocalizeOptions {
+    /// Decimal and thousands separators, `1,234.5` to `1.234,5` in German.
+    pub numbers: bool,
+    /// Numeric dates, `12/31/2020` to `31.12.2020` in German.
+    pub dates: bool,
+    /// The space between a number and `%` or `‰`, `50%` to `50 %` in French.
+    pub units: bool,
+}
+
+impl LocalizeOptions {
+    pub fn is_empty(&self) -> bool {
+        !self.numbers && !self.dates && !self.units
+    }
+}
+
+// A list of categories separated by commas, or `all`
+impl FromStr for LocalizeOptions {
+    type Err = String;
+
+    fn from_str(categories: &str) -> Result<Self, Self::Err> {
+        let mut options = Self::default();
+        for category in categories.split(',').map(str::trim) {
+            match category {
+                "numbers" => options.numbers = true,
+                "dates" => options.dates = true,
+                "units" => options.units = true,
+                "all" => {
+                    options = Self {
+                        numbers: true,
+                        dates: true,
+                        units: true,
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown format `{}`, expected `numbers`, `dates`, `units` or `all`",
+                        category
+                    ))
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DateOrder {
+    Dmy,
+    Mdy,
+    Ymd,
+}
+
+// How a language writes numbers and dates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Conventions {
+    decimal: char,
+    thousands: char,
+    // `None` when the language says nothing of the order, e.g. `EN` for British and American
+    date_order: Option<DateOrder>,
+    date_separator: char,
+    // The space written between a number and `%`, if any
+    percent_space: Option<char>,
+}
+
+// Language or regional variant, decimal and thousands separators, date order and separator,
+// space before `%`
+#[rustfmt::skip]
+const CONVENTIONS: &[(&str, Conventions)] = &[
+    ("EN-GB", Conventions::new('.', ',', Some(DateOrder::Dmy), '/', None)),
+    ("EN-US", Conventions::new('.', ',', Some(DateOrder::Mdy), '/', None)),
+    ("EN", Conventions::new('.', ',', None, '/', None)),
+    ("DE", Conventions::new(',', '.', Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("FR", Conventions::new(',', NNBSP, Some(DateOrder::Dmy), '/', Some(NNBSP))),
+    ("ES", Conventions::new(',', '.', Some(DateOrder::Dmy), '/', Some(NBSP))),
+    ("IT", Conventions::new(',', '.', Some(DateOrder::Dmy), '/', None)),
+    ("PT", Conventions::new(',', '.', Some(DateOrder::Dmy), '/', None)),
+    ("NL", Conventions::new(',', '.', Some(DateOrder::Dmy), '-', None)),
+    ("DA", Conventions::new(',', '.', Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("NB", Conventions::new(',', NBSP, Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("SV", Conventions::new(',', NBSP, Some(DateOrder::Ymd), '-', Some(NBSP))),
+    ("FI", Conventions::new(',', NBSP, Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("PL", Conventions::new(',', NBSP, Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("CS", Conventions::new(',', NBSP, Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("SK", Conventions::new(',', NBSP, Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("RU", Conventions::new(',', NBSP, Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("UK", Conventions::new(',', NBSP, Some(DateOrder::Dmy), '.', Some(NBSP))),
+    ("JA", Conventions::new('.', ',', Some(DateOrder::Ymd), '/', None)),
+    ("ZH", Conventions::new('.', ',', Some(DateOrder::Ymd), '/', None)),
+    ("KO", Conventions::new('.', ',', Some(DateOrder::Ymd), '.', None)),
+];
+
+impl Conventions {
+    // The conventions of a language tag or DeepL code, those of its region first.
+    fn of(language: &str) -> Option<Self> {
+        let code = language.trim().replace('_', "-").to_uppercase();
+        let base = code.split('-').next().unwrap_or_default();
+        let row = CONVENTIONS
+            .iter()
+            .find(|row| row.0 == code)
+            .or_else(|| CONVENTIONS.iter().find(|row| row.0 == base))?;
+        Some(row.1)
+    }
+
+    const fn new(
+        decimal: char,
+        thousands: char,
+        date_order: Option<DateOrder>,
+        date_separator: char,
+        percent_space: Option<char>,
+    ) -> Self {
+        Self {
+            decimal,
+            thousands,
+            date_order,
+            date_separator,
+            percent_space,
+        }
+    }
+}
+
+/// Rewrites the numbers and dates a translation copied from its source in the conventions of
+/// the target language.
+#[derive(Debug, Clone)]
+pub struct Localizer {
+    source: Conventions,
+    target: Conventions,
+    options: LocalizeOptions,
+}
+
+impl Localizer {
+    /// `None` when the conventions of either language are not known, or when `options` has
+    /// nothing to rewrite.
+    pub fn new(source_lang: &str, target_lang: &str, options: LocalizeOptions) -> Option<Self> {
+        if options.is_empty() {
+            return None;
+        }
+        Some(Self {
+            source: Conventions::of(source_lang)?,
+            target: Conventions::of(target_lang)?,
+            options,
+        })
+    }
+
+    /// Rewrites `translation`, in the format of `Segment::text`, whose tags are left untouched.
+    /// Only the numbers and dates also found in `source` as they are written are rewritten,
+    /// the others were already written for the target language. A number like `3.2` is taken
+    /// for a decimal one, whatever it stands for.
+    pub fn apply(&self, source: &str, translation: &str) -> String {
+        static TAG: OnceLock<Regex> = OnceLock::new();
+        let tag = TAG.get_or_init(|| Regex::new(r"<[^>]*>").unwrap());
+        let copied: HashSet<&str> = number_pattern()
+            .find_iter(source)
+            .map(|found| found.as_str())
+            .collect();
+        let mut output = String::new();
+        let mut last = 0;
+        for found in tag.find_iter(translation) {
+            output.push_str(&self.localize(&translation[last..found.start()], &copied));
+            output.push_str(found.as_str());
+            last = found.end();
+        }
+        output.push_str(&self.localize(&translation[last..], &copied));
+        output
+    }
+
+    fn localize(&self, text: &str, copied: &HashSet<&str>) -> String {
+        let text = number_pattern().replace_all(text, |captures: &Captures| {
+            let found = &captures[0];
+            if !copied.contains(found) {
+                return found.to_string();
+            }
+            let date = self.options.dates.then(|| self.date(found)).flatten();
+            let number = || self.options.numbers.then(|| self.number(found)).flatten();
+            date.or_else(number).unwrap_or_else(|| found.to_string())
+        });
+        if !self.options.units {
+            return text.into_owned();
+        }
+        static PERCENT: OnceLock<Regex> = OnceLock::new();
+        let percent =
+            PERCENT.get_or_init(|| Regex::new(r"(\d)[ \u{00A0}\u{202F}]?([%‰])").unwrap());
+        let space = self
+            .target
+            .percent_space
+            .map(String::from)
+            .unwrap_or_default();
+        percent
+            .replace_all(&text, |captures: &Captures| {
+                format!("{}{}{}", &captures[1], space, &captures[2])
+            })
+            .into_owned()
+    }
+
+    // A numeric date in the order of the source, in the order of the target.
+    fn date(&self, found: &str) -> Option<String> {
+        let separator = found.chars().find(|c| !c.is_ascii_digit())?;
+        if !matches!(separator, '/' | '.' | '-') {
+            return None;
+        }
+        let parts: Vec<&str> = found.split(separator).collect();
+        let [a, b, c] = parts[..] else {
+            return None;
+        };
+        // Years of 4 digits only, `1.2.3` is no date
+        let (day, month, year) = if a.len() == 4 {
+            // ISO dates are written the same everywhere
+            if separator == '-' {
+                return None;
+            }
+            (c, b, a)
+        } else if c.len() == 4 {
+            let valid = |day: &str, month: &str| {
+                matches!(day.parse::<u8>(), Ok(1..=31)) && matches!(month.parse::<u8>(), Ok(1..=12))
+            };
+            match self.source.date_order {
+                Some(DateOrder::Dmy) => (a, b, c),
+                Some(DateOrder::Mdy) => (b, a, c),
+                // Only when a single order makes sense
+                _ => match (valid(a, b), valid(b, a)) {
+                    (true, false) => (a, b, c),
+                    (false, true) => (b, a, c),
+                    _ => return None,
+                },
+            }
+        } else {
+            return None;
+        };
+        if !matches!(day.parse::<u8>(), Ok(1..=31)) || !matches!(month.parse::<u8>(), Ok(1..=12)) {
+            return None;
+        }
+        let separator = self.target.date_separator;
+        Some(match self.target.date_order.unwrap_or(DateOrder::Mdy) {
+            DateOrder::Dmy => format!("{day}{separator}{month}{separator}{year}"),
+            DateOrder::Mdy => format!("{month}{separator}{day}{separator}{year}"),
+            DateOrder::Ymd => format!("{year}{separator}{month}{separator}{day}"),
+        })
+    }
+
+    // A number with the separators of the source, with the ones of the target. Numbers without
+    // thousands separators are not given any.
+    fn number(&self, found: &str) -> Option<String> {
+        let (integer, fraction) = match found.rfind(self.source.decimal) {
+            Some(index) => (
+                &found[..index],
+                Some(&found[index + self.source.decimal.len_utf8()..]),
+            ),
+            None => (found, None),
+        };
+        let groups: Vec<&str> = integer.split(self.source.thousands).collect();
+        let valid = groups.iter().all(|group| !group.is_empty())
+            && groups[0].len() <= 3
+            && groups[1..].iter().all(|group| group.len() == 3)
+            && groups
+                .iter()
+                .chain(&fraction)
+                .all(|part| part.chars().all(|c| c.is_ascii_digit()));
+        if !valid || (groups.len() == 1 && fraction.is_none()) {
+            return None;
+        }
+        let mut number = groups.join(&self.target.thousands.to_string());
+        if let Some(fraction) = fraction {
+            number.push(self.target.decimal);
+            number.push_str(fraction);
+        }
+        Some(number)
+    }
+}
+
+// Digits with the separators of numbers and dates between them.
+fn number_pattern() -> &'static Regex {
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    NUMBER.get_or_init(|| Regex::new(r"\d+(?:[.,/\-\u{00A0}\u{202F}]\d+)*").unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_localize() {
+        let all: LocalizeOptions = "all".parse().unwrap();
+        let german = Localizer::new("en-US", "DE", all).unwrap();
+        assert_eq!(
+            german.apply(
+                "It cost 1,234.5 dollars, 20% more, on 12/31/2020 and 1.5 days later.",
+                "Es kostete 1,234.5 Dollar, 20% mehr, am <g0>12/31/2020</g0> und 1.5 Tage später.",
+            ),
+            "Es kostete 1.234,5 Dollar, 20\u{a0}% mehr, am <g0>31.12.2020</g0> und 1,5 Tage später."
+        );
+        // Numbers the translation already localized, or not found in the source, are kept
+        assert_eq!(
+            german.apply("In 1,234 days", "In 1.234 Tagen"),
+            "In 1.234 Tagen"
+        );
+        assert_eq!(
+            german.apply("Version 1.2.3", "Version 1.2.3"),
+            "Version 1.2.3"
+        );
+
+        // The order of dates is unknown without a region, a single one may make sense
+        let french = Localizer::new("en", "FR", all).unwrap();
+        assert_eq!(
+            french.apply("12/31/2020 or 05/06/2020", "12/31/2020 ou 05/06/2020"),
+            "31/12/2020 ou 05/06/2020"
+        );
+        let numbers: LocalizeOptions = "numbers".parse().unwrap();
+        let english = Localizer::new("FR", "EN-GB", numbers).unwrap();
+        assert_eq!(
+            english.apply("1\u{202f}234,5 %", "1\u{202f}234,5 %"),
+            "1,234.5 %"
+        );
+        assert!(Localizer::new("en", "XX", all).is_none());
+        assert!("numbers,times".parse::<LocalizeOptions>().is_err());
+        assert!(Localizer::new("en", "DE", LocalizeOptions::default()).is_none());
+    }
+}
@@ -1,239 +1,2849 @@
-use epub_translator::deepl::models::DeepLConfiguration;
-use epub_translator::deepl::{get_languages, get_test_config, get_usage, start_deepl_server};
-use epub_translator::{count_epub_char, translate_epub};
+use epub_translator::config::Config;
+use epub_translator::deepl::models::{
+    AuthKey, DeepLConfiguration, Formality, Language, LanguageType,
+};
+use epub_translator::deepl::{get_languages, get_test_config, get_usage, ClientOptions};
+#[cfg(feature = "mock-server")]
+use epub_translator::deepl::{
+    start_deepl_server, Cassette, MockBehavior, MockServer, MockServerOptions,
+};
+use epub_translator::epub::{
+    book_metadata, validate_epub, validate_epub_bytes, BookArchive, Epubcheck, UnzipLimits,
+    EPUBCHECK_JAR_VAR,
+};
+use epub_translator::error::EpubTranslatorError;
+use epub_translator::job::Job;
+use epub_translator::keys::{
+    delete_keyring_key, keyring_key, read_keys_file, store_keyring_key, ApiKey,
+};
+use epub_translator::languages::{match_language, LanguageMatch};
+use epub_translator::locale::LocalizeOptions;
+use epub_translator::memory::{parse_similarity, TranslationMemory};
+use epub_translator::options::{
+    parse_chapter_range, parse_output_template, parse_pattern, parse_sample_fraction, OutputFields,
+    OutputTemplate, Sample, Selection, TranslateOptions,
+};
+use epub_translator::pricing::{default_rates, estimate, Rate};
+use epub_translator::progress::{ProgressEvent, ProgressSink};
+use epub_translator::providers::Provider;
+use epub_translator::report::{
+    write_alignment_csv, write_alignment_html, write_alignment_tmx, write_report_html, AlignedUnit,
+    ChapterCount, TranslationReport,
+};
+#[cfg(feature = "tui")]
+use epub_translator::review::{apply_edits, review_pairs};
+use epub_translator::telemetry::{self, SummaryRecorder};
+use epub_translator::terms::{read_keep_terms, Glossary};
+use epub_translator::xhtml::{
+    language_tag, BilingualMode, EntityPolicy, ExtractOptions, RubyMode, Selector, SerializeOptions,
+};
+use epub_translator::xliff::{write_xliff, Xliff};
+use epub_translator::{
+    book_units, count_book_chapters, count_epub_chapters, translate_epub_bytes,
+    translate_epub_bytes_to_languages, translate_epub_with,
+};
+use indicatif::{
+    MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState as IndicatifState, ProgressStyle,
+};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng, SeedableRng};
 
-#[macro_use]
-extern crate epub_translator;
-
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::{generate, Shell};
+use clap_mangen::Man;
 use futures::future::join_all;
-use std::path::PathBuf;
+use glob::Pattern;
+use reqwest::Client;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
-#[derive(Parser, Debug)]
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+// Exit statuses besides 0 and 1, clap exits with 2 on invalid arguments.
+/// The EPUB, or a file given along with it, cannot be read or translated.
+const EXIT_INVALID_INPUT: i32 = 3;
+/// The character quota of the API keys is exhausted.
+const EXIT_QUOTA_EXCEEDED: i32 = 4;
+/// The API cannot be reached, or refuses the requests.
+const EXIT_PROVIDER_ERROR: i32 = 5;
+/// With `--strict`, units were left untranslated by a cancellation or the character budget.
+const EXIT_UNTRANSLATED: i32 = 6;
+
+/// Output template of the jobs that do not give one.
+const DEFAULT_JOB_OUTPUT: &str = "{dir}/{stem}.{lang}.epub";
+
+#[cfg(not(feature = "mock-server"))]
+const NO_MOCK_SERVER: &str = "epub-translator was built without the mock-server feature, \
+    --test and --record are not available";
+
+// Stands for the mock server on builds without it, never started.
+#[cfg(not(feature = "mock-server"))]
+enum MockServer {}
+
+#[cfg(not(feature = "mock-server"))]
+impl MockServer {
+    fn stop(self) {
+        match self {}
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(author = "Carlos Yago, @carlosfy", version = "0.1.0", about = "Translate EPUB files", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to the EPUB file
-    input_file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the EPUB file, `-` to read it from stdin
+    #[arg(required_unless_present = "job")]
+    input_file: Option<PathBuf>,
 
-    /// Path to the output translation EPUB file
-    output_file: PathBuf,
+    /// Path to the output translation EPUB file, `-` to write it to stdout. `{lang}` is
+    /// replaced by the target language
+    #[arg(required_unless_present_any = ["dry_run", "output_template", "job"])]
+    output_file: Option<PathBuf>,
+
+    /// Path of the output when OUTPUT_FILE is not given, with the fields {stem}, {dir}, {lang},
+    /// {title} and {author} taken from the input file and the book, e.g.
+    /// `{dir}/{stem}.{lang}.epub`
+    #[arg(
+        long,
+        env = "EPUB_TRANSLATOR_OUTPUT_TEMPLATE",
+        value_parser = parse_output_template,
+        conflicts_with = "output_file"
+    )]
+    output_template: Option<OutputTemplate>,
+
+    /// Overwrite output files that already exist
+    #[arg(long)]
+    force: bool,
 
-    /// Target language code
-    #[arg(short, long)]
-    target_lang: String,
+    /// Target language code, several separated by commas, e.g. `ES,FR,DE`, to write an EPUB
+    /// per language
+    #[arg(short, long, env = "EPUB_TRANSLATOR_TARGET_LANG")]
+    target_lang: Option<String>,
 
     /// Source language code (optional, auto-detect if not provided)
-    #[arg(short, long)]
+    #[arg(short, long, env = "EPUB_TRANSLATOR_SOURCE_LANG")]
     source_lang: Option<String>,
 
-    /// Number of parallel translation requests (default: 1)
-    #[arg(short, long, default_value_t = 400)]
+    /// Number of parallel translation requests
+    #[arg(short, long, env = "EPUB_TRANSLATOR_PARALLEL", default_value_t = 400)]
     parallel: usize,
 
-    /// DeepL API key (optional, defaults to DEEPL_API_KEY environment variable)
-    #[arg(short = 'k', long)]
-    api_key: Option<String>,
+    #[command(flatten)]
+    client: ClientArgs,
 
-    // Verbose
-    #[arg(short = 'v', long, default_value_t = false)]
-    verbose: bool,
+    /// DeepL API key, further keys are read from DEEPL_API_KEY_1, DEEPL_API_KEY_2...
+    #[arg(short = 'k', long, env = "DEEPL_API_KEY", hide_env_values = true)]
+    api_key: Option<AuthKey>,
+
+    /// TOML file listing DeepL API keys, with a `[[keys]]` table per key: `key` or `keyring`,
+    /// and an optional `label` and `weight`
+    #[arg(long, env = "EPUB_TRANSLATOR_KEYS_FILE")]
+    keys_file: Option<PathBuf>,
+
+    /// Names of keys stored in the OS keyring with `keys store`, separated by commas
+    #[arg(long, value_delimiter = ',')]
+    keyring: Vec<String>,
+
+    /// Configuration file, read after ~/.config/epub-translator/config.toml and
+    /// ./epub-translator.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// JSON file describing a whole job: inputs, output template, languages, provider, skip
+    /// rules and budget. Its settings override the configuration files, flags override them
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["input_file", "output_file"])]
+    job: Option<PathBuf>,
+
+    /// Log more, on stderr unless --log-file is given: -v for the run, -vv for every request,
+    /// -vvv for every step of the pipeline
+    #[arg(short = 'v', long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only log errors
+    #[arg(short = 'q', long)]
+    quiet: bool,
+
+    /// Write the logs to this file instead of stderr
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Translate without asking for confirmation. It is not asked either without a terminal
+    /// to answer it
+    #[arg(short = 'y', long)]
+    yes: bool,
 
     /// Use test configuration, call to mock server
     #[arg(long)]
     test: bool,
+
+    /// How the mock server answers with --test: its latency in ms and the shares of the
+    /// requests that fail, e.g. latency=100-900,429=0.1,456=0.05,500=0.1,malformed=0.02
+    #[cfg(feature = "mock-server")]
+    #[arg(
+        long,
+        value_name = "SETTINGS",
+        env = "EPUB_TRANSLATOR_MOCK",
+        requires = "test"
+    )]
+    mock: Option<MockBehavior>,
+
+    /// The target languages of the mock server with --test, a file like the one returned by
+    /// the languages endpoint of DeepL for `type=target`. Those of the library by default
+    #[cfg(feature = "mock-server")]
+    #[arg(long, value_name = "FILE", requires = "test")]
+    mock_languages: Option<PathBuf>,
+
+    /// Record the translation requests sent to DeepL and their responses in FILE, a cassette
+    /// to replay them with --test --replay. The keys are not recorded
+    #[arg(long, value_name = "FILE", conflicts_with = "test")]
+    record: Option<PathBuf>,
+
+    /// Answer the translation requests of --test with the responses recorded by --record in
+    /// FILE. Requests that were not recorded fail
+    #[cfg(feature = "mock-server")]
+    #[arg(long, value_name = "FILE", requires = "test")]
+    replay: Option<PathBuf>,
+
+    /// Where the translations come from: `deepl`, or `identity` and `pseudo` to check how the
+    /// book goes through a translation without any API call. `pseudo` accents the text and
+    /// makes it about 30% longer
+    #[arg(long, default_value = "deepl", conflicts_with = "test")]
+    provider: Provider,
+
+    /// Register of the DeepL translations: `more` or `less` formal, refused for the target
+    /// languages without one, or `prefer_more` and `prefer_less` that fall back to the default
+    #[arg(long)]
+    formality: Option<Formality>,
+
+    /// Classes marking elements that must not be translated (comma separated).
+    /// Elements with `translate="no"` are never translated.
+    #[arg(long, value_delimiter = ',', default_value = "notranslate")]
+    notranslate_classes: Vec<String>,
+
+    /// Elements left untranslated along with their content (comma separated)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "script,pre,code,kbd,samp,var"
+    )]
+    skip_tags: Vec<String>,
+
+    /// CSS selectors of elements left untranslated, e.g. `.poem,#colophon,aside.marginalia`
+    #[arg(long, value_delimiter = ',')]
+    exclude_selector: Vec<Selector>,
+
+    /// Translate the title, desc and text elements of SVG images
+    #[arg(long)]
+    translate_svg: bool,
+
+    /// Attributes whose value is translated (comma separated, empty to translate none)
+    #[arg(long, value_delimiter = ',', default_value = "alt,title,aria-label")]
+    translate_attributes: Vec<String>,
+
+    /// How ruby annotations (furigana) are handled: `translate`, `drop` or `preserve`
+    #[arg(long, default_value = "translate")]
+    ruby: RubyMode,
+
+    /// Keep the original text next to its translation: `interleaved` puts each translated
+    /// paragraph after its original, `footnote` links it to a footnote holding the original
+    #[arg(long)]
+    bilingual: Option<BilingualMode>,
+
+    /// Also translate passages already marked (lang/xml:lang) as being in the target language
+    #[arg(long)]
+    retranslate_target_lang: bool,
+
+    /// Set the lang of inner elements in the source language to the target language, not only the root one
+    #[arg(long)]
+    rewrite_inner_lang: bool,
+
+    /// Do not send the current chapter heading as context of the translation
+    #[arg(long)]
+    no_heading_context: bool,
+
+    /// File of terms kept untranslated (names, invented words), one per line. `/.../` lines are
+    /// regular expressions
+    #[arg(long, value_name = "FILE")]
+    keep_terms: Option<PathBuf>,
+
+    /// Keep the names of the book untranslated like --keep-terms: capitalized words seen
+    /// repeatedly in the middle of sentences. Not for German books
+    #[arg(long)]
+    protect_names: bool,
+
+    /// Rewrite the numbers the translations copied from the source for the target language,
+    /// e.g. `1,234.5` to `1.234,5` in German: `numbers`, `dates`, `units` (the space before
+    /// `%`) or `all`, separated by commas
+    #[arg(long, value_name = "FORMATS")]
+    localize: Option<LocalizeOptions>,
+
+    /// CSV glossary of `term,replacement` records, replacing terms of the translations
+    #[arg(long, value_name = "FILE")]
+    glossary: Option<PathBuf>,
+
+    /// TMX translation memory or TBX termbase whose translations are applied without any
+    /// request. Can be repeated, the first file wins
+    #[arg(long = "tm", value_name = "FILE")]
+    memories: Vec<PathBuf>,
+
+    /// Also apply the translation of the closest source of the translation memories, when it
+    /// is at least this similar, e.g. 85%
+    #[arg(long, value_name = "PERCENT", value_parser = parse_similarity, requires = "memories")]
+    tm_fuzzy: Option<f64>,
+
+    /// Translate the title, description and subjects of the book, the original title is kept
+    #[arg(long)]
+    translate_metadata: bool,
+
+    /// How character references of the source (`&hellip;`, `&#8212;`) are written: `preserve` or `characters`
+    #[arg(long, default_value = "preserve")]
+    entities: EntityPolicy,
+
+    /// Maximum size of a single file of the EPUB once extracted, in MiB
+    #[arg(long, default_value_t = 512)]
+    max_entry_size: u64,
+
+    /// Maximum size of the EPUB once extracted, in MiB
+    #[arg(long, default_value_t = 2048)]
+    max_unzipped_size: u64,
+
+    /// Translate the EPUB in memory, without extracting it into a temporary directory
+    #[arg(long)]
+    in_memory: bool,
+
+    /// Extract the EPUB into this directory, which must be empty, and keep it after the run to
+    /// inspect the translated documents and the journal
+    #[arg(long, value_name = "DIR", conflicts_with = "in_memory")]
+    workdir: Option<PathBuf>,
+
+    /// Keep the temporary directory the EPUB is extracted into, its path is printed
+    #[arg(long, conflicts_with_all = ["in_memory", "workdir"])]
+    keep_workdir: bool,
+
+    /// Create the temporary directory the EPUB is extracted into in DIR, instead of the
+    /// temporary directory of the system
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["in_memory", "workdir"])]
+    temp_dir: Option<PathBuf>,
+
+    /// Translate fixed-layout EPUBs, whose pages may not fit the translated text
+    #[arg(long)]
+    force_fixed_layout: bool,
+
+    /// Translate EPUBs already in the target language, by the language detected in their text
+    /// or else the declared one. They are refused otherwise, and skipped by jobs
+    #[arg(long)]
+    force_same_language: bool,
+
+    /// Chapters to translate, by position in the spine, e.g. `1-5,12`. The other documents
+    /// are left untouched
+    #[arg(long, value_delimiter = ',', value_parser = parse_chapter_range)]
+    chapters: Vec<RangeInclusive<usize>>,
+
+    /// Translate only the documents matching these glob patterns, relative to the root of
+    /// the book, e.g. `OEBPS/text/*`
+    #[arg(long, value_parser = parse_pattern)]
+    include: Vec<Pattern>,
+
+    /// Leave the documents matching these glob patterns untouched
+    #[arg(long, value_parser = parse_pattern)]
+    exclude: Vec<Pattern>,
+
+    /// Stop sending requests once this many billable characters are sent, retries included.
+    /// What is translated by then is written, the rest stays in the source language
+    #[arg(long, value_name = "CHARS")]
+    max_chars: Option<usize>,
+
+    /// Only translate a random share of the segments, spread across the book, e.g. `5%`. The
+    /// sampled segments are written next to their translation to an HTML review file
+    #[arg(long, value_name = "PERCENT", value_parser = parse_sample_fraction)]
+    sample: Option<f64>,
+
+    /// Seed of the sample, the same seed picks the same segments of a book. Random by default
+    #[arg(long, requires = "sample")]
+    sample_seed: Option<u64>,
+
+    /// Review file of the sample, the output file with a `.review.html` extension by default
+    #[arg(long, requires = "sample")]
+    review_out: Option<PathBuf>,
+
+    /// Translate a random share of the translated segments back to the source language, e.g.
+    /// `5%`, and score how close they come to their source, to compare providers or settings.
+    /// The same segments are picked on every run. Billed like the rest of the run
+    #[arg(long, value_name = "PERCENT", value_parser = parse_sample_fraction)]
+    back_translate: Option<f64>,
+
+    /// Fail with a non-zero exit status when any unit is left untranslated: failed, over the
+    /// character budget or cancelled
+    #[arg(long)]
+    strict: bool,
+
+    /// Write every translation unit next to its translation to this file, as an HTML table
+    /// when it ends with `.html`, as a TMX translation memory when it ends with `.tmx`, as CSV
+    /// otherwise
+    #[arg(long)]
+    alignment_out: Option<PathBuf>,
+
+    /// Write an HTML report of the translation to this file: the summary and cost of the run,
+    /// the counts of each file, and every unit next to its translation with the anomalies
+    /// found in it, e.g. missing numbers
+    #[arg(long)]
+    report_out: Option<PathBuf>,
+
+    /// Write the units whose translation failed to this JSON file: file, location, source
+    /// text and last error of each. Then the translated units that look wrong, with their
+    /// translation and anomalies
+    #[arg(long)]
+    failures_out: Option<PathBuf>,
+
+    /// List what would be translated, file by file, without calling the API. No output file
+    /// is needed
+    #[arg(long)]
+    dry_run: bool,
+
+    /// With --dry-run, also print the first N translation units
+    #[arg(long, value_name = "N", requires = "dry_run")]
+    show_segments: Option<usize>,
+
+    /// Write the progress and the final report as line-delimited JSON events on stdout instead
+    /// of the progress bar: started, node_translated, node_retried, node_failed, file_done,
+    /// warning, cancelled, then finished or error, or skipped for a book of a job already in
+    /// the target language
+    #[arg(long)]
+    json: bool,
+
+    /// Write the same EPUB on every run over the same book with the same options and
+    /// translations: the date written into the book is SOURCE_DATE_EPOCH, or 1980-01-01, keys
+    /// are used in a fixed order and the sample seed is 0 unless given
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Check the structure of the translated EPUB once written
+    #[arg(long)]
+    validate: bool,
+
+    /// Run epubcheck on the translated EPUB: `epubcheck` on the PATH, or the JAR named by
+    /// EPUBCHECK_JAR
+    #[arg(long)]
+    epubcheck: bool,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Check the structure of an EPUB: mimetype, container, manifest, spine and XML
+    /// well-formedness. No replacement for epubcheck
+    Validate {
+        /// Path to the EPUB file, `-` to read it from stdin
+        epub_file: PathBuf,
+    },
+    /// Show what there is to translate in an EPUB, file by file, with the default extraction
+    /// rules
+    Count {
+        /// Path to the EPUB file, `-` to read it from stdin
+        epub_file: PathBuf,
+
+        /// Print the breakdown as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the source and target languages of the API, side by side
+    Languages {
+        /// DeepL API key, the first key of the configuration files otherwise
+        #[arg(short = 'k', long, env = "DEEPL_API_KEY", hide_env_values = true)]
+        api_key: Option<AuthKey>,
+
+        /// List the languages of the mock server instead
+        #[arg(long)]
+        test: bool,
+
+        #[command(flatten)]
+        client: ClientArgs,
+
+        /// Print both lists as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Translate the first segments of an EPUB, or a chapter, to judge the translation before
+    /// spending the quota on the whole book. Uses the default extraction rules
+    Preview {
+        /// Path to the EPUB file
+        epub_file: PathBuf,
+
+        /// Target language code, the one of the configuration files otherwise
+        #[arg(short, long, env = "EPUB_TRANSLATOR_TARGET_LANG")]
+        target_lang: Option<String>,
+
+        /// Source language code (optional, auto-detect if not provided)
+        #[arg(short, long, env = "EPUB_TRANSLATOR_SOURCE_LANG")]
+        source_lang: Option<String>,
+
+        /// Number of segments translated, 10 unless --chapter is given
+        #[arg(short = 'n', long)]
+        segments: Option<usize>,
+
+        /// Translate this chapter, by position in the spine, instead of the start of the book
+        #[arg(long)]
+        chapter: Option<usize>,
+
+        /// Write the preview EPUB to this file instead of printing the segments
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Overwrite the preview EPUB if it already exists
+        #[arg(long, requires = "output")]
+        force: bool,
+
+        /// Print the segments as JSON
+        #[arg(long, conflicts_with = "output")]
+        json: bool,
+
+        /// DeepL API key, the first key of the configuration files otherwise
+        #[arg(short = 'k', long, env = "DEEPL_API_KEY", hide_env_values = true)]
+        api_key: Option<AuthKey>,
+
+        /// Translate with the mock server instead
+        #[arg(long)]
+        test: bool,
+
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Translate an EPUB and write every segment with its translation to an XLIFF 2.0 file,
+    /// to be post-edited in a CAT tool, then turned into the translated EPUB by `import-xliff`.
+    /// Uses the default extraction rules
+    ExportXliff {
+        /// Path to the EPUB file
+        epub_file: PathBuf,
+
+        /// Path to the XLIFF file written
+        xliff_file: PathBuf,
+
+        /// Target language code, the one of the configuration files otherwise
+        #[arg(short, long, env = "EPUB_TRANSLATOR_TARGET_LANG")]
+        target_lang: Option<String>,
+
+        /// Source language code, the language of the book otherwise
+        #[arg(short, long, env = "EPUB_TRANSLATOR_SOURCE_LANG")]
+        source_lang: Option<String>,
+
+        /// Overwrite the XLIFF file if it already exists
+        #[arg(long)]
+        force: bool,
+
+        /// DeepL API key, the first key of the configuration files otherwise
+        #[arg(short = 'k', long, env = "DEEPL_API_KEY", hide_env_values = true)]
+        api_key: Option<AuthKey>,
+
+        /// Translate with the mock server instead
+        #[arg(long)]
+        test: bool,
+
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Write the translated EPUB with the targets of a post-edited XLIFF file written by
+    /// `export-xliff`, without any request. Segments without a target keep their source text
+    ImportXliff {
+        /// Path to the EPUB file the XLIFF file was exported from
+        epub_file: PathBuf,
+
+        /// Path to the post-edited XLIFF file
+        xliff_file: PathBuf,
+
+        /// Path to the translated EPUB file
+        output_file: PathBuf,
+
+        /// Overwrite the output file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Step through the segments of a translated EPUB next to their source in the terminal,
+    /// correct their translation and write the corrected EPUB. Uses the default extraction
+    /// rules
+    Review {
+        /// Path to the EPUB file that was translated
+        original_file: PathBuf,
+
+        /// Path to the translated EPUB file
+        translated_file: PathBuf,
+
+        /// Path to the corrected EPUB file
+        output_file: PathBuf,
+
+        /// Overwrite the output file if it already exists, e.g. the translated one
+        #[arg(long)]
+        force: bool,
+    },
+    /// Store DeepL API keys in the OS keyring, to be used with --keyring or a keys file
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+    },
+    /// Print the completion script of a shell: bash, elvish, fish, powershell or zsh
+    Completions { shell: Shell },
+    /// Print the man page, in roff
+    Man,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum KeysAction {
+    /// Store the key read from stdin under a name, replacing the key stored there if any
+    Store {
+        #[arg(default_value = "default")]
+        name: String,
+    },
+    /// Remove the key stored under a name
+    Delete {
+        #[arg(default_value = "default")]
+        name: String,
+    },
+}
+
+/// Settings of the HTTP client, shared by the translation and the subcommands that query the
+/// API.
+#[derive(clap::Args, Debug, Clone)]
+struct ClientArgs {
+    /// Seconds a request to DeepL may take before it fails, and is retried
+    #[arg(long, env = "EPUB_TRANSLATOR_TIMEOUT")]
+    timeout: Option<u64>,
+
+    /// Proxy the requests to DeepL go through, e.g. `http://proxy:8080`, instead of the one of
+    /// HTTPS_PROXY
+    #[arg(long, env = "EPUB_TRANSLATOR_PROXY")]
+    proxy: Option<String>,
+}
+
+/// Prints a status message, on stderr when stdout carries the translated EPUB.
+macro_rules! status {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Sends the logs to stderr, or to `log_file`. Only warnings are logged by default, so the
+// progress bar stays readable.
+fn init_logging(verbose: u8, quiet: bool, log_file: Option<&Path>) -> std::io::Result<()> {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::WARN,
+        (false, 1) => LevelFilter::INFO,
+        (false, 2) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    // The logs of the dependencies would drown ours
+    let filter = Targets::new()
+        .with_target("epub_translator", level)
+        .with_default(level.min(LevelFilter::WARN));
+    let registry = tracing_subscriber::registry().with(filter);
+    match log_file {
+        Some(path) => {
+            let file = Mutex::new(File::create(path)?);
+            registry
+                .with(fmt::layer().with_ansi(false).with_writer(file))
+                .init()
+        }
+        None => registry
+            .with(fmt::layer().with_writer(std::io::stderr))
+            .init(),
+    }
+    Ok(())
+}
+
+// Fills the settings not given on the command line or in the environment from the configuration
+// files. API keys are handled apart, see `main`.
+fn apply_config(args: &mut Args, matches: &ArgMatches, config: &Config) -> Result<(), String> {
+    let unset = |id: &str| !given(matches, id);
+    let config = config.clone();
+    if unset("target_lang") {
+        args.target_lang = config.target_lang.or(args.target_lang.take());
+    }
+    if unset("source_lang") {
+        args.source_lang = config.source_lang.or(args.source_lang.take());
+    }
+    if let Some(parallel) = config.parallel.filter(|_| unset("parallel")) {
+        args.parallel = parallel;
+    }
+    if unset("formality") {
+        args.formality = config.formality.or(args.formality);
+    }
+    if unset("glossary") {
+        args.glossary = config.glossary.or(args.glossary.take());
+    }
+    if unset("keep_terms") {
+        args.keep_terms = config.keep_terms.or(args.keep_terms.take());
+    }
+    if let Some(classes) = config
+        .notranslate_classes
+        .filter(|_| unset("notranslate_classes"))
+    {
+        args.notranslate_classes = classes;
+    }
+    if let Some(tags) = config.skip_tags.filter(|_| unset("skip_tags")) {
+        args.skip_tags = tags;
+    }
+    if let Some(attributes) = config
+        .translate_attributes
+        .filter(|_| unset("translate_attributes"))
+    {
+        args.translate_attributes = attributes;
+    }
+    if let Some(selectors) = config
+        .exclude_selectors
+        .filter(|_| unset("exclude_selector"))
+    {
+        args.exclude_selector = selectors
+            .iter()
+            .map(|selector| selector.parse())
+            .collect::<Result<_, String>>()?;
+    }
+    Ok(())
+}
+
+// Fills the settings of a job that configuration files do not have, see `Job::config` for the
+// others. Flags of the command line take precedence.
+fn apply_job(args: &mut Args, matches: &ArgMatches, job: &Job) -> Result<(), String> {
+    let unset = |id: &str| !given(matches, id);
+    if unset("output_template") {
+        let template = job.output.as_deref().unwrap_or(DEFAULT_JOB_OUTPUT);
+        args.output_template = Some(parse_output_template(template)?);
+    }
+    if let Some(provider) = job.provider.name.filter(|_| unset("provider")) {
+        args.provider = provider;
+    }
+    let skip = &job.skip;
+    if let Some(chapters) = skip.chapters.as_ref().filter(|_| unset("chapters")) {
+        args.chapters = chapters
+            .iter()
+            .map(|chapters| parse_chapter_range(chapters))
+            .collect::<Result<_, _>>()?;
+    }
+    if let Some(include) = skip.include.as_ref().filter(|_| unset("include")) {
+        args.include = include
+            .iter()
+            .map(|pattern| parse_pattern(pattern))
+            .collect::<Result<_, _>>()?;
+    }
+    if let Some(exclude) = skip.exclude.as_ref().filter(|_| unset("exclude")) {
+        args.exclude = exclude
+            .iter()
+            .map(|pattern| parse_pattern(pattern))
+            .collect::<Result<_, _>>()?;
+    }
+    let budget = &job.budget;
+    if unset("max_chars") {
+        args.max_chars = budget.max_chars.or(args.max_chars);
+    }
+    if let Some(sample) = budget.sample.filter(|_| unset("sample")) {
+        args.sample = Some(parse_sample_fraction(&sample.to_string())?);
+    }
+    if unset("sample_seed") {
+        args.sample_seed = budget.sample_seed.or(args.sample_seed);
+    }
+    Ok(())
+}
+
+// Whether the setting `id` is given on the command line or in the environment.
+fn given(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+// The date written into the book by --deterministic: SOURCE_DATE_EPOCH, the convention of
+// reproducible builds, or the first date of the zip format.
+fn deterministic_time() -> Result<DateTime<Utc>, String> {
+    let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") else {
+        return Ok(DateTime::from_timestamp(315_532_800, 0).unwrap());
+    };
+    epoch
+        .trim()
+        .parse()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .ok_or_else(|| format!("invalid SOURCE_DATE_EPOCH `{}`", epoch))
+}
+
+// Whether `path` stands for stdin or stdout.
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+// Whether the confirmation can be answered: stdin, or the terminal when stdin carries the EPUB,
+// must be a terminal. Scripts and CI jobs are not asked.
+fn can_confirm(from_stdin: bool) -> bool {
+    if from_stdin {
+        File::open("/dev/tty").is_ok()
+    } else {
+        std::io::stdin().is_terminal()
+    }
+}
+
+// Asks the user to confirm the translation. The answer is read from the terminal when stdin
+// carries the EPUB.
+fn confirm(from_terminal: bool) -> std::io::Result<bool> {
+    Ok(read_answer(from_terminal)?.trim().to_lowercase() == "y")
+}
+
+// Reads a line typed by the user, from the terminal when stdin carries the EPUB.
+fn read_answer(from_terminal: bool) -> std::io::Result<String> {
+    let mut input = String::new();
+    if from_terminal {
+        BufReader::new(File::open("/dev/tty")?).read_line(&mut input)?;
+    } else {
+        std::io::stdin().read_line(&mut input)?;
+    }
+    Ok(input)
+}
+
+/// Renders the library progress events as indicatif progress bars, on stdout unless it
+/// carries the translated EPUB: the characters translated, with their throughput, the time
+/// left and the file of the last unit, then the retries once there are any.
+struct CliProgress {
+    bars: MultiProgress,
+    bar: ProgressBar,
+    retries: ProgressBar,
+    state: Mutex<CliProgressState>,
+}
+
+#[derive(Default)]
+struct CliProgressState {
+    /// File and billable characters of each unit, by id.
+    units: Vec<(PathBuf, usize)>,
+    total: usize,
+    done: usize,
+    /// Units sent again at least once.
+    retried: HashSet<usize>,
+    /// The retries bar is only drawn from the first retry on.
+    retries_shown: bool,
+}
+
+impl CliProgress {
+    fn new(to_stderr: bool) -> Self {
+        let target = if to_stderr {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::stdout()
+        };
+        let bars = MultiProgress::with_draw_target(target);
+        let bar = bars.add(ProgressBar::new(0));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "[{elapsed_precise}] {bar:40.cyan/blue} {percent}% {chars_per_sec} ETA {eta} \
+                     {msg}",
+                )
+                .unwrap()
+                .with_key(
+                    "chars_per_sec",
+                    |state: &IndicatifState, w: &mut dyn FmtWrite| {
+                        write!(w, "{:.0} chars/s", state.per_sec()).unwrap()
+                    },
+                )
+                .progress_chars("##-"),
+        );
+        let retries = ProgressBar::hidden();
+        retries.set_style(
+            ProgressStyle::default_bar()
+                .template("           {bar:40.yellow} {pos}/{len} retried {msg}")
+                .unwrap()
+                .progress_chars("##-"),
+        );
+        Self {
+            bars,
+            bar,
+            retries,
+            state: Mutex::new(CliProgressState::default()),
+        }
+    }
+}
+
+impl ProgressSink for CliProgress {
+    fn on_event(&self, event: ProgressEvent) {
+        let mut state = self.state.lock().unwrap();
+        match event {
+            ProgressEvent::NodePlanned {
+                id,
+                path,
+                characters,
+            } => {
+                // A new run, for the next target language
+                if id == 0 {
+                    state.units.clear();
+                }
+                state.units.push((path, characters));
+            }
+            ProgressEvent::Started { total } => {
+                let characters: usize = state.units.iter().map(|(_, characters)| characters).sum();
+                state.total = total;
+                state.done = 0;
+                state.retried.clear();
+                self.bar.set_length(characters as u64);
+                self.bar.reset();
+                self.bar.set_message(format!("0/{} units", total));
+                self.retries.set_length(0);
+                self.retries.reset();
+            }
+            ProgressEvent::NodeRetried { id, attempt, error } => {
+                if !state.retries_shown {
+                    state.retries_shown = true;
+                    self.bars.add(self.retries.clone());
+                }
+                if state.retried.insert(id) {
+                    self.retries.inc_length(1);
+                }
+                let error: String = error.chars().take(60).collect();
+                self.retries
+                    .set_message(format!("(#{} attempt {}: {})", id, attempt, error));
+            }
+            ProgressEvent::NodeTranslated { id, .. } | ProgressEvent::NodeFailed { id, .. } => {
+                state.done += 1;
+                if state.retried.contains(&id) {
+                    self.retries.inc(1);
+                }
+                let (path, characters) = state.units.get(id).cloned().unwrap_or_default();
+                self.bar.inc(characters as u64);
+                let file = path.file_name().unwrap_or_default().to_string_lossy();
+                self.bar
+                    .set_message(format!("{}/{} units {}", state.done, state.total, file));
+            }
+            ProgressEvent::Cancelled { .. } => {
+                self.bar.abandon();
+                self.retries.abandon();
+            }
+            ProgressEvent::Finished => {
+                self.bar
+                    .finish_with_message(format!("{}/{} units", state.done, state.total));
+                self.retries.finish();
+            }
+            ProgressEvent::Warning { message } => {
+                self.bars.suspend(|| eprintln!("Warning: {}", message))
+            }
+            ProgressEvent::NodeQueued { .. } | ProgressEvent::FileSerialized { .. } => {}
+        }
+    }
+}
+
+/// Writes the library progress events as line-delimited JSON on stdout, for programs driving
+/// the CLI. `finished` and `error` are written by `main` once the run is over.
+struct JsonProgress;
+
+impl ProgressSink for JsonProgress {
+    fn on_event(&self, event: ProgressEvent) {
+        let event = match event {
+            ProgressEvent::Started { total } => json!({ "event": "started", "total": total }),
+            ProgressEvent::NodeTranslated { id, .. } => {
+                json!({ "event": "node_translated", "id": id })
+            }
+            ProgressEvent::NodeFailed { id, error } => {
+                json!({ "event": "node_failed", "id": id, "error": error })
+            }
+            ProgressEvent::FileSerialized { path } => json!({ "event": "file_done", "path": path }),
+            ProgressEvent::Cancelled { remaining } => {
+                json!({ "event": "cancelled", "remaining": remaining })
+            }
+            ProgressEvent::Warning { message } => json!({ "event": "warning", "message": message }),
+            ProgressEvent::NodeRetried { id, attempt, error } => {
+                json!({ "event": "node_retried", "id": id, "attempt": attempt, "error": error })
+            }
+            ProgressEvent::NodePlanned { .. }
+            | ProgressEvent::NodeQueued { .. }
+            | ProgressEvent::Finished => return,
+        };
+        print_json_event(event);
+    }
+}
+
+fn print_json_event(event: serde_json::Value) {
+    println!("{}", event);
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    init_logging(args.verbose, args.quiet, args.log_file.as_deref())?;
+    // Every request of the run, or of the subcommand, goes through the same client, reusing its connections
+    let client_args = match &args.command {
+        Some(
+            Command::Languages { client, .. }
+            | Command::Preview { client, .. }
+            | Command::ExportXliff { client, .. },
+        ) => client,
+        _ => &args.client,
+    };
+    let client = ClientOptions {
+        timeout: client_args.timeout.map(Duration::from_secs),
+        proxy: client_args.proxy.clone(),
+        ..Default::default()
+    }
+    .build()
+    .unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    match &args.command {
+        Some(Command::Validate { epub_file }) => return validate(epub_file),
+        Some(Command::Count { epub_file, json }) => return count(epub_file, *json),
+        Some(Command::Languages {
+            api_key,
+            test,
+            json,
+            ..
+        }) => return languages(api_key.clone(), *test, *json, &client).await,
+        Some(Command::Preview { .. }) => return preview(&args, &client).await,
+        Some(Command::ExportXliff { .. }) => return export_xliff(&args, &client).await,
+        Some(Command::ImportXliff {
+            epub_file,
+            xliff_file,
+            output_file,
+            force,
+        }) => return import_xliff(epub_file, xliff_file, output_file, *force).await,
+        Some(Command::Review {
+            original_file,
+            translated_file,
+            output_file,
+            force,
+        }) => return review(original_file, translated_file, output_file, *force),
+        Some(Command::Keys { action }) => return manage_keys(action).await,
+        Some(Command::Completions { shell }) => {
+            let mut command = Args::command();
+            let name = command.get_name().to_string();
+            generate(*shell, &mut command, name, &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::Man) => return Ok(Man::new(Args::command()).render(&mut std::io::stdout())?),
+        None => {}
+    }
+    let mut config = Config::load(args.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Error in configuration file {}", e);
+        std::process::exit(1);
+    });
+    let job = args.job.as_deref().map(|path| {
+        Job::read(path).unwrap_or_else(|e| {
+            eprintln!("Error in job {}", e);
+            std::process::exit(1);
+        })
+    });
+    if let Some(job) = &job {
+        config = config.merge(job.config());
+    }
+    if let Err(e) = apply_config(&mut args, &matches, &config) {
+        eprintln!("Error in configuration: {}", e);
+        std::process::exit(1);
+    }
+    // The requests of each book are summed up at the end of its translation
+    let recorder = SummaryRecorder::default();
+    metrics::set_global_recorder(recorder.clone())?;
+    telemetry::describe();
+    let Some(job) = job else {
+        return translate_input(args, &config, &recorder, &client).await;
+    };
+    if let Err(e) = apply_job(&mut args, &matches, &job) {
+        eprintln!("Error in job: {}", e);
+        std::process::exit(1);
+    }
+    // The books are translated in turn, the job stops at the first one that fails
+    for input_file in job.inputs {
+        status!(args.json, "Translating {}", input_file.display());
+        translate_input(
+            Args {
+                input_file: Some(input_file),
+                ..args.clone()
+            },
+            &config,
+            &recorder,
+            &client,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Translates the input file of `args`, into each of its target languages.
+async fn translate_input(
+    mut args: Args,
+    config: &Config,
+    recorder: &SummaryRecorder,
+    client: &Client,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rates = config.rates.clone().unwrap_or_else(default_rates);
+    // Required by clap when no subcommand is given, the output file only without --dry-run
+    let Some(input_file) = args.input_file.take() else {
+        unreachable!("missing required arguments")
+    };
+    let output_file = args.output_file.take().unwrap_or_default();
+    let mut target_langs: Vec<String> = args
+        .target_lang
+        .take()
+        .unwrap_or_default()
+        .split(',')
+        .map(|lang| lang.trim().to_string())
+        .filter(|lang| !lang.is_empty())
+        .collect();
+    if target_langs.is_empty() {
+        eprintln!(
+            "Error: no target language, pass --target-lang or set target_lang in a \
+             configuration file"
+        );
+        std::process::exit(1);
+    }
+    let several_langs = target_langs.len() > 1;
+    let from_stdin = is_stdio(&input_file);
+    let to_stdout = is_stdio(&output_file);
+    if args.json && to_stdout {
+        eprintln!("Error: --json writes to stdout, the translated EPUB cannot go there too");
+        std::process::exit(1);
+    }
+    if several_langs && to_stdout {
+        eprintln!("Error: several target languages need an output file, not stdout");
+        std::process::exit(1);
+    }
+    if (args.workdir.is_some() || args.keep_workdir) && (from_stdin || to_stdout || several_langs) {
+        eprintln!(
+            "Error: a workdir needs input and output files and a single target language, the \
+             EPUB is translated in memory otherwise"
+        );
+        std::process::exit(1);
+    }
+    // Messages go to stderr when stdout carries the translated EPUB or the JSON events
+    let to_stderr = to_stdout || args.json;
+    if args.sample.is_some() && args.review_out.is_none() && to_stdout {
+        eprintln!("Error: --sample with the EPUB written to stdout needs --review-out");
+        std::process::exit(1);
+    }
+
+    let checks = OutputChecks {
+        validate: args.validate,
+        epubcheck: args.epubcheck.then(|| {
+            Epubcheck::find().unwrap_or_else(|| {
+                eprintln!(
+                    "Error: epubcheck not found, install it or set {}",
+                    EPUBCHECK_JAR_VAR
+                );
+                std::process::exit(1);
+            })
+        }),
+    };
 
     // Verify input file exists
-    if !args.input_file.exists() {
+    if !from_stdin && !input_file.exists() {
         eprintln!("Error: Input file does not exist");
-        std::process::exit(1);
+        std::process::exit(EXIT_INVALID_INPUT);
     }
 
     // Verify input file is an EPUB
-    if args.input_file.extension().unwrap_or_default() != "epub" {
+    if !from_stdin && input_file.extension().unwrap_or_default() != "epub" {
         eprintln!("Error: Input file is not an EPUB");
+        std::process::exit(EXIT_INVALID_INPUT);
+    }
+
+    // An EPUB piped in is read whole before anything else
+    let stdin_epub = if from_stdin {
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input)?;
+        Some(input)
+    } else {
+        None
+    };
+    let mut options = translate_options(&args, &target_langs[0], to_stdout);
+
+    if args.dry_run {
+        let input = match stdin_epub {
+            Some(input) => input,
+            None => std::fs::read(&input_file)?,
+        };
+        let result = BookArchive::read(input, &options.unzip_limits).and_then(|book| {
+            dry_run(
+                &book,
+                &options.extract,
+                &options.selection,
+                args.show_segments,
+                &rates,
+            )
+        });
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            std::process::exit(EXIT_INVALID_INPUT);
+        }
+        return Ok(());
+    }
+
+    let accounts = connect_accounts(&args, config, client, to_stderr).await?;
+    let local = args.provider.local().is_some();
+
+    // Local providers take any code, there is no list of the supported languages
+    if local {
+        for target_lang in target_langs.iter_mut() {
+            *target_lang = target_lang.trim().replace('_', "-").to_uppercase();
+        }
+    } else {
+        // Test languages code, names and aliases are turned into codes
+        let languages = get_languages(&accounts.primary, LanguageType::Target, client)
+            .await
+            .unwrap_or_else(|e| provider_error(e));
+        for target_lang in target_langs.iter_mut() {
+            let code = match match_language(target_lang, &languages.0) {
+                LanguageMatch::Found(code) => code,
+                LanguageMatch::Variants(variants) if !args.yes && can_confirm(from_stdin) => {
+                    status!(
+                        to_stderr,
+                        "{} has several variants, which one? ({})",
+                        target_lang,
+                        variants.join(", ")
+                    );
+                    let answer = read_answer(from_stdin)?;
+                    let choices: Vec<Language> = languages
+                        .0
+                        .iter()
+                        .filter(|language| variants.contains(&language.language))
+                        .cloned()
+                        .collect();
+                    match match_language(&answer, &choices) {
+                        LanguageMatch::Found(code) => code,
+                        _ => {
+                            eprintln!(
+                                "Error: {} is not one of {}",
+                                answer.trim(),
+                                variants.join(", ")
+                            );
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                unmatched => language_error(target_lang, unmatched, &languages.0),
+            };
+            if code != *target_lang {
+                status!(to_stderr, "Target language {}: {}", target_lang, code);
+            }
+            *target_lang = code;
+        }
+    }
+    options.target_lang = target_langs[0].clone();
+    if options.extract.skip_lang.is_some() {
+        options.extract.skip_lang = Some(target_langs[0].clone());
+    }
+    let outputs: Vec<PathBuf> = match &args.output_template {
+        Some(template) => {
+            let fields = output_fields(
+                template,
+                &input_file,
+                stdin_epub.as_deref(),
+                &options.unzip_limits,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(EXIT_INVALID_INPUT);
+            });
+            target_langs
+                .iter()
+                .map(|lang| {
+                    let lang_fields = OutputFields {
+                        lang: language_tag(lang),
+                        ..fields.clone()
+                    };
+                    let path = template.render(&lang_fields);
+                    language_path(&path, lang, several_langs && !template.uses("lang"))
+                })
+                .collect()
+        }
+        None => target_langs
+            .iter()
+            .map(|lang| language_path(&output_file, lang, several_langs))
+            .collect(),
+    };
+    if !args.force {
+        if let Some(existing) = outputs
+            .iter()
+            .find(|output| !is_stdio(output) && output.exists())
+        {
+            eprintln!(
+                "Error: {} already exists, pass --force to overwrite it",
+                existing.display()
+            );
+            std::process::exit(1);
+        }
+    }
+
+    status!(to_stderr, "       -----------        ");
+
+    // Count the number of characters to translate
+    let chapters = match &stdin_epub {
+        Some(input) => {
+            BookArchive::read(input.clone(), &options.unzip_limits).and_then(|archive| {
+                count_book_chapters(&archive, &options.extract, &options.selection)
+            })
+        }
+        None => count_epub_chapters(&input_file, &options.extract, &options.selection),
+    };
+    let total = match chapters {
+        Ok(chapters) => total_count(&chapters),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(EXIT_INVALID_INPUT);
+        }
+    };
+
+    // Show user the usage and the char count
+    if !local {
+        let usage = get_usage(&accounts.primary, client)
+            .await
+            .unwrap_or_else(|e| provider_error(e));
+        status!(
+            to_stderr,
+            "DeepL Usage: Your limit is: {}, you have already use: {}",
+            &usage.character_limit,
+            &usage.character_count
+        );
+        status!(
+            to_stderr,
+            " Your character translation capacity is {}",
+            accounts.total_capacity
+        );
+    }
+    status!(
+        to_stderr,
+        " Number of characters to translate: {}",
+        total.characters
+    );
+    if let Some(max_chars) = args.max_chars {
+        status!(
+            to_stderr,
+            " At most {} billable characters will be sent (--max-chars)",
+            max_chars
+        );
+    }
+    if several_langs {
+        status!(
+            to_stderr,
+            " The book is translated into {} languages, the cost is for all of them",
+            target_langs.len()
+        );
+    }
+    let billable = args.max_chars.map_or(total.billable_characters, |max| {
+        max.min(total.billable_characters)
+    }) * target_langs.len();
+    if !local {
+        for estimate in estimate(billable, &rates) {
+            status!(to_stderr, " Estimated cost: {}", estimate);
+        }
+    }
+
+    // Ask for user confirmation
+    if !args.yes && can_confirm(from_stdin) {
+        status!(
+            to_stderr,
+            "Do you want to proceed with the translation? (y/n)"
+        );
+        if !confirm(from_stdin)? {
+            status!(to_stderr, "Translation cancelled by user.");
+            std::process::exit(0);
+        }
+    }
+
+    // Ctrl+C stops the translation, what is already translated is still written
+    let cancel_on_signal = options.cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel_on_signal.cancel();
+        }
+    });
+
+    // The temporary workdir is kept by passing it as the workdir
+    if args.keep_workdir {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("epub-translator-");
+        let temp_dir = match &args.temp_dir {
+            Some(dir) => builder.tempdir_in(dir)?,
+            None => builder.tempdir()?,
+        };
+        options.workdir = Some(temp_dir.into_path());
+    }
+
+    if let Some(sample) = &options.sample {
+        status!(
+            to_stderr,
+            "Sampling {}% of the segments, pass --sample-seed {} to pick the same ones",
+            (sample.fraction * 10_000.0).round() / 100.0,
+            sample.seed
+        );
+    }
+    options.configurations = accounts.balanced;
+    options.client = client.clone();
+
+    let start = Instant::now();
+    let result = if several_langs {
+        translate_languages(
+            stdin_epub,
+            &input_file,
+            &outputs,
+            &target_langs,
+            &options,
+            &checks,
+        )
+        .await
+    } else if from_stdin || to_stdout {
+        translate_stdio(stdin_epub, &input_file, &outputs[0], &options, &checks)
+            .await
+            .map(|result| vec![result])
+    } else {
+        translate_epub_with(&input_file, &outputs[0], &options)
+            .await
+            .map_err(Into::into)
+            .and_then(|report| Ok(vec![(report, checks.run(&outputs[0])?)]))
+    };
+    let results = match result {
+        Ok(results) => results,
+        // A job goes on with its next book
+        Err(e)
+            if args.job.is_some()
+                && matches!(
+                    e.downcast_ref::<EpubTranslatorError>().map(|e| e.cause()),
+                    Some(EpubTranslatorError::SameLanguage(_))
+                ) =>
+        {
+            if args.json {
+                print_json_event(json!({ "event": "skipped", "message": e.to_string() }));
+            }
+            status!(to_stderr, "Skipped: {}", e);
+            return Ok(());
+        }
+        Err(e) => {
+            if args.json {
+                print_json_event(json!({ "event": "error", "message": e.to_string() }));
+            }
+            eprintln!("Error during translation: {}", e);
+            let Some(error) = e.downcast_ref::<EpubTranslatorError>() else {
+                std::process::exit(1);
+            };
+            match error.cause() {
+                EpubTranslatorError::FixedLayout(_) => {
+                    eprintln!("Use --force-fixed-layout to translate it anyway");
+                    std::process::exit(EXIT_INVALID_INPUT);
+                }
+                EpubTranslatorError::SameLanguage(_) => {
+                    eprintln!("Use --force-same-language to translate it anyway");
+                    std::process::exit(EXIT_INVALID_INPUT);
+                }
+                EpubTranslatorError::NoSpace { .. } => {
+                    eprintln!("Use --temp-dir to extract it on another filesystem");
+                    std::process::exit(1);
+                }
+                EpubTranslatorError::InvalidEpub(_)
+                | EpubTranslatorError::Encrypted(_)
+                | EpubTranslatorError::Zip(_)
+                | EpubTranslatorError::Parse(_)
+                | EpubTranslatorError::Config(_) => std::process::exit(EXIT_INVALID_INPUT),
+                EpubTranslatorError::QuotaExceeded { .. } => {
+                    std::process::exit(EXIT_QUOTA_EXCEEDED)
+                }
+                EpubTranslatorError::Provider { .. } | EpubTranslatorError::Network(_) => {
+                    std::process::exit(EXIT_PROVIDER_ERROR)
+                }
+                _ => std::process::exit(1),
+            }
+        }
+    };
+    let mut problem_count = 0;
+    let mut incomplete = None;
+    for ((target_lang, output), (report, problems)) in
+        target_langs.iter().zip(&outputs).zip(results)
+    {
+        if several_langs {
+            status!(to_stderr, "{} -> {}", target_lang, output.display());
+        }
+        print_outcome(&report, to_stderr);
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        if args.json {
+            print_json_event(json!({
+                "event": "finished",
+                "target_lang": target_lang,
+                "report": report,
+                "problems": problems
+            }));
+        }
+        // Local providers bill nothing, there is no cost to estimate
+        let rates = if local { &[][..] } else { &rates };
+        write_language_outputs(
+            &args,
+            target_lang,
+            output,
+            &report,
+            several_langs,
+            rates,
+            to_stderr,
+        )?;
+        problem_count += problems.len();
+        // Segments left out of the sample are not missing
+        let untranslated = report.total - report.translated - report.not_sampled;
+        if args.strict && untranslated > 0 {
+            incomplete = incomplete.or(Some((untranslated, exit_status(&report))));
+        }
+    }
+
+    if let Some(workdir) = &options.workdir {
+        status!(to_stderr, "Workdir kept at {}", workdir.display());
+    }
+
+    // Local providers send no request
+    let metrics = recorder.summary();
+    recorder.clear();
+    if metrics.total_requests() > 0 {
+        status!(to_stderr, "{}", metrics);
+        if args.json {
+            print_json_event(json!({ "event": "metrics", "metrics": metrics }));
+        }
+    }
+
+    let total_duration = start.elapsed();
+    info!("Total duration: {:?}", total_duration);
+
+    // Shutdown mock server if test mode
+    #[cfg(feature = "mock-server")]
+    if let Some(server) = accounts.mock_server {
+        status!(to_stderr, "Shutting down mock server...");
+        server.stop();
+    }
+
+    if let Some((untranslated, status)) = incomplete {
+        eprintln!(
+            "Error: {} segments left untranslated (--strict)",
+            untranslated
+        );
+        std::process::exit(status);
+    }
+    if problem_count > 0 {
+        eprintln!("The translated EPUB has {} problems", problem_count);
         std::process::exit(1);
     }
+    Ok(())
+}
+
+// The options of the translation of `args` into `target_lang`, but for the DeepL accounts and a
+// kept temporary workdir, known later. Exits when a terms, glossary or translation memory file
+// cannot be read.
+fn translate_options(args: &Args, target_lang: &str, to_stdout: bool) -> TranslateOptions {
+    // What is translated
+    let extract = ExtractOptions {
+        notranslate_classes: args.notranslate_classes.clone(),
+        skip_tags: args.skip_tags.clone(),
+        exclude_selectors: args.exclude_selector.clone(),
+        translate_svg: args.translate_svg,
+        attributes: args
+            .translate_attributes
+            .iter()
+            .filter(|attribute| !attribute.is_empty())
+            .cloned()
+            .collect(),
+        ruby: args.ruby,
+        skip_lang: (!args.retranslate_target_lang).then(|| target_lang.to_string()),
+        heading_context: !args.no_heading_context,
+        translate_metadata: args.translate_metadata,
+        keep_terms: match &args.keep_terms {
+            Some(path) => read_keep_terms(path).unwrap_or_else(|e| {
+                eprintln!("Error in the kept terms {}", e);
+                std::process::exit(EXIT_INVALID_INPUT);
+            }),
+            None => Vec::new(),
+        },
+    };
+    let glossary = match &args.glossary {
+        Some(path) => Glossary::read(path).unwrap_or_else(|e| {
+            eprintln!("Error in the glossary {}", e);
+            std::process::exit(EXIT_INVALID_INPUT);
+        }),
+        None => Glossary::default(),
+    };
+    let mut memory: Option<TranslationMemory> = None;
+    for path in &args.memories {
+        let read = TranslationMemory::read(path).unwrap_or_else(|e| {
+            eprintln!("Error in the translation memory {}", e);
+            std::process::exit(EXIT_INVALID_INPUT);
+        });
+        match &mut memory {
+            Some(memory) => memory.extend(read),
+            None => memory = Some(read),
+        }
+    }
+    if let Some(memory) = &mut memory {
+        memory.fuzzy_threshold = args.tm_fuzzy;
+    }
 
+    // A seed is drawn when none is given, and shown so that the sample can be picked again
+    let sample = args.sample.map(|fraction| Sample {
+        fraction,
+        seed: args
+            .sample_seed
+            .unwrap_or_else(|| match args.deterministic {
+                true => 0,
+                false => thread_rng().gen(),
+            }),
+    });
+    let fixed_time = if args.deterministic {
+        Some(deterministic_time().unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }))
+    } else {
+        None
+    };
+    TranslateOptions {
+        target_lang: target_lang.to_string(),
+        source_lang: args.source_lang.clone(),
+        concurrent_requests: args.parallel,
+        provider: args.provider,
+        formality: args.formality,
+        extract,
+        serialize: SerializeOptions {
+            entities: args.entities,
+            rewrite_inner_lang: args.rewrite_inner_lang,
+        },
+        progress: if args.json {
+            Arc::new(JsonProgress)
+        } else {
+            Arc::new(CliProgress::new(to_stdout))
+        },
+        unzip_limits: UnzipLimits {
+            max_entry_size: args.max_entry_size * 1024 * 1024,
+            max_total_size: args.max_unzipped_size * 1024 * 1024,
+        },
+        in_memory: args.in_memory,
+        workdir: args.workdir.clone(),
+        temp_dir: args.temp_dir.clone(),
+        fixed_layout: args.force_fixed_layout,
+        same_language: args.force_same_language,
+        selection: Selection {
+            chapters: args.chapters.clone(),
+            include: args.include.clone(),
+            exclude: args.exclude.clone(),
+        },
+        bilingual: args.bilingual,
+        alignment: args.alignment_out.is_some() || args.report_out.is_some() || sample.is_some(),
+        max_chars: args.max_chars,
+        max_units: None,
+        sample,
+        back_translation: args
+            .back_translate
+            .map(|fraction| Sample { fraction, seed: 0 }),
+        protect_names: args.protect_names,
+        localize: args.localize.unwrap_or_default(),
+        glossary,
+        memory: memory.map(Arc::new),
+        fixed_time,
+        ..Default::default()
+    }
+}
+
+// The DeepL accounts of a run, see `connect_accounts`.
+struct Accounts {
+    // The account of the usage and the languages shown before the translation
+    primary: DeepLConfiguration,
+    // The requests go to each account in turn, in proportion to its remaining quota
+    balanced: Vec<Arc<DeepLConfiguration>>,
+    total_capacity: u64,
+    // Translating in test mode, or recording the translations
+    #[cfg(feature = "mock-server")]
+    mock_server: Option<MockServer>,
+}
+
+// Connects to the DeepL accounts of `args`, or to the mock server in test mode, none with a local
+// provider. Exits when no account can be used.
+async fn connect_accounts(
+    args: &Args,
+    config: &Config,
+    client: &Client,
+    to_stderr: bool,
+) -> Result<Accounts, Box<dyn std::error::Error>> {
     let mut balanced_configurations = Vec::new();
     let mut total_capacity = 0;
     let mut primary_configuration = get_test_config();
 
-    if args.test {
-        let test_config = get_test_config();
-        balanced_configurations.push(Arc::new(test_config))
-    } else if let Some(api_key) = args.api_key.or_else(|| std::env::var("DEEPL_API_KEY").ok()) {
-        let mut api_keys = Vec::new();
+    // Keys of the command line, the environment, a keys file or the keyring replace the ones of
+    // the configuration files
+    let mut api_keys = Vec::new();
+    if let Some(api_key) = &args.api_key {
+        api_keys.push(ApiKey::new(api_key.clone()));
+
+        // Get extra keys.
+        let mut indice = 1;
+        while let Ok(key) = std::env::var(format!("DEEPL_API_KEY_{}", indice)) {
+            api_keys.push(ApiKey::new(key));
+            indice += 1;
+        }
+    }
+    if let Some(keys_file) = &args.keys_file {
+        let keys_file = keys_file.clone();
+        match off_runtime(move || read_keys_file(&keys_file)).await {
+            Ok(keys) => api_keys.extend(keys),
+            Err(e) => {
+                eprintln!("Error in keys file {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    for name in &args.keyring {
+        let lookup = name.clone();
+        match off_runtime(move || keyring_key(&lookup)).await {
+            Ok(key) => api_keys.push(ApiKey {
+                label: Some(name.clone()),
+                ..ApiKey::new(key)
+            }),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if api_keys.is_empty() {
+        api_keys = config
+            .api_keys
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(ApiKey::new)
+            .collect();
+    }
 
-        api_keys.push(api_key);
+    #[cfg(not(feature = "mock-server"))]
+    if args.test || args.record.is_some() {
+        eprintln!("Error: {}", NO_MOCK_SERVER);
+        std::process::exit(EXIT_INVALID_INPUT);
+    }
 
-        // Get extra keys.
-        let mut indice = 1;
-        loop {
-            if let Some(key) = std::env::var(format!("DEEPL_API_KEY_{}", indice)).ok() {
-                api_keys.push(key);
-                indice += 1;
-            } else {
-                break;
+    // If test then start mock server, on a free port so that test runs do not collide
+    #[cfg(feature = "mock-server")]
+    let mut mock_server = if args.test {
+        status!(to_stderr, "Starting mock server for test mode...");
+        let mock_options = MockServerOptions {
+            behavior: args.mock.clone().unwrap_or_default(),
+            cassette: args.replay.clone().map(|path| Cassette::Replay { path }),
+            languages: args.mock_languages.clone(),
+            ..MockServerOptions::ephemeral()
+        };
+        match start_deepl_server(&mock_options).await {
+            Ok(server) => {
+                status!(to_stderr, "Mock server started successfully");
+                primary_configuration = server.config();
+                Some(server)
+            }
+            Err(e) => {
+                eprintln!("Error starting mock server: {}", e);
+                return Err(e.into());
             }
         }
+    } else {
+        None
+    };
+
+    let local = args.provider.local().is_some();
+    if local {
+        status!(
+            to_stderr,
+            "Translating with the {:?} provider, nothing is sent",
+            args.provider
+        );
+    } else if args.test {
+        balanced_configurations.push(Arc::new(primary_configuration.clone()))
+    } else if !api_keys.is_empty() {
         let configuration_handlers: Vec<_> = api_keys
             .iter()
-            .map(|key| {
-                let key = key.clone();
+            .map(|api_key| {
+                let api_key = api_key.clone();
+                let client = client.clone();
                 tokio::spawn(async move {
-                    let configuration = DeepLConfiguration::new_with_determine(key).await.unwrap();
-                    let usage = get_usage(&configuration, args.verbose).await.unwrap();
-                    let capacity = usage.character_limit - usage.character_count;
-                    (configuration, capacity)
+                    let error = |e: EpubTranslatorError| format!("{}: {}", api_key.name(), e);
+                    let configuration =
+                        DeepLConfiguration::new_with_determine(api_key.key.clone(), &client)
+                            .await
+                            .map_err(error)?;
+                    let usage = get_usage(&configuration, &client).await.map_err(error)?;
+                    let capacity = usage.character_limit.saturating_sub(usage.character_count);
+                    Ok::<_, String>((configuration, capacity, api_key.weight))
                 })
             })
             .collect();
 
-        let configurations_with_capacity: Vec<(DeepLConfiguration, u64)> =
-            join_all(configuration_handlers)
-                .await
-                .into_iter()
-                .filter_map(|result| result.ok())
-                .filter(|(_, capacity)| *capacity > 20000) // TODO Improve safety
-                .collect();
+        let mut reachable = Vec::new();
+        let mut errors = Vec::new();
+        for result in join_all(configuration_handlers).await {
+            match result.map_err(|e| e.to_string()).and_then(|result| result) {
+                Ok(configuration) => reachable.push(configuration),
+                Err(e) => errors.push(e),
+            }
+        }
+        if reachable.is_empty() {
+            eprintln!("Error: the DeepL API could not be reached with any key");
+            for error in &errors {
+                eprintln!(" {}", error);
+            }
+            std::process::exit(EXIT_PROVIDER_ERROR);
+        }
+        let configurations_with_capacity: Vec<(DeepLConfiguration, u64, f64)> = reachable
+            .into_iter()
+            .filter(|(_, capacity, _)| *capacity > 20000) // TODO Improve safety
+            .collect();
+        if configurations_with_capacity.is_empty() {
+            eprintln!("Error: the character quota of every DeepL API key is exhausted");
+            std::process::exit(EXIT_QUOTA_EXCEEDED);
+        }
 
         total_capacity = configurations_with_capacity
             .iter()
-            .fold(0, |acc, (_, capacity)| acc + capacity);
+            .fold(0, |acc, (_, capacity, _)| acc + capacity);
+        let total_weighted_capacity = configurations_with_capacity
+            .iter()
+            .fold(0.0, |acc, (_, capacity, weight)| {
+                acc + *capacity as f64 * weight
+            });
 
-        (primary_configuration, _) = configurations_with_capacity[0].clone(); // Todo add check
+        (primary_configuration, _, _) = configurations_with_capacity[0].clone();
 
-        for (configuration, capacity) in configurations_with_capacity.iter() {
+        // A key gets a share of the requests proportional to its remaining quota and weight
+        for (configuration, capacity, weight) in configurations_with_capacity.iter() {
             if *capacity > 0 {
-                let proportion =
-                    ((*capacity as f64 / total_capacity as f64) * 100.0).round() as usize;
+                let proportion = ((*capacity as f64 * weight / total_weighted_capacity) * 100.0)
+                    .round() as usize;
                 for _ in 0..proportion {
                     balanced_configurations.push(Arc::new(configuration.clone()))
                 }
             }
         }
 
-        // Shuffle the balanced_configuration_vector
-        balanced_configurations.shuffle(&mut thread_rng())
+        // Shuffle the balanced_configuration_vector, the same way on every deterministic run
+        if args.deterministic {
+            balanced_configurations.shuffle(&mut StdRng::seed_from_u64(0))
+        } else {
+            balanced_configurations.shuffle(&mut thread_rng())
+        }
     } else {
         eprintln!(
-            "Error: DeepL API key not provided and DEEPL_API_KEY environment variable not set"
+            "Error: DeepL API key not provided, pass --api-key, --keys-file or --keyring, set \
+             DEEPL_API_KEY or api_keys in a configuration file"
         );
         std::process::exit(1);
     }
 
-    println!("");
-
-    // If test then start mock server
-    let shutdown_mock_server_signal = if args.test {
-        println!("Starting mock server for test mode...");
-        match start_deepl_server().await {
-            Ok(signal) => {
-                println!("Mock server started successfully");
-                Some(signal)
-            }
+    // The translations go through a proxy of the API recording them
+    #[cfg(feature = "mock-server")]
+    if let Some(path) = &args.record {
+        let upstream = primary_configuration.api_url.clone();
+        if balanced_configurations
+            .iter()
+            .any(|configuration| configuration.api_url != upstream)
+        {
+            eprintln!("Error: --record takes keys of a single plan, either free or pro");
+            std::process::exit(1);
+        }
+        let options = MockServerOptions {
+            cassette: Some(Cassette::Record {
+                upstream,
+                path: path.clone(),
+            }),
+            ..MockServerOptions::ephemeral()
+        };
+        let server = match start_deepl_server(&options).await {
+            Ok(server) => server,
             Err(e) => {
-                eprintln!("Error starting mock server: {}", e);
-                return Err(e);
+                eprintln!("Error starting the recording proxy: {}", e);
+                return Err(e.into());
             }
-        }
-    } else {
-        None
-    };
+        };
+        status!(
+            to_stderr,
+            "Recording the translations in {}",
+            path.display()
+        );
+        balanced_configurations = balanced_configurations
+            .iter()
+            .map(|configuration| Arc::new(server.key_config(configuration.auth_key.clone())))
+            .collect();
+        mock_server = Some(server);
+    }
+
+    status!(to_stderr, "");
 
     // Double check if mock server is running
     if args.test {
-        let config = get_test_config();
-        match get_usage(&config, args.verbose).await {
+        match get_usage(&primary_configuration, client).await {
             Ok(_) => {}
             Err(e) => {
                 eprintln!("Error: The mock server is not running or not responding correctly.");
                 eprintln!("Please ensure the mock server is started before running in test mode.");
                 eprintln!("Error details: {}", e);
+                std::process::exit(EXIT_PROVIDER_ERROR);
+            }
+        }
+    }
+
+    Ok(Accounts {
+        primary: primary_configuration,
+        balanced: balanced_configurations,
+        total_capacity,
+        #[cfg(feature = "mock-server")]
+        mock_server,
+    })
+}
+
+// Writes the files asked for besides the translated EPUB `output` in `target_lang`: the
+// alignment, the quality report, the review of the sample and the failed segments. Their names
+// take the language when the book is translated into several.
+fn write_language_outputs(
+    args: &Args,
+    target_lang: &str,
+    output: &Path,
+    report: &TranslationReport,
+    several_langs: bool,
+    rates: &[Rate],
+    to_stderr: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(alignment_out) = &args.alignment_out {
+        let alignment_out = language_path(alignment_out, target_lang, several_langs);
+        write_alignment(report, target_lang, &alignment_out)?;
+        status!(
+            to_stderr,
+            "Alignment written to {}",
+            alignment_out.display()
+        );
+    }
+    if let Some(report_out) = &args.report_out {
+        let report_out = language_path(report_out, target_lang, several_langs);
+        let mut output = BufWriter::new(File::create(&report_out)?);
+        write_report_html(report, &language_tag(target_lang), rates, &mut output)?;
+        output.flush()?;
+        status!(to_stderr, "Report written to {}", report_out.display());
+    }
+    if args.sample.is_some() {
+        let review_out = match &args.review_out {
+            Some(review_out) => language_path(review_out, target_lang, several_langs),
+            None => output.with_extension("review.html"),
+        };
+        let sampled: Vec<AlignedUnit> = report
+            .alignment
+            .iter()
+            .filter(|unit| unit.translation.is_some())
+            .cloned()
+            .collect();
+        write_alignment_html(&sampled, &mut BufWriter::new(File::create(&review_out)?))?;
+        status!(
+            to_stderr,
+            "Review of {} sampled segments written to {}",
+            sampled.len(),
+            review_out.display()
+        );
+    }
+    if let Some(failures_out) = &args.failures_out {
+        let failures_out = language_path(failures_out, target_lang, several_langs);
+        write_failures(report, &failures_out)?;
+        status!(
+            to_stderr,
+            "{} failed and {} flagged segments written to {}",
+            report.failures.len(),
+            report.flagged.len(),
+            failures_out.display()
+        );
+    }
+    Ok(())
+}
+
+// Prints how a translation ended: completed, cancelled or stopped by the character budget.
+fn print_outcome(report: &TranslationReport, to_stderr: bool) {
+    if report.was_cancelled() {
+        status!(
+            to_stderr,
+            "Translation cancelled: {} of {} segments translated, {} left in the source \
+             language.",
+            report.translated,
+            report.total,
+            report.total - report.translated
+        );
+    } else if let Some(stop) = &report.budget_stop {
+        let excerpt: String = stop.source.chars().take(60).collect();
+        status!(
+            to_stderr,
+            "Character budget reached after {} billable characters: translation stopped at {} \
+             unit {} (\"{}\"), {} of {} segments left in the source language.",
+            stop.billable_characters,
+            stop.path.display(),
+            stop.position,
+            excerpt,
+            report.over_budget,
+            report.total
+        );
+    } else {
+        if report.failed == 0 {
+            status!(to_stderr, "Translation completed successfully!");
+        } else {
+            status!(
+                to_stderr,
+                "Translation completed with errors: {} of {} segments failed and were left in the \
+                 source language.",
+                report.failed,
+                report.total
+            );
+        }
+        if report.quota_exceeded > 0 {
+            status!(
+                to_stderr,
+                " {} of them were refused because the character quota is exhausted",
+                report.quota_exceeded
+            );
+        }
+        if report.not_sampled > 0 {
+            status!(
+                to_stderr,
+                " {} of {} segments were sampled, the others are left in the source language",
+                report.total - report.not_sampled,
+                report.total
+            );
+        }
+        if report.from_memory > 0 {
+            status!(
+                to_stderr,
+                " {} of {} segments were taken from the translation memory",
+                report.from_memory,
+                report.total
+            );
+        }
+        if report.from_cache > 0 {
+            status!(
+                to_stderr,
+                " {} of {} segments were taken from the translation cache",
+                report.from_cache,
+                report.total
+            );
+        }
+        if !report.flagged.is_empty() {
+            status!(
+                to_stderr,
+                " {} translated segments look wrong, see --report-out or --failures-out",
+                report.flagged.len()
+            );
+        }
+        if !report.names.is_empty() {
+            let shown: Vec<&str> = report.names.iter().take(10).map(String::as_str).collect();
+            status!(
+                to_stderr,
+                " {} names kept untranslated: {}{}",
+                report.names.len(),
+                shown.join(", "),
+                if report.names.len() > shown.len() {
+                    "…"
+                } else {
+                    ""
+                }
+            );
+        }
+        if let Some(back_translation) = &report.back_translation {
+            if let Some(score) = back_translation.score {
+                status!(
+                    to_stderr,
+                    " {} segments translated back with a similarity of {:.2} to their source",
+                    back_translation.units.len(),
+                    score
+                );
+            }
+        }
+        for page in &report.fixed_layout_pages {
+            status!(to_stderr, " Check the fixed-layout page {}", page.display());
+        }
+    }
+}
+
+// The exit status of an incomplete run with --strict, from the most to the least specific
+// reason units were left untranslated.
+fn exit_status(report: &TranslationReport) -> i32 {
+    if report.quota_exceeded > 0 {
+        EXIT_QUOTA_EXCEEDED
+    } else if report.failed > 0 {
+        EXIT_PROVIDER_ERROR
+    } else {
+        EXIT_UNTRANSLATED
+    }
+}
+
+// Exits when the API cannot be queried before the translation.
+fn provider_error(error: EpubTranslatorError) -> ! {
+    eprintln!("Error: the DeepL API could not be queried: {}", error);
+    if let EpubTranslatorError::QuotaExceeded { .. } = error {
+        std::process::exit(EXIT_QUOTA_EXCEEDED);
+    }
+    std::process::exit(EXIT_PROVIDER_ERROR);
+}
+
+// Exits on a target language that is not a single supported one.
+fn language_error(target_lang: &str, unmatched: LanguageMatch, languages: &[Language]) -> ! {
+    match unmatched {
+        LanguageMatch::Variants(variants) => {
+            eprintln!(
+                "Error: target language {} has several variants, pass one of {}",
+                target_lang,
+                variants.join(", ")
+            );
+        }
+        LanguageMatch::Unknown { suggestion } => {
+            eprintln!("Error: Target language code {} not supported", target_lang);
+            if let Some(suggestion) = suggestion {
+                eprintln!("Did you mean {}?", suggestion);
+            }
+            eprintln!(
+                "Supported languages: {}",
+                languages
+                    .iter()
+                    .map(|l| l.language.clone())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+        }
+        LanguageMatch::Found(_) => {}
+    }
+    std::process::exit(1);
+}
+
+// The fields of the output template but the language, the book is only read for the ones taken
+// from its metadata.
+fn output_fields(
+    template: &OutputTemplate,
+    input_file: &Path,
+    stdin_epub: Option<&[u8]>,
+    unzip_limits: &UnzipLimits,
+) -> Result<OutputFields, Box<dyn std::error::Error>> {
+    if stdin_epub.is_some() && (template.uses("stem") || template.uses("dir")) {
+        return Err("{stem} and {dir} need an input file, not stdin".into());
+    }
+    let mut fields = OutputFields {
+        stem: input_file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        dir: match input_file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.display().to_string(),
+            _ => ".".to_string(),
+        },
+        ..Default::default()
+    };
+    if template.uses("title") || template.uses("author") {
+        let input = match stdin_epub {
+            Some(input) => input.to_vec(),
+            None => std::fs::read(input_file)?,
+        };
+        let book = BookArchive::read(input, unzip_limits)?;
+        fields.title = book_metadata(&book, "title")?;
+        fields.author = book_metadata(&book, "creator")?;
+    }
+    Ok(fields)
+}
+
+// The file written for `lang`: `{lang}` in the name of `path` is replaced by its language
+// tag, otherwise the tag is added before the extension when there are several languages,
+// `book.epub` -> `book.es.epub`.
+fn language_path(path: &Path, lang: &str, several_langs: bool) -> PathBuf {
+    let tag = language_tag(lang);
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return path.to_path_buf();
+    };
+    if name.contains("{lang}") {
+        return path.with_file_name(name.replace("{lang}", &tag));
+    }
+    if !several_langs {
+        return path.to_path_buf();
+    }
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            path.with_file_name(format!("{}.{}.{}", stem, tag, extension))
+        }
+        _ => path.with_file_name(format!("{}.{}", name, tag)),
+    }
+}
+
+// Writes the alignment file, its format is chosen by its extension.
+fn write_alignment(
+    report: &TranslationReport,
+    target_lang: &str,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let units = &report.alignment;
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    // Translation memories need the language of both sides
+    let source_lang = match (extension, &report.source_lang) {
+        (Some("tmx"), None) => {
+            return Err(
+                "the language of the book is unknown, pass --source-lang to write a TMX file"
+                    .into(),
+            )
+        }
+        (_, source_lang) => source_lang.as_deref().unwrap_or_default(),
+    };
+    let mut output = BufWriter::new(File::create(path)?);
+    match extension {
+        Some("html" | "htm") => write_alignment_html(units, &mut output)?,
+        Some("tmx") => {
+            write_alignment_tmx(units, source_lang, &language_tag(target_lang), &mut output)?
+        }
+        _ => write_alignment_csv(units, &mut output)?,
+    }
+    output.flush()?;
+    Ok(())
+}
+
+// Writes the failed units as a JSON array.
+fn write_failures(
+    report: &TranslationReport,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The failed units, then the flagged ones, with their translation and anomalies
+    let entries = report
+        .failures
+        .iter()
+        .map(serde_json::to_value)
+        .chain(report.flagged.iter().map(serde_json::to_value))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut output = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut output, &entries)?;
+    writeln!(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+// Runs the `validate` subcommand, exits with an error status when problems are found.
+fn validate(epub_file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let issues = if is_stdio(epub_file) {
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input)?;
+        validate_epub_bytes(input)?
+    } else {
+        validate_epub(epub_file)?
+    };
+    if issues.is_empty() {
+        println!("No problems found");
+        return Ok(());
+    }
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    std::process::exit(1);
+}
+
+// Prints what `--dry-run` would translate: the breakdown of `count`, with the extraction rules
+// and selection of the command line, with its cost, then the first `show_segments` units.
+fn dry_run(
+    book: &BookArchive,
+    extract: &ExtractOptions,
+    selection: &Selection,
+    show_segments: Option<usize>,
+    rates: &[Rate],
+) -> Result<(), EpubTranslatorError> {
+    let chapters = count_book_chapters(book, extract, selection)?;
+    let total = print_chapters(&chapters);
+    println!(
+        "{} billable characters, nothing was sent",
+        total.billable_characters
+    );
+    for estimate in estimate(total.billable_characters, rates) {
+        println!("Estimated cost: {}", estimate);
+    }
+
+    if let Some(limit) = show_segments {
+        println!();
+        for unit in book_units(book, extract, selection)?.iter().take(limit) {
+            println!(
+                "{} #{}: {}",
+                unit.path.display(),
+                unit.position,
+                unit.source
+            );
+        }
+    }
+    Ok(())
+}
+
+// Runs the `count` subcommand.
+fn count(epub_file: &Path, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let options = ExtractOptions::default();
+    let selection = Selection::default();
+    let chapters = if is_stdio(epub_file) {
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input)?;
+        let book = BookArchive::read(input, &UnzipLimits::default())?;
+        count_book_chapters(&book, &options, &selection)?
+    } else {
+        count_epub_chapters(epub_file, &options, &selection)?
+    };
+    if json {
+        let output = serde_json::json!({ "files": chapters, "total": total_count(&chapters) });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    print_chapters(&chapters);
+    Ok(())
+}
+
+// Runs `f` on a thread of its own: the keyring is reached through blocking D-Bus calls on
+// Linux, which start a runtime of their own.
+async fn off_runtime<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .expect("the keyring lookup panicked")
+}
+
+// Runs the `keys` subcommand. The key to store is the first line of stdin, so that it can be
+// piped from a password manager.
+async fn manage_keys(action: &KeysAction) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        KeysAction::Store { name } => {
+            if std::io::stdin().is_terminal() {
+                eprint!("DeepL API key to store as {}: ", name);
+                std::io::stderr().flush()?;
+            }
+            let mut key = String::new();
+            std::io::stdin().read_line(&mut key)?;
+            let key = key.trim();
+            if key.is_empty() {
+                eprintln!("Error: no key read from stdin");
                 std::process::exit(1);
             }
+            let (store, key) = (name.clone(), key.to_string());
+            if let Err(e) = off_runtime(move || store_keyring_key(&store, &key)).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            eprintln!("Key stored, use it with --keyring {}", name);
+        }
+        KeysAction::Delete { name } => {
+            let delete = name.clone();
+            if let Err(e) = off_runtime(move || delete_keyring_key(&delete)).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            eprintln!("Key {} deleted", name);
+        }
+    }
+    Ok(())
+}
+
+// The account of the subcommands, which use a single key: the given one or the first one of the
+// configuration files. With `test`, the mock server is started and must be stopped once done.
+async fn connect(
+    api_key: Option<AuthKey>,
+    test: bool,
+    client: &Client,
+) -> Result<(DeepLConfiguration, Option<MockServer>), Box<dyn std::error::Error>> {
+    if test {
+        #[cfg(feature = "mock-server")]
+        {
+            let server = start_deepl_server(&MockServerOptions::ephemeral()).await?;
+            return Ok((server.config(), Some(server)));
         }
+        #[cfg(not(feature = "mock-server"))]
+        return Err(NO_MOCK_SERVER.into());
     }
+    let api_key = match api_key {
+        Some(api_key) => api_key,
+        None => Config::load(None)?
+            .api_keys
+            .and_then(|api_keys| api_keys.into_iter().next())
+            .ok_or("DeepL API key not provided, pass --api-key or set DEEPL_API_KEY")?,
+    };
+    let configuration = DeepLConfiguration::new_with_determine(api_key, client)
+        .await
+        .unwrap_or_else(|e| provider_error(e));
+    Ok((configuration, None))
+}
 
-    // Test languages code
-    let languages = get_languages(&primary_configuration, args.verbose).await?;
-    if !languages.0.iter().any(|l| l.language == args.target_lang) {
-        eprintln!("Error: Target language code not supported");
+// Runs the `preview` subcommand: the first segments are translated, the rest of the book is
+// left as it is, and the segments are printed unless a preview EPUB is written.
+async fn preview(args: &Args, client: &Client) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(Command::Preview {
+        epub_file,
+        target_lang,
+        source_lang,
+        segments,
+        chapter,
+        output,
+        force,
+        json,
+        api_key,
+        test,
+        ..
+    }) = &args.command
+    else {
+        unreachable!("not a preview command")
+    };
+    let Some(target_lang) = target_lang.clone().or(Config::load(None)?.target_lang) else {
+        eprintln!("Error: no target language, pass --target-lang");
+        std::process::exit(1);
+    };
+    if !epub_file.is_file() {
+        eprintln!("Error: Input file does not exist");
+        std::process::exit(EXIT_INVALID_INPUT);
+    }
+    if let Some(output) = output.as_deref().filter(|output| output.exists() && !force) {
         eprintln!(
-            "Supported languages: {}",
-            languages
-                .0
-                .iter()
-                .map(|l| l.language.clone())
-                .collect::<Vec<String>>()
-                .join(", ")
+            "Error: {} already exists, pass --force to overwrite it",
+            output.display()
         );
         std::process::exit(1);
     }
+    let selection = Selection {
+        chapters: chapter
+            .map(|chapter| chapter..=chapter)
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
 
-    println!("       -----------        ");
+    let (configuration, mock_server) = connect(api_key.clone(), *test, client).await?;
+    let languages = get_languages(&configuration, LanguageType::Target, client)
+        .await
+        .unwrap_or_else(|e| provider_error(e));
+    let target_lang = match match_language(&target_lang, &languages.0) {
+        LanguageMatch::Found(code) => code,
+        unmatched => language_error(&target_lang, unmatched, &languages.0),
+    };
+    let options = TranslateOptions {
+        target_lang,
+        source_lang: source_lang.clone(),
+        configurations: vec![Arc::new(configuration)],
+        client: client.clone(),
+        selection,
+        alignment: true,
+        max_units: segments.or(chapter.is_none().then_some(10)),
+        ..Default::default()
+    };
+    let result = match output {
+        Some(output) => translate_epub_with(epub_file, output, &options).await,
+        None => match std::fs::read(epub_file) {
+            Ok(input) => translate_epub_bytes(input, &options)
+                .await
+                .map(|(_, report)| report),
+            Err(e) => Err(e.into()),
+        },
+    };
+    if let Some(server) = mock_server {
+        server.stop();
+    }
+    let report = result.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(EXIT_INVALID_INPUT);
+    });
 
-    // Count the number of characters to translate
-    let char_count = count_epub_char(&args.input_file)?;
+    let translated: Vec<&AlignedUnit> = report
+        .alignment
+        .iter()
+        .filter(|unit| unit.translation.is_some())
+        .collect();
+    if *json {
+        println!("{}", serde_json::to_string_pretty(&translated)?);
+    } else if output.is_none() {
+        for unit in &translated {
+            println!("{} #{}", unit.path.display(), unit.position);
+            println!("  {}", unit.source);
+            println!("  {}", unit.translation.as_deref().unwrap_or_default());
+            println!();
+        }
+    }
+    eprintln!(
+        "{} of {} segments translated{}",
+        report.translated,
+        report.total,
+        match output {
+            Some(output) => format!(", preview written to {}", output.display()),
+            None => String::new(),
+        }
+    );
+    for failure in &report.failures {
+        eprintln!(
+            "Failed: {} #{}: {}",
+            failure.path.display(),
+            failure.position,
+            failure.error
+        );
+    }
+    Ok(())
+}
+
+// Runs the `export-xliff` subcommand.
+async fn export_xliff(args: &Args, client: &Client) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(Command::ExportXliff {
+        epub_file,
+        xliff_file,
+        target_lang,
+        source_lang,
+        force,
+        api_key,
+        test,
+        ..
+    }) = &args.command
+    else {
+        unreachable!("not an export-xliff command")
+    };
+    let Some(target_lang) = target_lang.clone().or(Config::load(None)?.target_lang) else {
+        eprintln!("Error: no target language, pass --target-lang");
+        std::process::exit(1);
+    };
+    if !epub_file.is_file() {
+        eprintln!("Error: Input file does not exist");
+        std::process::exit(EXIT_INVALID_INPUT);
+    }
+    check_overwrite(xliff_file, *force);
 
-    let usage = get_usage(&primary_configuration, args.verbose).await?;
+    let (configuration, mock_server) = connect(api_key.clone(), *test, client).await?;
+    let languages = get_languages(&configuration, LanguageType::Target, client)
+        .await
+        .unwrap_or_else(|e| provider_error(e));
+    let target_lang = match match_language(&target_lang, &languages.0) {
+        LanguageMatch::Found(code) => code,
+        unmatched => language_error(&target_lang, unmatched, &languages.0),
+    };
+    let options = TranslateOptions {
+        target_lang: target_lang.clone(),
+        source_lang: source_lang.clone(),
+        configurations: vec![Arc::new(configuration)],
+        client: client.clone(),
+        alignment: true,
+        ..Default::default()
+    };
+    let result = match std::fs::read(epub_file) {
+        Ok(input) => translate_epub_bytes(input, &options)
+            .await
+            .map(|(_, report)| report),
+        Err(e) => Err(e.into()),
+    };
+    if let Some(server) = mock_server {
+        server.stop();
+    }
+    let report = result.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(EXIT_INVALID_INPUT);
+    });
+    let Some(source_lang) = &report.source_lang else {
+        eprintln!("Error: the language of the book is unknown, pass --source-lang");
+        std::process::exit(EXIT_INVALID_INPUT);
+    };
 
-    // Show user the usage and the char count
-    println!(
-        "DeepL Usage: Your limit is: {}, you have already use: {}",
-        &usage.character_limit, &usage.character_count
+    let mut output = BufWriter::new(File::create(xliff_file)?);
+    write_xliff(
+        &report.alignment,
+        source_lang,
+        &language_tag(&target_lang),
+        &mut output,
+    )?;
+    output.flush()?;
+    eprintln!(
+        "{} of {} segments translated, XLIFF written to {}",
+        report.translated,
+        report.total,
+        xliff_file.display()
     );
-    println!(" Your character translation capacity is {}", total_capacity);
-    println!(" Number of characters to translate: {}", char_count);
+    Ok(())
+}
 
-    // Ask for user confirmation
-    println!("Do you want to proceed with the translation? (y/n)");
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input)?;
-    if input.trim().to_lowercase() != "y" {
-        println!("Translation cancelled by user.");
-        std::process::exit(0);
+// Runs the `import-xliff` subcommand.
+async fn import_xliff(
+    epub_file: &Path,
+    xliff_file: &Path,
+    output_file: &Path,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !epub_file.is_file() {
+        eprintln!("Error: Input file does not exist");
+        std::process::exit(EXIT_INVALID_INPUT);
+    }
+    check_overwrite(output_file, force);
+    let xliff = Xliff::read(xliff_file).unwrap_or_else(|e| {
+        eprintln!("Error in the XLIFF file {}", e);
+        std::process::exit(EXIT_INVALID_INPUT);
+    });
+    let Some(target_lang) = xliff.target_lang.clone() else {
+        eprintln!("Error: the XLIFF file has no target language");
+        std::process::exit(EXIT_INVALID_INPUT);
+    };
+    let options = TranslateOptions {
+        target_lang,
+        source_lang: Some(xliff.source_lang.clone()).filter(|lang| !lang.is_empty()),
+        xliff: Some(Arc::new(xliff)),
+        ..Default::default()
+    };
+    let report = translate_epub_with(epub_file, output_file, &options)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(EXIT_INVALID_INPUT);
+        });
+    eprintln!(
+        "{} of {} segments imported, EPUB written to {}",
+        report.translated,
+        report.total,
+        output_file.display()
+    );
+    for failure in &report.failures {
+        eprintln!(
+            "Not imported: {} #{}: {}",
+            failure.path.display(),
+            failure.position,
+            failure.error
+        );
     }
+    Ok(())
+}
 
-    let start = Instant::now();
-    match translate_epub(
-        &args.input_file,
-        &args.output_file,
-        args.target_lang.to_string(),
-        args.source_lang,
-        args.parallel,
-        balanced_configurations,
-        args.verbose,
-    )
-    .await
-    {
-        Ok(_) => println!("Translation completed successfully!"),
-        Err(e) => {
-            eprintln!("Error during translation: {}", e);
-            std::process::exit(1);
+// Runs the `review` subcommand.
+#[cfg(feature = "tui")]
+fn review(
+    original_file: &Path,
+    translated_file: &Path,
+    output_file: &Path,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for file in [original_file, translated_file] {
+        if !file.is_file() {
+            eprintln!("Error: {} does not exist", file.display());
+            std::process::exit(EXIT_INVALID_INPUT);
         }
     }
+    if !std::io::stdout().is_terminal() {
+        eprintln!("Error: review needs a terminal");
+        std::process::exit(1);
+    }
+    check_overwrite(output_file, force);
 
-    let total_duration = start.elapsed();
-    profiling_log!(args.verbose, "Total duration: {:?}", total_duration);
-    eprintln!("End");
+    let limits = UnzipLimits::default();
+    let options = ExtractOptions::default();
+    let original = BookArchive::open(original_file, &limits)?;
+    let mut translated = BookArchive::open(translated_file, &limits)?;
+    let review = review_pairs(&original, &translated, &options)?;
+    if review.pairs.is_empty() {
+        eprintln!("Error: the books have no segments in common to review");
+        std::process::exit(EXIT_INVALID_INPUT);
+    }
+    let edits = epub_translator::review::tui::review(review.pairs)?;
+    for path in &review.skipped {
+        eprintln!(
+            "Warning: {} not reviewed, its segments differ from the original",
+            path.display()
+        );
+    }
+    let Some(edits) = edits else {
+        eprintln!("Quit without saving");
+        return Ok(());
+    };
+    apply_edits(&mut translated, &edits, &options)?;
+    std::fs::write(output_file, translated.to_bytes(None)?)?;
+    eprintln!(
+        "{} translations corrected, EPUB written to {}",
+        edits.len(),
+        output_file.display()
+    );
+    Ok(())
+}
 
-    // Shutdown mock server if test mode
-    if let Some(signal) = shutdown_mock_server_signal {
-        println!("Shutting down mock server...");
-        if let Err(e) = signal.send(()) {
-            eprintln!("Error shutting down mock server: {:?}", e);
-            std::process::exit(1);
-        }
+#[cfg(not(feature = "tui"))]
+fn review(_: &Path, _: &Path, _: &Path, _: bool) -> Result<(), Box<dyn std::error::Error>> {
+    eprintln!("Error: epub-translator was built without the tui feature, review is not available");
+    std::process::exit(1);
+}
+
+// Exits when `path` exists and can't be overwritten.
+fn check_overwrite(path: &Path, force: bool) {
+    if path.exists() && !force {
+        eprintln!(
+            "Error: {} already exists, pass --force to overwrite it",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+}
+
+// Runs the `languages` subcommand: a row per code, marked as a source and a target language
+// when it is one.
+async fn languages(
+    api_key: Option<AuthKey>,
+    test: bool,
+    json: bool,
+    client: &Client,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (configuration, mock_server) = connect(api_key, test, client).await?;
+    let source = get_languages(&configuration, LanguageType::Source, client)
+        .await
+        .unwrap_or_else(|e| provider_error(e));
+    let target = get_languages(&configuration, LanguageType::Target, client)
+        .await
+        .unwrap_or_else(|e| provider_error(e));
+    if let Some(server) = mock_server {
+        server.stop();
     }
 
+    if json {
+        let output = json!({ "source": source, "target": target });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    let mut codes: Vec<&Language> = source.0.iter().chain(&target.0).collect();
+    codes.sort_by(|a, b| a.language.cmp(&b.language));
+    codes.dedup_by(|a, b| a.language == b.language);
+    let mark = |languages: &[Language], code: &str| {
+        if languages.iter().any(|language| language.language == code) {
+            "yes"
+        } else {
+            "-"
+        }
+    };
+    println!("{:<8}  {:>6}  {:>6}  Name", "Code", "Source", "Target");
+    for language in codes {
+        println!(
+            "{:<8}  {:>6}  {:>6}  {}",
+            language.language,
+            mark(&source.0, &language.language),
+            mark(&target.0, &language.language),
+            language.name
+        );
+    }
     Ok(())
 }
+
+// The sum of the counts of every file.
+fn total_count(chapters: &[ChapterCount]) -> ChapterCount {
+    ChapterCount {
+        path: PathBuf::new(),
+        chapter: None,
+        characters: chapters.iter().map(|chapter| chapter.characters).sum(),
+        units: chapters.iter().map(|chapter| chapter.units).sum(),
+        billable_characters: chapters.iter().map(|c| c.billable_characters).sum(),
+    }
+}
+
+// Prints the breakdown of `count` as a table, returns the total.
+fn print_chapters(chapters: &[ChapterCount]) -> ChapterCount {
+    let total = total_count(chapters);
+    println!(
+        "{:>7}  {:>10}  {:>6}  {:>10}  File",
+        "Chapter", "Characters", "Units", "Billable"
+    );
+    for chapter in chapters {
+        let number = chapter.chapter.map(|n| n.to_string());
+        println!(
+            "{:>7}  {:>10}  {:>6}  {:>10}  {}",
+            number.as_deref().unwrap_or("-"),
+            chapter.characters,
+            chapter.units,
+            chapter.billable_characters,
+            chapter.path.display()
+        );
+    }
+    println!(
+        "{:>7}  {:>10}  {:>6}  {:>10}  Total",
+        "", total.characters, total.units, total.billable_characters
+    );
+    total
+}
+
+// Translates in memory when the EPUB comes from stdin or goes to stdout.
+async fn translate_stdio(
+    stdin_epub: Option<Vec<u8>>,
+    input_file: &Path,
+    output_file: &Path,
+    options: &TranslateOptions,
+    checks: &OutputChecks,
+) -> Result<(TranslationReport, Vec<String>), Box<dyn std::error::Error>> {
+    let input = match stdin_epub {
+        Some(input) => input,
+        None => std::fs::read(input_file)?,
+    };
+    let (output, report) = translate_epub_bytes(input, options).await?;
+    if !is_stdio(output_file) {
+        std::fs::write(output_file, output)?;
+        return Ok((report, checks.run(output_file)?));
+    }
+
+    // The checks need the EPUB as a file
+    let problems = if checks.is_empty() {
+        Vec::new()
+    } else {
+        let temp_dir = tempfile::tempdir()?;
+        let checked_file = temp_dir.path().join("output.epub");
+        std::fs::write(&checked_file, &output)?;
+        checks.run(&checked_file)?
+    };
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+    Ok((report, problems))
+}
+
+// Translates into several languages, the book is read once and each translation written to
+// its output file.
+async fn translate_languages(
+    stdin_epub: Option<Vec<u8>>,
+    input_file: &Path,
+    outputs: &[PathBuf],
+    target_langs: &[String],
+    options: &TranslateOptions,
+    checks: &OutputChecks,
+) -> Result<Vec<(TranslationReport, Vec<String>)>, Box<dyn std::error::Error>> {
+    let input = match stdin_epub {
+        Some(input) => input,
+        None => std::fs::read(input_file)?,
+    };
+    let translations = translate_epub_bytes_to_languages(input, target_langs, options).await?;
+    let mut results = Vec::new();
+    for ((output, report), output_file) in translations.into_iter().zip(outputs) {
+        std::fs::write(output_file, output)?;
+        results.push((report, checks.run(output_file)?));
+    }
+    Ok(results)
+}
+
+/// Checks run on the translated EPUB once written.
+struct OutputChecks {
+    validate: bool,
+    epubcheck: Option<Epubcheck>,
+}
+
+impl OutputChecks {
+    fn is_empty(&self) -> bool {
+        !self.validate && self.epubcheck.is_none()
+    }
+
+    // Problems found in the EPUB at `path`, one line each.
+    fn run(&self, path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut problems = Vec::new();
+        if self.validate {
+            for issue in validate_epub(path)? {
+                problems.push(format!("Validation: {}", issue));
+            }
+        }
+        if let Some(epubcheck) = &self.epubcheck {
+            let report = epubcheck.check(path)?;
+            eprintln!(
+                "epubcheck: {} errors, {} warnings",
+                report.errors().count(),
+                report.warnings().count()
+            );
+            for message in report.errors() {
+                problems.push(format!("epubcheck: {}", message));
+            }
+        }
+        Ok(problems)
+    }
+}
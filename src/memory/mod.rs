@@ -0,0 +1,342 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use quick_xml::escape::unescape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::config::ConfigError;
+use crate::error::EpubTranslatorError;
+
+// Elements of an entry, of its text in one language and of the text itself: TMX ones, then
+// TBX ones.
+const ENTRIES: &[&str] = &["tu", "termEntry", "conceptEntry"];
+const VARIANTS: &[&str] = &["tuv", "langSet", "langSec"];
+const TEXTS: &[&str] = &["seg", "term"];
+// Inline codes of TMX segments, the markup of the document they come from
+const CODES: &[&str] = &["bpt", "ept", "it", "ph", "ut"];
+
+/// Translations reused instead of being requested again: translation memories (TMX) and
+/// termbases (TBX), e.g. of the previous books of a series. A unit whose text is the source of
+/// an entry, whitespace aside, takes the text of the entry in the target language.
+///
+/// Entries are plain text, units with inline markup or kept terms are still sent.
+#[derive(Debug, Clone, Default)]
+pub struct TranslationMemory {
+    // The texts of each entry, with their language
+    entries: Vec<Vec<(String, String)>>,
+    // Entries by source text, whitespace collapsed
+    sources: HashMap<String, Vec<usize>>,
+    /// Also reuse the entry of the most similar source, when it is at least this similar, see
+    /// `MemoryMatch::similarity`. Exact matches only when `None`.
+    pub fuzzy_threshold: Option<f64>,
+}
+
+/// A translation found in a `TranslationMemory`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryMatch {
+    pub translation: String,
+    /// 1 for an exact match, else the normalized Levenshtein similarity of the sources, from 0
+    /// to 1.
+    pub similarity: f64,
+}
+
+impl TranslationMemory {
+    /// Parses a TMX translation memory or a TBX termbase. The source of a TMX unit is its
+    /// segment in the `srclang` of the header, any of them with `*all*`. Every term of a TBX
+    /// entry is a source.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut memory = Self::default();
+        let mut reader = Reader::from_str(content);
+        let mut source_lang: Option<String> = None;
+        let mut entry: Option<Vec<(String, String)>> = None;
+        let mut lang: Option<String> = None;
+        // Escaped text of the current segment or term
+        let mut text: Option<String> = None;
+        // Depth in an inline code, whose content is left out
+        let mut code = 0;
+        loop {
+            let event = reader
+                .read_event()
+                .map_err(|e| format!("position {}: {}", reader.error_position(), e))?;
+            match event {
+                Event::Start(_) if code > 0 => code += 1,
+                Event::End(_) if code > 0 => code -= 1,
+                Event::Start(element) | Event::Empty(element)
+                    if element.local_name().as_ref() == "header" =>
+                {
+                    source_lang = attribute(&element, "srclang")?.filter(|lang| lang != "*all*");
+                }
+                Event::Start(element) => {
+                    let name = element.local_name();
+                    if ENTRIES.contains(&name.as_ref()) {
+                        entry = Some(Vec::new());
+                    } else if VARIANTS.contains(&name.as_ref()) {
+                        lang = attribute(&element, "lang")?;
+                    } else if TEXTS.contains(&name.as_ref()) {
+                        text = Some(String::new());
+                    } else if CODES.contains(&name.as_ref()) {
+                        code = 1;
+                    }
+                }
+                Event::End(element) => {
+                    let name = element.local_name();
+                    if ENTRIES.contains(&name.as_ref()) {
+                        if let Some(texts) = entry.take() {
+                            memory.push(texts, source_lang.as_deref());
+                        }
+                    } else if TEXTS.contains(&name.as_ref()) {
+                        let escaped = text.take().unwrap_or_default();
+                        let value = unescape(&escaped).map_err(|e| e.to_string())?;
+                        let value = collapse_whitespace(&value);
+                        if let (Some(entry), Some(lang), false) =
+                            (&mut entry, &lang, value.is_empty())
+                        {
+                            entry.push((lang.clone(), value));
+                        }
+                    }
+                }
+                Event::Text(content) if code == 0 => {
+                    if let Some(text) = &mut text {
+                        text.push_str(&content);
+                    }
+                }
+                Event::GeneralRef(reference) if code == 0 => {
+                    if let Some(text) = &mut text {
+                        text.push('&');
+                        text.push_str(&reference);
+                        text.push(';');
+                    }
+                }
+                Event::CData(content) if code == 0 => {
+                    if let Some(text) = &mut text {
+                        text.push_str(&quick_xml::escape::escape(&*content));
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        if memory.is_empty() {
+            return Err("no translation units nor term entries".to_string());
+        }
+        Ok(memory)
+    }
+
+    /// Reads the TMX or TBX file at `path`, see `parse`.
+    pub fn read(path: &Path) -> Result<Self, EpubTranslatorError> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|message| {
+            ConfigError {
+                path: path.to_path_buf(),
+                message,
+            }
+            .into()
+        })
+    }
+
+    /// Adds the entries of `other`. Of two exact matches, the entry of `self` wins.
+    pub fn extend(&mut self, other: Self) {
+        let offset = self.entries.len();
+        self.entries.extend(other.entries);
+        for (source, ids) in other.sources {
+            self.sources
+                .entry(source)
+                .or_default()
+                .extend(ids.into_iter().map(|id| id + offset));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The translation of `source` into `target_lang`, a DeepL code or a language tag. Entries
+    /// match by primary language: `EN-GB` takes the `en-US` text of an entry.
+    pub fn lookup(&self, source: &str, target_lang: &str) -> Option<MemoryMatch> {
+        let source = collapse_whitespace(source);
+        let translation = |id: usize| {
+            self.entries[id]
+                .iter()
+                .find(|(lang, _)| same_language(lang, target_lang))
+                .map(|(_, text)| text.clone())
+        };
+        let exact = self
+            .sources
+            .get(&source)
+            .and_then(|ids| ids.iter().find_map(|&id| translation(id)));
+        if let Some(translation) = exact {
+            return Some(MemoryMatch {
+                translation,
+                similarity: 1.0,
+            });
+        }
+
+        let threshold = self.fuzzy_threshold?;
+        let length = source.chars().count();
+        // The most similar source, the first entry of the most similar ones
+        let mut best: Option<(f64, Reverse<usize>, String)> = None;
+        for (candidate, ids) in &self.sources {
+            // At least the difference of their lengths is edited
+            let candidate_length = candidate.chars().count();
+            let bound = length.min(candidate_length) as f64 / length.max(candidate_length) as f64;
+            if bound < threshold || best.as_ref().is_some_and(|(best, ..)| bound < *best) {
+                continue;
+            }
+            let similarity = strsim::normalized_levenshtein(&source, candidate);
+            if similarity < threshold {
+                continue;
+            }
+            for &id in ids {
+                let key = (similarity, Reverse(id));
+                if best
+                    .as_ref()
+                    .is_some_and(|(similarity, id, _)| key <= (*similarity, *id))
+                {
+                    continue;
+                }
+                if let Some(translation) = translation(id) {
+                    best = Some((key.0, key.1, translation));
+                }
+            }
+        }
+        best.map(|(similarity, _, translation)| MemoryMatch {
+            translation,
+            similarity,
+        })
+    }
+
+    fn push(&mut self, texts: Vec<(String, String)>, source_lang: Option<&str>) {
+        let id = self.entries.len();
+        for (lang, text) in &texts {
+            if source_lang.is_none_or(|source_lang| same_language(lang, source_lang)) {
+                let ids = self.sources.entry(text.clone()).or_default();
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        self.entries.push(texts);
+    }
+}
+
+/// Parses a similarity threshold, a percentage like `85%` or a fraction like `0.85`.
+pub fn parse_similarity(value: &str) -> Result<f64, String> {
+    let error = || {
+        format!(
+            "invalid similarity `{}`, expected a percentage like 85%",
+            value
+        )
+    };
+    let similarity = match value.trim().strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map_err(|_| error())? / 100.0,
+        None => value.trim().parse::<f64>().map_err(|_| error())?,
+    };
+    if !(similarity > 0.0 && similarity <= 1.0) {
+        return Err(error());
+    }
+    Ok(similarity)
+}
+
+// The value of the attribute `name` of `element`, whatever its prefix: `lang` is `xml:lang`.
+fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>, String> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        if attribute.key.local_name().as_ref() == name {
+            let value = unescape(&attribute.value).map_err(|e| e.to_string())?;
+            return Ok(Some(value.trim().to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn same_language(tag: &str, other: &str) -> bool {
+    let primary = |tag: &str| {
+        tag.split(['-', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    };
+    primary(tag) == primary(other)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tmx version="1.4">
+<header creationtool="test" srclang="en-US" datatype="plaintext" segtype="paragraph" adminlang="en" o-tmf="test"/>
+<body>
+<tu><tuv xml:lang="en-US"><seg>The  lighthouse keeper counted the ships.</seg></tuv><tuv xml:lang="es-ES"><seg>El farero contó los barcos.</seg></tuv></tu>
+<tu><tuv xml:lang="en-US"><seg>Fish &amp; <bpt i="1">&lt;b&gt;</bpt>chips<ept i="1">&lt;/b&gt;</ept></seg></tuv><tuv xml:lang="fr"><seg>Poisson et frites</seg></tuv></tu>
+</body>
+</tmx>"#;
+
+    #[test]
+    fn test_lookup() -> Result<(), String> {
+        let mut memory = TranslationMemory::parse(TMX)?;
+        assert_eq!(memory.len(), 2);
+        let exact = memory.lookup("The lighthouse keeper counted\nthe ships.", "ES");
+        assert_eq!(
+            exact,
+            Some(MemoryMatch {
+                translation: "El farero contó los barcos.".to_string(),
+                similarity: 1.0
+            })
+        );
+        // Inline codes are left out, translations only come from the source language
+        assert!(memory.lookup("Fish & chips", "FR").is_some());
+        assert_eq!(memory.lookup("Fish & chips", "ES"), None);
+        assert_eq!(memory.lookup("El farero contó los barcos.", "EN"), None);
+
+        let close = "The lighthouse keeper counted the boats.";
+        assert_eq!(memory.lookup(close, "ES"), None);
+        memory.fuzzy_threshold = Some(parse_similarity("85%")?);
+        let fuzzy = memory.lookup(close, "ES").expect("a fuzzy match");
+        assert_eq!(fuzzy.translation, "El farero contó los barcos.");
+        assert!(fuzzy.similarity >= 0.85 && fuzzy.similarity < 1.0);
+        assert_eq!(memory.lookup("The keeper counted.", "ES"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_tbx() -> Result<(), String> {
+        let tbx = r#"<tbx type="TBX-Basic" xml:lang="en"><text><body>
+            <conceptEntry id="c1">
+              <langSec xml:lang="en"><termSec><term>The Shire</term></termSec></langSec>
+              <langSec xml:lang="es"><termSec><term>La Comarca</term></termSec></langSec>
+            </conceptEntry>
+            <termEntry id="c2">
+              <langSet xml:lang="en"><tig><term>Hobbit</term></tig></langSet>
+              <langSet xml:lang="es"><tig><term>Hobbit</term></tig></langSet>
+            </termEntry>
+        </body></text></tbx>"#;
+        let memory = TranslationMemory::parse(tbx)?;
+        assert_eq!(memory.len(), 2);
+        assert_eq!(
+            memory
+                .lookup("The Shire", "ES")
+                .map(|found| found.translation),
+            Some("La Comarca".to_string())
+        );
+        assert_eq!(
+            memory
+                .lookup("La Comarca", "EN")
+                .map(|found| found.translation),
+            Some("The Shire".to_string())
+        );
+        assert!(TranslationMemory::parse("<tmx><body/></tmx>").is_err());
+        Ok(())
+    }
+}
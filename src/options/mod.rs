@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use tokio_util::sync::CancellationToken;
+
+use crate::deepl::models::{DeepLConfiguration, Formality};
+use crate::epub::UnzipLimits;
+use crate::locale::LocalizeOptions;
+use crate::memory::TranslationMemory;
+use crate::progress::{ProgressSink, SilentProgress};
+use crate::providers::Provider;
+use crate::terms::Glossary;
+use crate::xhtml::{BilingualMode, ExtractOptions, SerializeOptions};
+use crate::xliff::Xliff;
+
+mod selection;
+pub use selection::{parse_chapter_range, parse_pattern, Selection};
+
+mod output;
+pub use output::{parse_output_template, OutputFields, OutputTemplate};
+
+mod sample;
+pub use sample::{parse_sample_fraction, Sample};
+
+/// Everything a translation needs besides its input and output.
+#[derive(Clone)]
+pub struct TranslateOptions {
+    /// DeepL code of the target language, e.g. `ES` or `EN-GB`.
+    pub target_lang: String,
+    pub source_lang: Option<String>,
+    /// Maximum number of requests sent to the API at the same time.
+    pub concurrent_requests: usize,
+    /// Times a failed unit is sent again before its original text is kept.
+    pub max_retries: usize,
+    /// DeepL accounts the requests are balanced across, at least one is needed.
+    pub configurations: Vec<Arc<DeepLConfiguration>>,
+    /// Where the translations come from, DeepL by default. The local providers need no
+    /// `configurations`.
+    pub provider: Provider,
+    /// Register of the DeepL translations, the default one of the target language otherwise.
+    pub formality: Option<Formality>,
+    /// HTTP client of the requests to DeepL, see `ClientOptions`. Clones share their
+    /// connections.
+    pub client: Client,
+    pub extract: ExtractOptions,
+    pub serialize: SerializeOptions,
+    /// Receives the progress events, silent by default.
+    pub progress: Arc<dyn ProgressSink>,
+    /// Stops the translation, what is already translated is still written.
+    pub cancel: CancellationToken,
+    /// Size limits of the extracted EPUB.
+    pub unzip_limits: UnzipLimits,
+    /// Translate EPUBs in memory instead of extracting them into a temporary directory.
+    pub in_memory: bool,
+    /// Extract EPUBs into this directory, which must be empty, instead of a temporary one. It
+    /// is kept after the run with the translated documents and the journal.
+    pub workdir: Option<PathBuf>,
+    /// Directory the temporary workdir is created in, the temporary directory of the system by
+    /// default.
+    pub temp_dir: Option<PathBuf>,
+    /// Translate fixed-layout books, whose pages may not fit the translated text. They are
+    /// refused with a `FixedLayoutError` otherwise.
+    pub fixed_layout: bool,
+    /// Translate books already in the target language, by the language detected in their text
+    /// or else the declared one. They are refused with a `SameLanguageError` otherwise.
+    pub same_language: bool,
+    /// The content documents translated, all of them by default.
+    pub selection: Selection,
+    /// Keep the original text next to its translation. The original is declared as a second
+    /// language of the book.
+    pub bilingual: Option<BilingualMode>,
+    /// Fill `TranslationReport::alignment` with every unit and its translation. EPUBs only.
+    pub alignment: bool,
+    /// Billable characters the run may send, retries included. No unit is sent past it, see
+    /// `TranslationReport::budget_stop`.
+    pub max_chars: Option<usize>,
+    /// Units the run may translate, the first ones in reading order. The others are left
+    /// untranslated like past `max_chars`, e.g. to preview a translation.
+    pub max_units: Option<usize>,
+    /// Only translate a random subset of the units, the others are left untranslated and
+    /// counted in `TranslationReport::not_sampled`.
+    pub sample: Option<Sample>,
+    /// Translate a sample of the translated units back to the source language and compare
+    /// them to their source, see `TranslationReport::back_translation`. The language of the
+    /// book is the source language when `source_lang` is not given. EPUBs only.
+    pub back_translation: Option<Sample>,
+    /// Keep the names of the book untranslated like `extract.keep_terms`, found by
+    /// `terms::detect_names` in its units, see `TranslationReport::names`. Not for German.
+    pub protect_names: bool,
+    /// Formats rewritten in the translations for the target language, e.g. the separators of
+    /// numbers, see `locale::Localizer`. The language of the book is the source language when
+    /// `source_lang` is not given. Nothing by default.
+    pub localize: LocalizeOptions,
+    /// Replacements applied to every translation before it is written back.
+    pub glossary: Glossary,
+    /// Translations applied before any request, whatever the budget and the sample. Units
+    /// found there are never sent.
+    pub memory: Option<Arc<TranslationMemory>>,
+    /// File of the translations received from DeepL, reused by the next runs instead of being
+    /// billed again, see `cache::TranslationCache`. Created when missing. The local providers
+    /// don't use it.
+    pub cache: Option<PathBuf>,
+    /// Targets of a post-edited XLIFF file, applied instead of translating: nothing is sent,
+    /// and units without a target fail. EPUBs only.
+    pub xliff: Option<Arc<Xliff>>,
+    /// Time written into translated EPUBs instead of the current one: the date of their
+    /// provenance and of their rewritten zip entries. With it, two runs over the same book with
+    /// the same options and translations write the same bytes.
+    pub fixed_time: Option<DateTime<Utc>>,
+}
+
+impl Default for TranslateOptions {
+    fn default() -> Self {
+        Self {
+            target_lang: String::new(),
+            source_lang: None,
+            concurrent_requests: 400,
+            max_retries: 4,
+            configurations: Vec::new(),
+            provider: Provider::default(),
+            formality: None,
+            client: Client::new(),
+            extract: ExtractOptions::default(),
+            serialize: SerializeOptions::default(),
+            progress: Arc::new(SilentProgress),
+            cancel: CancellationToken::new(),
+            unzip_limits: UnzipLimits::default(),
+            in_memory: false,
+            workdir: None,
+            temp_dir: None,
+            fixed_layout: false,
+            same_language: false,
+            selection: Selection::default(),
+            bilingual: None,
+            alignment: false,
+            max_chars: None,
+            max_units: None,
+            sample: None,
+            back_translation: None,
+            protect_names: false,
+            localize: LocalizeOptions::default(),
+            glossary: Glossary::default(),
+            memory: None,
+            cache: None,
+            xliff: None,
+            fixed_time: None,
+        }
+    }
+}
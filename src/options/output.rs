@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+/// Fields of an output template, see `OutputFields`.
+const FIELDS: &[&str] = &["stem", "dir", "lang", "title", "author"];
+
+fn field_pattern() -> &'static Regex {
+    static FIELD: OnceLock<Regex> = OnceLock::new();
+    FIELD.get_or_init(|| Regex::new(r"\{([^{}]*)\}").unwrap())
+}
+
+/// The path of a translation, given as a template whose `{field}`s are replaced, e.g.
+/// `{stem}.{lang}.epub`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTemplate(String);
+
+/// The values of the fields of an output template.
+#[derive(Debug, Clone, Default)]
+pub struct OutputFields {
+    /// Name of the input file without its extension.
+    pub stem: String,
+    /// Directory of the input file, `.` when it is in the current one.
+    pub dir: String,
+    /// Language tag of the target language, e.g. `pt-BR`.
+    pub lang: String,
+    /// First `dc:title` of the book, the stem when it has none.
+    pub title: Option<String>,
+    /// First `dc:creator` of the book, `Unknown` when it has none.
+    pub author: Option<String>,
+}
+
+/// Parses an output template, the fields are checked.
+pub fn parse_output_template(value: &str) -> Result<OutputTemplate, String> {
+    for captures in field_pattern().captures_iter(value) {
+        let field = &captures[1];
+        if !FIELDS.contains(&field) {
+            return Err(format!(
+                "unknown field `{{{}}}` in output template, expected one of {}",
+                field,
+                FIELDS.join(", ")
+            ));
+        }
+    }
+    if field_pattern().replace_all(value, "").contains(['{', '}']) {
+        return Err(format!("unbalanced braces in output template `{}`", value));
+    }
+    Ok(OutputTemplate(value.to_string()))
+}
+
+impl OutputTemplate {
+    /// Whether `{field}` appears in the template.
+    pub fn uses(&self, field: &str) -> bool {
+        self.0.contains(&format!("{{{}}}", field))
+    }
+
+    /// The path of a translation. Values taken from the book are made safe for a file name:
+    /// path separators and characters that some file systems refuse are replaced by `_`.
+    pub fn render(&self, fields: &OutputFields) -> PathBuf {
+        let path = field_pattern().replace_all(&self.0, |captures: &Captures| match &captures[1] {
+            "stem" => file_name_safe(&fields.stem),
+            "dir" => fields.dir.clone(),
+            "lang" => fields.lang.clone(),
+            "title" => file_name_safe(fields.title.as_deref().unwrap_or(&fields.stem)),
+            _ => file_name_safe(fields.author.as_deref().unwrap_or("Unknown")),
+        });
+        PathBuf::from(path.into_owned())
+    }
+}
+
+fn file_name_safe(value: &str) -> String {
+    let value: String = value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // No hidden files, nor `..`
+    match value.strip_prefix('.') {
+        Some(rest) => format!("_{}", rest),
+        None => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_template() -> Result<(), String> {
+        let fields = OutputFields {
+            stem: "moby-dick".to_string(),
+            dir: "books".to_string(),
+            lang: "es".to_string(),
+            title: Some("Moby Dick: or, The Whale".to_string()),
+            author: None,
+        };
+        let template = parse_output_template("{dir}/{stem}.{lang}.epub")?;
+        assert!(template.uses("lang") && !template.uses("title"));
+        assert_eq!(
+            template.render(&fields),
+            PathBuf::from("books/moby-dick.es.epub")
+        );
+        assert_eq!(
+            parse_output_template("{author} - {title} ({lang}).epub")?.render(&fields),
+            PathBuf::from("Unknown - Moby Dick_ or, The Whale (es).epub")
+        );
+
+        assert!(parse_output_template("{name}.epub")
+            .unwrap_err()
+            .starts_with("unknown field `{name}`"));
+        assert!(parse_output_template("{stem.epub").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// A random subset of the translation units, spread across the book: the units are split into
+/// as many equal stretches as there are units to pick, and one unit is picked in each. The same
+/// seed picks the same units of the same book, to compare translations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Share of the units picked, between 0 and 1.
+    pub fraction: f64,
+    pub seed: u64,
+}
+
+impl Sample {
+    /// Whether each of `total` units, in reading order, is picked. At least one unit is.
+    pub fn picks(&self, total: usize) -> Vec<bool> {
+        let mut picks = vec![false; total];
+        if total == 0 {
+            return picks;
+        }
+        let count = ((total as f64 * self.fraction).ceil() as usize).clamp(1, total);
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        for stretch in 0..count {
+            let start = stretch * total / count;
+            let end = (stretch + 1) * total / count;
+            picks[rng.gen_range(start..end)] = true;
+        }
+        picks
+    }
+}
+
+/// Parses the share of a sample, a percentage like `5%` or a fraction like `0.05`.
+pub fn parse_sample_fraction(value: &str) -> Result<f64, String> {
+    let error = || format!("invalid sample `{}`, expected a percentage like 5%", value);
+    let fraction = match value.trim().strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map_err(|_| error())? / 100.0,
+        None => value.trim().parse::<f64>().map_err(|_| error())?,
+    };
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err(error());
+    }
+    Ok(fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() -> Result<(), String> {
+        assert_eq!(parse_sample_fraction("5%")?, 0.05);
+        assert_eq!(parse_sample_fraction("0.5")?, 0.5);
+        assert!(parse_sample_fraction("0%").is_err());
+        assert!(parse_sample_fraction("150%").is_err());
+
+        let sample = Sample {
+            fraction: parse_sample_fraction("10%")?,
+            seed: 7,
+        };
+        let picks = sample.picks(95);
+        assert_eq!(picks.iter().filter(|picked| **picked).count(), 10);
+        // One unit in each tenth of the book
+        for stretch in 0..10 {
+            let stretch = &picks[stretch * 95 / 10..(stretch + 1) * 95 / 10];
+            assert_eq!(stretch.iter().filter(|picked| **picked).count(), 1);
+        }
+        assert_eq!(sample.picks(95), picks);
+        assert_ne!(Sample { seed: 8, ..sample }.picks(95), picks);
+        assert_eq!(sample.picks(3).iter().filter(|picked| **picked).count(), 1);
+        assert_eq!(
+            Sample {
+                fraction: 1.0,
+                ..sample
+            }
+            .picks(3),
+            vec![true; 3]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use glob::Pattern;
+
+/// The content documents of a book that are translated, the others are left untouched.
+/// Everything is selected by default.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Chapters by position in the spine, from 1. Empty selects every document.
+    pub chapters: Vec<RangeInclusive<usize>>,
+    /// When not empty, only documents matching one of the patterns are selected.
+    pub include: Vec<Pattern>,
+    /// Documents matching one of the patterns are not selected.
+    pub exclude: Vec<Pattern>,
+}
+
+impl Selection {
+    /// Whether the document at `path`, relative to the root of the book, is translated.
+    /// `chapter` is its position in the spine, documents outside of it are left out as soon
+    /// as chapters are given.
+    pub fn selects(&self, path: &Path, chapter: Option<usize>) -> bool {
+        let in_chapters = self.chapters.is_empty()
+            || chapter
+                .is_some_and(|chapter| self.chapters.iter().any(|range| range.contains(&chapter)));
+        let included = self.include.is_empty() || self.include.iter().any(|p| p.matches_path(path));
+        in_chapters && included && !self.exclude.iter().any(|p| p.matches_path(path))
+    }
+}
+
+/// Parses a chapter or a range of chapters, e.g. `12` or `1-5`.
+pub fn parse_chapter_range(value: &str) -> Result<RangeInclusive<usize>, String> {
+    let error = || format!("invalid chapter range `{}`", value);
+    let number = |number: &str| match number.trim().parse::<usize>() {
+        Ok(0) | Err(_) => Err(error()),
+        Ok(number) => Ok(number),
+    };
+    let range = match value.split_once('-') {
+        Some((start, end)) => number(start)?..=number(end)?,
+        None => number(value)?..=number(value)?,
+    };
+    if range.is_empty() {
+        return Err(error());
+    }
+    Ok(range)
+}
+
+/// Parses a glob pattern matched against paths relative to the root of the book.
+pub fn parse_pattern(value: &str) -> Result<Pattern, String> {
+    Pattern::new(value).map_err(|error| format!("invalid pattern `{}`: {}", value, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection() -> Result<(), String> {
+        assert_eq!(parse_chapter_range("1-5")?, 1..=5);
+        assert_eq!(parse_chapter_range("12")?, 12..=12);
+        assert!(parse_chapter_range("0").is_err());
+        assert!(parse_chapter_range("5-1").is_err());
+
+        let chapter = Path::new("OEBPS/text/chapter 1.htm");
+        let nav = Path::new("OEBPS/nav.xhtml");
+        assert!(Selection::default().selects(nav, None));
+
+        let selection = Selection {
+            chapters: vec![parse_chapter_range("1-5")?, parse_chapter_range("12")?],
+            ..Default::default()
+        };
+        assert!(selection.selects(chapter, Some(1)));
+        assert!(selection.selects(chapter, Some(12)));
+        assert!(!selection.selects(chapter, Some(6)));
+        assert!(!selection.selects(nav, None));
+
+        let selection = Selection {
+            include: vec![parse_pattern("OEBPS/text/*")?],
+            exclude: vec![parse_pattern("**/notes")?],
+            ..Default::default()
+        };
+        assert!(selection.selects(chapter, Some(1)));
+        assert!(!selection.selects(Path::new("OEBPS/text/notes"), Some(2)));
+        assert!(!selection.selects(nav, None));
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The usage price of a provider plan. Subscriptions and free allowances are left out, the
+/// price is only an estimate.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    /// Provider and plan, e.g. `DeepL API Pro`.
+    pub plan: String,
+    /// Price of a million billable characters.
+    pub per_million: f64,
+    /// Currency of the price, e.g. `EUR`.
+    pub currency: String,
+}
+
+/// Rates used when none are configured: the list prices of DeepL API Pro usage.
+pub fn default_rates() -> Vec<Rate> {
+    let rate = |per_million, currency: &str| Rate {
+        plan: "DeepL API Pro".to_string(),
+        per_million,
+        currency: currency.to_string(),
+    };
+    vec![rate(20.0, "EUR"), rate(25.0, "USD")]
+}
+
+/// The approximate price of translating some characters with a plan.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Estimate {
+    pub plan: String,
+    pub price: f64,
+    pub currency: String,
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "~{:.2} {} with {}", self.price, self.currency, self.plan)
+    }
+}
+
+/// The price of sending `billable_characters` with each of `rates`.
+pub fn estimate(billable_characters: usize, rates: &[Rate]) -> Vec<Estimate> {
+    rates
+        .iter()
+        .map(|rate| Estimate {
+            plan: rate.plan.clone(),
+            price: billable_characters as f64 / 1_000_000.0 * rate.per_million,
+            currency: rate.currency.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let estimates = estimate(250_000, &default_rates());
+        assert_eq!(estimates[0].price, 5.0);
+        assert_eq!(estimates[0].to_string(), "~5.00 EUR with DeepL API Pro");
+        assert_eq!(estimates[1].to_string(), "~6.25 USD with DeepL API Pro");
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::error::EpubTranslatorError;
+use crate::report::TranslationReport;
+
+/// Events emitted by the translation pipeline while it runs.
+///
+/// `id` is the index of the translation unit, stable for the whole run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// Before `Started`, for every translation unit in turn: the file it comes from, relative
+    /// to the root of the book, and its billable characters. The path is empty when a single
+    /// document is translated.
+    NodePlanned {
+        id: usize,
+        path: PathBuf,
+        characters: usize,
+    },
+    /// All documents are parsed, `total` translation units will be dispatched.
+    Started { total: usize },
+    /// A translation unit has been sent to the translator.
+    NodeQueued { id: usize },
+    /// A failed attempt is sent again, `attempt` counts from 1.
+    NodeRetried {
+        id: usize,
+        attempt: usize,
+        error: String,
+    },
+    /// A translation unit has been translated and written back into its document. Both texts
+    /// are plain text, without the placeholders of the inline markup.
+    NodeTranslated {
+        id: usize,
+        source: String,
+        translation: String,
+    },
+    /// A translation unit exhausted its retries and keeps its original text.
+    NodeFailed { id: usize, error: String },
+    /// The run was cancelled, `remaining` units were left untranslated.
+    Cancelled { remaining: usize },
+    /// A document has been written back to disk.
+    FileSerialized { path: PathBuf },
+    /// The translation pipeline is done.
+    Finished,
+    /// Something the user should know about that does not stop the run.
+    Warning { message: String },
+}
+
+/// Items of `translate_epub_stream`: the progress events of the run, then its outcome.
+// The report is sent once per run, not worth a box
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum TranslationEvent {
+    Progress(ProgressEvent),
+    /// The last item, once the translated EPUB is written or the run has failed.
+    Finished(Result<TranslationReport, EpubTranslatorError>),
+}
+
+/// Receives the progress events of a translation.
+///
+/// The library never prints progress on its own, frontends implement this trait
+/// (or use an `UnboundedSender<ProgressEvent>`) to render it.
+pub trait ProgressSink: Send + Sync {
+    fn on_event(&self, event: ProgressEvent);
+}
+
+/// Progress sink that drops every event, used when nobody is listening.
+pub struct SilentProgress;
+
+impl ProgressSink for SilentProgress {
+    fn on_event(&self, _event: ProgressEvent) {}
+}
+
+/// Forwards every event through a channel, errors are ignored since the receiver
+/// is allowed to stop listening at any time.
+impl ProgressSink for UnboundedSender<ProgressEvent> {
+    fn on_event(&self, event: ProgressEvent) {
+        let _ = self.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_channel_sink_forwards_events() {
+        let (tx, mut rx) = mpsc::unbounded_channel::<ProgressEvent>();
+
+        tx.on_event(ProgressEvent::Started { total: 2 });
+        tx.on_event(ProgressEvent::NodeQueued { id: 1 });
+
+        assert_eq!(
+            rx.try_recv().ok(),
+            Some(ProgressEvent::Started { total: 2 })
+        );
+        assert_eq!(
+            rx.try_recv().ok(),
+            Some(ProgressEvent::NodeQueued { id: 1 })
+        );
+    }
+}
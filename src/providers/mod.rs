@@ -0,0 +1,160 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Where the translations come from, see `TranslateOptions::provider`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// The DeepL API, through `TranslateOptions::configurations`.
+    #[default]
+    DeepL,
+    /// Every unit is "translated" into itself, see `IdentityProvider`.
+    Identity,
+    /// Every unit is pseudo-translated, see `PseudoProvider`.
+    Pseudo,
+}
+
+impl Provider {
+    /// The provider translating without any request, `None` for DeepL.
+    pub fn local(self) -> Option<&'static dyn LocalProvider> {
+        match self {
+            Self::DeepL => None,
+            Self::Identity => Some(&IdentityProvider),
+            Self::Pseudo => Some(&PseudoProvider),
+        }
+    }
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(provider: &str) -> Result<Self, Self::Err> {
+        match provider {
+            "deepl" => Ok(Self::DeepL),
+            "identity" => Ok(Self::Identity),
+            "pseudo" => Ok(Self::Pseudo),
+            _ => Err(format!(
+                "unknown provider `{}`, expected `deepl`, `identity` or `pseudo`",
+                provider
+            )),
+        }
+    }
+}
+
+/// A provider translating on the spot, without any API call nor cost. Meant to check how a
+/// book goes through a translation: its markup, its layout and its round trip.
+pub trait LocalProvider: Send + Sync {
+    /// Translates a unit. `text` is XML: the inline markup of the unit is kept as placeholder
+    /// elements, and the special characters as entities, both must be left as they are.
+    fn translate(&self, text: &str, target_lang: &str) -> String;
+}
+
+/// Returns every unit as it is. A book translated this way must come out the same, but for
+/// its serialization.
+pub struct IdentityProvider;
+
+impl LocalProvider for IdentityProvider {
+    fn translate(&self, text: &str, _target_lang: &str) -> String {
+        text.to_string()
+    }
+}
+
+/// Turns every unit into an obviously translated text of about 30% more characters, the usual
+/// growth from English: letters are accented, e.g. `Hello` becomes `[Ĥéļļö ~~]`. Untranslated
+/// text, clipped text and overflowing pages stand out.
+pub struct PseudoProvider;
+
+impl LocalProvider for PseudoProvider {
+    fn translate(&self, text: &str, _target_lang: &str) -> String {
+        let start = text.len() - text.trim_start().len();
+        let end = text.trim_end().len();
+        if start >= end {
+            return text.to_string();
+        }
+
+        let mut core = String::new();
+        let mut characters = 0;
+        let mut chars = text[start..end].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                // Placeholders and entities are copied whole
+                '<' | '&' => {
+                    let closing = if c == '<' { '>' } else { ';' };
+                    core.push(c);
+                    for c in chars.by_ref() {
+                        core.push(c);
+                        if c == closing {
+                            break;
+                        }
+                    }
+                    characters += usize::from(c == '&');
+                }
+                c => {
+                    core.push(accented(c));
+                    characters += 1;
+                }
+            }
+        }
+        let padding = "~".repeat((characters * 3).div_ceil(10));
+        format!("{}[{} {}]{}", &text[..start], core, padding, &text[end..])
+    }
+}
+
+// A look-alike of `c` with a diacritic, `c` itself when there is none.
+fn accented(c: char) -> char {
+    match c {
+        'a' => 'á',
+        'c' => 'ç',
+        'e' => 'é',
+        'g' => 'ĝ',
+        'h' => 'ĥ',
+        'i' => 'î',
+        'l' => 'ļ',
+        'n' => 'ñ',
+        'o' => 'ö',
+        's' => 'š',
+        'u' => 'ü',
+        'y' => 'ý',
+        'z' => 'ž',
+        'A' => 'Å',
+        'C' => 'Ç',
+        'E' => 'É',
+        'G' => 'Ĝ',
+        'H' => 'Ĥ',
+        'I' => 'Î',
+        'L' => 'Ļ',
+        'N' => 'Ñ',
+        'O' => 'Ö',
+        'S' => 'Š',
+        'U' => 'Ü',
+        'Y' => 'Ý',
+        'Z' => 'Ž',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudo_provider() {
+        let pseudo = Provider::Pseudo.local().unwrap();
+        assert_eq!(pseudo.translate("Hello", "ES"), "[Ĥéļļö ~~]");
+        assert_eq!(
+            pseudo.translate(" Tom <g1>&amp; Jerry</g1> ", "ES"),
+            " [Töm <g1>&amp; Jérrý</g1> ~~~~] "
+        );
+        assert_eq!(pseudo.translate("  ", "ES"), "  ");
+        assert_eq!(
+            pseudo.translate("Hello", "ES"),
+            pseudo.translate("Hello", "FR")
+        );
+
+        let identity = Provider::Identity.local().unwrap();
+        assert_eq!(identity.translate("<g1>Hello</g1>", "ES"), "<g1>Hello</g1>");
+        assert!(Provider::DeepL.local().is_none());
+        assert_eq!("pseudo".parse(), Ok(Provider::Pseudo));
+    }
+}
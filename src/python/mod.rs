@@ -0,0 +1,188 @@
+//! Python module of the library, built with maturin, see `pyproject.toml`.
+//!
+//! ```python
+//! import epub_translator
+//!
+//! key = epub_translator.DeepLConfiguration("my-key:fx")
+//! report = epub_translator.translate_epub("book.epub", "book.es.epub", "ES", [key])
+//! print(report.translated, "units translated")
+//! ```
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+
+use crate::deepl::models::DeepLConfiguration;
+use crate::options::TranslateOptions;
+use crate::providers::Provider;
+use crate::report::TranslationReport;
+
+create_exception!(
+    epub_translator,
+    EpubTranslatorError,
+    PyException,
+    "Raised when a book cannot be translated, see the Rust `EpubTranslatorError`."
+);
+
+impl From<crate::error::EpubTranslatorError> for PyErr {
+    fn from(error: crate::error::EpubTranslatorError) -> Self {
+        EpubTranslatorError::new_err(error.to_string())
+    }
+}
+
+/// A DeepL account the requests are sent to.
+#[pyclass(name = "DeepLConfiguration", frozen)]
+#[derive(Clone)]
+struct PyDeepLConfiguration(DeepLConfiguration);
+
+#[pymethods]
+impl PyDeepLConfiguration {
+    /// `pro` selects the API of the paid plans, `api_url` overrides both.
+    #[new]
+    #[pyo3(signature = (auth_key, pro = false, api_url = None))]
+    fn new(auth_key: &str, pro: bool, api_url: Option<String>) -> Self {
+        let mut configuration = DeepLConfiguration::new(auth_key, pro);
+        if let Some(api_url) = api_url {
+            configuration.api_url = api_url;
+        }
+        Self(configuration)
+    }
+
+    #[getter]
+    fn api_url(&self) -> &str {
+        &self.0.api_url
+    }
+
+    // The key is never shown
+    fn __repr__(&self) -> String {
+        format!("DeepLConfiguration(api_url={:?})", self.0.api_url)
+    }
+}
+
+/// Where the translations come from, see the Rust `Provider`.
+#[pyclass(name = "Provider", eq, eq_int, frozen)]
+#[derive(Clone, Copy, PartialEq)]
+enum PyProvider {
+    DeepL,
+    Identity,
+    Pseudo,
+}
+
+impl From<PyProvider> for Provider {
+    fn from(provider: PyProvider) -> Self {
+        match provider {
+            PyProvider::DeepL => Self::DeepL,
+            PyProvider::Identity => Self::Identity,
+            PyProvider::Pseudo => Self::Pseudo,
+        }
+    }
+}
+
+/// The counts of a run, see the Rust `TranslationReport`.
+#[pyclass(name = "TranslationReport", get_all, frozen)]
+struct PyTranslationReport {
+    total: usize,
+    translated: usize,
+    failed: usize,
+    quota_exceeded: usize,
+    cancelled: usize,
+    over_budget: usize,
+    not_sampled: usize,
+    /// True when every translation unit has been translated.
+    complete: bool,
+}
+
+#[pymethods]
+impl PyTranslationReport {
+    fn __repr__(&self) -> String {
+        format!(
+            "TranslationReport(total={}, translated={}, failed={})",
+            self.total, self.translated, self.failed
+        )
+    }
+}
+
+impl From<TranslationReport> for PyTranslationReport {
+    fn from(report: TranslationReport) -> Self {
+        Self {
+            total: report.total,
+            translated: report.translated,
+            failed: report.failed,
+            quota_exceeded: report.quota_exceeded,
+            cancelled: report.cancelled,
+            over_budget: report.over_budget,
+            not_sampled: report.not_sampled,
+            complete: report.is_complete(),
+        }
+    }
+}
+
+/// Translates an EPUB file into another one. The GIL is released while the book is translated.
+#[pyfunction]
+#[pyo3(signature = (
+    input_file,
+    output_file,
+    target_lang,
+    configurations = Vec::new(),
+    source_lang = None,
+    provider = PyProvider::DeepL,
+    concurrency = None,
+    max_retries = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn translate_epub(
+    py: Python<'_>,
+    input_file: PathBuf,
+    output_file: PathBuf,
+    target_lang: String,
+    configurations: Vec<PyDeepLConfiguration>,
+    source_lang: Option<String>,
+    provider: PyProvider,
+    concurrency: Option<usize>,
+    max_retries: Option<usize>,
+) -> PyResult<PyTranslationReport> {
+    let defaults = TranslateOptions::default();
+    let options = TranslateOptions {
+        target_lang,
+        source_lang,
+        configurations: configurations
+            .into_iter()
+            .map(|configuration| Arc::new(configuration.0))
+            .collect(),
+        provider: provider.into(),
+        concurrent_requests: concurrency.unwrap_or(defaults.concurrent_requests),
+        max_retries: max_retries.unwrap_or(defaults.max_retries),
+        ..defaults
+    };
+    let report = py.allow_threads(|| {
+        tokio::runtime::Runtime::new()?.block_on(crate::translate_epub_with(
+            &input_file,
+            &output_file,
+            &options,
+        ))
+    })?;
+    Ok(report.into())
+}
+
+/// Counts the characters of an EPUB file that would be sent for translation.
+#[pyfunction]
+fn count_epub_char(epub_path: PathBuf) -> PyResult<usize> {
+    Ok(crate::count_epub_char(&epub_path)?)
+}
+
+#[pymodule]
+fn epub_translator(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyDeepLConfiguration>()?;
+    m.add_class::<PyProvider>()?;
+    m.add_class::<PyTranslationReport>()?;
+    m.add_function(wrap_pyfunction!(translate_epub, m)?)?;
+    m.add_function(wrap_pyfunction!(count_epub_char, m)?)?;
+    m.add(
+        "EpubTranslatorError",
+        m.py().get_type::<EpubTranslatorError>(),
+    )?;
+    Ok(())
+}
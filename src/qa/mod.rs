@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::{Serialize, Serializer};
+
+use crate::segment::Segment;
+
+// Sources shorter than this are left out of the length check, a word may double
+const MIN_LENGTH_CHECKED: usize = 20;
+const LENGTH_RATIOS: (f64, f64) = (0.5, 2.0);
+// Shorter sentences repeat for good, e.g. `No, no.`
+const MIN_REPEATED_WORDS: usize = 3;
+
+/// Something suspicious in the translation of a unit, see `check`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    /// The placeholders of the inline elements came out as text, or an inline element lost
+    /// its text.
+    BrokenTags,
+    /// Numbers of the source missing from the translation.
+    MissingNumbers(Vec<String>),
+    /// The translation is much shorter or longer than the source, by this ratio of characters.
+    Length(f64),
+    /// The translation is the source, a sentence of several words.
+    SameAsSource,
+    /// A sentence of the translation comes again, when no sentence of the source does.
+    RepeatedSentence(String),
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anomaly::BrokenTags => write!(f, "broken inline markup"),
+            Anomaly::MissingNumbers(numbers) => write!(f, "missing {}", numbers.join(", ")),
+            Anomaly::Length(ratio) => write!(f, "length ×{:.1}", ratio),
+            Anomaly::SameAsSource => write!(f, "same as the source"),
+            Anomaly::RepeatedSentence(sentence) => write!(f, "repeated \"{}\"", sentence),
+        }
+    }
+}
+
+// Written as its message in JSON reports
+impl Serialize for Anomaly {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Checks the translation of a unit against its source, as plain text and in the format of
+/// `Segment::text`.
+pub fn check(
+    source: &str,
+    translation: &str,
+    source_markup: &str,
+    translation_markup: &str,
+) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    if broken_tags(translation, source_markup, translation_markup) {
+        anomalies.push(Anomaly::BrokenTags);
+    }
+
+    // Digits only, the separators of numbers change with the language
+    let mut seen: HashSet<&str> = numbers(translation).collect();
+    let missing: Vec<String> = numbers(source)
+        .filter(|number| seen.insert(number))
+        .map(str::to_string)
+        .collect();
+    if !missing.is_empty() {
+        anomalies.push(Anomaly::MissingNumbers(missing));
+    }
+
+    let source_length = source.trim().chars().count();
+    if source_length >= MIN_LENGTH_CHECKED {
+        let ratio = translation.trim().chars().count() as f64 / source_length as f64;
+        if ratio < LENGTH_RATIOS.0 || ratio > LENGTH_RATIOS.1 {
+            anomalies.push(Anomaly::Length(ratio));
+        }
+    }
+
+    if translation.trim() == source.trim() && words(source) >= MIN_REPEATED_WORDS {
+        anomalies.push(Anomaly::SameAsSource);
+    }
+
+    if repeated_sentence(source).is_none() {
+        if let Some(sentence) = repeated_sentence(translation) {
+            anomalies.push(Anomaly::RepeatedSentence(sentence.to_string()));
+        }
+    }
+    anomalies
+}
+
+/// Checks the translation applied to `segment`, see `check`. Empty when there is none.
+pub fn check_segment(segment: &Segment) -> Vec<Anomaly> {
+    match (segment.translation(), segment.translation_markup()) {
+        (Some(translation), Some(markup)) => {
+            check(&segment.source_text(), &translation, &segment.text, &markup)
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// How close two texts of the same language are, from 0 to 1 for the same text: the
+/// normalized Levenshtein similarity of their lowercase words.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let normalize = |text: &str| {
+        text.split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    strsim::normalized_levenshtein(&normalize(a), &normalize(b))
+}
+
+// Whether placeholders are left in the text, or a `<gN>` with letters in the source has none
+// in the translation.
+fn broken_tags(translation: &str, source_markup: &str, translation_markup: &str) -> bool {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    static OPENING: OnceLock<Regex> = OnceLock::new();
+    let placeholder =
+        PLACEHOLDER.get_or_init(|| Regex::new(r"</?\s*(?:[gxk]\d+|lb)\s*/?>").unwrap());
+    if placeholder.is_match(translation) {
+        return true;
+    }
+    let opening = OPENING.get_or_init(|| Regex::new(r"<(g\d+)>").unwrap());
+    opening.captures_iter(source_markup).any(|captures| {
+        let id = &captures[1];
+        let has_letters = |markup: &str| {
+            inner_markup(markup, id).is_some_and(|inner| {
+                placeholder
+                    .replace_all(inner, "")
+                    .chars()
+                    .any(char::is_alphabetic)
+            })
+        };
+        has_letters(source_markup) && !has_letters(translation_markup)
+    })
+}
+
+// The markup between `<id>` and `</id>`.
+fn inner_markup<'a>(markup: &'a str, id: &str) -> Option<&'a str> {
+    let start = markup.find(&format!("<{}>", id))? + id.len() + 2;
+    let end = start + markup[start..].find(&format!("</{}>", id))?;
+    Some(&markup[start..end])
+}
+
+// The first sentence of at least `MIN_REPEATED_WORDS` words that comes again in `text`.
+fn repeated_sentence(text: &str) -> Option<&str> {
+    static SENTENCE: OnceLock<Regex> = OnceLock::new();
+    let re = SENTENCE.get_or_init(|| Regex::new(r"[^.!?…。！？]+[.!?…。！？]*").unwrap());
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    re.find_iter(text)
+        .map(|sentence| sentence.as_str().trim())
+        .filter(|sentence| words(sentence) >= MIN_REPEATED_WORDS)
+        .find(|sentence| {
+            let count = counts.entry(sentence).or_default();
+            *count += 1;
+            *count > 1
+        })
+}
+
+fn words(text: &str) -> usize {
+    text.split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphabetic))
+        .count()
+}
+
+fn numbers(text: &str) -> impl Iterator<Item = &str> {
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    let re = NUMBER.get_or_init(|| Regex::new(r"\d+").unwrap());
+    re.find_iter(text).map(|number| number.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::get_segments;
+    use crate::xhtml::{get_document_node, ExtractOptions};
+
+    #[test]
+    fn test_check() {
+        let plain = |source, translation| check(source, translation, source, translation);
+        assert_eq!(plain("Call me Ishmael.", "Llamadme Ismael."), vec![]);
+        assert_eq!(
+            plain("Call me Ishmael.", "Call me Ishmael."),
+            vec![Anomaly::SameAsSource]
+        );
+        assert_eq!(plain("Moby Dick", "Moby Dick"), vec![]);
+        assert_eq!(
+            plain("It was a long voyage, in 1851.", "Fue en 1815."),
+            vec![
+                Anomaly::MissingNumbers(vec!["1851".to_string()]),
+                Anomaly::Length(0.4)
+            ]
+        );
+        assert_eq!(
+            plain("It cost 1,000.50 dollars", "Costó 1.000,50 dólares"),
+            vec![]
+        );
+        assert_eq!(
+            plain(
+                "He left the house. Then he came back.",
+                "Salió de la casa. Salió de la casa."
+            ),
+            vec![Anomaly::RepeatedSentence("Salió de la casa.".to_string())]
+        );
+        assert_eq!(plain("No, no. No, no.", "Non, non. Non, non."), vec![]);
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("Call me  Ishmael.", "call me ishmael."), 1.0);
+        assert_eq!(similarity("", ""), 1.0);
+        let score = similarity("Call me Ishmael.", "Call me Ismael.");
+        assert!(score > 0.9 && score < 1.0);
+        assert!(similarity("Call me Ishmael.", "It was cold.") < 0.5);
+    }
+
+    #[test]
+    fn test_check_segment() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            "<html><body><p>A <em>bold</em> move</p><p>Another <b>one</b></p></body></html>",
+        )?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+        assert_eq!(check_segment(&segments[0]), vec![]);
+
+        segments[0].apply("Un <g0>audaz</g0> paso")?;
+        assert_eq!(check_segment(&segments[0]), vec![]);
+        // An inline element emptied, placeholders the API returned escaped
+        segments[0].apply("Un paso <g0></g0>")?;
+        assert_eq!(check_segment(&segments[0]), vec![Anomaly::BrokenTags]);
+        segments[1].apply("Otro &lt;g0&gt;<g0>uno</g0>")?;
+        assert_eq!(check_segment(&segments[1]), vec![Anomaly::BrokenTags]);
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+mod quality;
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+pub use quality::write_report_html;
+
+use crate::qa::Anomaly;
+
+/// Summary of a translation run, returned even when the run was cancelled.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TranslationReport {
+    /// Number of translation units found in the documents.
+    pub total: usize,
+    /// Units translated and written back into their documents.
+    pub translated: usize,
+    /// Units that exhausted their retries and kept the original text.
+    pub failed: usize,
+    /// Units among `failed` whose last attempt was refused because the character quota of the
+    /// API key was exhausted.
+    pub quota_exceeded: usize,
+    /// Units that were never translated because the run was cancelled.
+    pub cancelled: usize,
+    /// Units that were never sent because the character or unit budget was reached.
+    pub over_budget: usize,
+    /// Units left out of the sample of `TranslateOptions::sample`, never sent.
+    pub not_sampled: usize,
+    /// Units among `translated` that took their translation from `TranslateOptions::memory`,
+    /// never sent.
+    pub from_memory: usize,
+    /// Units among `translated` that took their translation from `TranslateOptions::cache`,
+    /// never sent.
+    pub from_cache: usize,
+    /// Billable characters sent, retries included. Zero with local providers, and in the
+    /// report of each file of `translate_directory`.
+    pub billed_characters: usize,
+    /// Where the run stopped when `TranslateOptions::max_chars` or `max_units` was reached.
+    pub budget_stop: Option<BudgetStop>,
+    /// Every unit among `failed`, in reading order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<FailedUnit>,
+    /// Every translated unit with anomalies found by `qa::check`, in reading order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flagged: Vec<FlaggedUnit>,
+    /// The names kept untranslated with `TranslateOptions::protect_names`, the most frequent
+    /// first. Empty in the report of each file of `translate_directory`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    /// The sample translated back to the source language when
+    /// `TranslateOptions::back_translation` is set. `None` in the report of each file of
+    /// `translate_directory`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub back_translation: Option<BackTranslation>,
+    /// Fixed-layout pages with translated text, to check for overflowing text.
+    pub fixed_layout_pages: Vec<PathBuf>,
+    /// Every unit with its translation, filled when `TranslateOptions::alignment` is set.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alignment: Vec<AlignedUnit>,
+    /// Language tag of the source of `alignment`: `TranslateOptions::source_lang`, or the
+    /// language of the book when not given. `None` when neither is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_lang: Option<String>,
+}
+
+impl TranslationReport {
+    /// True when every translation unit has been translated.
+    pub fn is_complete(&self) -> bool {
+        self.translated == self.total
+    }
+
+    /// True when the run was stopped by its cancellation token.
+    pub fn was_cancelled(&self) -> bool {
+        self.cancelled > 0
+    }
+}
+
+/// The part of a run in one file, see `translate_directory`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FileReport {
+    /// Path relative to the root of the book or directory.
+    pub path: PathBuf,
+    /// The units of the file only. `budget_stop` is only set in the file where the run
+    /// stopped.
+    pub report: TranslationReport,
+    /// Why the file was left as it is, e.g. a document that cannot be parsed. Its units are
+    /// not counted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The first unit left untranslated by a run that reached its character or unit budget, every
+/// unit after it is left untranslated too.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BudgetStop {
+    /// Path of the file of the unit, relative to the root of the book. Empty when a single
+    /// document is translated.
+    pub path: PathBuf,
+    /// Position of the unit in its file, from 1.
+    pub position: usize,
+    /// Source text of the unit.
+    pub source: String,
+    /// Billable characters sent before stopping.
+    pub billable_characters: usize,
+}
+
+/// A unit left in the source language because its translation failed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FailedUnit {
+    /// Path of the file of the unit, relative to the root of the book. Empty when a single
+    /// document is translated.
+    pub path: PathBuf,
+    /// Position of the unit in its file, from 1.
+    pub position: usize,
+    /// Element or attribute of the unit in its document, e.g. `/html/body/p[3]`. `None` for
+    /// the package metadata and the NCX.
+    pub location: Option<String>,
+    /// Source text of the unit.
+    pub source: String,
+    /// Error of the last attempt.
+    pub error: String,
+}
+
+/// A translated unit whose translation looks wrong, to be checked.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FlaggedUnit {
+    /// Path of the file of the unit, relative to the root of the book. Empty when a single
+    /// document is translated.
+    pub path: PathBuf,
+    /// Position of the unit in its file, from 1.
+    pub position: usize,
+    /// Element or attribute of the unit in its document, see `FailedUnit::location`.
+    pub location: Option<String>,
+    /// Source text of the unit.
+    pub source: String,
+    /// Translation of the unit.
+    pub translation: String,
+    /// What looks wrong, written as messages in JSON.
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// Translated units of a run translated back to the source language, a cheap signal of the
+/// quality of their translation.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BackTranslation {
+    /// Mean `similarity` of the units, between 0 and 1. `None` when none was translated back.
+    pub score: Option<f64>,
+    /// Units of the sample whose back translation failed, left out of `units`.
+    pub failed: usize,
+    /// Every unit translated back, in reading order.
+    pub units: Vec<BackTranslatedUnit>,
+}
+
+/// A translated unit next to its translation back to the source language.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BackTranslatedUnit {
+    /// Path of the file of the unit, relative to the root of the book.
+    pub path: PathBuf,
+    /// Position of the unit in its file, from 1.
+    pub position: usize,
+    /// Source text of the unit.
+    pub source: String,
+    pub translation: String,
+    /// The translation translated back to the language of `source`.
+    pub back_translation: String,
+    /// How close `back_translation` is to `source`, see `qa::similarity`.
+    pub similarity: f64,
+}
+
+/// What there is to translate in a file of a book, see `count_book_chapters`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChapterCount {
+    /// Path relative to the root of the book.
+    pub path: PathBuf,
+    /// Position of the document in the spine, from 1.
+    pub chapter: Option<usize>,
+    /// Characters of the translatable text nodes, whitespace included.
+    pub characters: usize,
+    /// Translation units, one request each.
+    pub units: usize,
+    /// Characters sent to the API: excluded elements, blank text and inline markup left out.
+    /// Repeated text is sent, and billed, every time it appears.
+    pub billable_characters: usize,
+}
+
+/// A translation unit next to its translation, see `write_alignment_csv`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AlignedUnit {
+    /// Path of the file the unit was found in, relative to the root of the book.
+    pub path: PathBuf,
+    /// Position of the unit in its file, from 1.
+    pub position: usize,
+    /// Source text, without the markup of inline elements.
+    pub source: String,
+    /// `None` when the unit was not translated.
+    pub translation: Option<String>,
+    /// Source text as sent, with the placeholders of its inline elements, see `Segment`.
+    #[serde(skip)]
+    pub source_markup: String,
+    /// Translation in the format of `source_markup`.
+    #[serde(skip)]
+    pub translation_markup: Option<String>,
+}
+
+/// Writes `units` as CSV, with a `file,position,source,translation` header. Untranslated units
+/// have an empty translation.
+pub fn write_alignment_csv(units: &[AlignedUnit], output: &mut impl Write) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(output);
+    writer.write_record(["file", "position", "source", "translation"])?;
+    for unit in units {
+        writer.write_record([
+            unit.path.to_string_lossy().as_ref(),
+            &unit.position.to_string(),
+            &unit.source,
+            unit.translation.as_deref().unwrap_or_default(),
+        ])?;
+    }
+    writer.flush()
+}
+
+/// Writes `units` as a standalone HTML page, one table row per unit.
+pub fn write_alignment_html(units: &[AlignedUnit], output: &mut impl Write) -> io::Result<()> {
+    writeln!(output, "<!DOCTYPE html>")?;
+    writeln!(
+        output,
+        "<html><head><meta charset=\"utf-8\"/><title>Alignment</title></head><body>"
+    )?;
+    writeln!(
+        output,
+        "<table><tr><th>File</th><th>Position</th><th>Source</th><th>Translation</th></tr>"
+    )?;
+    for unit in units {
+        writeln!(
+            output,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            html_text(&unit.path.to_string_lossy()),
+            unit.position,
+            html_text(&unit.source),
+            html_text(unit.translation.as_deref().unwrap_or_default())
+        )?;
+    }
+    writeln!(output, "</table></body></html>")
+}
+
+/// Writes the translated `units` as a TMX 1.4 translation memory, to import the translations
+/// into a CAT tool. Languages are BCP 47 tags, e.g. `en` and `es`. Untranslated units are
+/// left out, the file and position of the others are kept as properties.
+pub fn write_alignment_tmx(
+    units: &[AlignedUnit],
+    source_lang: &str,
+    target_lang: &str,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(output, r#"<tmx version="1.4">"#)?;
+    writeln!(
+        output,
+        r#"<header creationtool="epub-translator" creationtoolversion="{}" datatype="plaintext" segtype="paragraph" adminlang="en" srclang="{}" o-tmf="epub-translator"/>"#,
+        env!("CARGO_PKG_VERSION"),
+        xml_attribute(source_lang)
+    )?;
+    writeln!(output, "<body>")?;
+    for unit in units {
+        let Some(translation) = &unit.translation else {
+            continue;
+        };
+        writeln!(output, "<tu>")?;
+        writeln!(
+            output,
+            r#"<prop type="x-file">{}</prop><prop type="x-position">{}</prop>"#,
+            html_text(&unit.path.to_string_lossy()),
+            unit.position
+        )?;
+        for (lang, text) in [(source_lang, &unit.source), (target_lang, translation)] {
+            writeln!(
+                output,
+                r#"<tuv xml:lang="{}"><seg>{}</seg></tuv>"#,
+                xml_attribute(lang),
+                html_text(text)
+            )?;
+        }
+        writeln!(output, "</tu>")?;
+    }
+    writeln!(output, "</body>")?;
+    writeln!(output, "</tmx>")
+}
+
+fn xml_attribute(value: &str) -> String {
+    html_text(value).replace('"', "&quot;")
+}
+
+fn html_text(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_alignment() -> Result<(), Box<dyn std::error::Error>> {
+        let units = vec![
+            AlignedUnit {
+                path: PathBuf::from("OEBPS/text/chapter 1.htm"),
+                position: 1,
+                source: "Fish & \"chips\", please".to_string(),
+                translation: Some("Pescado y patatas".to_string()),
+                ..Default::default()
+            },
+            AlignedUnit {
+                path: PathBuf::from("OEBPS/toc.ncx"),
+                position: 2,
+                source: "<Notes>".to_string(),
+                translation: None,
+                ..Default::default()
+            },
+        ];
+
+        let mut csv = Vec::new();
+        write_alignment_csv(&units, &mut csv)?;
+        assert_eq!(
+            String::from_utf8(csv)?,
+            "file,position,source,translation\n\
+             OEBPS/text/chapter 1.htm,1,\"Fish & \"\"chips\"\", please\",Pescado y patatas\n\
+             OEBPS/toc.ncx,2,<Notes>,\n"
+        );
+
+        let mut html = Vec::new();
+        write_alignment_html(&units, &mut html)?;
+        let html = String::from_utf8(html)?;
+        assert!(html
+            .contains("<tr><td>OEBPS/toc.ncx</td><td>2</td><td>&lt;Notes&gt;</td><td></td></tr>"));
+
+        // The untranslated unit is left out
+        let mut tmx = Vec::new();
+        write_alignment_tmx(&units, "en", "es", &mut tmx)?;
+        let tmx = String::from_utf8(tmx)?;
+        assert!(tmx.contains(r#"srclang="en""#));
+        assert!(tmx.contains(
+            "<tu>\n\
+             <prop type=\"x-file\">OEBPS/text/chapter 1.htm</prop><prop type=\"x-position\">1</prop>\n\
+             <tuv xml:lang=\"en\"><seg>Fish &amp; \"chips\", please</seg></tuv>\n\
+             <tuv xml:lang=\"es\"><seg>Pescado y patatas</seg></tuv>\n\
+             </tu>\n"
+        ));
+        assert!(!tmx.contains("Notes"));
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::{html_text, AlignedUnit, TranslationReport};
+use crate::pricing::{estimate, Rate};
+use crate::qa::Anomaly;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}table{border-collapse:collapse;\
+width:100%;margin-bottom:2em}th,td{border:1px solid #ccc;padding:.3em .5em;text-align:left;\
+vertical-align:top}td.number{text-align:right}tr.flagged{background:#fff3cd}\
+tr.untranslated{background:#f8d7da}.flags{color:#856404;font-size:.9em}";
+
+/// Writes a run with `TranslateOptions::alignment` as a standalone HTML page: its summary and
+/// the estimated cost of the characters billed with each of `rates`, the counts of each file,
+/// then every unit next to its translation, with its anomalies or why it failed.
+pub fn write_report_html(
+    report: &TranslationReport,
+    target_lang: &str,
+    rates: &[Rate],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let flags: HashMap<(&Path, usize), &[Anomaly]> = report
+        .flagged
+        .iter()
+        .map(|unit| ((unit.path.as_path(), unit.position), &unit.anomalies[..]))
+        .collect();
+    let errors: HashMap<(&Path, usize), &str> = report
+        .failures
+        .iter()
+        .map(|failure| ((failure.path.as_path(), failure.position), &*failure.error))
+        .collect();
+    let is_flagged = |unit: &AlignedUnit| flags.contains_key(&(&*unit.path, unit.position));
+
+    writeln!(output, "<!DOCTYPE html>")?;
+    writeln!(
+        output,
+        "<html><head><meta charset=\"utf-8\"/><title>Translation report</title>\
+         <style>{}</style></head><body>",
+        STYLE
+    )?;
+    writeln!(
+        output,
+        "<h1>Translation report</h1><p>{} → {}</p>",
+        html_text(report.source_lang.as_deref().unwrap_or("?")),
+        html_text(target_lang)
+    )?;
+
+    writeln!(output, "<h2>Summary</h2><table>")?;
+    let untranslated = report.total - report.translated - report.failed;
+    let rows = [
+        ("Segments", report.total),
+        ("Translated", report.translated),
+        ("From the translation memory", report.from_memory),
+        ("From the translation cache", report.from_cache),
+        ("Failed", report.failed),
+        ("Not sent", untranslated),
+        ("Flagged", report.flagged.len()),
+        ("Billed characters", report.billed_characters),
+    ];
+    for (label, count) in rows {
+        writeln!(
+            output,
+            "<tr><th>{}</th><td class=\"number\">{}</td></tr>",
+            label, count
+        )?;
+    }
+    for estimate in estimate(report.billed_characters, rates) {
+        writeln!(
+            output,
+            "<tr><th>Estimated cost with {}</th><td class=\"number\">~{:.2} {}</td></tr>",
+            html_text(&estimate.plan),
+            estimate.price,
+            html_text(&estimate.currency)
+        )?;
+    }
+    writeln!(output, "</table>")?;
+
+    // The files in the order of the book
+    let mut files: Vec<(&Path, Vec<&AlignedUnit>)> = Vec::new();
+    for unit in &report.alignment {
+        match files.iter_mut().find(|(path, _)| *path == unit.path) {
+            Some((_, units)) => units.push(unit),
+            None => files.push((&unit.path, vec![unit])),
+        }
+    }
+    writeln!(
+        output,
+        "<h2>Files</h2><table><tr><th>File</th><th>Segments</th><th>Translated</th>\
+         <th>Flagged</th><th>Source characters</th><th>Translation characters</th></tr>"
+    )?;
+    for (number, (path, units)) in files.iter().enumerate() {
+        let translations: Vec<&String> = units
+            .iter()
+            .filter_map(|unit| unit.translation.as_ref())
+            .collect();
+        writeln!(
+            output,
+            "<tr><td><a href=\"#file-{}\">{}</a></td><td class=\"number\">{}</td>\
+             <td class=\"number\">{}</td><td class=\"number\">{}</td>\
+             <td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+            number + 1,
+            html_text(&path.to_string_lossy()),
+            units.len(),
+            translations.len(),
+            units.iter().filter(|unit| is_flagged(unit)).count(),
+            units
+                .iter()
+                .map(|unit| unit.source.chars().count())
+                .sum::<usize>(),
+            translations
+                .iter()
+                .map(|translation| translation.chars().count())
+                .sum::<usize>()
+        )?;
+    }
+    writeln!(output, "</table>")?;
+
+    // The least similar first, the likeliest to be mistranslated
+    if let Some(back_translation) = &report.back_translation {
+        let mut units: Vec<_> = back_translation.units.iter().collect();
+        units.sort_by(|a, b| a.similarity.total_cmp(&b.similarity));
+        writeln!(
+            output,
+            "<h2>Back translation</h2><p>{} segments translated back to the source language, \
+             mean similarity {}.</p>",
+            units.len(),
+            back_translation
+                .score
+                .map_or("-".to_string(), |score| format!("{:.2}", score))
+        )?;
+        writeln!(
+            output,
+            "<table><tr><th>File</th><th>#</th><th>Source</th><th>Translation</th>\
+             <th>Back translation</th><th>Similarity</th></tr>"
+        )?;
+        for unit in units {
+            writeln!(
+                output,
+                "<tr><td>{}</td><td class=\"number\">{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                 <td class=\"number\">{:.2}</td></tr>",
+                html_text(&unit.path.to_string_lossy()),
+                unit.position,
+                html_text(&unit.source),
+                html_text(&unit.translation),
+                html_text(&unit.back_translation),
+                unit.similarity
+            )?;
+        }
+        writeln!(output, "</table>")?;
+    }
+
+    writeln!(
+        output,
+        "<h2>Segments</h2><table><tr><th>#</th><th>Source</th><th>Translation</th>\
+         <th>Flags</th></tr>"
+    )?;
+    for (number, (path, units)) in files.iter().enumerate() {
+        writeln!(
+            output,
+            "<tr><th colspan=\"4\" id=\"file-{}\">{}</th></tr>",
+            number + 1,
+            html_text(&path.to_string_lossy())
+        )?;
+        for unit in units {
+            let key = (&*unit.path, unit.position);
+            let (class, mut notes) = match (&unit.translation, flags.get(&key)) {
+                (None, _) => (" class=\"untranslated\"", vec!["untranslated".to_string()]),
+                (Some(_), Some(anomalies)) => (
+                    " class=\"flagged\"",
+                    anomalies.iter().map(ToString::to_string).collect(),
+                ),
+                (Some(_), None) => ("", Vec::new()),
+            };
+            if let Some(error) = errors.get(&key) {
+                notes.push(format!("failed: {}", error));
+            }
+            writeln!(
+                output,
+                "<tr{}><td class=\"number\">{}</td><td>{}</td><td>{}</td>\
+                 <td class=\"flags\">{}</td></tr>",
+                class,
+                unit.position,
+                html_text(&unit.source),
+                html_text(unit.translation.as_deref().unwrap_or_default()),
+                html_text(&notes.join("; "))
+            )?;
+        }
+    }
+    writeln!(output, "</table></body></html>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pricing::default_rates;
+    use crate::report::{FailedUnit, FlaggedUnit};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_write_report_html() -> Result<(), Box<dyn std::error::Error>> {
+        let path = PathBuf::from("OEBPS/text/chapter 1.htm");
+        let unit = |position: usize, source: &str, translation: Option<&str>| AlignedUnit {
+            path: path.clone(),
+            position,
+            source: source.to_string(),
+            translation: translation.map(str::to_string),
+            ..Default::default()
+        };
+        let report = TranslationReport {
+            total: 3,
+            translated: 2,
+            failed: 1,
+            billed_characters: 500_000,
+            failures: vec![FailedUnit {
+                path: path.clone(),
+                position: 3,
+                source: "Loomings".to_string(),
+                error: "timeout".to_string(),
+                ..Default::default()
+            }],
+            flagged: vec![FlaggedUnit {
+                path: path.clone(),
+                position: 2,
+                anomalies: vec![Anomaly::Length(0.3)],
+                ..Default::default()
+            }],
+            alignment: vec![
+                unit(1, "Call me <Ishmael>.", Some("Llamadme <Ismael>.")),
+                unit(
+                    2,
+                    "Some years ago, never mind how long.",
+                    Some("Hace años."),
+                ),
+                unit(3, "Loomings", None),
+            ],
+            source_lang: Some("en".to_string()),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_report_html(&report, "es", &default_rates(), &mut output)?;
+        let output = String::from_utf8(output)?;
+        assert!(output.contains("<p>en → es</p>"));
+        assert!(output.contains("<td>Llamadme &lt;Ismael&gt;.</td>"));
+        assert!(output.contains("<tr><th>Flagged</th><td class=\"number\">1</td></tr>"));
+        assert!(output.contains("~10.00 EUR"));
+        assert!(output.contains("<tr class=\"flagged\"><td class=\"number\">2</td>"));
+        assert!(output.contains("length ×0.3</td>"));
+        assert!(output.contains("untranslated; failed: timeout"));
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+#[cfg(feature = "tui")]
+pub mod tui;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::epub::{content_document_paths, BookFiles};
+use crate::error::EpubTranslatorError;
+use crate::parse_xhtml;
+use crate::segment::{escape_xml, get_segments, unescape_xml};
+use crate::xhtml::{
+    decode_xhtml, get_document_node, serialize_document_to_string_with, EntityPolicy,
+    ExtractOptions,
+};
+
+/// A unit of a translated book next to the unit of the book it was translated from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReviewPair {
+    pub path: PathBuf,
+    /// Position of the unit in its document, from 1.
+    pub position: usize,
+    /// The source, as plain text.
+    pub source: String,
+    /// The translation, with the tags of its inline elements, `<g1>…</g1>`, and of its line
+    /// breaks, `<lb/>`, which an edit must keep.
+    pub translation: String,
+}
+
+impl ReviewPair {
+    /// Checks that `edited`, a new translation, has the tags of the translation, each once.
+    pub fn check(&self, edited: &str) -> Result<(), String> {
+        let (mut expected, mut found) = (tags(&self.translation), tags(edited));
+        expected.sort_unstable();
+        found.sort_unstable();
+        if expected != found {
+            return Err(format!(
+                "the translation must keep the tags {}",
+                tags(&self.translation).join(" ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The units of the content documents of a book and of its translation, paired by position.
+#[derive(Debug, Clone, Default)]
+pub struct Review {
+    pub pairs: Vec<ReviewPair>,
+    /// Documents left out, without as many units in both books, e.g. bilingual ones.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Pairs the units of the content documents of `translated` with the ones of `original`,
+/// extracted with the same `options`.
+pub fn review_pairs(
+    original: &dyn BookFiles,
+    translated: &dyn BookFiles,
+    options: &ExtractOptions,
+) -> Result<Review, EpubTranslatorError> {
+    let mut review = Review::default();
+    for path in content_document_paths(translated)? {
+        if !original.is_file(&path) {
+            review.skipped.push(path);
+            continue;
+        }
+        let source = get_document_node(&decode_xhtml(&original.read(&path)?)?)?;
+        let target = get_document_node(&decode_xhtml(&translated.read(&path)?)?)?;
+        let (sources, targets) = (
+            get_segments(&source, options),
+            get_segments(&target, options),
+        );
+        if sources.len() != targets.len() {
+            review.skipped.push(path);
+            continue;
+        }
+        for (index, (source, target)) in sources.iter().zip(&targets).enumerate() {
+            review.pairs.push(ReviewPair {
+                path: path.clone(),
+                position: index + 1,
+                source: source.source_text(),
+                translation: unescape_xml(&target.text),
+            });
+        }
+    }
+    Ok(review)
+}
+
+/// Writes `edits`, pairs of `review_pairs` with a new translation, to the documents of
+/// `translated`. The other documents are left as they are.
+pub fn apply_edits(
+    translated: &mut dyn BookFiles,
+    edits: &[ReviewPair],
+    options: &ExtractOptions,
+) -> Result<(), EpubTranslatorError> {
+    let mut documents: BTreeMap<&Path, Vec<&ReviewPair>> = BTreeMap::new();
+    for edit in edits {
+        documents.entry(&edit.path).or_default().push(edit);
+    }
+    for (path, edits) in documents {
+        let content = decode_xhtml(&translated.read(path)?)?;
+        let (document, references) = parse_xhtml(&content, EntityPolicy::Preserve)?;
+        let segments = get_segments(&document, options);
+        for edit in edits {
+            let segment = edit
+                .position
+                .checked_sub(1)
+                .and_then(|index| segments.get(index))
+                .ok_or_else(|| {
+                    EpubTranslatorError::InvalidTranslation(format!(
+                        "{} has no unit #{}",
+                        path.display(),
+                        edit.position
+                    ))
+                })?;
+            segment.apply(&markup(&edit.translation))?;
+        }
+        let output = serialize_document_to_string_with(&document, &references)?;
+        translated.write(path, output.into_bytes())?;
+    }
+    Ok(())
+}
+
+fn tag_pattern() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| Regex::new(r"</?(?:g\d+|x\d+|k\d+|lb)\s*/?>").unwrap())
+}
+
+fn tags(text: &str) -> Vec<&str> {
+    tag_pattern()
+        .find_iter(text)
+        .map(|tag| tag.as_str())
+        .collect()
+}
+
+// The text of a unit for an edited translation: its text escaped, its tags as they are.
+fn markup(text: &str) -> String {
+    let mut markup = String::new();
+    let mut end = 0;
+    for tag in tag_pattern().find_iter(text) {
+        markup.push_str(&escape_xml(&text[end..tag.start()]));
+        markup.push_str(tag.as_str());
+        end = tag.end();
+    }
+    markup.push_str(&escape_xml(&text[end..]));
+    markup
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::{BookArchive, UnzipLimits};
+    use crate::testing::read_fixture;
+
+    #[test]
+    fn test_review_and_apply() -> Result<(), Box<dyn std::error::Error>> {
+        let epub = read_fixture(Path::new("tests/data/opf_book"))?;
+        let original = BookArchive::read(epub.clone(), &UnzipLimits::default())?;
+        let mut translated = BookArchive::read(epub, &UnzipLimits::default())?;
+        let options = ExtractOptions::default();
+
+        let review = review_pairs(&original, &translated, &options)?;
+        assert_eq!(review.skipped, Vec::<PathBuf>::new());
+        let pair = review
+            .pairs
+            .iter()
+            .find(|pair| pair.translation.contains("<g"))
+            .expect("a unit with inline elements");
+        let edited = format!("{} & <more>", pair.translation);
+        assert_eq!(pair.check(&edited), Ok(()));
+        let without_tags = tag_pattern().replace_all(&pair.translation, "").to_string();
+        assert!(pair.check(&without_tags).is_err());
+
+        let edit = ReviewPair {
+            translation: edited,
+            ..pair.clone()
+        };
+        apply_edits(&mut translated, std::slice::from_ref(&edit), &options)?;
+        let review = review_pairs(&original, &translated, &options)?;
+        assert!(review.pairs.contains(&edit));
+        Ok(())
+    }
+}
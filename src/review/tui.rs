@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::Frame;
+
+use super::ReviewPair;
+
+const HELP: &str = "↑/↓ previous/next  PgUp/PgDn ±10  e edit  u undo  s save and quit  q quit";
+const EDIT_HELP: &str = "Enter accept  Esc cancel  ←/→ Home/End move  Backspace/Del delete";
+
+/// Steps through `pairs` in the terminal, each source above its translation, which can be
+/// edited. Returns the edited pairs, or `None` when the user quits without saving.
+pub fn review(pairs: Vec<ReviewPair>) -> io::Result<Option<Vec<ReviewPair>>> {
+    let mut state = State::new(pairs);
+    let mut terminal = ratatui::init();
+    let outcome = loop {
+        if let Err(e) = terminal.draw(|frame| state.draw(frame)) {
+            break Err(e);
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if let Some(saved) = state.handle(key) {
+                    break Ok(saved);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    ratatui::restore();
+    Ok(outcome?.then(|| state.edits()))
+}
+
+// The translation being edited, as characters for the moves of the cursor.
+struct Editor {
+    text: Vec<char>,
+    cursor: usize,
+}
+
+struct State {
+    pairs: Vec<ReviewPair>,
+    // The translation of each edited pair before its edit, by index
+    originals: BTreeMap<usize, String>,
+    index: usize,
+    editor: Option<Editor>,
+    message: Option<String>,
+    // Set by a first `q` with unsaved edits, a second one quits
+    quitting: bool,
+}
+
+impl State {
+    fn new(pairs: Vec<ReviewPair>) -> Self {
+        Self {
+            pairs,
+            originals: BTreeMap::new(),
+            index: 0,
+            editor: None,
+            message: None,
+            quitting: false,
+        }
+    }
+
+    fn edits(&self) -> Vec<ReviewPair> {
+        self.originals
+            .keys()
+            .map(|&index| self.pairs[index].clone())
+            .collect()
+    }
+
+    // Handles a key press, `Some(true)` to save and quit, `Some(false)` to quit.
+    fn handle(&mut self, key: KeyEvent) -> Option<bool> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(false);
+        }
+        if self.editor.is_some() {
+            self.handle_edit(key);
+            return None;
+        }
+        let quitting = std::mem::take(&mut self.quitting);
+        self.message = None;
+        let last = self.pairs.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Down | KeyCode::Char('j') => self.index = (self.index + 1).min(last),
+            KeyCode::Up | KeyCode::Char('k') => self.index = self.index.saturating_sub(1),
+            KeyCode::PageDown => self.index = (self.index + 10).min(last),
+            KeyCode::PageUp => self.index = self.index.saturating_sub(10),
+            KeyCode::Home | KeyCode::Char('g') => self.index = 0,
+            KeyCode::End | KeyCode::Char('G') => self.index = last,
+            KeyCode::Enter | KeyCode::Char('e') if !self.pairs.is_empty() => {
+                let text: Vec<char> = self.pairs[self.index].translation.chars().collect();
+                self.editor = Some(Editor {
+                    cursor: text.len(),
+                    text,
+                });
+            }
+            KeyCode::Char('u') => {
+                if let Some(original) = self.originals.remove(&self.index) {
+                    self.pairs[self.index].translation = original;
+                }
+            }
+            KeyCode::Char('s') => return Some(true),
+            KeyCode::Char('q') | KeyCode::Esc => {
+                if quitting || self.originals.is_empty() {
+                    return Some(false);
+                }
+                self.quitting = true;
+                self.message = Some(format!(
+                    "{} unsaved edits: q again to quit without saving them, s to save them",
+                    self.originals.len()
+                ));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_edit(&mut self, key: KeyEvent) {
+        let Some(editor) = &mut self.editor else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => {
+                self.editor = None;
+                self.message = None;
+            }
+            KeyCode::Enter => {
+                let edited: String = editor.text.iter().collect();
+                let pair = &mut self.pairs[self.index];
+                if let Err(message) = pair.check(&edited) {
+                    self.message = Some(message);
+                    return;
+                }
+                let original = self
+                    .originals
+                    .remove(&self.index)
+                    .unwrap_or_else(|| pair.translation.clone());
+                if edited != original {
+                    self.originals.insert(self.index, original);
+                }
+                pair.translation = edited;
+                self.editor = None;
+                self.message = None;
+            }
+            KeyCode::Left => editor.cursor = editor.cursor.saturating_sub(1),
+            KeyCode::Right => editor.cursor = (editor.cursor + 1).min(editor.text.len()),
+            KeyCode::Home => editor.cursor = 0,
+            KeyCode::End => editor.cursor = editor.text.len(),
+            KeyCode::Backspace if editor.cursor > 0 => {
+                editor.cursor -= 1;
+                editor.text.remove(editor.cursor);
+            }
+            KeyCode::Delete if editor.cursor < editor.text.len() => {
+                editor.text.remove(editor.cursor);
+            }
+            KeyCode::Char(c) => {
+                editor.text.insert(editor.cursor, c);
+                editor.cursor += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, source, translation, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let Some(pair) = self.pairs.get(self.index) else {
+            frame.render_widget(Paragraph::new("Nothing to review"), header);
+            return;
+        };
+        frame.render_widget(
+            Line::from(format!(
+                "{} #{}  {}/{}  {} edited",
+                pair.path.display(),
+                pair.position,
+                self.index + 1,
+                self.pairs.len(),
+                self.originals.len()
+            ))
+            .bold(),
+            header,
+        );
+        frame.render_widget(
+            Paragraph::new(pair.source.as_str())
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title("Source")),
+            source,
+        );
+
+        let (text, title) = match &self.editor {
+            // The cursor is the reversed character, or a reversed space at the end
+            Some(editor) => {
+                let before: String = editor.text[..editor.cursor].iter().collect();
+                let at = editor.text.get(editor.cursor).map_or(' ', |c| *c);
+                let after: String = editor.text.iter().skip(editor.cursor + 1).collect();
+                let line = Line::from(vec![
+                    Span::raw(before),
+                    Span::styled(
+                        at.to_string(),
+                        Style::new().add_modifier(Modifier::REVERSED),
+                    ),
+                    Span::raw(after),
+                ]);
+                (Text::from(line), "Translation (editing)")
+            }
+            None if self.originals.contains_key(&self.index) => (
+                Text::from(pair.translation.as_str()),
+                "Translation (edited)",
+            ),
+            None => (Text::from(pair.translation.as_str()), "Translation"),
+        };
+        frame.render_widget(
+            Paragraph::new(text)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(title)),
+            translation,
+        );
+
+        let footer_line = match (&self.message, &self.editor) {
+            (Some(message), _) => Line::from(message.as_str()).red(),
+            (None, Some(_)) => Line::from(EDIT_HELP).dim(),
+            (None, None) => Line::from(HELP).dim(),
+        };
+        frame.render_widget(footer_line, footer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn press(state: &mut State, keys: &[KeyCode]) -> Option<bool> {
+        keys.iter()
+            .map(|&code| state.handle(KeyEvent::from(code)))
+            .last()
+            .flatten()
+    }
+
+    #[test]
+    fn test_edit_and_quit() {
+        let pair = |position: usize, translation: &str| ReviewPair {
+            path: PathBuf::from("OEBPS/one.xhtml"),
+            position,
+            source: String::new(),
+            translation: translation.to_string(),
+        };
+        let mut state = State::new(vec![pair(1, "Uno"), pair(2, "<g0>Dso</g0>")]);
+
+        // Edits that lose a tag are refused
+        press(&mut state, &[KeyCode::Down, KeyCode::Char('e')]);
+        press(
+            &mut state,
+            &[KeyCode::Home, KeyCode::Delete, KeyCode::Enter],
+        );
+        assert!(state.message.is_some());
+        assert!(state.editor.is_some());
+        press(&mut state, &[KeyCode::Esc, KeyCode::Enter]);
+        let keys = [KeyCode::End, KeyCode::Left, KeyCode::Left, KeyCode::Left];
+        press(&mut state, &keys);
+        let keys = [
+            KeyCode::Left,
+            KeyCode::Left,
+            KeyCode::Left,
+            KeyCode::Backspace,
+        ];
+        press(&mut state, &keys);
+        press(
+            &mut state,
+            &[KeyCode::Right, KeyCode::Char('s'), KeyCode::Enter],
+        );
+        assert_eq!(state.pairs[1].translation, "<g0>Dos</g0>");
+        assert_eq!(state.edits(), vec![pair(2, "<g0>Dos</g0>")]);
+
+        // Unsaved edits take a second `q`, undone ones don't
+        assert_eq!(press(&mut state, &[KeyCode::Char('q')]), None);
+        assert_eq!(press(&mut state, &[KeyCode::Char('q')]), Some(false));
+        press(&mut state, &[KeyCode::Char('u')]);
+        assert_eq!(state.edits(), vec![]);
+        assert_eq!(press(&mut state, &[KeyCode::Char('q')]), Some(false));
+    }
+}
@@ -0,0 +1,1106 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::OnceLock;
+
+use html5ever::tendril::StrTendril;
+use markup5ever_rcdom::{Node, NodeData};
+use regex::Regex;
+
+use crate::error::EpubTranslatorError;
+use crate::xhtml::{element_path, ExtractOptions, RubyMode};
+
+mod store;
+
+pub use store::SharedText;
+
+/// Elements that flow inside a sentence. A run of these (and text) is translated as a whole.
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "big", "br", "cite", "code", "del", "dfn", "em", "font", "i",
+    "img", "ins", "kbd", "label", "mark", "q", "rp", "rt", "ruby", "s", "samp", "small", "span",
+    "strike", "strong", "sub", "sup", "time", "tspan", "tt", "u", "var", "wbr",
+];
+
+/// A translation unit: a run of inline content (text and inline elements) inside a block,
+/// the value of a translated attribute (`alt`, `title`...), or a detached value outside of
+/// any document (package metadata).
+///
+/// `text` is the run serialized as XML with placeholders, so that a sentence split across
+/// `<em>`, `<a>`... is translated in one piece:
+/// - `<gN>…</gN>` wraps the content of the N-th inline element,
+/// - `<xN/>` stands for the N-th element or comment without translatable text, or excluded
+///   from translation, kept untouched.
+///
+/// Text is XML escaped, the translation is expected back in the same format.
+///
+/// Under whitespace-preserving elements (`pre`, `xml:space="preserve"`), line breaks are sent
+/// as `<lb/>` so the translation keeps the lines of the source. Occurrences of the kept terms
+/// (`ExtractOptions::keep_terms`) are sent as `<kN/>` and put back in the translation.
+pub struct Segment {
+    /// The segments of a document share one buffer for their texts and contexts.
+    pub text: SharedText,
+    /// The last `h1`-`h3` heading before the segment, sent as translation context.
+    pub context: Option<SharedText>,
+    /// The segment is under a whitespace-preserving element, its formatting must be kept.
+    pub preserve_whitespace: bool,
+    target: Target,
+    /// The occurrence of a kept term each `<kN/>` stands for.
+    kept: Vec<String>,
+    /// Plain text of the translation, once applied.
+    translation: RefCell<Option<String>>,
+    /// The translation as applied, in the format of `text`.
+    markup: RefCell<Option<String>>,
+}
+
+const LINE_BREAK: &str = "<lb/>";
+
+fn kept_term_tag() -> &'static Regex {
+    static KEPT_TERM: OnceLock<Regex> = OnceLock::new();
+    KEPT_TERM.get_or_init(|| Regex::new(r"<k(\d+)\s*/>").unwrap())
+}
+
+/// Elements whose whitespace is significant.
+const WHITESPACE_PRESERVING_ELEMENTS: &[&str] = &["pre", "textarea", "listing"];
+
+enum Target {
+    Run {
+        parent: Rc<Node>,
+        nodes: RefCell<Vec<Rc<Node>>>,
+        /// Node of each placeholder and whether it was sent as `<xN/>`
+        placeholders: Vec<(Rc<Node>, bool)>,
+    },
+    Attribute {
+        element: Rc<Node>,
+        name: String,
+    },
+    Detached,
+    /// A unit of a document held by another thread, see `Segment::unattached`.
+    Unattached {
+        /// Whether each placeholder was sent as `<xN/>`, `None` for units sent as plain text
+        voids: Option<Vec<bool>>,
+        location: Option<String>,
+    },
+}
+
+/// The parts of a segment that can be sent to another thread than the one of its document, see
+/// `Segment::unattached`.
+pub struct UnattachedSegment {
+    text: SharedText,
+    context: Option<SharedText>,
+    preserve_whitespace: bool,
+    kept: Vec<String>,
+    voids: Option<Vec<bool>>,
+    location: Option<String>,
+}
+
+impl From<UnattachedSegment> for Segment {
+    fn from(unattached: UnattachedSegment) -> Self {
+        Self {
+            text: unattached.text,
+            context: unattached.context,
+            preserve_whitespace: unattached.preserve_whitespace,
+            target: Target::Unattached {
+                voids: unattached.voids,
+                location: unattached.location,
+            },
+            kept: unattached.kept,
+            translation: RefCell::new(None),
+            markup: RefCell::new(None),
+        }
+    }
+}
+
+enum Piece {
+    Text(String),
+    Element(usize, Vec<Piece>),
+    Void(usize),
+}
+
+impl Segment {
+    fn new(
+        parent: &Rc<Node>,
+        nodes: Vec<Rc<Node>>,
+        options: &ExtractOptions,
+        context: Option<String>,
+        preserve_whitespace: bool,
+    ) -> Self {
+        let mut text = String::new();
+        let mut placeholders = Vec::new();
+        let mut kept = Vec::new();
+        write_placeholder_text(&nodes, &mut text, &mut placeholders, &mut kept, options);
+        if preserve_whitespace {
+            text = text.replace('\n', LINE_BREAK);
+        }
+        Self {
+            text: text.into(),
+            context: context.map(SharedText::from),
+            preserve_whitespace,
+            target: Target::Run {
+                parent: parent.clone(),
+                nodes: RefCell::new(nodes),
+                placeholders,
+            },
+            kept,
+            translation: RefCell::new(None),
+            markup: RefCell::new(None),
+        }
+    }
+
+    fn attribute(
+        element: &Rc<Node>,
+        name: &str,
+        value: &str,
+        context: Option<String>,
+        options: &ExtractOptions,
+    ) -> Self {
+        let mut kept = Vec::new();
+        Self {
+            text: protect_terms(value, options, &mut kept).into(),
+            context: context.map(SharedText::from),
+            preserve_whitespace: false,
+            target: Target::Attribute {
+                element: element.clone(),
+                name: name.to_string(),
+            },
+            kept,
+            translation: RefCell::new(None),
+            markup: RefCell::new(None),
+        }
+    }
+
+    /// A unit for a plain text value that isn't part of a document. Its translation is read
+    /// back with `translation`.
+    pub fn detached(value: &str, context: Option<String>) -> Self {
+        Self::detached_with(value, context, &ExtractOptions::default())
+    }
+
+    /// Like `detached`, keeping the terms of `options` untranslated.
+    pub fn detached_with(value: &str, context: Option<String>, options: &ExtractOptions) -> Self {
+        let mut kept = Vec::new();
+        Self {
+            text: protect_terms(value, options, &mut kept).into(),
+            context: context.map(SharedText::from),
+            preserve_whitespace: false,
+            target: Target::Detached,
+            kept,
+            translation: RefCell::new(None),
+            markup: RefCell::new(None),
+        }
+    }
+
+    /// The unit without its document, to be sent to another thread. It becomes a `Segment` there
+    /// that translations are checked and applied to like this one, its document aside: they
+    /// are applied to the document with this one, on its thread, once checked.
+    pub fn unattached(&self) -> UnattachedSegment {
+        let voids = match &self.target {
+            Target::Run { placeholders, .. } => {
+                Some(placeholders.iter().map(|(_, void)| *void).collect())
+            }
+            Target::Unattached { voids, .. } => voids.clone(),
+            Target::Attribute { .. } | Target::Detached => None,
+        };
+        UnattachedSegment {
+            text: self.text.clone(),
+            context: self.context.clone(),
+            preserve_whitespace: self.preserve_whitespace,
+            kept: self.kept.clone(),
+            voids,
+            location: self.location(),
+        }
+    }
+
+    /// The translation applied to the unit, if any, as plain text: the placeholders of inline
+    /// elements are left out.
+    pub fn translation(&self) -> Option<String> {
+        self.translation.borrow().clone()
+    }
+
+    /// The translation applied to the unit, if any, in the format of `text`: with the
+    /// placeholders of its inline elements.
+    pub fn translation_markup(&self) -> Option<String> {
+        self.markup.borrow().clone()
+    }
+
+    /// The text sent for translation as plain text, see `translation`.
+    pub fn source_text(&self) -> String {
+        let text = self.restore_line_breaks(&self.text);
+        let text = self.restore_kept_terms(&text).unwrap_or(text);
+        match parse_placeholder_text(&text) {
+            Ok(pieces) => pieces_text(&pieces),
+            Err(_) => text,
+        }
+    }
+
+    /// The element holding the run, `None` for attributes, detached and unattached units.
+    pub fn block(&self) -> Option<&Rc<Node>> {
+        match &self.target {
+            Target::Run { parent, .. } => Some(parent),
+            _ => None,
+        }
+    }
+
+    /// Where the unit is in its document, see `element_path`: the element holding the run, or
+    /// the attribute, e.g. `/html/body/img/@alt`. `None` for detached units.
+    pub fn location(&self) -> Option<String> {
+        match &self.target {
+            Target::Run { parent, .. } => Some(element_path(parent)),
+            Target::Attribute { element, name } => {
+                Some(format!("{}/@{}", element_path(element), name))
+            }
+            Target::Detached => None,
+            Target::Unattached { location, .. } => location.clone(),
+        }
+    }
+
+    /// Characters of the text without its placeholders, the ones billed by the API. Kept terms
+    /// are not sent.
+    pub fn billable_chars(&self) -> usize {
+        let text = kept_term_tag().replace_all(&self.text, "");
+        match parse_placeholder_text(&text) {
+            Ok(pieces) => text_chars(&pieces),
+            Err(_) => text.chars().count(),
+        }
+    }
+
+    // Whether there are letters outside of the kept terms.
+    fn has_unkept_text(&self) -> bool {
+        if self.kept.is_empty() {
+            return true;
+        }
+        let text = kept_term_tag().replace_all(&self.text, "");
+        match parse_placeholder_text(&text) {
+            Ok(pieces) => pieces_text(&pieces).chars().any(char::is_alphabetic),
+            Err(_) => true,
+        }
+    }
+
+    /// Replaces the run, or the attribute value, with its translation, mapping the placeholders
+    /// back to the original inline elements. Fails, leaving the document untouched, if the
+    /// placeholders of the translation don't match the ones that were sent.
+    ///
+    /// The translation API trims whitespace, the leading and trailing whitespace of the
+    /// segment and of each `<gN>` is restored as it was sent.
+    pub fn apply(&self, translated: &str) -> Result<(), EpubTranslatorError> {
+        self.write_translation(translated)?;
+        *self.markup.borrow_mut() = Some(translated.to_string());
+        Ok(())
+    }
+
+    fn write_translation(&self, translated: &str) -> Result<(), EpubTranslatorError> {
+        let translated = self.restore_kept_terms(&self.restore_line_breaks(translated))?;
+        let mut pieces = parse_placeholder_text(&translated)?;
+
+        let mut whitespace = HashMap::new();
+        let sent = self.restore_kept_terms(&self.restore_line_breaks(&self.text))?;
+        let sent = parse_placeholder_text(&sent)?;
+        collect_edge_whitespace(&sent, None, &mut whitespace);
+        restore_edge_whitespace(&mut pieces, None, &whitespace);
+
+        let (parent, nodes, placeholders) = match &self.target {
+            Target::Run {
+                parent,
+                nodes,
+                placeholders,
+            } => (parent, nodes, placeholders),
+            Target::Attribute { element, name } => {
+                let translation = plain_text(&pieces)?;
+                set_attribute(element, name, &translation)?;
+                *self.translation.borrow_mut() = Some(translation);
+                return Ok(());
+            }
+            Target::Detached | Target::Unattached { voids: None, .. } => {
+                *self.translation.borrow_mut() = Some(plain_text(&pieces)?);
+                return Ok(());
+            }
+            Target::Unattached {
+                voids: Some(voids), ..
+            } => {
+                check_all_placeholders(voids, &pieces)?;
+                *self.translation.borrow_mut() = Some(pieces_text(&pieces));
+                return Ok(());
+            }
+        };
+
+        let voids: Vec<bool> = placeholders.iter().map(|(_, void)| *void).collect();
+        check_all_placeholders(&voids, &pieces)?;
+
+        let mut old_nodes = nodes.borrow_mut();
+        let start = parent
+            .children
+            .borrow()
+            .iter()
+            .position(|child| Rc::ptr_eq(child, &old_nodes[0]))
+            .ok_or_else(|| {
+                EpubTranslatorError::InvalidTranslation(
+                    "Segment nodes are no longer in the document".to_string(),
+                )
+            })?;
+
+        let translation = pieces_text(&pieces);
+        let new_nodes = build_nodes(placeholders, pieces, parent);
+        parent
+            .children
+            .borrow_mut()
+            .splice(start..start + old_nodes.len(), new_nodes.iter().cloned());
+        *old_nodes = new_nodes;
+        *self.translation.borrow_mut() = Some(translation);
+
+        Ok(())
+    }
+
+    // Puts the kept terms back in place of their `<kN/>`, each one must be found once.
+    fn restore_kept_terms(&self, text: &str) -> Result<String, EpubTranslatorError> {
+        let mut seen = vec![false; self.kept.len()];
+        let mut unexpected = None;
+        let restored = kept_term_tag().replace_all(text, |captures: &regex::Captures| {
+            let id = captures[1].parse::<usize>().ok();
+            match id.filter(|&id| id < seen.len() && !seen[id]) {
+                Some(id) => {
+                    seen[id] = true;
+                    escape_xml(&self.kept[id])
+                }
+                None => {
+                    unexpected = Some(captures[0].to_string());
+                    String::new()
+                }
+            }
+        });
+        if let Some(tag) = unexpected {
+            return Err(EpubTranslatorError::InvalidTranslation(format!(
+                "Unexpected kept term {} in translation",
+                tag
+            )));
+        }
+        if let Some(missing) = seen.iter().position(|seen| !seen) {
+            return Err(EpubTranslatorError::InvalidTranslation(format!(
+                "Kept term {} missing in translation",
+                missing
+            )));
+        }
+        Ok(restored.into_owned())
+    }
+
+    fn restore_line_breaks(&self, text: &str) -> String {
+        static LINE_BREAK_TAG: OnceLock<Regex> = OnceLock::new();
+        if !self.preserve_whitespace {
+            return text.to_string();
+        }
+        let re = LINE_BREAK_TAG.get_or_init(|| Regex::new(r"<lb\s*/>").unwrap());
+        re.replace_all(text, "\n").into_owned()
+    }
+}
+
+type EdgeWhitespace = HashMap<Option<usize>, (String, String)>;
+
+// Leading and trailing whitespace of the segment (`None`) and of each `<gN>` (`Some(N)`).
+fn collect_edge_whitespace(pieces: &[Piece], id: Option<usize>, whitespace: &mut EdgeWhitespace) {
+    let leading = match pieces.first() {
+        Some(Piece::Text(text)) => &text[..text.len() - text.trim_start().len()],
+        _ => "",
+    };
+    let trailing = match pieces.last() {
+        Some(Piece::Text(text)) => &text[text.trim_end().len()..],
+        _ => "",
+    };
+    whitespace.insert(id, (leading.to_string(), trailing.to_string()));
+    for piece in pieces {
+        if let Piece::Element(id, children) = piece {
+            collect_edge_whitespace(children, Some(*id), whitespace);
+        }
+    }
+}
+
+fn restore_edge_whitespace(
+    pieces: &mut Vec<Piece>,
+    id: Option<usize>,
+    whitespace: &EdgeWhitespace,
+) {
+    for piece in pieces.iter_mut() {
+        if let Piece::Element(id, children) = piece {
+            restore_edge_whitespace(children, Some(*id), whitespace);
+        }
+    }
+    let Some((leading, trailing)) = whitespace.get(&id) else {
+        return;
+    };
+
+    match pieces.first_mut() {
+        Some(Piece::Text(text)) => *text = format!("{}{}", leading, text.trim_start()),
+        _ if !leading.is_empty() => pieces.insert(0, Piece::Text(leading.clone())),
+        _ => {}
+    }
+    match pieces.last_mut() {
+        Some(Piece::Text(text)) => *text = format!("{}{}", text.trim_end(), trailing),
+        _ if !trailing.is_empty() => pieces.push(Piece::Text(trailing.clone())),
+        _ => {}
+    }
+}
+
+// Checks that each placeholder sent comes back once, as it was sent: `voids` tells whether each
+// one was sent as `<xN/>`.
+fn check_all_placeholders(voids: &[bool], pieces: &[Piece]) -> Result<(), EpubTranslatorError> {
+    let mut seen = vec![false; voids.len()];
+    check_placeholders(voids, pieces, &mut seen)?;
+    if let Some(missing) = seen.iter().position(|seen| !seen) {
+        return Err(EpubTranslatorError::InvalidTranslation(format!(
+            "Placeholder {} missing in translation",
+            missing
+        )));
+    }
+    Ok(())
+}
+
+fn check_placeholders(
+    voids: &[bool],
+    pieces: &[Piece],
+    seen: &mut [bool],
+) -> Result<(), EpubTranslatorError> {
+    for piece in pieces {
+        let (id, children) = match piece {
+            Piece::Text(_) => continue,
+            Piece::Element(id, children) => (*id, Some(children)),
+            Piece::Void(id) => (*id, None),
+        };
+        let sent_as_void = voids.get(id).copied();
+        if sent_as_void != Some(children.is_none()) || seen[id] {
+            return Err(EpubTranslatorError::InvalidTranslation(format!(
+                "Unexpected placeholder {} in translation",
+                id
+            )));
+        }
+        seen[id] = true;
+        if let Some(children) = children {
+            check_placeholders(voids, children, seen)?;
+        }
+    }
+    Ok(())
+}
+
+fn build_nodes(
+    placeholders: &[(Rc<Node>, bool)],
+    pieces: Vec<Piece>,
+    parent: &Rc<Node>,
+) -> Vec<Rc<Node>> {
+    pieces
+        .into_iter()
+        .map(|piece| {
+            let node = match piece {
+                Piece::Text(text) => Node::new(NodeData::Text {
+                    contents: RefCell::new(StrTendril::from(text)),
+                }),
+                Piece::Element(id, children) => {
+                    let node = placeholders[id].0.clone();
+                    let new_children = build_nodes(placeholders, children, &node);
+                    *node.children.borrow_mut() = new_children;
+                    node
+                }
+                Piece::Void(id) => placeholders[id].0.clone(),
+            };
+            node.parent.set(Some(Rc::downgrade(parent)));
+            node
+        })
+        .collect()
+}
+
+// The translation of a unit sent without placeholders.
+fn plain_text(pieces: &[Piece]) -> Result<String, EpubTranslatorError> {
+    match pieces {
+        [] => Ok(String::new()),
+        [Piece::Text(text)] => Ok(text.clone()),
+        _ => Err(EpubTranslatorError::InvalidTranslation(
+            "Unexpected placeholder in plain text translation".to_string(),
+        )),
+    }
+}
+
+fn set_attribute(element: &Node, name: &str, value: &str) -> Result<(), EpubTranslatorError> {
+    let NodeData::Element { attrs, .. } = &element.data else {
+        return Err(EpubTranslatorError::InvalidTranslation(
+            "Segment attribute is not on an element".to_string(),
+        ));
+    };
+    let mut attrs = attrs.borrow_mut();
+    let attr = attrs
+        .iter_mut()
+        .find(|attr| attr.name.local.as_ref() == name)
+        .ok_or_else(|| {
+            EpubTranslatorError::InvalidTranslation(format!(
+                "Attribute {} is no longer in the document",
+                name
+            ))
+        })?;
+    attr.value = StrTendril::from(value);
+    Ok(())
+}
+
+/// Collects the translation units of a document, in document order.
+/// Content excluded by `options` is never part of a translation unit.
+pub fn get_segments(node: &Rc<Node>, options: &ExtractOptions) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    collect_segments(node, &mut segments, options, &mut None, false);
+    // Units made of kept terms only have nothing to translate
+    segments.retain(Segment::has_unkept_text);
+    store::intern(&mut segments);
+    segments
+}
+
+// `heading` is the last heading seen, in document order. `preserve` tells whether the
+// whitespace of the parent of `node` is significant.
+fn collect_segments(
+    node: &Rc<Node>,
+    segments: &mut Vec<Segment>,
+    options: &ExtractOptions,
+    heading: &mut Option<String>,
+    preserve: bool,
+) {
+    match &node.data {
+        NodeData::Element { .. } if options.is_excluded(node) => {}
+        NodeData::Document | NodeData::Element { .. } => {
+            let preserve = preserves_whitespace(node, preserve);
+            collect_attribute_segments(node, segments, options, heading);
+            let children = node.children.borrow().clone();
+            let mut run = Vec::new();
+            for child in children {
+                if is_inline(&child) {
+                    collect_inline_attribute_segments(&child, segments, options, heading);
+                    run.push(child);
+                } else {
+                    flush_run(node, &mut run, segments, options, heading, preserve);
+                    collect_segments(&child, segments, options, heading, preserve);
+                }
+            }
+            flush_run(node, &mut run, segments, options, heading, preserve);
+
+            if options.heading_context && is_heading(node) {
+                let text = text_content(node);
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !text.is_empty() {
+                    *heading = Some(text);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// `xml:space` takes precedence over the element, `inherited` applies otherwise.
+fn preserves_whitespace(node: &Node, inherited: bool) -> bool {
+    let NodeData::Element { name, attrs, .. } = &node.data else {
+        return inherited;
+    };
+    let xml_space = attrs
+        .borrow()
+        .iter()
+        .find(|attr| attr.name.local.as_ref() == "xml:space")
+        .map(|attr| attr.value.trim() == "preserve");
+    xml_space.unwrap_or(inherited || WHITESPACE_PRESERVING_ELEMENTS.contains(&name.local.as_ref()))
+}
+
+fn is_heading(node: &Node) -> bool {
+    matches!(&node.data, NodeData::Element { name, .. } if matches!(name.local.as_ref(), "h1" | "h2" | "h3"))
+}
+
+fn text_content(node: &Node) -> String {
+    match &node.data {
+        NodeData::Text { contents } => contents.borrow().to_string(),
+        _ => node
+            .children
+            .borrow()
+            .iter()
+            .map(|child| text_content(child))
+            .collect(),
+    }
+}
+
+/// Translated attributes of `node` with translatable text, e.g. `alt` or `title`.
+fn collect_attribute_segments(
+    node: &Rc<Node>,
+    segments: &mut Vec<Segment>,
+    options: &ExtractOptions,
+    heading: &Option<String>,
+) {
+    let NodeData::Element { attrs, .. } = &node.data else {
+        return;
+    };
+    for attr in attrs.borrow().iter() {
+        let name = attr.name.local.as_ref();
+        if options
+            .attributes
+            .iter()
+            .any(|translated| translated == name)
+            && attr.value.chars().any(char::is_alphabetic)
+        {
+            segments.push(Segment::attribute(
+                node,
+                name,
+                &attr.value,
+                heading.clone(),
+                options,
+            ));
+        }
+    }
+}
+
+// Inline elements are part of a run and not visited by `collect_segments`.
+fn collect_inline_attribute_segments(
+    node: &Rc<Node>,
+    segments: &mut Vec<Segment>,
+    options: &ExtractOptions,
+    heading: &Option<String>,
+) {
+    if matches!(node.data, NodeData::Element { .. }) && !options.is_excluded(node) {
+        collect_attribute_segments(node, segments, options, heading);
+        for child in node.children.borrow().iter() {
+            collect_inline_attribute_segments(child, segments, options, heading);
+        }
+    }
+}
+
+fn flush_run(
+    parent: &Rc<Node>,
+    run: &mut Vec<Rc<Node>>,
+    segments: &mut Vec<Segment>,
+    options: &ExtractOptions,
+    heading: &Option<String>,
+    preserve: bool,
+) {
+    if run.iter().any(|node| has_translatable_text(node, options)) {
+        segments.push(Segment::new(
+            parent,
+            std::mem::take(run),
+            options,
+            heading.clone(),
+            preserve,
+        ));
+    } else {
+        run.clear();
+    }
+}
+
+/// Text, comments, and inline elements that only contain inline content.
+fn is_inline(node: &Rc<Node>) -> bool {
+    match &node.data {
+        NodeData::Text { .. } | NodeData::Comment { .. } => true,
+        NodeData::Element { name, .. } => {
+            INLINE_ELEMENTS.contains(&name.local.as_ref())
+                && node.children.borrow().iter().all(is_inline)
+        }
+        _ => false,
+    }
+}
+
+fn has_translatable_text(node: &Rc<Node>, options: &ExtractOptions) -> bool {
+    match &node.data {
+        NodeData::Text { contents } => contents.borrow().chars().any(char::is_alphabetic),
+        NodeData::Element { .. } if options.is_excluded(node) => false,
+        NodeData::Element { .. } => node
+            .children
+            .borrow()
+            .iter()
+            .any(|child| has_translatable_text(child, options)),
+        _ => false,
+    }
+}
+
+fn write_placeholder_text(
+    nodes: &[Rc<Node>],
+    text: &mut String,
+    placeholders: &mut Vec<(Rc<Node>, bool)>,
+    kept: &mut Vec<String>,
+    options: &ExtractOptions,
+) {
+    for node in nodes {
+        if let NodeData::Text { contents } = &node.data {
+            text.push_str(&protect_terms(&contents.borrow(), options, kept));
+            continue;
+        }
+
+        // Dropped ruby: the base text is sent without its element, annotations are not sent
+        if options.ruby == RubyMode::Drop {
+            if let NodeData::Element { name, .. } = &node.data {
+                match name.local.as_ref() {
+                    "ruby" => {
+                        write_placeholder_text(
+                            &node.children.borrow(),
+                            text,
+                            placeholders,
+                            kept,
+                            options,
+                        );
+                        continue;
+                    }
+                    "rt" | "rp" => continue,
+                    _ => {}
+                }
+            }
+        }
+
+        let id = placeholders.len();
+        let void = !(matches!(node.data, NodeData::Element { .. })
+            && has_translatable_text(node, options));
+        placeholders.push((node.clone(), void));
+        if !void {
+            text.push_str(&format!("<g{}>", id));
+            write_placeholder_text(&node.children.borrow(), text, placeholders, kept, options);
+            text.push_str(&format!("</g{}>", id));
+        } else {
+            text.push_str(&format!("<x{}/>", id));
+        }
+    }
+}
+
+// Escapes `text`, the occurrences of the kept terms of `options` are replaced by `<kN/>`, N
+// being their index in `kept`. The longest of the terms found first wins.
+fn protect_terms(text: &str, options: &ExtractOptions, kept: &mut Vec<String>) -> String {
+    let mut protected = String::new();
+    let mut last = 0;
+    while let Some(found) = options
+        .keep_terms
+        .iter()
+        .filter_map(|term| term.find_at(text, last))
+        .filter(|found| !found.is_empty())
+        .min_by_key(|found| (found.start(), Reverse(found.end())))
+    {
+        protected.push_str(&escape_xml(&text[last..found.start()]));
+        protected.push_str(&format!("<k{}/>", kept.len()));
+        kept.push(found.as_str().to_string());
+        last = found.end();
+    }
+    protected.push_str(&escape_xml(&text[last..]));
+    protected
+}
+
+// The text of `pieces`, without placeholders.
+fn pieces_text(pieces: &[Piece]) -> String {
+    pieces
+        .iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text.clone(),
+            Piece::Element(_, children) => pieces_text(children),
+            Piece::Void(_) => String::new(),
+        })
+        .collect()
+}
+
+fn text_chars(pieces: &[Piece]) -> usize {
+    pieces
+        .iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text.chars().count(),
+            Piece::Element(_, children) => text_chars(children),
+            Piece::Void(_) => 0,
+        })
+        .sum()
+}
+
+fn parse_placeholder_text(text: &str) -> Result<Vec<Piece>, EpubTranslatorError> {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let re = PLACEHOLDER.get_or_init(|| Regex::new(r"<(/?)([gx])(\d+)\s*(/?)>").unwrap());
+
+    // Stack of open `<gN>` elements, the bottom one is the segment itself
+    let mut stack: Vec<(Option<usize>, Vec<Piece>)> = vec![(None, Vec::new())];
+    let mut last = 0;
+
+    for captures in re.captures_iter(text) {
+        let tag = captures.get(0).unwrap();
+        let pieces = &mut stack.last_mut().unwrap().1;
+        if tag.start() > last {
+            pieces.push(Piece::Text(unescape_xml(&text[last..tag.start()])));
+        }
+        last = tag.end();
+
+        let id: usize = captures[3].parse().map_err(|_| {
+            EpubTranslatorError::InvalidTranslation(format!(
+                "Malformed placeholder {}",
+                tag.as_str()
+            ))
+        })?;
+        let closing = !captures[1].is_empty();
+        let self_closing = !captures[4].is_empty();
+        match (&captures[2], closing, self_closing) {
+            ("x", false, true) => pieces.push(Piece::Void(id)),
+            ("g", false, false) => stack.push((Some(id), Vec::new())),
+            ("g", true, false) => match stack.pop() {
+                Some((Some(open), children)) if open == id => {
+                    let pieces = &mut stack.last_mut().unwrap().1;
+                    pieces.push(Piece::Element(id, children));
+                }
+                _ => {
+                    return Err(EpubTranslatorError::InvalidTranslation(format!(
+                        "Unbalanced placeholder </g{}>",
+                        id
+                    )))
+                }
+            },
+            _ => {
+                return Err(EpubTranslatorError::InvalidTranslation(format!(
+                    "Malformed placeholder {}",
+                    tag.as_str()
+                )))
+            }
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(EpubTranslatorError::InvalidTranslation(
+            "Unclosed placeholder in translation".to_string(),
+        ));
+    }
+    let mut pieces = stack.pop().unwrap().1;
+    if last < text.len() {
+        pieces.push(Piece::Text(unescape_xml(&text[last..])));
+    }
+    Ok(pieces)
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub(crate) fn unescape_xml(text: &str) -> String {
+    static ENTITY: OnceLock<Regex> = OnceLock::new();
+    let re = ENTITY
+        .get_or_init(|| Regex::new(r"&(amp|lt|gt|quot|apos|#x[0-9a-fA-F]+|#[0-9]+);").unwrap());
+
+    re.replace_all(text, |captures: &regex::Captures| {
+        let entity = &captures[1];
+        match entity {
+            "amp" => "&".to_string(),
+            "lt" => "<".to_string(),
+            "gt" => ">".to_string(),
+            "quot" => "\"".to_string(),
+            "apos" => "'".to_string(),
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity[1..].parse().ok(),
+                };
+                code.and_then(char::from_u32)
+                    .map(String::from)
+                    .unwrap_or_else(|| captures[0].to_string())
+            }
+        }
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xhtml::{get_document_node, serialize_document_to_string};
+
+    #[test]
+    fn test_segments_keep_inline_tags() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<html><body><div><p>It was a <em>dark</em> night &amp; <a href="x">more</a><span id="pg1"/>.</p><p>Second</p></div></body></html>"#,
+        )?;
+
+        let segments = get_segments(&document, &ExtractOptions::default());
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "It was a <g0>dark</g0> night &amp; <g1>more</g1><x2/>.",
+                "Second"
+            ]
+        );
+
+        segments[0].apply("Era una noche <g0>oscura</g0> &amp; <g1>más</g1><x2/>.")?;
+        segments[1].apply("Segundo")?;
+
+        let output = serialize_document_to_string(&document)?;
+        assert!(output.contains(
+            r#"<p>Era una noche <em>oscura</em> &amp; <a href="x">más</a><span id="pg1"/>.</p><p>Segundo</p>"#
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_excluded_inline_element_is_kept_verbatim() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<p>He said <i translate="no">carpe diem</i> twice.</p><p class="notranslate">Skip</p>"#,
+        )?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].text, "He said <x0/> twice.");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_attributes_are_segments() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<p title="Intro">See <img src="a.png" alt="A lighthouse"/> <span aria-label="Note" id="n1"/></p><img alt="12"/>"#,
+        )?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["Intro", "A lighthouse", "Note", "See <x0/> <x1/>"]
+        );
+
+        segments[0].apply("Introducción")?;
+        segments[1].apply("Un faro")?;
+        assert!(segments[2].apply("Nota <x0/>").is_err());
+
+        let output = serialize_document_to_string(&document)?;
+        assert!(output.contains(
+            r#"<p title="Introducción">See <img src="a.png" alt="Un faro"/> <span aria-label="Note" id="n1"/></p>"#
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_restores_edge_whitespace() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node("<p>\n  Read <i>this </i>and<i> that</i>\n</p>")?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+        assert_eq!(
+            segments[0].text,
+            "\n  Read <g0>this </g0>and<g1> that</g1>\n"
+        );
+
+        segments[0].apply("Lee <g0>esto</g0>y<g1>aquello</g1>")?;
+
+        let output = serialize_document_to_string(&document)?;
+        assert!(output.contains("<p>\n  Lee <i>esto </i>y<i> aquello</i>\n</p>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ruby_modes() -> Result<(), Box<dyn std::error::Error>> {
+        let input = "<p>私は<ruby>漢<rp>(</rp><rt>かん</rt><rp>)</rp>字</ruby>が好き</p>";
+
+        let document = get_document_node(input)?;
+        let options = ExtractOptions {
+            ruby: RubyMode::Drop,
+            ..Default::default()
+        };
+        let segments = get_segments(&document, &options);
+        assert_eq!(segments[0].text, "私は漢字が好き");
+        segments[0].apply("I like kanji")?;
+        assert!(serialize_document_to_string(&document)?.contains("<p>I like kanji</p>"));
+
+        let document = get_document_node(input)?;
+        let options = ExtractOptions {
+            ruby: RubyMode::Preserve,
+            ..Default::default()
+        };
+        let segments = get_segments(&document, &options);
+        assert_eq!(segments[0].text, "私は<g0>漢<x1/><x2/><x3/>字</g0>が好き");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_heading_context() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            "<body><p>Preface</p><h2>The <em>Storm</em></h2><p>It rained.</p><div><h3>Aftermath</h3></div><p>Calm.</p></body>",
+        )?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+        let contexts: Vec<Option<&str>> = segments.iter().map(|s| s.context.as_deref()).collect();
+        assert_eq!(
+            contexts,
+            vec![
+                None,
+                None,
+                Some("The Storm"),
+                Some("The Storm"),
+                Some("Aftermath")
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preserved_whitespace() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            "<body><pre>First line\n  second <b>line</b></pre><div xml:space=\"preserve\"><p xml:space=\"default\">A\nB</p><p>C\nD</p></div></body>",
+        )?;
+        let options = ExtractOptions {
+            skip_tags: Vec::new(),
+            ..Default::default()
+        };
+        let segments = get_segments(&document, &options);
+        let sent: Vec<(&str, bool)> = segments
+            .iter()
+            .map(|s| (s.text.as_str(), s.preserve_whitespace))
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                ("First line<lb/>  second <g0>line</g0>", true),
+                ("A\nB", false),
+                ("C<lb/>D", true)
+            ]
+        );
+
+        segments[0].apply("Primera línea <lb /> segunda <g0>línea</g0>")?;
+        let output = serialize_document_to_string(&document)?;
+        assert!(output.contains("<pre>Primera línea \n segunda <b>línea</b></pre>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_rejects_broken_placeholders() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node("<p>A <b>bold</b> move<br/></p>")?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+        assert_eq!(segments[0].text, "A <g0>bold</g0> move<x1/>");
+
+        assert!(segments[0].apply("Un <g0>audaz</g0> movimiento").is_err());
+        assert!(segments[0].apply("Un <g0>audaz movimiento<x1/>").is_err());
+        assert!(segments[0]
+            .apply("Un <g0>audaz</g0> <g0>x</g0><x1/>")
+            .is_err());
+        assert!(segments[0].apply("Un <x0/> movimiento<x1/>").is_err());
+
+        // Nothing has been modified by the failed attempts
+        let output = serialize_document_to_string(&document)?;
+        assert!(output.contains("<p>A <b>bold</b> move<br/></p>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_terms() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            "<p title=\"Frodo\">Frodo met <i>Sam &amp; Frodo</i> in Hobbiton.</p>",
+        )?;
+        let options = ExtractOptions {
+            keep_terms: crate::terms::parse_keep_terms("Frodo\n/Hobbit\\w*/\n")?,
+            ..Default::default()
+        };
+        let segments = get_segments(&document, &options);
+        // The title is nothing but a kept term, it is not sent
+        assert_eq!(segments.len(), 1);
+        assert_eq!(
+            segments[0].text,
+            "<k0/> met <g0>Sam &amp; <k1/></g0> in <k2/>."
+        );
+        assert_eq!(segments[0].billable_chars(), 16);
+        assert_eq!(
+            segments[0].source_text(),
+            "Frodo met Sam & Frodo in Hobbiton."
+        );
+
+        // Every kept term must come back once
+        assert!(segments[0]
+            .apply("<k0/> conoció a <g0>Sam y</g0> en <k2/>.")
+            .is_err());
+        assert!(segments[0]
+            .apply("<k0/> conoció a <g0>Sam y <k1/></g0> en <k2/><k2/>.")
+            .is_err());
+        segments[0].apply("<k0/> conoció a <g0>Sam y <k1/></g0> en <k2/>.")?;
+        let output = serialize_document_to_string(&document)?;
+        assert!(output.contains("Frodo conoció a <i>Sam y Frodo</i> en Hobbiton."));
+        Ok(())
+    }
+}
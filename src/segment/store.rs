@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use super::Segment;
+
+/// A text of a segment, a range of a buffer shared by the segments of its file. Cloning it
+/// shares the buffer, it can be sent to the translation tasks as it is.
+#[derive(Clone)]
+pub struct SharedText {
+    buffer: Arc<str>,
+    range: Range<usize>,
+}
+
+impl SharedText {
+    /// The text, borrowed from the buffer.
+    pub fn as_str(&self) -> &str {
+        &self.buffer[self.range.clone()]
+    }
+}
+
+impl From<String> for SharedText {
+    fn from(text: String) -> Self {
+        let range = 0..text.len();
+        Self {
+            buffer: text.into(),
+            range,
+        }
+    }
+}
+
+impl Deref for SharedText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for SharedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl fmt::Debug for SharedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl PartialEq for SharedText {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<&str> for SharedText {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+/// Moves the texts and the contexts of `segments` to a single buffer, each distinct one once.
+/// A heading is the context of every segment under it and short texts repeat, the buffer is
+/// much smaller than the texts and contexts it replaces.
+pub(crate) fn intern(segments: &mut [Segment]) {
+    let mut buffer = String::new();
+    let mut ranges = Vec::with_capacity(segments.len());
+    let mut interned: HashMap<&str, Range<usize>> = HashMap::new();
+    let mut push = |text| {
+        interned
+            .entry(text)
+            .or_insert_with(|| {
+                buffer.push_str(text);
+                buffer.len() - text.len()..buffer.len()
+            })
+            .clone()
+    };
+    for segment in segments.iter() {
+        let context = segment.context.as_deref().map(&mut push);
+        ranges.push((push(&segment.text), context));
+    }
+    let buffer: Arc<str> = buffer.into();
+    let shared = |range| SharedText {
+        buffer: buffer.clone(),
+        range,
+    };
+    for (segment, (text, context)) in segments.iter_mut().zip(ranges) {
+        segment.text = shared(text);
+        segment.context = context.map(shared);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::get_segments;
+    use crate::xhtml::{get_document_node, ExtractOptions};
+
+    #[test]
+    fn test_intern() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            "<html><body><h1>One</h1><p>Fin</p><p>Two</p><p>Fin</p><p>One</p></body></html>",
+        )?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, vec!["One", "Fin", "Two", "Fin", "One"]);
+        let contexts: Vec<Option<&str>> = segments.iter().map(|s| s.context.as_deref()).collect();
+        assert_eq!(
+            contexts,
+            vec![None, Some("One"), Some("One"), Some("One"), Some("One")]
+        );
+
+        // Each distinct text once, in a buffer shared by every segment of the document
+        let buffer = &segments[0].text.buffer;
+        assert_eq!(&**buffer, "OneFinTwo");
+        assert!(segments.iter().all(|s| Arc::ptr_eq(&s.text.buffer, buffer)));
+        assert_eq!(segments[1].text.range, segments[3].text.range);
+        assert_eq!(
+            segments[0].text.range,
+            segments[1].context.as_ref().unwrap().range
+        );
+        Ok(())
+    }
+}
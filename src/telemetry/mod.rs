@@ -0,0 +1,286 @@
+//! Metrics of the translations, recorded through the `metrics` facade: nothing is recorded
+//! until a recorder is installed, e.g. a Prometheus exporter, or `SummaryRecorder` to print
+//! the totals of a run.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use metrics::{
+    counter, describe_counter, describe_histogram, histogram, Counter, Gauge, Histogram,
+    HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+};
+use serde::Serialize;
+
+use crate::deepl::models::AuthKey;
+
+/// Counter of the requests sent to DeepL, labeled with the `key` they were sent with, its
+/// last characters.
+pub const REQUESTS: &str = "epub_translator_requests_total";
+/// Histogram of the durations of the requests sent to DeepL, in seconds, labeled with `key`.
+pub const REQUEST_DURATION: &str = "epub_translator_request_duration_seconds";
+/// Counter of the requests that failed, labeled with `key` and the `kind` of error: `status`,
+/// `quota_exceeded`, `network` or `invalid_response`.
+pub const ERRORS: &str = "epub_translator_errors_total";
+/// Counter of the units sent again after a failed attempt.
+pub const RETRIES: &str = "epub_translator_retries_total";
+/// Counter of the billable characters sent to DeepL, retries included.
+pub const BILLED_CHARACTERS: &str = "epub_translator_billed_characters_total";
+
+/// Describes the metrics of the library to the installed recorder.
+pub fn describe() {
+    describe_counter!(REQUESTS, "Requests sent to DeepL");
+    describe_histogram!(
+        REQUEST_DURATION,
+        Unit::Seconds,
+        "Duration of the DeepL requests"
+    );
+    describe_counter!(ERRORS, "DeepL requests that failed");
+    describe_counter!(RETRIES, "Units sent again after a failed attempt");
+    describe_counter!(
+        BILLED_CHARACTERS,
+        Unit::Count,
+        "Billable characters sent to DeepL"
+    );
+}
+
+// Records a request sent with `key`, `error` is the kind of its failure.
+pub(crate) fn record_request(key: &AuthKey, duration: Duration, error: Option<&'static str>) {
+    let key = key.hint();
+    counter!(REQUESTS, "key" => key.clone()).increment(1);
+    histogram!(REQUEST_DURATION, "key" => key.clone()).record(duration.as_secs_f64());
+    if let Some(kind) = error {
+        counter!(ERRORS, "key" => key, "kind" => kind).increment(1);
+    }
+}
+
+pub(crate) fn record_retry() {
+    counter!(RETRIES).increment(1);
+}
+
+pub(crate) fn record_billed(characters: usize) {
+    counter!(BILLED_CHARACTERS).increment(characters as u64);
+}
+
+/// A recorder keeping the metrics in memory, to sum them up at the end of a run. Clones share
+/// the same metrics, so one can be installed while another one is kept for `summary`.
+///
+/// ```
+/// # use epub_translator::telemetry::SummaryRecorder;
+/// let recorder = SummaryRecorder::default();
+/// metrics::set_global_recorder(recorder.clone()).unwrap();
+/// // ... translate ...
+/// println!("{}", recorder.summary());
+/// ```
+#[derive(Clone, Default)]
+pub struct SummaryRecorder {
+    counters: Arc<Mutex<HashMap<Key, Arc<AtomicU64>>>>,
+    histograms: Arc<Mutex<HashMap<Key, Arc<Samples>>>>,
+}
+
+#[derive(Default)]
+struct Samples(Mutex<Vec<f64>>);
+
+impl HistogramFn for Samples {
+    fn record(&self, value: f64) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(value);
+    }
+}
+
+impl Recorder for SummaryRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        Counter::from_arc(counters.entry(key.clone()).or_default().clone())
+    }
+
+    // The library records no gauge
+    fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::noop()
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        let mut histograms = self
+            .histograms
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Histogram::from_arc(histograms.entry(key.clone()).or_default().clone())
+    }
+}
+
+impl SummaryRecorder {
+    /// The totals of the metrics of the library recorded so far.
+    pub fn summary(&self) -> MetricsSummary {
+        let mut summary = MetricsSummary::default();
+        let counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        for (key, value) in counters.iter() {
+            let value = value.load(Ordering::Relaxed);
+            let label = |name: &str| {
+                key.labels()
+                    .find(|label| label.key() == name)
+                    .map(|label| label.value().to_string())
+                    .unwrap_or_default()
+            };
+            match key.name() {
+                REQUESTS => *summary.requests.entry(label("key")).or_default() += value,
+                ERRORS => *summary.errors.entry(label("kind")).or_default() += value,
+                RETRIES => summary.retries += value,
+                BILLED_CHARACTERS => summary.billed_characters += value,
+                _ => {}
+            }
+        }
+
+        let histograms = self
+            .histograms
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut durations: Vec<f64> = histograms
+            .iter()
+            .filter(|(key, _)| key.name() == REQUEST_DURATION)
+            .flat_map(|(_, samples)| {
+                samples
+                    .0
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone()
+            })
+            .collect();
+        durations.sort_by(f64::total_cmp);
+        summary.latency = LatencySummary::of(&durations);
+        summary
+    }
+
+    /// Forgets the metrics recorded so far, e.g. between the books of a run.
+    pub fn clear(&self) {
+        let counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        for value in counters.values() {
+            value.store(0, Ordering::Relaxed);
+        }
+        let histograms = self
+            .histograms
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for samples in histograms.values() {
+            samples
+                .0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+    }
+}
+
+/// The totals of a run, see `SummaryRecorder`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetricsSummary {
+    /// Requests sent to DeepL, by the last characters of their key.
+    pub requests: BTreeMap<String, u64>,
+    /// Requests that failed, by kind of error, see `ERRORS`.
+    pub errors: BTreeMap<String, u64>,
+    pub retries: u64,
+    pub billed_characters: u64,
+    /// `None` when no request was sent.
+    pub latency: Option<LatencySummary>,
+}
+
+/// Durations of the requests, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    // `None` when `sorted` is empty.
+    fn of(sorted: &[f64]) -> Option<Self> {
+        let max = *sorted.last()?;
+        let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+        Some(Self {
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            max,
+        })
+    }
+}
+
+impl MetricsSummary {
+    /// Requests sent to DeepL, with every key.
+    pub fn total_requests(&self) -> u64 {
+        self.requests.values().sum()
+    }
+}
+
+impl fmt::Display for MetricsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys: Vec<String> = self
+            .requests
+            .iter()
+            .map(|(key, count)| format!("key ending in {}: {}", key, count))
+            .collect();
+        write!(
+            f,
+            "{} requests ({}), {} failed, {} retries, {} billable characters",
+            self.total_requests(),
+            keys.join(", "),
+            self.errors.values().sum::<u64>(),
+            self.retries,
+            self.billed_characters
+        )?;
+        if let Some(latency) = &self.latency {
+            write!(
+                f,
+                "\nLatency: mean {:.0} ms, p50 {:.0} ms, p95 {:.0} ms, max {:.0} ms",
+                latency.mean * 1000.0,
+                latency.p50 * 1000.0,
+                latency.p95 * 1000.0,
+                latency.max * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_recorder() {
+        let recorder = SummaryRecorder::default();
+        metrics::with_local_recorder(&recorder, || {
+            let key = AuthKey::new("0123-abcd:fx");
+            record_request(&key, Duration::from_millis(100), None);
+            record_request(&key, Duration::from_millis(300), Some("status"));
+            record_request(&"other-wxyz".into(), Duration::from_millis(200), None);
+            record_retry();
+            record_billed(42);
+        });
+
+        let summary = recorder.summary();
+        assert_eq!(summary.total_requests(), 3);
+        assert_eq!(summary.requests.get("abcd"), Some(&2));
+        assert_eq!(summary.errors.get("status"), Some(&1));
+        assert_eq!((summary.retries, summary.billed_characters), (1, 42));
+        let latency = summary.latency.unwrap();
+        assert_eq!((latency.p50, latency.max), (0.2, 0.3));
+        assert!(summary.to_string().starts_with(
+            "3 requests (key ending in abcd: 2, key ending in wxyz: 1), 1 failed, 1 retries"
+        ));
+        recorder.clear();
+        assert_eq!(recorder.summary().total_requests(), 0);
+        assert_eq!(recorder.summary().latency, None);
+    }
+}
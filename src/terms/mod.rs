@@ -0,0 +1,209 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+use crate::config::ConfigError;
+use crate::error::EpubTranslatorError;
+use crate::segment::escape_xml;
+
+mod names;
+pub use names::detect_names;
+
+/// Parses a list of terms kept untranslated, one per line. A line between slashes, e.g.
+/// `/Hogw\w+/`, is a regular expression, any other line a literal term matched as a whole
+/// word, case included. Blank lines and lines starting with `#` are skipped.
+pub fn parse_keep_terms(content: &str) -> Result<Vec<Regex>, String> {
+    let mut terms = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", index + 1, message);
+        let pattern = match line
+            .strip_prefix('/')
+            .and_then(|line| line.strip_suffix('/'))
+        {
+            Some(pattern) => pattern.to_string(),
+            None => literal_pattern(line),
+        };
+        let term = Regex::new(&pattern).map_err(|e| error(e.to_string()))?;
+        if term.is_match("") {
+            return Err(error(format!("`{}` matches empty text", line)));
+        }
+        terms.push(term);
+    }
+    Ok(terms)
+}
+
+// Matches `term` as a whole word: word characters at its edges must not be followed or
+// preceded by other ones.
+fn literal_pattern(term: &str) -> String {
+    let boundary = |c: Option<char>| match c {
+        Some(c) if c.is_alphanumeric() || c == '_' => r"\b",
+        _ => "",
+    };
+    format!(
+        "{}{}{}",
+        boundary(term.chars().next()),
+        regex::escape(term),
+        boundary(term.chars().last())
+    )
+}
+
+/// A kept term matching `term` as a whole word, case included, like a line of a terms file.
+pub fn keep_term(term: &str) -> Regex {
+    Regex::new(&literal_pattern(term)).expect("an escaped term is a valid pattern")
+}
+
+/// Reads the terms file at `path`, see `parse_keep_terms`.
+pub fn read_keep_terms(path: &Path) -> Result<Vec<Regex>, EpubTranslatorError> {
+    let content = fs::read_to_string(path)?;
+    parse_keep_terms(&content).map_err(|message| config_error(path, message))
+}
+
+fn config_error(path: &Path, message: String) -> EpubTranslatorError {
+    ConfigError {
+        path: path.to_path_buf(),
+        message,
+    }
+    .into()
+}
+
+/// Replacements of target language terms, applied to every translation whatever the
+/// provider. Each entry is a term and its replacement, matched as a whole word.
+///
+/// A term written in lowercase matches whatever its case, and the replacement takes the case
+/// of the match: capitalized at the start of a sentence, uppercase in an uppercase heading. A
+/// term with uppercase letters only matches as it is written.
+#[derive(Debug, Clone, Default)]
+pub struct Glossary {
+    entries: Vec<(Regex, String)>,
+}
+
+impl Glossary {
+    /// Parses a CSV glossary without header, with a `term,replacement` record per line. Lines
+    /// starting with `#` are skipped.
+    pub fn parse_csv(content: &str) -> Result<Self, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes());
+        let mut entries = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            let line = record.position().map_or(0, |position| position.line());
+            let (Some(term), Some(replacement), None) =
+                (record.get(0), record.get(1), record.get(2))
+            else {
+                return Err(format!("line {}: expected `term,replacement`", line));
+            };
+            if term.is_empty() {
+                return Err(format!("line {}: empty term", line));
+            }
+            let case_insensitive = term == term.to_lowercase();
+            let pattern = format!(
+                "{}{}",
+                if case_insensitive { "(?i)" } else { "" },
+                literal_pattern(&escape_xml(term))
+            );
+            let term = Regex::new(&pattern).map_err(|e| format!("line {}: {}", line, e))?;
+            entries.push((term, escape_xml(replacement)));
+        }
+        Ok(Self { entries })
+    }
+
+    /// Reads the CSV glossary at `path`, see `parse_csv`.
+    pub fn read(path: &Path) -> Result<Self, EpubTranslatorError> {
+        let content = fs::read_to_string(path)?;
+        Self::parse_csv(&content).map_err(|message| config_error(path, message))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Applies the replacements to a translation as it comes back from the API: XML escaped,
+    /// with the placeholders of the segment, which are left untouched.
+    pub fn apply(&self, translation: &str) -> String {
+        static TAG: OnceLock<Regex> = OnceLock::new();
+        if self.is_empty() {
+            return translation.to_string();
+        }
+        let tag = TAG.get_or_init(|| Regex::new(r"<[^>]*>").unwrap());
+        let mut output = String::new();
+        let mut last = 0;
+        for found in tag.find_iter(translation) {
+            output.push_str(&self.replace(&translation[last..found.start()]));
+            output.push_str(found.as_str());
+            last = found.end();
+        }
+        output.push_str(&self.replace(&translation[last..]));
+        output
+    }
+
+    fn replace(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (term, replacement) in &self.entries {
+            text = term
+                .replace_all(&text, |captures: &Captures| {
+                    with_case_of(replacement, &captures[0])
+                })
+                .into_owned();
+        }
+        text
+    }
+}
+
+// `replacement` in uppercase when `found` is, capitalized when `found` is.
+fn with_case_of(replacement: &str, found: &str) -> String {
+    let letters = || found.chars().filter(|c| c.is_alphabetic());
+    if letters().count() > 1 && letters().all(char::is_uppercase) {
+        return replacement.to_uppercase();
+    }
+    let mut chars = replacement.chars();
+    match (found.chars().next(), chars.next()) {
+        (Some(first), Some(start)) if first.is_uppercase() => {
+            start.to_uppercase().chain(chars).collect()
+        }
+        _ => replacement.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keep_terms() -> Result<(), String> {
+        let terms = parse_keep_terms("# Names\nFrodo\n\n/Hobbit(s|on)/\nC++\n")?;
+        assert_eq!(terms.len(), 3);
+        assert!(terms[0].is_match("Frodo left"));
+        assert!(!terms[0].is_match("Frodos"));
+        assert!(terms[1].is_match("Hobbiton"));
+        assert!(terms[2].is_match("in C++ code"));
+
+        assert!(parse_keep_terms("/(/").unwrap_err().starts_with("line 1"));
+        assert!(parse_keep_terms("ok\n/a*/").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_glossary() -> Result<(), String> {
+        let glossary =
+            Glossary::parse_csv("# Spanish\ncomarca,cuaderna\nSméagol,Sméagol el Gollum\n")?;
+        assert_eq!(
+            glossary.apply("La comarca. <g0>Comarcas y COMARCA</g0><x1/> Comarca"),
+            "La cuaderna. <g0>Comarcas y CUADERNA</g0><x1/> Cuaderna"
+        );
+        assert_eq!(
+            glossary.apply("sméagol y Sméagol"),
+            "sméagol y Sméagol el Gollum"
+        );
+        assert!(Glossary::parse_csv("a,b,c\n").is_err());
+        Ok(())
+    }
+}
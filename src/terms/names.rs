@@ -0,0 +1,121 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+// Times a name must be seen in the middle of a sentence
+const MIN_NAME_COUNT: usize = 3;
+
+// Capitalized words that are not names, or are translated along with the names they precede:
+// titles, days and months. A sequence of capitalized words is split at them.
+const NOT_NAMES: &str = "Mr Mrs Ms Miss Dr Sir Madam Lady Lord Captain Colonel General \
+    Professor Saint St King Queen Prince Princess Uncle Aunt Father Mother God Monday Tuesday \
+    Wednesday Thursday Friday Saturday Sunday January February March April May June July \
+    August September October November December";
+
+// Punctuation ending a sentence, after which a capital says nothing
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', '…', ':', ';', '。', '！', '？'];
+// Quotes, brackets and dashes that may open a sentence
+const OPENINGS: &[char] = &[
+    '"', '\'', '“', '”', '‘', '’', '«', '»', '„', '(', '[', '—', '–', '-', '¡', '¿',
+];
+
+/// Finds the names of `texts`, e.g. the units of a book: sequences of capitalized words seen
+/// at least `MIN_NAME_COUNT` times in the middle of a sentence, where a capital is no sign of
+/// a name. A single word also seen in lowercase is not a name, e.g. `Will`, and texts without
+/// lowercase words, e.g. headings, are left out. Sorted by decreasing count.
+///
+/// Meant for languages writing common nouns in lowercase, not for German.
+pub fn detect_names<'a>(texts: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    static WORD: OnceLock<Regex> = OnceLock::new();
+    let word =
+        WORD.get_or_init(|| Regex::new(r"\p{L}[\p{L}\p{M}]*(?:-\p{L}[\p{L}\p{M}]*)*").unwrap());
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut lowercase: HashSet<String> = HashSet::new();
+    for text in texts {
+        // Headings like `Chapter One` have capitals only, they say nothing
+        if !word
+            .find_iter(text)
+            .any(|found| found.as_str().starts_with(char::is_lowercase))
+        {
+            continue;
+        }
+        // The capitalized words being read, and whether the first one starts a sentence. That
+        // one is left out, the others may be a name, `So Jane Bennet`
+        let mut sequence: Vec<&str> = Vec::new();
+        let mut at_start = false;
+        let mut end = 0;
+        let mut flush = |sequence: &mut Vec<&str>, at_start: bool| {
+            let name = &sequence[usize::from(at_start).min(sequence.len())..];
+            if !name.is_empty() {
+                *counts.entry(name.join(" ")).or_default() += 1;
+            }
+            sequence.clear();
+        };
+        for found in word.find_iter(text) {
+            let gap = &text[end..found.start()];
+            end = found.end();
+            let word = found.as_str();
+            if !is_capitalized(word) {
+                lowercase.insert(word.to_lowercase());
+                flush(&mut sequence, at_start);
+                continue;
+            }
+            if NOT_NAMES.split_whitespace().any(|title| title == word) {
+                flush(&mut sequence, at_start);
+                continue;
+            }
+            let joined = gap == " " && !sequence.is_empty();
+            if !joined {
+                flush(&mut sequence, at_start);
+                at_start = starts_sentence(&text[..found.start()]);
+            }
+            sequence.push(word);
+        }
+        flush(&mut sequence, at_start);
+    }
+
+    let mut names: Vec<(String, usize)> = counts
+        .into_iter()
+        .filter(|(name, count)| {
+            *count >= MIN_NAME_COUNT
+                && (name.contains(' ') || !lowercase.contains(&name.to_lowercase()))
+        })
+        .collect();
+    names.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    names.into_iter().map(|(name, _)| name).collect()
+}
+
+// An uppercase letter followed by at least one lowercase one, `NASA` and `I` are left out.
+fn is_capitalized(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(char::is_uppercase) && chars.any(char::is_lowercase)
+}
+
+// Whether a word after `before` starts a sentence.
+fn starts_sentence(before: &str) -> bool {
+    let before = before.trim_end_matches(|c: char| c.is_whitespace() || OPENINGS.contains(&c));
+    before.is_empty() || before.ends_with(SENTENCE_ENDS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_names() {
+        let texts = [
+            "It is a truth universally acknowledged, said Elizabeth, and Mr Darcy left.",
+            "They met Elizabeth at Netherfield Park. Will Mr Darcy dance? He said he will.",
+            "“Elizabeth,” said Jane Bennet, “is at Netherfield Park with Mr Darcy on Monday.”",
+            "So Jane Bennet wrote to Elizabeth. Will she go to Netherfield Park with Jane Bennet?",
+            "Chapter One",
+            "Chapter One",
+            "Chapter One",
+        ];
+        assert_eq!(
+            detect_names(texts),
+            vec!["Darcy", "Elizabeth", "Jane Bennet", "Netherfield Park"]
+        );
+    }
+}
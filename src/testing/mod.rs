@@ -0,0 +1,307 @@
+//! Golden-file checks of the round trip of a book: translated with the identity provider, an
+//! EPUB must come out with the same structure and text, and still be valid. The serialized
+//! documents can be kept as snapshots, so a problematic book contributed as a fixture keeps
+//! coming out the same.
+//!
+//! ```no_run
+//! # async fn check() -> Result<(), epub_translator::error::EpubTranslatorError> {
+//! use std::path::Path;
+//! epub_translator::testing::assert_golden_dir(
+//!     Path::new("tests/data/golden/books"),
+//!     Path::new("tests/data/golden/snapshots"),
+//! )
+//! .await
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use markup5ever_rcdom::{Node, NodeData};
+
+use crate::epub::{
+    content_document_paths, validate_epub_bytes, zip_folder_to_epub, BookArchive, BookFiles,
+    Package, UnzipLimits, ValidationIssue,
+};
+use crate::error::EpubTranslatorError;
+use crate::options::TranslateOptions;
+use crate::providers::Provider;
+use crate::translate_epub_bytes;
+use crate::xhtml::{decode_xhtml, get_document_node};
+
+/// Set to rewrite the snapshots instead of comparing the documents to them, e.g.
+/// `EPUB_TRANSLATOR_UPDATE_SNAPSHOTS=1 cargo test`.
+pub const UPDATE_SNAPSHOTS_VAR: &str = "EPUB_TRANSLATOR_UPDATE_SNAPSHOTS";
+
+/// A book translated with the identity provider.
+#[derive(Debug, Clone)]
+pub struct RoundTrip {
+    /// The translated EPUB.
+    pub output: Vec<u8>,
+    /// How the translation differs from the book, then what `validate_epub_bytes` found in
+    /// it. Empty when the book came out fine.
+    pub issues: Vec<ValidationIssue>,
+}
+
+/// Translates `input` with the identity provider and compares the translation to it. Content
+/// documents must keep their elements, attributes and text, `lang` and `xml:lang` aside, the
+/// package document its manifest and spine, and every other file its bytes.
+pub async fn identity_round_trip(input: Vec<u8>) -> Result<RoundTrip, EpubTranslatorError> {
+    let options = TranslateOptions {
+        target_lang: "EN".to_string(),
+        provider: Provider::Identity,
+        same_language: true,
+        ..TranslateOptions::default()
+    };
+    let (output, _) = translate_epub_bytes(input.clone(), &options).await?;
+
+    let limits = UnzipLimits::default();
+    let source = BookArchive::read(input, &limits)?;
+    let translated = BookArchive::read(output.clone(), &limits)?;
+    let mut issues = compare_books(&source, &translated)?;
+    issues.extend(validate_epub_bytes(output.clone())?);
+    Ok(RoundTrip { output, issues })
+}
+
+/// Reads a fixture: an EPUB, or a folder with an extracted one, zipped on the fly.
+pub fn read_fixture(path: &Path) -> Result<Vec<u8>, EpubTranslatorError> {
+    if !path.is_dir() {
+        return Ok(fs::read(path)?);
+    }
+    let temp_dir = tempfile::tempdir()?;
+    let epub_path = temp_dir.path().join("fixture.epub");
+    zip_folder_to_epub(path, &epub_path)?;
+    Ok(fs::read(epub_path)?)
+}
+
+/// Compares the content documents of `epub` to their snapshots in `dir`, by path in the book.
+/// Missing snapshots are written, and so is every snapshot when `update` is set. Returns the
+/// documents that differ from their snapshot.
+pub fn check_snapshots(
+    epub: Vec<u8>,
+    dir: &Path,
+    update: bool,
+) -> Result<Vec<ValidationIssue>, EpubTranslatorError> {
+    let book = BookArchive::read(epub, &UnzipLimits::default())?;
+    let mut issues = Vec::new();
+    for path in content_document_paths(&book)? {
+        let document = decode_xhtml(&book.read(&path)?)?;
+        let snapshot_path = dir.join(&path);
+        match fs::read_to_string(&snapshot_path) {
+            Ok(snapshot) if !update => {
+                if let Some(message) = first_difference(&snapshot, &document) {
+                    issues.push(ValidationIssue {
+                        path: Some(path),
+                        message: format!("differs from its snapshot: {}", message),
+                    });
+                }
+            }
+            _ => {
+                if let Some(parent) = snapshot_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&snapshot_path, document)?;
+            }
+        }
+    }
+    Ok(issues)
+}
+
+/// Runs the round trip of the fixture at `path`, see `read_fixture`, and checks its snapshots
+/// in `snapshot_dir`, rewritten when `UPDATE_SNAPSHOTS_VAR` is set.
+///
+/// # Panics
+///
+/// When the book does not come out fine, with every issue found.
+pub async fn assert_golden(path: &Path, snapshot_dir: &Path) -> Result<(), EpubTranslatorError> {
+    let round_trip = identity_round_trip(read_fixture(path)?).await?;
+    let update = env::var_os(UPDATE_SNAPSHOTS_VAR).is_some();
+    let mut issues = round_trip.issues;
+    issues.extend(check_snapshots(round_trip.output, snapshot_dir, update)?);
+    let issues: Vec<String> = issues.iter().map(ToString::to_string).collect();
+    assert!(
+        issues.is_empty(),
+        "{}:\n{}",
+        path.display(),
+        issues.join("\n")
+    );
+    Ok(())
+}
+
+/// Runs `assert_golden` on every fixture of `books`, EPUBs and folders. The snapshots of each
+/// one are in the folder of its name in `snapshots`, without the `.epub` extension.
+pub async fn assert_golden_dir(books: &Path, snapshots: &Path) -> Result<(), EpubTranslatorError> {
+    let mut fixtures: Vec<PathBuf> = fs::read_dir(books)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<_, std::io::Error>>()?;
+    fixtures.sort();
+    for fixture in fixtures {
+        let is_epub = fixture.extension().is_some_and(|ext| ext == "epub");
+        if !fixture.is_dir() && !is_epub {
+            continue;
+        }
+        let name = fixture.file_stem().unwrap_or_default();
+        assert_golden(&fixture, &snapshots.join(name)).await?;
+    }
+    Ok(())
+}
+
+fn compare_books(
+    source: &BookArchive,
+    translated: &BookArchive,
+) -> Result<Vec<ValidationIssue>, EpubTranslatorError> {
+    let issue = |path: &Path, message: String| ValidationIssue {
+        path: Some(path.to_path_buf()),
+        message,
+    };
+    let mut issues = Vec::new();
+    let documents = content_document_paths(source)?;
+    let package = Package::open(source).ok();
+
+    let mut paths = source.paths();
+    paths.sort();
+    let mut translated_paths = translated.paths();
+    translated_paths.sort();
+    for path in translated_paths.iter().filter(|path| !paths.contains(path)) {
+        issues.push(issue(path, "added by the translation".to_string()));
+    }
+    for path in &paths {
+        if !translated.is_file(path) {
+            issues.push(issue(path, "lost in the translation".to_string()));
+            continue;
+        }
+        let (before, after) = (source.read(path)?, translated.read(path)?);
+        let difference = if documents.contains(path) {
+            let before = document_tokens(&decode_xhtml(&before)?)?;
+            let after = document_tokens(&decode_xhtml(&after)?)?;
+            first_token_difference(&before, &after)
+        } else if package
+            .as_ref()
+            .is_some_and(|package| package.path == *path)
+        {
+            // The language and the provenance of the book are meant to change
+            let before = package.as_ref().expect("checked above");
+            let after = Package::open(translated)?;
+            (before.manifest != after.manifest || before.spine != after.spine)
+                .then(|| "the manifest or the spine changed".to_string())
+        } else {
+            (before != after).then(|| "the content changed".to_string())
+        };
+        if let Some(difference) = difference {
+            issues.push(issue(path, difference));
+        }
+    }
+    Ok(issues)
+}
+
+// The structure of a document as a list of tokens: its elements with their sorted attributes,
+// `lang` and `xml:lang` aside, their text, comments and end tags.
+fn document_tokens(content: &str) -> Result<Vec<String>, EpubTranslatorError> {
+    let document = get_document_node(content)?;
+    let mut tokens = Vec::new();
+    push_tokens(&document, &mut tokens);
+    Ok(tokens)
+}
+
+fn push_tokens(node: &Rc<Node>, tokens: &mut Vec<String>) {
+    match &node.data {
+        NodeData::Element { name, attrs, .. } => {
+            let attributes: BTreeMap<String, String> = attrs
+                .borrow()
+                .iter()
+                .filter(|attr| &*attr.name.local != "lang")
+                .map(|attr| {
+                    let prefix = attr.name.prefix.as_deref().unwrap_or_default();
+                    let name = format!("{}:{}", prefix, attr.name.local);
+                    (name, attr.value.to_string())
+                })
+                .collect();
+            tokens.push(format!("<{} {:?}>", name.local, attributes));
+            for child in node.children.borrow().iter() {
+                push_tokens(child, tokens);
+            }
+            tokens.push(format!("</{}>", name.local));
+        }
+        // Merged text nodes leave empty ones behind
+        NodeData::Text { contents } if contents.borrow().is_empty() => {}
+        NodeData::Text { contents } => tokens.push(format!("{:?}", contents.borrow())),
+        NodeData::Comment { contents } => tokens.push(format!("<!--{}-->", contents)),
+        _ => {
+            for child in node.children.borrow().iter() {
+                push_tokens(child, tokens);
+            }
+        }
+    }
+}
+
+fn first_token_difference(before: &[String], after: &[String]) -> Option<String> {
+    let index = (0..before.len().max(after.len())).find(|&i| before.get(i) != after.get(i))?;
+    let token = |tokens: &[String]| tokens.get(index).cloned().unwrap_or("the end".to_string());
+    Some(format!("`{}` became `{}`", token(before), token(after)))
+}
+
+// The first line of `actual` that differs from `expected`, numbered from 1.
+fn first_difference(expected: &str, actual: &str) -> Option<String> {
+    let (mut expected_lines, mut actual_lines) = (expected.lines(), actual.lines());
+    for number in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return None,
+            (expected, actual) if expected == actual => continue,
+            (expected, actual) => {
+                return Some(format!(
+                    "line {}: expected {:?}, found {:?}",
+                    number,
+                    expected.unwrap_or_default(),
+                    actual.unwrap_or_default()
+                ))
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_golden_books() -> Result<(), Box<dyn std::error::Error>> {
+        assert_golden_dir(
+            Path::new("tests/data/golden/books"),
+            Path::new("tests/data/golden/snapshots"),
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_differences() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let epub = read_fixture(Path::new("tests/data/opf_book"))?;
+        let round_trip = identity_round_trip(epub.clone()).await?;
+        assert_eq!(round_trip.issues, vec![]);
+
+        // A document whose text changed no longer matches the book nor its snapshot
+        let snapshots = temp_dir.path().join("snapshots");
+        assert_eq!(check_snapshots(epub.clone(), &snapshots, false)?, vec![]);
+        let mut book = BookArchive::read(epub.clone(), &UnzipLimits::default())?;
+        let path = Path::new("OEBPS/nav.xhtml");
+        let nav = book.read_to_string(path)?.replace("Contents", "Content");
+        book.write(path, nav.into_bytes())?;
+        let changed = book.to_bytes(None)?;
+        let source = BookArchive::read(epub, &UnzipLimits::default())?;
+        let issues = compare_books(
+            &source,
+            &BookArchive::read(changed.clone(), &UnzipLimits::default())?,
+        )?;
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].message.contains("\"Content\""));
+        let issues = check_snapshots(changed, &snapshots, false)?;
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].path.as_deref(), Some(path));
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::{future, stream, Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::deepl::models::{DeepLConfiguration, Formality};
+use crate::error::EpubTranslatorError;
+use crate::options::{Selection, TranslateOptions};
+use crate::progress::{ProgressSink, TranslationEvent};
+use crate::providers::Provider;
+use crate::report::{FileReport, TranslationReport};
+use crate::terms::Glossary;
+use crate::xhtml::{BilingualMode, ExtractOptions, Selector};
+
+/// Builds the options of a translation one setting at a time, and runs it. Settings left out
+/// keep the defaults of `TranslateOptions`.
+///
+/// ```no_run
+/// # use std::path::Path;
+/// # use epub_translator::deepl::models::DeepLConfiguration;
+/// # use epub_translator::translator::EpubTranslator;
+/// # async fn run() -> Result<(), epub_translator::error::EpubTranslatorError> {
+/// let report = EpubTranslator::new("ES")
+///     .source_lang("EN")
+///     .account(DeepLConfiguration::new("my-key:fx", false))
+///     .concurrency(50)
+///     .translate_epub(Path::new("book.epub"), Path::new("book.es.epub"))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct EpubTranslator {
+    options: TranslateOptions,
+}
+
+impl EpubTranslator {
+    /// A translator into `target_lang`, a DeepL code like `ES` or `EN-GB`.
+    pub fn new(target_lang: impl Into<String>) -> Self {
+        Self {
+            options: TranslateOptions {
+                target_lang: target_lang.into(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Language of the book, detected by DeepL when not given.
+    pub fn source_lang(mut self, source_lang: impl Into<String>) -> Self {
+        self.options.source_lang = Some(source_lang.into());
+        self
+    }
+
+    /// Adds a DeepL account the requests are balanced across. At least one is needed, unless
+    /// a local provider is given to `translate_with`.
+    pub fn account(mut self, configuration: DeepLConfiguration) -> Self {
+        self.options.configurations.push(Arc::new(configuration));
+        self
+    }
+
+    /// Translates with `provider` instead of DeepL, e.g. `Provider::Pseudo` to check a book
+    /// without any request.
+    pub fn translate_with(mut self, provider: Provider) -> Self {
+        self.options.provider = provider;
+        self
+    }
+
+    /// Register of the DeepL translations, see `Formality`.
+    pub fn formality(mut self, formality: Formality) -> Self {
+        self.options.formality = Some(formality);
+        self
+    }
+
+    /// Maximum number of requests sent to the API at the same time.
+    pub fn concurrency(mut self, concurrent_requests: usize) -> Self {
+        self.options.concurrent_requests = concurrent_requests;
+        self
+    }
+
+    /// Times a failed unit is sent again before its original text is kept.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.options.max_retries = max_retries;
+        self
+    }
+
+    /// Leaves these elements untranslated, along with their content.
+    pub fn skip_tags(mut self, tags: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options
+            .extract
+            .skip_tags
+            .extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Leaves the elements matched by `selector` untranslated, along with their content.
+    pub fn exclude(mut self, selector: Selector) -> Self {
+        self.options.extract.exclude_selectors.push(selector);
+        self
+    }
+
+    /// How the translation units are found in the documents, replacing the skip rules set so
+    /// far.
+    pub fn extract(mut self, extract: ExtractOptions) -> Self {
+        self.options.extract = extract;
+        self
+    }
+
+    /// The content documents translated, all of them by default.
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.options.selection = selection;
+        self
+    }
+
+    pub fn glossary(mut self, glossary: Glossary) -> Self {
+        self.options.glossary = glossary;
+        self
+    }
+
+    pub fn bilingual(mut self, mode: BilingualMode) -> Self {
+        self.options.bilingual = Some(mode);
+        self
+    }
+
+    /// Receives the progress events, silent by default.
+    pub fn progress(mut self, progress: impl ProgressSink + 'static) -> Self {
+        self.options.progress = Arc::new(progress);
+        self
+    }
+
+    /// Stops the translation when triggered, what is already translated is still written.
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.options.cancel = cancel;
+        self
+    }
+
+    /// Keeps the translations received from DeepL in the file at `path`, and reuses them
+    /// instead of sending their units again.
+    pub fn cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.cache = Some(path.into());
+        self
+    }
+
+    /// Extracts EPUBs into `workdir`, which must be empty, and keeps it after the run.
+    pub fn workdir(mut self, workdir: impl Into<PathBuf>) -> Self {
+        self.options.workdir = Some(workdir.into());
+        self
+    }
+
+    /// Creates the temporary workdirs in `temp_dir` instead of the temporary directory of the
+    /// system.
+    pub fn temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.options.temp_dir = Some(temp_dir.into());
+        self
+    }
+
+    /// Translates EPUBs in memory instead of extracting them.
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.options.in_memory = in_memory;
+        self
+    }
+
+    pub fn options(&self) -> &TranslateOptions {
+        &self.options
+    }
+
+    pub fn into_options(self) -> TranslateOptions {
+        self.options
+    }
+
+    /// Translates an EPUB file into another one, see `crate::translate_epub_with`.
+    pub async fn translate_epub(
+        &self,
+        input_file: &Path,
+        output_file: &Path,
+    ) -> Result<TranslationReport, EpubTranslatorError> {
+        self.check()?;
+        crate::translate_epub_with(input_file, output_file, &self.options).await
+    }
+
+    /// Translates an EPUB file into another one as a stream of events, see
+    /// `crate::translate_epub_stream`. Options that cannot be used end the stream at once.
+    pub fn translate_epub_stream(
+        &self,
+        input_file: PathBuf,
+        output_file: PathBuf,
+    ) -> impl Stream<Item = TranslationEvent> {
+        match self.check() {
+            Ok(()) => crate::translate_epub_stream(input_file, output_file, self.options.clone())
+                .left_stream(),
+            Err(error) => {
+                stream::once(future::ready(TranslationEvent::Finished(Err(error)))).right_stream()
+            }
+        }
+    }
+
+    /// Translates an EPUB held in memory, see `crate::translate_epub_bytes`.
+    pub async fn translate_epub_bytes(
+        &self,
+        input: Vec<u8>,
+    ) -> Result<(Vec<u8>, TranslationReport), EpubTranslatorError> {
+        self.check()?;
+        crate::translate_epub_bytes(input, &self.options).await
+    }
+
+    /// Translates the documents of a directory in place, with a report per file, see
+    /// `crate::translate_directory`.
+    pub async fn translate_directory(
+        &self,
+        dir_path: &Path,
+    ) -> Result<Vec<FileReport>, EpubTranslatorError> {
+        self.check()?;
+        crate::translate_directory(dir_path, &self.options).await
+    }
+
+    /// Translates a standalone XHTML document, see `crate::translate_xhtml_string`.
+    pub async fn translate_xhtml_string(
+        &self,
+        content: &str,
+    ) -> Result<String, EpubTranslatorError> {
+        self.check()?;
+        crate::translate_xhtml_string(content, &self.options).await
+    }
+
+    // Refuses options that cannot translate anything, before the book is read.
+    fn check(&self) -> Result<(), EpubTranslatorError> {
+        if self.options.target_lang.is_empty() {
+            return Err(EpubTranslatorError::InvalidOptions(
+                "no target language".to_string(),
+            ));
+        }
+        if self.options.configurations.is_empty() && self.options.provider.local().is_none() {
+            return Err(EpubTranslatorError::InvalidOptions(
+                "no DeepL configuration to send the requests to".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl From<TranslateOptions> for EpubTranslator {
+    fn from(options: TranslateOptions) -> Self {
+        Self { options }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_builder() {
+        let translator = EpubTranslator::new("ES")
+            .source_lang("EN")
+            .concurrency(10)
+            .max_retries(0)
+            .skip_tags(["aside"])
+            .formality(Formality::PreferLess)
+            .cache("translations.jsonl");
+        let options = translator.options();
+        assert_eq!(options.target_lang, "ES");
+        assert_eq!(options.source_lang.as_deref(), Some("EN"));
+        assert_eq!(options.concurrent_requests, 10);
+        assert_eq!(options.max_retries, 0);
+        assert_eq!(options.formality, Some(Formality::PreferLess));
+        assert!(options.extract.skip_tags.contains(&"aside".to_string()));
+        assert_eq!(options.cache, Some(PathBuf::from("translations.jsonl")));
+
+        let error = translator
+            .translate_xhtml_string("<p>Hello</p>")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EpubTranslatorError::InvalidOptions(_)));
+    }
+}
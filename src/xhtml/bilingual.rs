@@ -0,0 +1,428 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use html5ever::tendril::StrTendril;
+use html5ever::{namespace_url, ns, Attribute, LocalName, QualName};
+use markup5ever_rcdom::{Node, NodeData};
+
+use super::get_lang;
+use super::selector::parent;
+
+/// Class of the translated blocks of bilingual documents.
+pub const TRANSLATION_CLASS: &str = "epub-translator-translation";
+
+/// Style of the translated blocks, added to the head of bilingual documents.
+const BILINGUAL_STYLE: &str = ".epub-translator-translation { color: #555; font-style: italic; }";
+
+/// Prefix of the ids of the footnotes holding the originals, numbered from 1.
+const FOOTNOTE_ID: &str = "epub-translator-original";
+
+/// Text of the links to the footnotes.
+const NOTEREF_MARK: &str = "*";
+
+/// Namespace of the `epub:type` attribute.
+const OPS_NAMESPACE: &str = "http://www.idpf.org/2007/ops";
+
+/// Blocks that cannot be repeated without breaking the structure around them, like table
+/// cells: the original and the translation are both kept inside them.
+const CONTAINER_BLOCKS: &[&str] = &["li", "dt", "dd", "td", "th", "caption", "figcaption"];
+
+/// Blocks holding only phrasing content, translated in place.
+const UNSPLIT_BLOCKS: &[&str] = &["body", "summary", "legend", "option"];
+
+/// How the original text is kept in a translated document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BilingualMode {
+    /// Each translated block follows its original.
+    Interleaved,
+    /// Each translated block links to an EPUB 3 footnote holding its original.
+    Footnote,
+}
+
+impl FromStr for BilingualMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "interleaved" => Ok(Self::Interleaved),
+            "footnote" => Ok(Self::Footnote),
+            _ => Err(format!(
+                "unknown bilingual mode `{}`, expected `interleaved` or `footnote`",
+                mode
+            )),
+        }
+    }
+}
+
+/// Copies of the blocks of a document taken before their translation, put back next to it by
+/// `insert`.
+pub struct Originals {
+    document: Rc<Node>,
+    mode: BilingualMode,
+    /// Each block to be translated with its copy, outermost blocks only.
+    blocks: Vec<(Rc<Node>, Rc<Node>)>,
+    lang: Option<String>,
+}
+
+impl Originals {
+    /// Copies `blocks`, the elements holding the translation units of `document`. Blocks
+    /// nested in another one are copied along with it. `lang`, the language of the original,
+    /// defaults to the one of the root element.
+    pub fn copy(
+        document: &Rc<Node>,
+        blocks: &[Rc<Node>],
+        mode: BilingualMode,
+        lang: Option<String>,
+    ) -> Self {
+        let eligible: Vec<&Rc<Node>> = blocks.iter().filter(|block| is_splittable(block)).collect();
+        let mut outermost: Vec<Rc<Node>> = Vec::new();
+        for block in &eligible {
+            let nested = ancestors(block)
+                .any(|ancestor| eligible.iter().any(|other| Rc::ptr_eq(other, &ancestor)));
+            if !nested && !outermost.iter().any(|other| Rc::ptr_eq(other, block)) {
+                outermost.push(Rc::clone(block));
+            }
+        }
+
+        let lang = lang.or_else(|| root_element(document).and_then(|root| get_lang(&root)));
+        Self {
+            document: document.clone(),
+            mode,
+            blocks: outermost
+                .into_iter()
+                .map(|block| {
+                    let copy = deep_copy(&block);
+                    (block, copy)
+                })
+                .collect(),
+            lang,
+        }
+    }
+
+    /// Puts the originals back into the translated document, marked with their language, as
+    /// laid out by the `BilingualMode`.
+    pub fn insert(self) {
+        for (_, copy) in &self.blocks {
+            if let Some(lang) = &self.lang {
+                set_attribute(copy, "lang", lang);
+                set_attribute(copy, "xml:lang", lang);
+            }
+        }
+        match self.mode {
+            BilingualMode::Interleaved => self.interleave(),
+            BilingualMode::Footnote => self.add_footnotes(),
+        }
+    }
+
+    // Puts each original before its translated block. The translations get the
+    // `TRANSLATION_CLASS` and lose their ids, links keep pointing to the originals.
+    fn interleave(self) {
+        for (block, copy) in &self.blocks {
+            if CONTAINER_BLOCKS.contains(&local_name(block).as_str()) {
+                let translation = element("div", &[("class", TRANSLATION_CLASS)]);
+                move_children(block, &translation);
+                remove_ids(&translation);
+                append(block, contents(copy));
+                append(block, translation);
+            } else if let Some(parent) = parent(block) {
+                remove_ids(block);
+                add_class(block, TRANSLATION_CLASS);
+                let mut children = parent.children.borrow_mut();
+                if let Some(position) = children.iter().position(|c| Rc::ptr_eq(c, block)) {
+                    copy.parent.set(Some(Rc::downgrade(&parent)));
+                    children.insert(position, copy.clone());
+                }
+            }
+        }
+
+        if !self.blocks.is_empty() {
+            add_style(&self.document);
+        }
+    }
+
+    // Appends a link to each translated block, pointing to a footnote with its original at the
+    // end of the body. Reading systems usually show these footnotes in a popup.
+    fn add_footnotes(self) {
+        let Some(root) = root_element(&self.document) else {
+            return;
+        };
+        let body = root
+            .children
+            .borrow()
+            .iter()
+            .find(|child| local_name(child) == "body")
+            .cloned();
+        let Some(body) = body else {
+            return;
+        };
+
+        for (index, (block, copy)) in self.blocks.iter().enumerate() {
+            let id = format!("{}-{}", FOOTNOTE_ID, index + 1);
+            let href = format!("#{}", id);
+            let noteref = element("a", &[("epub:type", "noteref"), ("href", &href)]);
+            append(&noteref, text(NOTEREF_MARK));
+            append(block, noteref);
+
+            let original = contents(copy);
+            remove_ids(&original);
+            let footnote = element("aside", &[("epub:type", "footnote"), ("id", &id)]);
+            append(&footnote, original);
+            append(&body, footnote);
+        }
+
+        if !self.blocks.is_empty() {
+            set_attribute(&root, "xmlns:epub", OPS_NAMESPACE);
+        }
+    }
+}
+
+// Whether a copy of `block` can be put next to it: HTML elements of the body only.
+fn is_splittable(block: &Rc<Node>) -> bool {
+    let NodeData::Element { name, .. } = &block.data else {
+        return false;
+    };
+    name.ns == ns!(html)
+        && !UNSPLIT_BLOCKS.contains(&name.local.as_ref())
+        && ancestors(block).any(|ancestor| local_name(&ancestor) == "body")
+}
+
+fn ancestors(node: &Rc<Node>) -> impl Iterator<Item = Rc<Node>> {
+    std::iter::successors(parent(node), |node| parent(node))
+}
+
+fn root_element(document: &Rc<Node>) -> Option<Rc<Node>> {
+    document
+        .children
+        .borrow()
+        .iter()
+        .find(|child| matches!(child.data, NodeData::Element { .. }))
+        .cloned()
+}
+
+fn local_name(node: &Node) -> String {
+    match &node.data {
+        NodeData::Element { name, .. } => name.local.to_string(),
+        _ => String::new(),
+    }
+}
+
+// A new, unattached copy of `node` and its descendants.
+fn deep_copy(node: &Rc<Node>) -> Rc<Node> {
+    let data = match &node.data {
+        NodeData::Element {
+            name,
+            attrs,
+            template_contents,
+            mathml_annotation_xml_integration_point,
+        } => NodeData::Element {
+            name: name.clone(),
+            attrs: RefCell::new(attrs.borrow().clone()),
+            template_contents: RefCell::new(template_contents.borrow().as_ref().map(deep_copy)),
+            mathml_annotation_xml_integration_point: *mathml_annotation_xml_integration_point,
+        },
+        NodeData::Text { contents } => NodeData::Text {
+            contents: RefCell::new(contents.borrow().clone()),
+        },
+        NodeData::Comment { contents } => NodeData::Comment {
+            contents: contents.clone(),
+        },
+        NodeData::ProcessingInstruction { target, contents } => NodeData::ProcessingInstruction {
+            target: target.clone(),
+            contents: contents.clone(),
+        },
+        NodeData::Doctype {
+            name,
+            public_id,
+            system_id,
+        } => NodeData::Doctype {
+            name: name.clone(),
+            public_id: public_id.clone(),
+            system_id: system_id.clone(),
+        },
+        NodeData::Document => NodeData::Document,
+    };
+    let copy = Node::new(data);
+    for child in node.children.borrow().iter() {
+        let child = deep_copy(child);
+        child.parent.set(Some(Rc::downgrade(&copy)));
+        copy.children.borrow_mut().push(child);
+    }
+    copy
+}
+
+fn element(name: &str, attributes: &[(&str, &str)]) -> Rc<Node> {
+    let attrs = attributes
+        .iter()
+        .map(|(name, value)| Attribute {
+            name: QualName::new(None, ns!(), LocalName::from(*name)),
+            value: StrTendril::from(*value),
+        })
+        .collect();
+    Node::new(NodeData::Element {
+        name: QualName::new(None, ns!(html), LocalName::from(name)),
+        attrs: RefCell::new(attrs),
+        template_contents: RefCell::new(None),
+        mathml_annotation_xml_integration_point: false,
+    })
+}
+
+fn text(contents: &str) -> Rc<Node> {
+    Node::new(NodeData::Text {
+        contents: RefCell::new(StrTendril::from(contents)),
+    })
+}
+
+fn append(parent: &Rc<Node>, child: Rc<Node>) {
+    child.parent.set(Some(Rc::downgrade(parent)));
+    parent.children.borrow_mut().push(child);
+}
+
+// A `div` holding the children of `copy`, in its language.
+fn contents(copy: &Rc<Node>) -> Rc<Node> {
+    let div = element("div", &[]);
+    move_children(copy, &div);
+    copy_lang(copy, &div);
+    div
+}
+
+fn move_children(from: &Rc<Node>, to: &Rc<Node>) {
+    let children = std::mem::take(&mut *from.children.borrow_mut());
+    for child in &children {
+        child.parent.set(Some(Rc::downgrade(to)));
+    }
+    to.children.borrow_mut().extend(children);
+}
+
+fn copy_lang(from: &Node, to: &Node) {
+    if let Some(lang) = get_lang(from) {
+        set_attribute(to, "lang", &lang);
+        set_attribute(to, "xml:lang", &lang);
+    }
+}
+
+fn set_attribute(node: &Node, name: &str, value: &str) {
+    let NodeData::Element { attrs, .. } = &node.data else {
+        return;
+    };
+    let mut attrs = attrs.borrow_mut();
+    match attrs
+        .iter_mut()
+        .find(|attr| attr.name.local.as_ref() == name)
+    {
+        Some(attr) => attr.value = StrTendril::from(value),
+        None => attrs.push(Attribute {
+            name: QualName::new(None, ns!(), LocalName::from(name)),
+            value: StrTendril::from(value),
+        }),
+    }
+}
+
+fn add_class(node: &Node, class: &str) {
+    let NodeData::Element { attrs, .. } = &node.data else {
+        return;
+    };
+    let existing = attrs
+        .borrow()
+        .iter()
+        .find(|attr| attr.name.local.as_ref() == "class")
+        .map(|attr| attr.value.to_string());
+    match existing {
+        Some(classes) => set_attribute(node, "class", &format!("{} {}", classes, class)),
+        None => set_attribute(node, "class", class),
+    }
+}
+
+fn remove_ids(node: &Node) {
+    if let NodeData::Element { attrs, .. } = &node.data {
+        attrs
+            .borrow_mut()
+            .retain(|attr| attr.name.local.as_ref() != "id");
+    }
+    for child in node.children.borrow().iter() {
+        remove_ids(child);
+    }
+}
+
+// Appends the bilingual style to the head of `document`.
+fn add_style(document: &Rc<Node>) {
+    let Some(head) = root_element(document).and_then(|root| {
+        root.children
+            .borrow()
+            .iter()
+            .find(|child| local_name(child) == "head")
+            .cloned()
+    }) else {
+        return;
+    };
+    let style = element("style", &[("type", "text/css")]);
+    append(&style, text(BILINGUAL_STYLE));
+    append(&head, style);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::{get_segments, Segment};
+    use crate::xhtml::{get_document_node, serialize_document_to_string, ExtractOptions};
+
+    #[test]
+    fn test_interleave() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<html lang="en"><head><title>Book</title></head><body><div id="d">Zero<p>One <em>two</em></p><p id="p">Three</p></div><ul><li id="l">Four</li></ul></body></html>"#,
+        )?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+        let blocks: Vec<Rc<Node>> = segments
+            .iter()
+            .filter_map(Segment::block)
+            .cloned()
+            .collect();
+        let originals = Originals::copy(&document, &blocks, BilingualMode::Interleaved, None);
+        for segment in &segments {
+            let mut translation = segment.text.to_string();
+            for word in ["Book", "Zero", "One", "two", "Three", "Four"] {
+                translation = translation.replace(word, &word.to_uppercase());
+            }
+            segment.apply(&translation)?;
+        }
+        originals.insert();
+
+        let output = serialize_document_to_string(&document)?;
+        // The title is in the head, the paragraphs are copied along with their div
+        assert!(output.contains("<title>BOOK</title><style"));
+        assert!(output.contains(
+            r#"<div id="d" lang="en" xml:lang="en">Zero<p>One <em>two</em></p><p id="p">Three</p></div><div class="epub-translator-translation">ZERO<p>ONE <em>TWO</em></p><p>THREE</p></div>"#
+        ));
+        assert!(output.contains(
+            r#"<li id="l"><div lang="en" xml:lang="en">Four</div><div class="epub-translator-translation">FOUR</div></li>"#
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_footnotes() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<html><head></head><body><p id="p">One</p><img alt="Two"/></body></html>"#,
+        )?;
+        let segments = get_segments(&document, &ExtractOptions::default());
+        let blocks: Vec<Rc<Node>> = segments
+            .iter()
+            .filter_map(Segment::block)
+            .cloned()
+            .collect();
+        let lang = Some("en".to_string());
+        let originals = Originals::copy(&document, &blocks, BilingualMode::Footnote, lang);
+        for segment in &segments {
+            segment.apply(&segment.text.to_uppercase())?;
+        }
+        originals.insert();
+
+        let output = serialize_document_to_string(&document)?;
+        // The run of the body itself is translated in place
+        assert_eq!(
+            output,
+            r##"<html xmlns:epub="http://www.idpf.org/2007/ops"><head></head><body><p id="p">ONE<a epub:type="noteref" href="#epub-translator-original-1">*</a></p><img alt="TWO"/><aside epub:type="footnote" id="epub-translator-original-1"><div lang="en" xml:lang="en">One</div></aside></body></html>"##
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use html5ever::data::NAMED_ENTITIES;
+use regex::Regex;
+
+use crate::error::EpubTranslatorError;
+
+/// How characters written as references in the source (`&hellip;`, `&#8212;`) are written back.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum EntityPolicy {
+    /// A character written as a reference anywhere in a document is written with the same
+    /// reference everywhere in that document, translated text included.
+    #[default]
+    Preserve,
+    /// Characters are written as is, only `&`, `<` and `>` are escaped.
+    Characters,
+}
+
+impl FromStr for EntityPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "preserve" => Ok(Self::Preserve),
+            "characters" => Ok(Self::Characters),
+            _ => Err(format!(
+                "unknown entity policy `{}`, expected `preserve` or `characters`",
+                policy
+            )),
+        }
+    }
+}
+
+/// Character and entity references found in a source document, by character.
+#[derive(Debug, Clone, Default)]
+pub struct EntityReferences {
+    references: HashMap<char, String>,
+}
+
+impl EntityReferences {
+    /// Collects the references of `source`, the first spelling of a character wins.
+    pub fn scan(source: &str) -> Result<Self, EpubTranslatorError> {
+        let re = Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z][a-zA-Z0-9]*);").unwrap();
+        let mut references = HashMap::new();
+
+        for capture in re.captures_iter(source) {
+            let name = &capture[1];
+            let code = match name.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok(),
+                Some(decimal) => decimal.parse().ok(),
+                // Entities expanding to two characters can't be mapped back to one
+                None => match NAMED_ENTITIES.get(&format!("{};", name)[..]) {
+                    Some(&(code, 0)) => Some(code),
+                    _ => None,
+                },
+            };
+            if let Some(c) = code.and_then(char::from_u32) {
+                references
+                    .entry(c)
+                    .or_insert_with(|| capture[0].to_string());
+            }
+        }
+
+        Ok(Self { references })
+    }
+
+    /// The reference used for `c` in the source, if any.
+    pub fn get(&self, c: char) -> Option<&str> {
+        self.references.get(&c).map(String::as_str)
+    }
+}
@@ -2,37 +2,390 @@ use regex::Regex;
 use std::fs::File;
 use std::io::{Read, Write};
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use html5ever::serialize::SerializeOpts;
-use html5ever::serialize::TraversalScope;
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_8};
 use html5ever::tendril::TendrilSink;
-use html5ever::{parse_document, serialize};
+use html5ever::{namespace_url, ns, parse_document};
 
-use markup5ever_rcdom::SerializableHandle;
+use html5ever::tendril::StrTendril;
+use html5ever::{Attribute, LocalName, QualName};
 use markup5ever_rcdom::{Node, NodeData, RcDom};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::error::EpubTranslatorError;
+
+mod bilingual;
+mod entities;
+mod selector;
+mod serializer;
+pub use bilingual::{BilingualMode, Originals, TRANSLATION_CLASS};
+pub use entities::{EntityPolicy, EntityReferences};
+pub use selector::Selector;
+
+/// Elements skipped by default, code samples should not be translated.
+pub const DEFAULT_SKIP_TAGS: &[&str] = &["script", "pre", "code", "kbd", "samp", "var"];
+
+/// Attributes holding accessibility text, translated by default.
+pub const DEFAULT_ATTRIBUTES: &[&str] = &["alt", "title", "aria-label"];
+
+/// How `<ruby>` annotations (furigana) are translated.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RubyMode {
+    /// Base text and annotations are translated together.
+    #[default]
+    Translate,
+    /// Annotations are not sent, the translation replaces the whole `<ruby>` element.
+    Drop,
+    /// Annotations are not sent and kept untouched next to the translated base text.
+    Preserve,
+}
+
+impl std::str::FromStr for RubyMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "translate" => Ok(Self::Translate),
+            "drop" => Ok(Self::Drop),
+            "preserve" => Ok(Self::Preserve),
+            _ => Err(format!(
+                "unknown ruby mode `{}`, expected `translate`, `drop` or `preserve`",
+                mode
+            )),
+        }
+    }
+}
+
+/// Rules deciding which parts of a document are translated.
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Elements with one of these classes are left untranslated, along with their content.
+    /// Elements with `translate="no"` are always left untranslated.
+    pub notranslate_classes: Vec<String>,
+    /// Elements left untranslated, along with their content. `style` is always skipped.
+    pub skip_tags: Vec<String>,
+    /// Elements matched by one of these selectors are left untranslated, along with their content.
+    pub exclude_selectors: Vec<Selector>,
+    /// Translate the `title`, `desc` and `text` elements of inline SVG images.
+    pub translate_svg: bool,
+    /// Attributes whose value is translated, e.g. `alt`, `title` and `aria-label`.
+    pub attributes: Vec<String>,
+    pub ruby: RubyMode,
+    /// Elements whose `lang` or `xml:lang` is this language are left untranslated, along with
+    /// their content. Usually the target language, so that passages already in it are kept.
+    pub skip_lang: Option<String>,
+    /// Send the last `h1`-`h3` heading as context of the translation units that follow it.
+    pub heading_context: bool,
+    /// Translate the `dc:title`, `dc:description` and `dc:subject` of the package metadata,
+    /// the original title is kept next to the translated one. EPUBs only.
+    pub translate_metadata: bool,
+    /// Occurrences of these terms are not sent, they are kept as they are in the translation,
+    /// see `terms::parse_keep_terms`.
+    pub keep_terms: Vec<Regex>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            notranslate_classes: vec!["notranslate".to_string()],
+            skip_tags: DEFAULT_SKIP_TAGS
+                .iter()
+                .map(|tag| tag.to_string())
+                .collect(),
+            exclude_selectors: Vec::new(),
+            translate_svg: false,
+            attributes: DEFAULT_ATTRIBUTES
+                .iter()
+                .map(|attribute| attribute.to_string())
+                .collect(),
+            ruby: RubyMode::Translate,
+            skip_lang: None,
+            heading_context: true,
+            translate_metadata: false,
+            keep_terms: Vec::new(),
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// Whether `content`, an XHTML document, may have something to translate, found without
+    /// parsing it: text outside of the skipped elements, or a translated attribute. Documents
+    /// that are not well-formed XML may.
+    pub fn may_have_text(&self, content: &str) -> bool {
+        let mut reader = Reader::from_str(content);
+        // Depth in a skipped element, whose text is left as it is
+        let mut skipped = 0;
+        let translated = |element: &BytesStart| {
+            element.attributes().flatten().any(|attribute| {
+                let name = attribute.key.local_name();
+                self.attributes
+                    .iter()
+                    .any(|translated| translated == name.as_ref())
+                    && !attribute.value.trim().is_empty()
+            })
+        };
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(_)) if skipped > 0 => skipped += 1,
+                Ok(Event::Start(element)) => {
+                    let name = element.local_name();
+                    let tag = name.as_ref();
+                    if tag == "style"
+                        || (tag == "svg" && !self.translate_svg)
+                        || self.skip_tags.iter().any(|skipped| skipped == tag)
+                    {
+                        skipped = 1;
+                    } else if translated(&element) {
+                        return true;
+                    }
+                }
+                Ok(Event::Empty(element)) if skipped == 0 && translated(&element) => return true,
+                Ok(Event::End(_)) if skipped > 0 => skipped -= 1,
+                Ok(Event::Text(text)) if skipped == 0 && !text.trim().is_empty() => return true,
+                Ok(Event::CData(_) | Event::GeneralRef(_)) if skipped == 0 => return true,
+                Ok(Event::Eof) => return false,
+                Err(_) => return true,
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether the content of `node` must be left untranslated.
+    ///
+    /// Note: a `translate="yes"` inside an excluded element is not honored, the whole
+    /// subtree is excluded.
+    pub fn is_excluded(&self, node: &Node) -> bool {
+        let NodeData::Element { name, attrs, .. } = &node.data else {
+            return false;
+        };
+        let tag = name.local.as_ref();
+        if tag == "style" || self.skip_tags.iter().any(|skipped| skipped == tag) {
+            return true;
+        }
+        if self.ruby != RubyMode::Translate && (tag == "rt" || tag == "rp") {
+            return true;
+        }
+        // Path data lives in attributes, so only SVG text elements remain once metadata is out
+        if name.ns == ns!(svg) && (tag == "metadata" || (tag == "svg" && !self.translate_svg)) {
+            return true;
+        }
+        let marked = attrs
+            .borrow()
+            .iter()
+            .any(|attr| match attr.name.local.as_ref() {
+                "translate" => attr.value.trim().eq_ignore_ascii_case("no"),
+                "class" => attr
+                    .value
+                    .split_whitespace()
+                    .any(|class| self.notranslate_classes.iter().any(|c| c == class)),
+                "lang" | "xml:lang" => self
+                    .skip_lang
+                    .as_deref()
+                    .is_some_and(|lang| same_language(&attr.value, lang)),
+                _ => false,
+            });
+        marked
+            || self
+                .exclude_selectors
+                .iter()
+                .any(|selector| selector.matches(node))
+    }
+}
+
+// Compares the primary subtags of two language codes, `en-GB` and `EN` are the same language.
+fn same_language(a: &str, b: &str) -> bool {
+    let primary = |code: &str| {
+        code.trim()
+            .split(['-', '_'])
+            .next()
+            .unwrap_or("")
+            .to_string()
+    };
+    !a.trim().is_empty() && primary(a).eq_ignore_ascii_case(&primary(b))
+}
+
+/// Rules deciding how documents are written back.
+#[derive(Debug, Clone, Default)]
+pub struct SerializeOptions {
+    pub entities: EntityPolicy,
+    /// Besides the root element, rewrite the `lang` of the elements in the source language.
+    pub rewrite_inner_lang: bool,
+}
+
+// Converts a DeepL language code to a BCP 47 tag: `EN-GB` -> `en-GB`, `ZH-HANS` -> `zh-Hans`.
+pub fn language_tag(code: &str) -> String {
+    code.split(['-', '_'])
+        .enumerate()
+        .map(|(i, part)| match (i, part.len()) {
+            (0, _) => part.to_ascii_lowercase(),
+            (_, 4) => part[..1].to_ascii_uppercase() + &part[1..].to_ascii_lowercase(),
+            _ => part.to_ascii_uppercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Sets `lang` and `xml:lang` of the root element to `lang`, adding them if missing.
+// With `inner`, the elements marked with the original language of the root are updated too,
+// passages in other languages keep theirs.
+pub fn rewrite_lang(document: &Rc<Node>, lang: &str, inner: bool) {
+    let Some(root) = document
+        .children
+        .borrow()
+        .iter()
+        .find(|child| matches!(child.data, NodeData::Element { .. }))
+        .cloned()
+    else {
+        return;
+    };
+    let source_lang = get_lang(&root);
+
+    if let NodeData::Element { attrs, .. } = &root.data {
+        let mut attrs = attrs.borrow_mut();
+        for name in ["lang", "xml:lang"] {
+            if !attrs.iter().any(|attr| attr.name.local.as_ref() == name) {
+                attrs.push(Attribute {
+                    name: QualName::new(None, ns!(), LocalName::from(name)),
+                    value: StrTendril::new(),
+                });
+            }
+        }
+    }
+    set_lang(&root, lang);
+
+    if let (true, Some(source_lang)) = (inner, source_lang) {
+        for child in root.children.borrow().iter() {
+            rewrite_inner_lang(child, &source_lang, lang);
+        }
+    }
+}
+
+fn rewrite_inner_lang(node: &Rc<Node>, source_lang: &str, lang: &str) {
+    if get_lang(node).is_some_and(|node_lang| same_language(&node_lang, source_lang)) {
+        set_lang(node, lang);
+    }
+    for child in node.children.borrow().iter() {
+        rewrite_inner_lang(child, source_lang, lang);
+    }
+}
+
+fn get_lang(node: &Node) -> Option<String> {
+    let NodeData::Element { attrs, .. } = &node.data else {
+        return None;
+    };
+    let attrs = attrs.borrow();
+    attrs
+        .iter()
+        .find(|attr| matches!(attr.name.local.as_ref(), "lang" | "xml:lang"))
+        .map(|attr| attr.value.to_string())
+}
+
+fn set_lang(node: &Node, lang: &str) {
+    if let NodeData::Element { attrs, .. } = &node.data {
+        for attr in attrs.borrow_mut().iter_mut() {
+            if matches!(attr.name.local.as_ref(), "lang" | "xml:lang") {
+                attr.value = StrTendril::from(lang);
+            }
+        }
+    }
+}
 
 // Parses a string containing XHTML and returns the document node.
-pub fn get_document_node(content: &str) -> Result<Rc<Node>, Box<dyn std::error::Error>> {
+/// The location of `element` in its document, XPath-like, e.g. `/html/body/div[2]/p`: the
+/// position among the siblings of the same name, from 1, is given when there are several.
+pub fn element_path(element: &Rc<Node>) -> String {
+    let mut steps = Vec::new();
+    let mut node = element.clone();
+    while let NodeData::Element { name, .. } = &node.data {
+        let Some(parent) = selector::parent(&node) else {
+            steps.push(name.local.to_string());
+            break;
+        };
+        let siblings: Vec<Rc<Node>> = parent
+            .children
+            .borrow()
+            .iter()
+            .filter(|child| match &child.data {
+                NodeData::Element { name: other, .. } => other.local == name.local,
+                _ => false,
+            })
+            .cloned()
+            .collect();
+        match siblings
+            .iter()
+            .position(|sibling| Rc::ptr_eq(sibling, &node))
+        {
+            Some(index) if siblings.len() > 1 => {
+                steps.push(format!("{}[{}]", name.local, index + 1))
+            }
+            _ => steps.push(name.local.to_string()),
+        }
+        node = parent;
+    }
+    steps.reverse();
+    format!("/{}", steps.join("/"))
+}
+
+pub fn get_document_node(content: &str) -> Result<Rc<Node>, EpubTranslatorError> {
     // remove self closing span tags <span*/> => <span*></span>
-    let re = Regex::new(r"<span([^>]*?)/>")?;
-    let content = re.replace_all(&content, "<span$1></span>");
+    let re = Regex::new(r"<span([^>]*?)/>").unwrap();
+    let content = re.replace_all(content, "<span$1></span>");
 
     let rc_dom = parse_document(RcDom::default(), Default::default())
         .from_utf8()
         .read_from(&mut content.as_bytes())?;
 
+    merge_adjacent_text_nodes(&rc_dom.document);
+
     Ok(rc_dom.document)
 }
 
+// Coalesces consecutive sibling text nodes so a sentence is never split in two translation units.
+// The merged text goes into the first node and the following ones are left empty, so the
+// node handles stay valid.
+pub fn merge_adjacent_text_nodes(node: &Rc<Node>) {
+    let children = node.children.borrow();
+    let mut first_text: Option<&Rc<Node>> = None;
+
+    for child in children.iter() {
+        match (&child.data, first_text) {
+            (NodeData::Text { contents }, Some(first)) => {
+                if let NodeData::Text {
+                    contents: first_contents,
+                } = &first.data
+                {
+                    first_contents.borrow_mut().push_tendril(&contents.borrow());
+                    contents.borrow_mut().clear();
+                }
+            }
+            (NodeData::Text { .. }, None) => first_text = Some(child),
+            _ => {
+                first_text = None;
+                merge_adjacent_text_nodes(child);
+            }
+        }
+    }
+}
+
 // TODO: Optimize
 // Gets all descendant text nodes from a node, use it on document node to get all text nodes
 // Depth-first search, but this is not used for serialization so the order is not important so far.
-pub fn get_text_nodes(node: &Rc<Node>) -> Result<Vec<Rc<Node>>, Box<dyn std::error::Error>> {
+pub fn get_text_nodes(node: &Rc<Node>) -> Result<Vec<Rc<Node>>, EpubTranslatorError> {
+    get_text_nodes_with(node, &ExtractOptions::default())
+}
+
+// Same as `get_text_nodes`, leaving out the subtrees excluded by `options`.
+pub fn get_text_nodes_with(
+    node: &Rc<Node>,
+    options: &ExtractOptions,
+) -> Result<Vec<Rc<Node>>, EpubTranslatorError> {
     let mut text_nodes = Vec::new();
 
-    let alpha_regex = Regex::new(r"[a-zA-Z]")?;
+    let alpha_regex = Regex::new(r"[a-zA-Z]").unwrap();
 
     match &node.data {
         NodeData::Text { contents } => {
@@ -42,10 +395,10 @@ pub fn get_text_nodes(node: &Rc<Node>) -> Result<Vec<Rc<Node>>, Box<dyn std::err
             }
         }
 
-        NodeData::Element { ref name, .. } if name.local.as_ref() == "style" => {}
+        NodeData::Element { .. } if options.is_excluded(node) => {}
         _ => {
             for child in node.children.borrow().iter() {
-                let child_text_nodes = get_text_nodes(&child)?;
+                let child_text_nodes = get_text_nodes_with(child, options)?;
                 text_nodes.extend(child_text_nodes);
             }
         }
@@ -57,8 +410,17 @@ pub fn get_text_nodes(node: &Rc<Node>) -> Result<Vec<Rc<Node>>, Box<dyn std::err
 pub fn serialize_document(
     document: &Rc<Node>,
     output_path: &PathBuf,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let output_string = serialize_document_to_string(document)?;
+) -> Result<(), EpubTranslatorError> {
+    serialize_document_with(document, output_path, &EntityReferences::default())
+}
+
+// Same as `serialize_document`, writing the characters of `references` as they were in the source.
+pub fn serialize_document_with(
+    document: &Rc<Node>,
+    output_path: &PathBuf,
+    references: &EntityReferences,
+) -> Result<(), EpubTranslatorError> {
+    let output_string = serialize_document_to_string_with(document, references)?;
 
     let mut file = File::create(output_path)?;
     file.write_all(output_string.as_bytes())?;
@@ -66,68 +428,72 @@ pub fn serialize_document(
     Ok(())
 }
 
-pub fn serialize_document_to_string(
-    document: &Rc<Node>,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let opts = SerializeOpts {
-        traversal_scope: TraversalScope::IncludeNode,
-        ..Default::default()
-    };
-
-    let mut output = Vec::new();
-
-    // Serialize from html node, not from document. The html node is the only child of document.
-    for node in document.children.borrow().iter() {
-        let serializable = SerializableHandle::from(node.clone());
-        serialize(&mut output, &serializable, opts.clone())?;
-    }
-
-    let mut output_string = String::from_utf8(output)?;
-
-    // Change `>` to `/>` in self closing tags
-    let self_closing_tags = ["br", "hr", "img", "input", "link", "meta"];
-    let tags_pattern = self_closing_tags.join("|");
-
-    // Fix Bug n.4
-    let re_self_closing = Regex::new(&format!(r"<({})([^>]*?)>", tags_pattern))?;
-    output_string = re_self_closing
-        .replace_all(&output_string, "<$1$2/>")
-        .to_string();
+pub fn serialize_document_to_string(document: &Rc<Node>) -> Result<String, EpubTranslatorError> {
+    serialize_document_to_string_with(document, &EntityReferences::default())
+}
 
-    // Fix Bug n.3
-    let re_nbsp = Regex::new(r"&nbsp;")?;
-    output_string = re_nbsp.replace_all(&output_string, "\u{00A0}").to_string();
+pub fn serialize_document_to_string_with(
+    document: &Rc<Node>,
+    references: &EntityReferences,
+) -> Result<String, EpubTranslatorError> {
+    let mut output = String::new();
+    serializer::write_document(document, references, &mut output);
+    Ok(output)
+}
 
-    // Fix Bug n.2
-    let re_span = Regex::new(r"<span([^>]*?)></span>")?;
-    output_string = re_span.replace_all(&output_string, "<span$1/>").to_string();
+// Reads an XHTML file whatever its encoding. The encoding is taken from the BOM, then the
+// XML declaration, then guessed. The content is returned as UTF-8 and its declaration updated.
+pub fn read_xhtml_file(file_path: &Path) -> Result<String, EpubTranslatorError> {
+    let mut bytes = Vec::new();
+    File::open(file_path)?.read_to_end(&mut bytes)?;
+    decode_xhtml(&bytes)
+}
 
-    // Fix Bug n.5
-    let re_em = Regex::new(r"([^>\s])(</?em>)([^<\s])")?;
-    output_string = re_em.replace_all(&output_string, "$1 $2$3").to_string();
+/// Decodes the content of an XHTML file, see `read_xhtml_file`.
+pub fn decode_xhtml(bytes: &[u8]) -> Result<String, EpubTranslatorError> {
+    let declaration = Regex::new(r#"^\s*<\?xml[^>]*?encoding\s*=\s*["']([^"']+)["']"#).unwrap();
+
+    let encoding = match Encoding::for_bom(bytes) {
+        Some((encoding, _)) => encoding,
+        None => {
+            // The declaration is ASCII in every encoding it can be read from
+            let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+            let declared = declaration
+                .captures(&head)
+                .and_then(|captures| Encoding::for_label(captures[1].as_bytes()));
+            match declared {
+                Some(encoding) => encoding,
+                None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+                None => {
+                    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+                    detector.feed(bytes, true);
+                    detector.guess(None, Utf8Detection::Allow)
+                }
+            }
+        }
+    };
 
-    let re_a = Regex::new(r"([^>\s])(</?a [^>]*?>)([^<\s])")?;
-    output_string = re_a.replace_all(&output_string, "$1 $2$3").to_string();
+    let (content, _, had_errors) = encoding.decode(bytes);
+    if had_errors {
+        return Err(EpubTranslatorError::Parse(format!(
+            "Invalid {} content",
+            encoding.name()
+        )));
+    }
+    if encoding == UTF_8 {
+        return Ok(content.into_owned());
+    }
 
-    Ok(output_string)
+    let declaration = Regex::new(r#"^(\s*<\?xml[^>]*?encoding\s*=\s*["'])([^"']+)(["'])"#).unwrap();
+    Ok(declaration.replace(&content, "${1}utf-8${3}").into_owned())
 }
 
-pub fn get_document_node_from_path(
-    file_path: &PathBuf,
-) -> Result<Rc<Node>, Box<dyn std::error::Error>> {
-    // Read file content
-    let mut file = File::open(file_path)?;
-    let mut buf = String::new();
-    file.read_to_string(&mut buf)?;
-
-    let document = get_document_node(&buf)?;
-
-    Ok(document)
+pub fn get_document_node_from_path(file_path: &Path) -> Result<Rc<Node>, EpubTranslatorError> {
+    let content = read_xhtml_file(file_path)?;
+    get_document_node(&content)
 }
 
-pub fn get_text_nodes_from_path(
-    file_path: &PathBuf,
-) -> Result<Vec<Rc<Node>>, Box<dyn std::error::Error>> {
+pub fn get_text_nodes_from_path(file_path: &Path) -> Result<Vec<Rc<Node>>, EpubTranslatorError> {
     let document = get_document_node_from_path(file_path)?;
     get_text_nodes(&document)
 }
@@ -136,6 +502,7 @@ pub fn get_text_nodes_from_path(
 mod tests {
     use super::*;
     use html5ever::tendril::StrTendril;
+    use std::cell::RefCell;
     use std::fs;
 
     #[test]
@@ -173,6 +540,245 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_element_path() -> Result<(), Box<dyn std::error::Error>> {
+        let document =
+            get_document_node("<body><div><p>One</p><p>Two <em>and</em></p></div></body>")?;
+        let text_nodes = get_text_nodes(&document)?;
+        let and = text_nodes
+            .iter()
+            .find(|node| match &node.data {
+                NodeData::Text { contents } => &**contents.borrow() == "and",
+                _ => false,
+            })
+            .unwrap();
+        let em = selector::parent(and).unwrap();
+        assert_eq!(element_path(&em), "/html/body/div/p[2]/em");
+        Ok(())
+    }
+
+    #[test]
+    fn test_notranslate_is_excluded() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<body><p>One</p><p translate="no">Two</p><p class="x notranslate">Three</p><p class="latin">Four</p></body>"#,
+        )?;
+
+        assert_eq!(get_text_nodes(&document)?.len(), 2);
+
+        let options = ExtractOptions {
+            notranslate_classes: vec!["latin".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(get_text_nodes_with(&document, &options)?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_may_have_text() {
+        let options = ExtractOptions::default();
+        let cover = "<html><head><style>p {}</style></head><body><img src='a.jpg'/></body></html>";
+        assert!(!options.may_have_text(cover));
+        assert!(!options.may_have_text("<html><body><pre>let a;</pre> </body></html>"));
+        assert!(options.may_have_text("<html><body><img alt='A lighthouse'/></body></html>"));
+        assert!(options.may_have_text("<html><body><p>&nbsp;</p></body></html>"));
+        assert!(options.may_have_text("<html><body><p>Text</p></body></html>"));
+        // Not XML, parsed to be sure
+        assert!(options.may_have_text("<html><body><br></body></html>"));
+    }
+
+    #[test]
+    fn test_skip_tags() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            "<body><p>Run it</p><pre>let a = b;</pre><script>var x;</script><p>Use <code>ls</code></p></body>",
+        )?;
+
+        assert_eq!(get_text_nodes(&document)?.len(), 2);
+
+        let options = ExtractOptions {
+            skip_tags: vec!["script".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(get_text_nodes_with(&document, &options)?.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_selectors() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<body><div class="poem"><p>Verse</p></div><section id="colophon"><p>Printed</p></section><aside class="marginalia"><p>Note</p></aside><aside><p>Aside</p></aside><p>Prose</p></body>"#,
+        )?;
+
+        let options = ExtractOptions {
+            exclude_selectors: vec![
+                ".poem".parse()?,
+                "#colophon".parse()?,
+                "aside.marginalia > p".parse()?,
+            ],
+            ..Default::default()
+        };
+        assert_eq!(get_text_nodes_with(&document, &options)?.len(), 2);
+
+        let options = ExtractOptions {
+            exclude_selectors: vec!["body aside".parse()?],
+            ..Default::default()
+        };
+        assert_eq!(get_text_nodes_with(&document, &options)?.len(), 3);
+
+        assert!("aside >".parse::<Selector>().is_err());
+        assert!("p[title".parse::<Selector>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_translate_svg() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<body><p>Map</p><svg><title>Island</title><desc>A map</desc><metadata>Author</metadata><path d="M 0 0 L 1 1"/><text>North</text></svg></body>"#,
+        )?;
+
+        assert_eq!(get_text_nodes(&document)?.len(), 1);
+
+        let options = ExtractOptions {
+            translate_svg: true,
+            ..Default::default()
+        };
+        assert_eq!(get_text_nodes_with(&document, &options)?.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_lang() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            r#"<body><p>Hello</p><p lang="es-ES">Hola</p><p xml:lang="ES">Adiós</p><p lang="en">Bye</p></body>"#,
+        )?;
+
+        assert_eq!(get_text_nodes(&document)?.len(), 4);
+
+        let options = ExtractOptions {
+            skip_lang: Some("ES".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(get_text_nodes_with(&document, &options)?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_lang() -> Result<(), Box<dyn std::error::Error>> {
+        let input = r#"<html lang="en"><head></head><body><p xml:lang="en-US">Hi</p><p lang="la">Carpe diem</p></body></html>"#;
+
+        let document = get_document_node(input)?;
+        rewrite_lang(&document, &language_tag("PT-BR"), false);
+        assert_eq!(
+            serialize_document_to_string(&document)?,
+            r#"<html lang="pt-BR" xml:lang="pt-BR"><head></head><body><p xml:lang="en-US">Hi</p><p lang="la">Carpe diem</p></body></html>"#
+        );
+
+        let document = get_document_node(input)?;
+        rewrite_lang(&document, &language_tag("ZH-HANS"), true);
+        assert!(serialize_document_to_string(&document)?
+            .contains(r#"<p xml:lang="zh-Hans">Hi</p><p lang="la">Carpe diem</p>"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_adjacent_text_nodes() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node("<p>Hello</p>")?;
+        // document > html > [head, body] > p
+        let html = document.children.borrow()[0].clone();
+        let body = html.children.borrow()[1].clone();
+        let paragraph = body.children.borrow()[0].clone();
+
+        // Split "Hello" as a parse artifact would
+        let second = Node::new(NodeData::Text {
+            contents: RefCell::new(StrTendril::from(" world")),
+        });
+        paragraph.children.borrow_mut().push(second);
+
+        merge_adjacent_text_nodes(&document);
+
+        let text_nodes = get_text_nodes(&document)?;
+        assert_eq!(text_nodes.len(), 1);
+        if let NodeData::Text { contents } = &text_nodes[0].data {
+            assert_eq!(contents.borrow().as_ref(), "Hello world");
+        }
+        assert_eq!(paragraph.children.borrow().len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prolog_is_preserved() -> Result<(), Box<dyn std::error::Error>> {
+        let input_xhtml = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.1//EN\" \"http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd\">\n<html><head></head><body><p>Text</p></body></html>";
+
+        let document = get_document_node(input_xhtml)?;
+        let output = serialize_document_to_string(&document)?;
+
+        assert!(output.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.1//EN\" \"http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd\">\n<html>"));
+
+        let document = get_document_node("<!DOCTYPE html><html><body></body></html>")?;
+        assert!(serialize_document_to_string(&document)?.starts_with("<!DOCTYPE html>\n<html>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_is_well_formed_xhtml() -> Result<(), Box<dyn std::error::Error>> {
+        let document = get_document_node(
+            "<html><head><script>if (a < b && c) {}</script></head><body><p title=\"&quot;A&amp;B&quot;\">1&nbsp;&lt;&nbsp;2<br><span id=\"pg1\"/></p><div></div></body></html>",
+        )?;
+
+        assert_eq!(
+            serialize_document_to_string(&document)?,
+            "<html><head><script>if (a < b && c) {}</script></head><body><p title=\"&quot;A&amp;B&quot;\">1\u{00A0}&lt;\u{00A0}2<br/><span id=\"pg1\"/></p><div></div></body></html>"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_entities_are_preserved() -> Result<(), Box<dyn std::error::Error>> {
+        let input_xhtml = "<html><head></head><body><p title=\"&#8220;Q&#8221;\">Wait&hellip; R&amp;D &#x2014; now\u{2026}</p></body></html>";
+
+        let document = get_document_node(input_xhtml)?;
+        let references = EntityReferences::scan(input_xhtml)?;
+
+        assert_eq!(
+            serialize_document_to_string_with(&document, &references)?,
+            "<html><head></head><body><p title=\"&#8220;Q&#8221;\">Wait&hellip; R&amp;D &#x2014; now&hellip;</p></body></html>"
+        );
+        assert_eq!(
+            serialize_document_to_string(&document)?,
+            "<html><head></head><body><p title=\"\u{201C}Q\u{201D}\">Wait\u{2026} R&amp;D \u{2014} now\u{2026}</p></body></html>"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_non_utf8() -> Result<(), Box<dyn std::error::Error>> {
+        // "Café" in ISO-8859-1
+        let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<p>Caf\xe9</p>";
+        assert_eq!(
+            decode_xhtml(latin1)?,
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<p>Café</p>"
+        );
+
+        let mut utf16 = vec![0xFF, 0xFE];
+        for unit in "<p>Café</p>".encode_utf16() {
+            utf16.extend_from_slice(&unit.to_le_bytes());
+        }
+        assert_eq!(decode_xhtml(&utf16)?, "<p>Café</p>");
+
+        assert_eq!(decode_xhtml("<p>Café</p>".as_bytes())?, "<p>Café</p>");
+
+        Ok(())
+    }
+
     #[test]
     fn test_serialize_and_deserialize() -> Result<(), Box<dyn std::error::Error>> {
         let input_xhtml = r#"
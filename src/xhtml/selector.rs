@@ -0,0 +1,220 @@
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use markup5ever_rcdom::{Node, NodeData};
+
+/// A small subset of CSS selectors, enough to point at parts of a book:
+/// type (`aside`, `*`), `.class`, `#id`, `[attr]`, `[attr=value]`, combined with the
+/// descendant (` `) and child (`>`) combinators, e.g. `aside.marginalia > p`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    source: String,
+    // Compound selectors from left to right, each with the combinator that links it to
+    // the previous one (ignored for the first).
+    parts: Vec<(Combinator, Compound)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attrs: Vec<(String, Option<String>)>,
+}
+
+impl Selector {
+    /// Whether the element `node` is matched by the selector.
+    pub fn matches(&self, node: &Node) -> bool {
+        match self.parts.last() {
+            Some((_, compound)) if compound.matches(node) => {
+                self.matches_ancestors(node, self.parts.len() - 1)
+            }
+            _ => false,
+        }
+    }
+
+    // `parts[index]` matches `node`, check the parts on its left against its ancestors.
+    fn matches_ancestors(&self, node: &Node, index: usize) -> bool {
+        if index == 0 {
+            return true;
+        }
+        let (combinator, _) = self.parts[index];
+        let (_, compound) = &self.parts[index - 1];
+
+        let mut ancestor = parent(node);
+        while let Some(current) = ancestor {
+            if compound.matches(&current) && self.matches_ancestors(&current, index - 1) {
+                return true;
+            }
+            if combinator == Combinator::Child {
+                return false;
+            }
+            ancestor = parent(&current);
+        }
+        false
+    }
+}
+
+impl Compound {
+    fn matches(&self, node: &Node) -> bool {
+        let NodeData::Element { name, attrs, .. } = &node.data else {
+            return false;
+        };
+        if let Some(tag) = &self.tag {
+            if !name.local.as_ref().eq_ignore_ascii_case(tag) {
+                return false;
+            }
+        }
+
+        let attrs = attrs.borrow();
+        let value_of = |attr_name: &str| {
+            attrs
+                .iter()
+                .find(|attr| attr.name.local.as_ref() == attr_name)
+                .map(|attr| attr.value.as_ref())
+        };
+
+        if let Some(id) = &self.id {
+            if value_of("id") != Some(id.as_str()) {
+                return false;
+            }
+        }
+        if !self.classes.is_empty() {
+            let classes: Vec<&str> = value_of("class").unwrap_or("").split_whitespace().collect();
+            if !self
+                .classes
+                .iter()
+                .all(|class| classes.contains(&class.as_str()))
+            {
+                return false;
+            }
+        }
+        self.attrs.iter().all(
+            |(attr_name, expected)| match (value_of(attr_name), expected) {
+                (Some(value), Some(expected)) => value == expected,
+                (found, None) => found.is_some(),
+                (None, Some(_)) => false,
+            },
+        )
+    }
+}
+
+// rcdom only exposes the parent through a `Cell`, so it is taken and put back.
+pub(super) fn parent(node: &Node) -> Option<Rc<Node>> {
+    let weak = node.parent.take();
+    let parent = weak.as_ref().and_then(|weak| weak.upgrade());
+    node.parent.set(weak);
+    parent
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | ':')
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let error = |reason: &str| format!("invalid selector `{}`: {}", source, reason);
+        let chars: Vec<char> = source.trim().chars().collect();
+        let mut parts: Vec<(Combinator, Compound)> = Vec::new();
+        let mut combinator = Combinator::Descendant;
+        let mut i = 0;
+
+        let read_name = |i: &mut usize| {
+            let start = *i;
+            while *i < chars.len() && is_name_char(chars[*i]) {
+                *i += 1;
+            }
+            chars[start..*i].iter().collect::<String>()
+        };
+
+        while i < chars.len() {
+            if chars[i].is_whitespace() || chars[i] == '>' {
+                while i < chars.len() && (chars[i].is_whitespace() || chars[i] == '>') {
+                    if chars[i] == '>' {
+                        combinator = Combinator::Child;
+                    }
+                    i += 1;
+                }
+                continue;
+            }
+
+            let mut compound = Compound::default();
+            if chars[i] == '*' {
+                i += 1;
+            } else if is_name_char(chars[i]) {
+                compound.tag = Some(read_name(&mut i).to_ascii_lowercase());
+            }
+
+            while i < chars.len() && !chars[i].is_whitespace() && chars[i] != '>' {
+                let marker = chars[i];
+                i += 1;
+                match marker {
+                    '.' | '#' => {
+                        let name = read_name(&mut i);
+                        if name.is_empty() {
+                            return Err(error("missing name"));
+                        }
+                        if marker == '.' {
+                            compound.classes.push(name);
+                        } else {
+                            compound.id = Some(name);
+                        }
+                    }
+                    '[' => {
+                        let end = chars[i..]
+                            .iter()
+                            .position(|&c| c == ']')
+                            .ok_or_else(|| error("unclosed `[`"))?;
+                        let inner: String = chars[i..i + end].iter().collect();
+                        i += end + 1;
+                        let attr = match inner.split_once('=') {
+                            Some((attr_name, value)) => (
+                                attr_name.trim().to_string(),
+                                Some(value.trim().trim_matches(['"', '\'']).to_string()),
+                            ),
+                            None => (inner.trim().to_string(), None),
+                        };
+                        if attr.0.is_empty() || !attr.0.chars().all(is_name_char) {
+                            return Err(error("bad attribute name"));
+                        }
+                        compound.attrs.push(attr);
+                    }
+                    other => return Err(error(&format!("unexpected `{}`", other))),
+                }
+            }
+
+            if parts.is_empty() && combinator == Combinator::Child {
+                return Err(error("starts with `>`"));
+            }
+            parts.push((combinator, compound));
+            combinator = Combinator::Descendant;
+        }
+
+        if parts.is_empty() {
+            return Err(error("empty"));
+        }
+        if combinator == Combinator::Child {
+            return Err(error("ends with `>`"));
+        }
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            parts,
+        })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
@@ -0,0 +1,146 @@
+use markup5ever_rcdom::{Node, NodeData};
+
+use super::EntityReferences;
+
+/// Elements without content in HTML, always written as `<tag/>`.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements written as `<tag/>` when empty. Page markers like `<span id="pg5"/>` must stay
+/// empty elements, see Bug n.2.
+const SELF_CLOSING_WHEN_EMPTY: &[&str] = &["span"];
+
+/// Elements whose text is raw in HTML and must not be escaped.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Writes a parsed document as well-formed XHTML.
+///
+/// Unlike the html5ever serializer, which writes HTML, void elements are self-closed,
+/// non-breaking spaces are written as characters (the `&nbsp;` entity is not defined in
+/// XHTML) and the XML declaration and DOCTYPE are kept as in the source.
+///
+/// Characters found in `references` are written with the reference used in the source.
+pub fn write_document(document: &Node, references: &EntityReferences, output: &mut String) {
+    for node in document.children.borrow().iter() {
+        match &node.data {
+            NodeData::Comment { .. } | NodeData::Doctype { .. } => {
+                write_node(node, references, output, false);
+                output.push('\n');
+            }
+            _ => write_node(node, references, output, false),
+        }
+    }
+}
+
+fn write_node(node: &Node, references: &EntityReferences, output: &mut String, raw_text: bool) {
+    match &node.data {
+        NodeData::Document => {
+            for child in node.children.borrow().iter() {
+                write_node(child, references, output, raw_text);
+            }
+        }
+        // The HTML parser keeps the XML declaration as a `?xml ...?` comment
+        NodeData::Comment { contents } if contents.starts_with("?xml") => {
+            output.push('<');
+            output.push_str(contents);
+            output.push('>');
+        }
+        NodeData::Comment { contents } => {
+            output.push_str("<!--");
+            output.push_str(contents);
+            output.push_str("-->");
+        }
+        NodeData::Doctype {
+            name,
+            public_id,
+            system_id,
+        } => {
+            output.push_str("<!DOCTYPE ");
+            output.push_str(name);
+            match (public_id.is_empty(), system_id.is_empty()) {
+                (true, true) => {}
+                (true, false) => output.push_str(&format!(" SYSTEM \"{}\"", system_id)),
+                (false, _) => {
+                    output.push_str(&format!(" PUBLIC \"{}\" \"{}\"", public_id, system_id))
+                }
+            }
+            output.push('>');
+        }
+        NodeData::ProcessingInstruction { target, contents } => {
+            output.push_str(&format!("<?{} {}?>", target, contents));
+        }
+        NodeData::Text { contents } => {
+            let text = contents.borrow();
+            if raw_text {
+                output.push_str(&text);
+            } else {
+                escape_text(&text, references, output);
+            }
+        }
+        NodeData::Element { name, attrs, .. } => {
+            let tag = name.local.as_ref();
+            output.push('<');
+            output.push_str(tag);
+            for attr in attrs.borrow().iter() {
+                output.push(' ');
+                if let Some(prefix) = &attr.name.prefix {
+                    output.push_str(prefix);
+                    output.push(':');
+                }
+                output.push_str(&attr.name.local);
+                output.push_str("=\"");
+                escape_attribute(&attr.value, references, output);
+                output.push('"');
+            }
+
+            let children = node.children.borrow();
+            if VOID_ELEMENTS.contains(&tag)
+                || (children.is_empty() && SELF_CLOSING_WHEN_EMPTY.contains(&tag))
+            {
+                output.push_str("/>");
+                return;
+            }
+
+            output.push('>');
+            let raw_text = RAW_TEXT_ELEMENTS.contains(&tag);
+            for child in children.iter() {
+                write_node(child, references, output, raw_text);
+            }
+            output.push_str("</");
+            output.push_str(tag);
+            output.push('>');
+        }
+    }
+}
+
+fn escape_text(text: &str, references: &EntityReferences, output: &mut String) {
+    for c in text.chars() {
+        if let Some(reference) = references.get(c) {
+            output.push_str(reference);
+            continue;
+        }
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            _ => output.push(c),
+        }
+    }
+}
+
+fn escape_attribute(value: &str, references: &EntityReferences, output: &mut String) {
+    for c in value.chars() {
+        if let Some(reference) = references.get(c) {
+            output.push_str(reference);
+            continue;
+        }
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '"' => output.push_str("&quot;"),
+            _ => output.push(c),
+        }
+    }
+}
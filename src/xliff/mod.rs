@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use quick_xml::escape::unescape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use regex::{Captures, Regex};
+
+use crate::config::ConfigError;
+use crate::error::EpubTranslatorError;
+use crate::report::AlignedUnit;
+use crate::segment::escape_xml;
+
+/// Namespace of XLIFF 2.0 documents.
+pub const XLIFF_NAMESPACE: &str = "urn:oasis:names:tc:xliff:document:2.0";
+
+/// Sub-state of the machine translated segments of `write_xliff`, to be reviewed.
+pub const NEEDS_REVIEW: &str = "epub-translator:needs-review";
+
+/// Writes `units` as an XLIFF 2.0 document, with a `file` per file of the book and a `unit`
+/// per translation unit, `u1`, `u2`... by position. Translated units are `translated` with the
+/// `NEEDS_REVIEW` sub-state, the others are `initial`, without target. Inline elements are
+/// `pc` codes, the elements without text and the kept terms `ph` ones.
+pub fn write_xliff(
+    units: &[AlignedUnit],
+    source_lang: &str,
+    target_lang: &str,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    // The units of each file, in the order of the book
+    let mut files: Vec<(&Path, Vec<&AlignedUnit>)> = Vec::new();
+    for unit in units {
+        match files.iter_mut().find(|(path, _)| *path == unit.path) {
+            Some((_, units)) => units.push(unit),
+            None => files.push((&unit.path, vec![unit])),
+        }
+    }
+
+    writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        output,
+        r#"<xliff xmlns="{}" version="2.0" srcLang="{}" trgLang="{}">"#,
+        XLIFF_NAMESPACE,
+        xml_attribute(source_lang),
+        xml_attribute(target_lang)
+    )?;
+    for (index, (path, units)) in files.iter().enumerate() {
+        writeln!(
+            output,
+            r#"<file id="f{}" original="{}">"#,
+            index + 1,
+            xml_attribute(&path.to_string_lossy())
+        )?;
+        for unit in units {
+            writeln!(output, r#"<unit id="u{}">"#, unit.position)?;
+            match &unit.translation_markup {
+                Some(_) => writeln!(
+                    output,
+                    r#"<segment state="translated" subState="{}">"#,
+                    NEEDS_REVIEW
+                )?,
+                None => writeln!(output, r#"<segment state="initial">"#)?,
+            }
+            writeln!(
+                output,
+                "<source>{}</source>",
+                xliff_markup(&unit.source_markup)
+            )?;
+            if let Some(translation) = &unit.translation_markup {
+                writeln!(output, "<target>{}</target>", xliff_markup(translation))?;
+            }
+            writeln!(output, "</segment>")?;
+            writeln!(output, "</unit>")?;
+        }
+        writeln!(output, "</file>")?;
+    }
+    writeln!(output, "</xliff>")
+}
+
+/// The targets of an XLIFF 2.0 document written by `write_xliff`, once post-edited.
+#[derive(Debug, Clone, Default)]
+pub struct Xliff {
+    pub source_lang: String,
+    /// `None` when the document has no `trgLang`.
+    pub target_lang: Option<String>,
+    // Target of each unit with one, in the format of `Segment::text`, by file and position
+    targets: HashMap<PathBuf, HashMap<usize, String>>,
+}
+
+impl Xliff {
+    /// Parses an XLIFF 2.0 document. The target of a unit is the one of each of its segments,
+    /// with the source of the ignorable parts between them.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut xliff = Self::default();
+        let mut reader = Reader::from_str(content);
+        let mut file: Option<PathBuf> = None;
+        let mut unit: Option<(usize, Option<String>)> = None;
+        let mut ignorable = false;
+        // The target being read, with its open `pc` codes
+        let mut target: Option<(String, Vec<String>)> = None;
+        // Text read since the last element, escaped
+        let mut text = String::new();
+        loop {
+            let event = reader
+                .read_event()
+                .map_err(|e| format!("position {}: {}", reader.error_position(), e))?;
+            match &event {
+                Event::Text(content) => {
+                    text.push_str(content);
+                    continue;
+                }
+                Event::GeneralRef(reference) => {
+                    text.push('&');
+                    text.push_str(reference);
+                    text.push(';');
+                    continue;
+                }
+                Event::CData(content) => {
+                    text.push_str(&escape_xml(content));
+                    continue;
+                }
+                _ => {}
+            }
+            // Escaped again like the text of a segment, references resolved
+            if let Some((markup, _)) = &mut target {
+                markup.push_str(&escape_xml(&unescape(&text).map_err(|e| e.to_string())?));
+            }
+            text.clear();
+            match event {
+                Event::Start(element) => match element.local_name().as_ref() {
+                    "xliff" => {
+                        xliff.source_lang = attribute(&element, "srcLang")?.unwrap_or_default();
+                        xliff.target_lang = attribute(&element, "trgLang")?;
+                    }
+                    "file" => file = attribute(&element, "original")?.map(PathBuf::from),
+                    "unit" => {
+                        let id = attribute(&element, "id")?.unwrap_or_default();
+                        let position = id
+                            .strip_prefix('u')
+                            .and_then(|position| position.parse().ok())
+                            .ok_or(format!("unit id `{}` is not a position like `u1`", id))?;
+                        unit = Some((position, None));
+                    }
+                    "ignorable" => ignorable = true,
+                    "target" if !ignorable => target = Some((String::new(), Vec::new())),
+                    "source" if ignorable => target = Some((String::new(), Vec::new())),
+                    "pc" => {
+                        if let Some((markup, open)) = &mut target {
+                            let id = attribute(&element, "id")?.unwrap_or_default();
+                            if is_code(&id, 'g') {
+                                markup.push_str(&format!("<{}>", id));
+                            }
+                            open.push(id);
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Empty(element) if element.local_name().as_ref() == "ph" => {
+                    if let Some((markup, _)) = &mut target {
+                        let id = attribute(&element, "id")?.unwrap_or_default();
+                        if is_code(&id, 'x') || is_code(&id, 'k') {
+                            markup.push_str(&format!("<{}/>", id));
+                        } else if id.starts_with("lb") {
+                            markup.push_str("<lb/>");
+                        }
+                    }
+                }
+                Event::End(element) => match element.local_name().as_ref() {
+                    "pc" => {
+                        if let Some((markup, open)) = &mut target {
+                            let id = open.pop().unwrap_or_default();
+                            if is_code(&id, 'g') {
+                                markup.push_str(&format!("</{}>", id));
+                            }
+                        }
+                    }
+                    "target" | "source" => {
+                        if let (Some((markup, _)), Some((_, translation))) =
+                            (target.take(), &mut unit)
+                        {
+                            translation
+                                .get_or_insert_with(String::new)
+                                .push_str(&markup);
+                        }
+                    }
+                    "ignorable" => ignorable = false,
+                    "unit" => {
+                        if let (Some((position, Some(translation))), Some(file)) =
+                            (unit.take(), &file)
+                        {
+                            xliff
+                                .targets
+                                .entry(file.clone())
+                                .or_default()
+                                .insert(position, translation);
+                        }
+                    }
+                    "file" => file = None,
+                    _ => {}
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(xliff)
+    }
+
+    /// Reads the XLIFF file at `path`, see `parse`.
+    pub fn read(path: &Path) -> Result<Self, EpubTranslatorError> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|message| {
+            ConfigError {
+                path: path.to_path_buf(),
+                message,
+            }
+            .into()
+        })
+    }
+
+    /// The target of the unit at `position` in the file `path`, from 1, in the format of
+    /// `Segment::text`.
+    pub fn target(&self, path: &Path, position: usize) -> Option<&str> {
+        self.targets.get(path)?.get(&position).map(String::as_str)
+    }
+
+    /// Number of units with a target.
+    pub fn len(&self) -> usize {
+        self.targets.values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Replaces the placeholders of a segment text with XLIFF inline codes. Line breaks get ids of
+// their own, the ones of a unit must differ.
+fn xliff_markup(markup: &str) -> String {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let re = PLACEHOLDER.get_or_init(|| Regex::new(r"<(/?)([gxk]\d+|lb)\s*(/?)>").unwrap());
+    let mut line_breaks = 0;
+    re.replace_all(markup, |captures: &Captures| {
+        match (&captures[1], &captures[2], &captures[3]) {
+            ("", "lb", _) => {
+                line_breaks += 1;
+                format!(r#"<ph id="lb{}"/>"#, line_breaks)
+            }
+            ("", id, "/") => format!(r#"<ph id="{}"/>"#, id),
+            ("", id, _) => format!(r#"<pc id="{}">"#, id),
+            _ => "</pc>".to_string(),
+        }
+    })
+    .into_owned()
+}
+
+// Whether `id` is the id of a placeholder of kind `kind`, e.g. `g0` for `g`.
+fn is_code(id: &str, kind: char) -> bool {
+    id.strip_prefix(kind)
+        .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>, String> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        if attribute.key.local_name().as_ref() == name {
+            let value = unescape(&attribute.value).map_err(|e| e.to_string())?;
+            return Ok(Some(value.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+fn xml_attribute(value: &str) -> String {
+    escape_xml(value).replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_parse() -> Result<(), Box<dyn std::error::Error>> {
+        let units = vec![
+            AlignedUnit {
+                path: PathBuf::from("OEBPS/one.xhtml"),
+                position: 1,
+                source_markup: "A <g0>bold</g0> word<lb/>and <x1/> &amp; <k2/>".to_string(),
+                translation_markup: Some(
+                    "Une <g0>grasse</g0> parole<lb/>et <x1/> &amp; <k2/>".to_string(),
+                ),
+                ..Default::default()
+            },
+            AlignedUnit {
+                path: PathBuf::from("OEBPS/one.xhtml"),
+                position: 2,
+                source_markup: "Untranslated".to_string(),
+                ..Default::default()
+            },
+        ];
+        let mut output = Vec::new();
+        write_xliff(&units, "en", "fr", &mut output)?;
+        let output = String::from_utf8(output)?;
+        assert!(output.contains(
+            r#"<target>Une <pc id="g0">grasse</pc> parole<ph id="lb1"/>et <ph id="x1"/> &amp; <ph id="k2"/></target>"#
+        ));
+        assert!(output.contains(
+            r#"<unit id="u2">
+<segment state="initial">"#
+        ));
+
+        // The targets come back as they were sent, entities resolved the way segments have them
+        let xliff = Xliff::parse(&output.replace("parole", "mot&#x20;"))?;
+        assert_eq!(
+            (xliff.source_lang.as_str(), xliff.target_lang.as_deref()),
+            ("en", Some("fr"))
+        );
+        assert_eq!(xliff.len(), 1);
+        let path = Path::new("OEBPS/one.xhtml");
+        assert_eq!(
+            xliff.target(path, 1),
+            Some("Une <g0>grasse</g0> mot <lb/>et <x1/> &amp; <k2/>")
+        );
+        assert_eq!(xliff.target(path, 2), None);
+        assert!(
+            Xliff::parse(r#"<xliff><file original="a"><unit id="x"></unit></file></xliff>"#)
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -14,8 +14,8 @@ output_file="$2"
 # Temporary file for intermediary CSV
 temp_csv_file=$(mktemp)
 
-# Extract `[TRACE]` lines from the log file and remove the prefix
-grep '^\[TRACE\]' "$input_log_file" | sed 's/^\[TRACE\]//' > "$temp_csv_file"
+# Extract `[TRACE]` lines from the log file (written with -vvv --log-file) and remove the prefix
+grep '\[TRACE\]' "$input_log_file" | sed 's/^.*\[TRACE\]//' > "$temp_csv_file"
 
 # Write the header to the output file
 read -r header < "$temp_csv_file"